@@ -0,0 +1,97 @@
+// The pixel conversions of src/client/pixels.rs against the per-pixel loops they replaced, on a
+// 1080p frame: `cargo bench --bench pixels`. The client modules live in the binary rather than the
+// library, so the module is compiled in here directly.
+#[path = "../src/client/pixels.rs"]
+#[allow(dead_code, unused_imports)]
+mod pixels;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use pixels::{Alpha, Order};
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+const ROUNDS: u32 = 20;
+
+// a name, the scalar conversion of a pixel, and what pixels.rs does instead
+type Case = (&'static str, fn(&[u8]) -> u32, Order, Alpha);
+
+fn rgb(r: u8, g: u8, b: u8) -> u32 {
+    (r as u32) << 16 | (g as u32) << 8 | (b as u32)
+}
+
+fn premultiplied(r: u8, g: u8, b: u8, a: u8) -> u32 {
+    let a = a as u32;
+    let scale = |c: u8| (c as u32 * a + 127) / 255;
+    a << 24 | scale(r) << 16 | scale(g) << 8 | scale(b)
+}
+
+// XpraWindow::paint as it was: a function pointer call per pixel
+fn scalar_paint(src: &[u8], dst: &mut [u32], to_pixel: fn(&[u8]) -> u32) {
+    for (px, out) in src.chunks_exact(4).zip(dst.iter_mut()) {
+        *out = to_pixel(px);
+    }
+}
+
+fn simd_paint(src: &[u8], dst: &mut [u32], order: Order, alpha: Alpha) {
+    for (src_row, dst_row) in src.chunks_exact(WIDTH * 4).zip(dst.chunks_exact_mut(WIDTH)) {
+        pixels::to_u32_row(src_row, dst_row, order, alpha);
+    }
+}
+
+// the best of a few rounds, which is the least disturbed by whatever else the machine is doing
+fn time(mut run: impl FnMut()) -> Duration {
+    run();
+    (0..ROUNDS).map(|_| {
+        let t0 = Instant::now();
+        run();
+        t0.elapsed()
+    }).min().unwrap()
+}
+
+fn report(name: &str, scalar: Duration, simd: Duration) {
+    let mpixels = |elapsed: Duration| (WIDTH * HEIGHT) as f64 / elapsed.as_secs_f64() / 1e6;
+    println!("{name:<24} scalar {scalar:>10.2?} ({:>6.0} Mpx/s)  simd {simd:>10.2?} ({:>6.0} Mpx/s)  x{:.1}",
+             mpixels(scalar), mpixels(simd), scalar.as_secs_f64() / simd.as_secs_f64());
+}
+
+fn main() {
+    let src: Vec<u8> = (0..WIDTH * HEIGHT * 4).map(|i| (i * 7 + i / 13) as u8).collect();
+    let mut dst = vec![0u32; WIDTH * HEIGHT];
+    println!("{WIDTH}x{HEIGHT}, best of {ROUNDS}");
+
+    let cases: [Case; 3] = [
+        ("jpeg (BGRX)", |px| rgb(px[2], px[1], px[0]), Order::Bgra, Alpha::Clear),
+        ("png (RGBA)", |px| rgb(px[0], px[1], px[2]), Order::Rgba, Alpha::Clear),
+        ("png premultiplied", |px| premultiplied(px[0], px[1], px[2], px[3]), Order::Rgba, Alpha::Premultiply),
+    ];
+    for (name, to_pixel, order, alpha) in cases {
+        let scalar = time(|| scalar_paint(black_box(&src), black_box(&mut dst), to_pixel));
+        let simd = time(|| simd_paint(black_box(&src), black_box(&mut dst), order, alpha));
+        report(name, scalar, simd);
+    }
+
+    // the mmap and rgb paths: BGRX made opaque, and RGBA swapped to BGRA, in place
+    let mut bytes = src.clone();
+    let scalar = time(|| {
+        for px in black_box(&mut bytes).chunks_exact_mut(4) {
+            px[3] = 0xFF;
+        }
+    });
+    let simd = time(|| pixels::to_bgra_in_place(black_box(&mut bytes), Order::Bgra, Alpha::Opaque));
+    report("mmap (BGRX in place)", scalar, simd);
+    let scalar = time(|| {
+        let mut out = Vec::with_capacity(src.len());
+        for px in black_box(&src).chunks_exact(4) {
+            out.extend_from_slice(&[px[2], px[1], px[0], px[3]]);
+        }
+        black_box(out);
+    });
+    let simd = time(|| {
+        let mut out = black_box(&src).clone();
+        pixels::to_bgra_in_place(&mut out, Order::Rgba, Alpha::Keep);
+        black_box(out);
+    });
+    report("rgb32 (RGBA)", scalar, simd);
+}
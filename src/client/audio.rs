@@ -12,7 +12,7 @@ use std::collections::VecDeque;
 use serde_json::{Value, json};
 use yaml_rust2::Yaml;
 
-use xpra::net::packet::{Packet, yaml_bool, yaml_bytes, yaml_str};
+use xpra::net::packet::{Packet, yaml_binary, yaml_bool, yaml_bytes, yaml_str};
 
 pub const CODEC: &str = "opus";
 pub const AUDIO_DATA_PACKET: &str = "audio-data";
@@ -190,7 +190,10 @@ impl IncomingAudio {
         let metadata = AudioMetadata::parse(&packet.main[3]);
         let headers = if packet.len() > 4 {
             match &packet.main[4] {
-                Yaml::Array(values) => values.iter().map(yaml_bytes).collect(),
+                // each one a nested byte string (see yaml_binary), or base64 from older senders
+                Yaml::Array(values) => values.iter()
+                    .map(|value| yaml_binary(value).unwrap_or_else(|| yaml_bytes(value)))
+                    .collect(),
                 // Be liberal for implementations which send one header rather than a sequence.
                 value => {
                     let header = yaml_bytes(value);
@@ -563,6 +566,9 @@ mod tests {
         assert_eq!(parsed.metadata.duration_ns, Some(20_000_000));
         assert_eq!(parsed.headers[0], b"OpusHead\x01\x02");
         assert_eq!(parsed.headers[1], b"OpusTags");
+        // as rencode and PyYAML's `!!binary` send them
+        packet.main[4] = yaml("[[79, 112, 117, 115, 72, 101, 97, 100, 1, 2]]");
+        assert_eq!(IncomingAudio::parse(&mut packet).unwrap().headers, [b"OpusHead\x01\x02"]);
     }
 
     #[test]
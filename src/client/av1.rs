@@ -0,0 +1,129 @@
+// AV1 video decoder built on dav1d. Only compiled with the `av1` cargo feature, which links the
+// system libdav1d (found with pkg-config, through dav1d-sys).
+//
+// Each draw packet carries one temporal unit of an AV1 "low overhead" OBU stream, with the sequence
+// header in-band on keyframes. dav1d decodes on threads of its own and may hold a frame back to
+// overlap the next one, which we cap at a delay of one frame: a remote desktop has no next frame
+// to wait for. The planar YUV it outputs goes through csc.rs like the other software decoders'.
+// Like the h264 decoders, one `Av1Decoder` is stateful and lives per xpra window, on one decode
+// worker only (see video.rs).
+use dav1d::pixel::{MatrixCoefficients, YUVRange};
+use dav1d::{Decoder, PixelLayout, PlanarImageComponent, Settings};
+use log::trace;
+
+use super::csc::{default_matrix, yuv_to_bgrx, YuvImage, BT601, BT709};
+
+pub struct Av1Decoder {
+    decoder: Decoder,
+    full_range: Option<bool>, // xpra's `full-range`, which wins over what the stream itself says
+}
+
+impl Av1Decoder {
+    pub fn new() -> Result<Self, String> {
+        let mut settings = Settings::new();
+        settings.set_max_frame_delay(1);
+        let decoder = Decoder::with_settings(&settings).map_err(|e| format!("failed to create av1 decoder: {e}"))?;
+        Ok(Av1Decoder { decoder, full_range: None })
+    }
+
+    // Decode one encoded frame. Returns Ok(Some(bgrx)) when a frame is ready (exactly w*h*4 bytes),
+    // Ok(None) when the input was consumed but no frame is available yet -- the caller must still
+    // ack the draw sequence -- or Err on failure.
+    pub fn decode(&mut self, data: &[u8], w: u32, h: u32, full_range: Option<bool>)
+        -> Result<Option<Vec<u8>>, String>
+    {
+        if w == 0 || h == 0 {
+            return Err("invalid zero frame size".to_string());
+        }
+        if full_range.is_some() {
+            self.full_range = full_range;
+        }
+        // dav1d takes ownership of the buffer, and may not take all of it in one go: it then wants
+        // a picture collected before it accepts the rest. Keep the latest of those pictures.
+        let mut picture = None;
+        let mut sent = self.decoder.send_data(data.to_vec(), None, None, None);
+        while let Err(dav1d::Error::Again) = sent {
+            match self.decoder.get_picture() {
+                Ok(next) => picture = Some(next),
+                Err(dav1d::Error::Again) => {}
+                Err(e) => return Err(format!("av1 decoding failed: {e}")),
+            }
+            sent = self.decoder.send_pending_data();
+        }
+        sent.map_err(|e| format!("av1 decoding failed: {e}"))?;
+        match self.decoder.get_picture() {
+            Ok(next) => picture = Some(next),
+            Err(dav1d::Error::Again) => {}
+            Err(e) => return Err(format!("av1 decoding failed: {e}")),
+        }
+        let Some(picture) = picture else {
+            return Ok(None);
+        };
+        trace!("av1 decoded a {}x{} {:?} frame for a {}x{} draw",
+               picture.width(), picture.height(), picture.pixel_layout(), w, h);
+        if picture.bit_depth() != 8 {
+            // we only advertise 8-bit colourspaces, see video.rs
+            return Err(format!("unsupported av1 bit depth {}", picture.bit_depth()));
+        }
+        let chroma_shift = match picture.pixel_layout() {
+            PixelLayout::I420 => (1, 1),
+            PixelLayout::I422 => (1, 0),
+            PixelLayout::I444 => (0, 0),
+            PixelLayout::I400 => return Err("unsupported monochrome av1 frame".to_string()),
+        };
+        if picture.width() < w || picture.height() < h {
+            return Err(format!("decoded a {}x{} frame for a {w}x{h} draw", picture.width(), picture.height()));
+        }
+        // AV1 signals its colourspace and range in the sequence header, but encoders often leave
+        // the matrix unspecified
+        let matrix = match picture.matrix_coefficients() {
+            MatrixCoefficients::BT709 => &BT709,
+            MatrixCoefficients::BT470BG | MatrixCoefficients::ST170M => &BT601,
+            _ => default_matrix(h),
+        };
+        let full_range = self.full_range.unwrap_or(picture.color_range() == YUVRange::Full);
+        let planes = [PlanarImageComponent::Y, PlanarImageComponent::U, PlanarImageComponent::V]
+            .map(|component| picture.plane(component));
+        let yuv = YuvImage {
+            planes: [&planes[0], &planes[1], &planes[2]],
+            strides: [PlanarImageComponent::Y, PlanarImageComponent::U, PlanarImageComponent::V]
+                .map(|component| picture.stride(component) as usize),
+            chroma_shift,
+        };
+        Ok(Some(yuv_to_bgrx(&yuv, w as usize, h as usize, matrix, full_range)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 16x16 keyframe of rgb(40, 120, 200), encoded by rav1e as one temporal unit of the low
+    // overhead OBU stream xpra's encoders send: temporal delimiter, sequence header and frame.
+    const FRAME: [u8; 51] = [
+        0x12, 0x00, 0x0a, 0x0a, 0x00, 0x00, 0x00, 0xf8, 0xcf, 0xfc, 0x42, 0x14, 0x01, 0x40, 0x32, 0x23,
+        0x10, 0x02, 0x89, 0x1d, 0x8b, 0xfe, 0xf1, 0xe9, 0x60, 0x00, 0x10, 0x00, 0x08, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x30, 0xc3, 0x0c, 0x10, 0x1c, 0xe8, 0x15, 0xd9, 0x07, 0x58, 0x90, 0xae,
+        0x70, 0xf5, 0x60,
+    ];
+
+    #[test]
+    fn frames_are_decoded_to_bgrx() {
+        // an odd sized draw, cropped from the frame the encoder padded it to
+        let (w, h) = (15u32, 13u32);
+        let mut decoder = Av1Decoder::new().unwrap();
+        assert_eq!(decoder.decode(&FRAME, 0, h, None), Err("invalid zero frame size".to_string()));
+        let pixels = decoder.decode(&FRAME, w, h, None).unwrap().expect("a frame");
+        assert_eq!(pixels.len(), (w * h * 4) as usize);
+        for px in pixels.chunks_exact(4) {
+            let expected = [200, 120, 40, 0xFF];
+            assert!(px.iter().zip(expected).all(|(&got, want)| got.abs_diff(want) <= 8), "{px:?}");
+        }
+    }
+
+    #[test]
+    fn garbage_is_an_error() {
+        let mut decoder = Av1Decoder::new().unwrap();
+        assert!(decoder.decode(&[0xFF; 64], 16, 16, None).is_err());
+    }
+}
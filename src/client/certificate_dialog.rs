@@ -0,0 +1,146 @@
+use std::num::NonZeroU32;
+use std::rc::Rc;
+
+use log::error;
+use softbuffer::{Context, Surface};
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, KeyEvent};
+use winit::event_loop::{ActiveEventLoop, OwnedDisplayHandle};
+use winit::keyboard::{Key, NamedKey};
+use winit::window::Window;
+
+use xpra::net::tls::format_fingerprint;
+
+use super::auth_dialog::DialogAction;
+use super::font;
+use super::paint::outline;
+
+// The trust-on-first-use question, asked when an `ssl://`/`wss://` server's certificate fails
+// verification and we have no fingerprint pinned for it (see main.rs): the SHA-256 fingerprint
+// the server presented, to be compared with the one on the server (`openssl x509 -fingerprint
+// -sha256` prints it in the same form), and Accept/Cancel. Painted by hand like AuthDialog, whose
+// DialogAction it reports - Submit meaning "trust it". Accepting pins it in the known hosts store.
+pub struct CertificateDialog {
+    pub window: Rc<Window>,
+    surface: Surface<OwnedDisplayHandle, Rc<Window>>,
+    framebuffer: Vec<u32>,
+    width: u32,
+    height: u32,
+    address: String,
+    fingerprint: String,
+}
+
+const BG: u32 = 0x0020_2020;
+const FG: u32 = 0x00E0_E0E0;
+const HINT: u32 = 0x0090_9090;
+
+// 16 of the 32 colon-separated bytes per line: the whole fingerprint would be 760 pixels wide.
+const BYTES_PER_LINE: usize = 16;
+
+impl CertificateDialog {
+    pub fn new(
+        event_loop: &ActiveEventLoop,
+        context: &Context<OwnedDisplayHandle>,
+        address: String,
+        fingerprint: &str,
+    ) -> Result<Self, String> {
+        let (width, height) = (460u32, 210u32);
+        let attrs = Window::default_attributes()
+            .with_title("Xpra: Unknown Certificate")
+            .with_inner_size(PhysicalSize::new(width, height))
+            .with_resizable(false);
+        let window = event_loop
+            .create_window(attrs)
+            .map_err(|e| format!("failed to create certificate dialog window: {e:?}"))?;
+        let window = Rc::new(window);
+        let mut surface = Surface::new(context, window.clone())
+            .map_err(|e| format!("failed to create certificate dialog surface: {e:?}"))?;
+        surface
+            .resize(NonZeroU32::new(width).unwrap(), NonZeroU32::new(height).unwrap())
+            .map_err(|e| format!("failed to size certificate dialog surface: {e:?}"))?;
+        let mut dialog = CertificateDialog {
+            window,
+            surface,
+            framebuffer: vec![BG; (width * height) as usize],
+            width,
+            height,
+            address,
+            fingerprint: format_fingerprint(fingerprint),
+        };
+        dialog.draw();
+        Ok(dialog)
+    }
+
+    pub fn draw(&mut self) {
+        // as in AuthDialog: follow whatever inner size the compositor actually gave us
+        let size = self.window.inner_size();
+        let (w, h) = (size.width.max(1), size.height.max(1));
+        if w != self.width || h != self.height {
+            self.width = w;
+            self.height = h;
+            self.framebuffer = vec![BG; (w * h) as usize];
+            if let (Some(nw), Some(nh)) = (NonZeroU32::new(w), NonZeroU32::new(h)) {
+                let _ = self.surface.resize(nw, nh);
+            }
+        }
+        let fbw = self.width as usize;
+        for px in self.framebuffer.iter_mut() {
+            *px = BG;
+        }
+        let lines = [
+            format!("The certificate of {} is not trusted.", self.address),
+            "Its SHA-256 fingerprint is:".to_string(),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            font::blit_str(&mut self.framebuffer, fbw, 12, 16 + i as i32 * 20, 1, line, FG);
+        }
+        // the fingerprint, boxed, over two lines of 16 bytes each
+        let (fx, fy) = (12i32, 62i32);
+        let (fwid, fhei) = (self.width as i32 - 24, 2 * font::GLYPH_H + 16);
+        outline(&mut self.framebuffer, fbw, fx, fy, fwid, fhei, FG);
+        let row_len = BYTES_PER_LINE * 3;
+        for (i, row) in [&self.fingerprint[..row_len], &self.fingerprint[row_len..]].iter().enumerate() {
+            font::blit_str(&mut self.framebuffer, fbw, fx + 8, fy + 8 + i as i32 * font::GLYPH_H, 1, row, FG);
+        }
+        font::blit_str(&mut self.framebuffer, fbw, 12, fy + fhei + 14, 1,
+                       "Trust it for this server from now on?", FG);
+        font::blit_str(
+            &mut self.framebuffer,
+            fbw,
+            12,
+            self.height as i32 - 26,
+            1,
+            "Enter = trust     Esc = cancel",
+            HINT,
+        );
+        self.present();
+    }
+
+    fn present(&mut self) {
+        let mut buffer = match self.surface.buffer_mut() {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                error!("failed to get certificate dialog buffer: {:?}", e);
+                return;
+            }
+        };
+        if buffer.len() != self.framebuffer.len() {
+            return;
+        }
+        buffer.copy_from_slice(&self.framebuffer);
+        if let Err(e) = buffer.present() {
+            error!("failed to present certificate dialog: {:?}", e);
+        }
+    }
+
+    pub fn handle_key(&self, event: &KeyEvent) -> DialogAction {
+        if event.state != ElementState::Pressed {
+            return DialogAction::None;
+        }
+        match &event.logical_key {
+            Key::Named(NamedKey::Enter) => DialogAction::Submit,
+            Key::Named(NamedKey::Escape) => DialogAction::Cancel,
+            _ => DialogAction::None,
+        }
+    }
+}
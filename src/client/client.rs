@@ -6,6 +6,7 @@ use std::fmt;
 use std::io;
 use std::rc::Rc;
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::sync::Arc;
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::thread;
@@ -22,7 +23,7 @@ use winit::event_loop::{ActiveEventLoop, EventLoopProxy, OwnedDisplayHandle};
 use winit::keyboard::{Key, ModifiersState, NamedKey, PhysicalKey};
 use winit::platform::scancode::PhysicalKeyExtScancode;
 use winit::window::{
-    CursorGrabMode, CursorIcon, CustomCursor, Fullscreen, Icon, ResizeDirection, Window,
+    CursorGrabMode, CursorIcon, CustomCursor, Fullscreen, ResizeDirection, Window,
     WindowId, WindowLevel,
 };
 
@@ -30,9 +31,10 @@ use xpra::exit_codes::ExitCode;
 use xpra::net::serde::VERSION_KEY_STR;
 use xpra::VERSION;
 use xpra::net::connection::Connection;
-use xpra::net::io::{write_packet, read_packet};
-use xpra::net::serde::parse_packet;
-use xpra::net::packet::{Packet, yaml_hash, yaml_hash_bool, yaml_hash_str, yaml_i32};
+use xpra::net::crypto::{Cipher, CipherParams, Encryption};
+use xpra::net::io::{write_packet, read_packet, COMPRESSORS};
+use xpra::net::serde::{encode_packet, parse_packet, PacketEncoder, PACKET_ENCODERS};
+use xpra::net::packet::{Packet, yaml_hash_bool, yaml_hash_str};
 use xpra::net::rand::secure_hex;
 use xpra::net::sha256::hmac_sha256_hex;
 use super::auth_dialog::{AuthDialog, DialogAction};
@@ -42,26 +44,44 @@ use super::audio::{
     AUDIO_CAPABILITIES_PACKET, CODEC,
 };
 use super::clipboard::start_clipboard_loop;
+use super::decode_pool::{self, DecodeRequest, VideoDecoders};
+use super::desktop::{self, DesktopSession, DESKTOP_SIZE_DELAY};
 use super::draw_decoder;
+use super::icons::{self, IconImage, WindowIcons};
+use super::packets::{
+    scrolls_yaml, Challenge, ClipboardData, Cursor, Draw, DrawDecoded, DrawFailed, MoveResize,
+    NewWindow, Notification, PointerPosition, TypedPacket, WindowIcon,
+};
 use super::mmap::{self, MmapArea};
 use super::pinentry::{find_pinentry, spawn_pinentry};
+use super::pixels::{self, Alpha, Order};
 use super::remote_logging::LogSink;
 #[cfg(windows)]
 use super::tray;
 #[cfg(windows)]
 use super::windows_audio::{AudioWorker, EnqueueError};
-use super::window::XpraWindow;
+use super::video::{self, VideoDecoder, VIDEO_ENCODINGS};
+use super::scaling::{DesktopScaling, Scaling};
+use super::size_hints::SizeHints;
+use super::window::{has_transparency, XpraWindow};
+use super::window_hints::{WindowHints, WindowType};
+use super::window_properties::{self, State, WORKSPACE_UNSET};
 
 
 // How often we send our own `ping` once the session is up. A few seconds keeps the server's view
 // of our latency fresh without being chatty; xpra's own client pings on a similar cadence.
 const PING_INTERVAL: Duration = Duration::from_secs(5);
 
-#[derive(Debug, Default, PartialEq)]
-struct WindowSizeConstraints {
-    minimum: Option<(u32, u32)>,
-    maximum: Option<(u32, u32)>,
-    increment: Option<(u32, u32)>,
+// With --reconnect, the pause before the first attempt to get the connection back, doubled after
+// every failed one up to the second: a network blip is over in seconds, an outage may take minutes.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// `--reconnect`: how to connect to the same server again - main.rs's `connect`, with the target and
+// TLS options of the first connection - and for how long to keep trying before giving up.
+pub struct Reconnect {
+    pub connect: Arc<dyn Fn() -> Result<Connection, (ExitCode, String)> + Send + Sync>,
+    pub timeout: Duration,
 }
 
 #[derive(Debug, Default, PartialEq)]
@@ -73,7 +93,28 @@ struct WindowMetadataUpdate {
     iconic: Option<bool>,
     above: Option<bool>,
     below: Option<bool>,
-    size_constraints: Option<WindowSizeConstraints>,
+    // whether the window has an alpha channel (an ARGB visual on the server's side): only read
+    // when the window is created, since ours cannot acquire one afterwards
+    has_alpha: Option<bool>,
+    transparent: Option<bool>,
+    size_constraints: Option<SizeHints>,
+    // where the window stands among the others (see window_hints.rs): also only read when it is
+    // created
+    transient_for: Option<u64>,
+    modal: Option<bool>,
+    window_type: Option<Vec<String>>,
+    group_leader: Option<u64>,
+    role: Option<String>,
+    // the ones winit has no API for (see window_properties.rs). `opacity` is None for an opaque
+    // window: the server sends -1 when it has none.
+    opacity: Option<Option<u32>>,
+    sticky: Option<bool>,
+    skip_taskbar: Option<bool>,
+    skip_pager: Option<bool>,
+    workspace: Option<u32>,
+    // WM_CLASS: (instance, class)
+    class_instance: Option<(String, String)>,
+    shadow: Option<bool>,
 }
 
 impl WindowMetadataUpdate {
@@ -86,13 +127,33 @@ impl WindowMetadataUpdate {
             iconic: metadata_bool(metadata, "iconic"),
             above: metadata_bool(metadata, "above"),
             below: metadata_bool(metadata, "below"),
+            has_alpha: metadata_bool(metadata, "has-alpha"),
+            transparent: metadata_bool(metadata, "transparent"),
             size_constraints: metadata_hash(metadata, "size-constraints").map(|constraints| {
-                WindowSizeConstraints {
+                SizeHints {
                     minimum: metadata_pair(constraints, "minimum-size"),
                     maximum: metadata_pair(constraints, "maximum-size"),
+                    base: metadata_pair(constraints, "base-size"),
                     increment: metadata_pair(constraints, "increment"),
+                    minimum_aspect: metadata_pair(constraints, "minimum-aspect"),
+                    maximum_aspect: metadata_pair(constraints, "maximum-aspect"),
                 }
             }),
+            transient_for: metadata_wid(metadata, "transient-for"),
+            modal: metadata_bool(metadata, "modal"),
+            window_type: metadata_str_list(metadata, "window-type"),
+            group_leader: metadata_wid(metadata, "group-leader"),
+            role: metadata_str(metadata, "role"),
+            opacity: metadata_int(metadata, "opacity").map(|opacity| u32::try_from(opacity).ok()),
+            sticky: metadata_bool(metadata, "sticky"),
+            skip_taskbar: metadata_bool(metadata, "skip-taskbar"),
+            skip_pager: metadata_bool(metadata, "skip-pager"),
+            workspace: metadata_int(metadata, "workspace").and_then(|workspace| u32::try_from(workspace).ok()),
+            class_instance: metadata_str_list(metadata, "class-instance").and_then(|names| match names.as_slice() {
+                [instance, class, ..] => Some((instance.clone(), class.clone())),
+                _ => None,
+            }),
+            shadow: metadata_bool(metadata, "shadow"),
         }
     }
 }
@@ -126,6 +187,31 @@ fn metadata_bool(metadata: &Yaml, key: &str) -> Option<bool> {
     }
 }
 
+fn metadata_int(metadata: &Yaml, key: &str) -> Option<i64> {
+    let Yaml::Hash(hash) = metadata else {
+        return None;
+    };
+    match hash.get(&Yaml::String(key.to_string())) {
+        Some(Yaml::Integer(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+// a window id: 0 (or none at all) is how the server says there is no such window
+fn metadata_wid(metadata: &Yaml, key: &str) -> Option<u64> {
+    metadata_int(metadata, key).filter(|&wid| wid > 0).map(|wid| wid as u64)
+}
+
+fn metadata_str_list(metadata: &Yaml, key: &str) -> Option<Vec<String>> {
+    let Yaml::Hash(hash) = metadata else {
+        return None;
+    };
+    let Some(Yaml::Array(values)) = hash.get(&Yaml::String(key.to_string())) else {
+        return None;
+    };
+    Some(values.iter().filter_map(|value| value.as_str().map(str::to_string)).collect())
+}
+
 fn metadata_pair(metadata: &Yaml, key: &str) -> Option<(u32, u32)> {
     let Yaml::Hash(hash) = metadata else {
         return None;
@@ -258,11 +344,16 @@ pub struct XpraClient {
     // backwards-compatible mode includes the legacy `damage-sequence` alias; older servers which
     // do not return the list are assumed to be compatible, since that remains xpra's default.
     pub server_backwards_compatible: bool,
+    // the encoder for our outgoing packets: YAML, which every server reads, until the server's
+    // hello tells us which of the others it supports (see server_packet_encoder).
+    pub packet_encoder: PacketEncoder,
+    // whether the server takes lz4 compressed packets from us, from its hello's `compressors`
+    pub compress_packets: bool,
     pub windows: HashMap<u64, XpraWindow>,
     pub id_map: HashMap<WindowId, u64>,
     pub stream: Connection,
     pub proxy: EventLoopProxy<Packet>,
-    pub decode_sender: Sender<Packet>,
+    pub decode_sender: Sender<DecodeRequest>,
     pub softbuffer_ctx: Option<Context<OwnedDisplayHandle>>,
     pub modifiers: ModifiersState,
     pub startup_complete: bool,
@@ -278,6 +369,8 @@ pub struct XpraClient {
     // kept so it can be applied to windows created after the last "cursor" packet. `None` = the
     // platform default cursor.
     pub current_cursor: Option<CustomCursor>,
+    // every window's icon, and the default one (see icons.rs)
+    pub window_icons: WindowIcons,
     // the local monitors and the total size of the display area they span (in physical pixels),
     // both sent to the server in `hello` - see `local_monitors` / `total_display_size` and
     // `send_hello`. Filled in from `resumed`, which is the first callback that hands us an
@@ -285,6 +378,26 @@ pub struct XpraClient {
     // usable monitor, in which case we send no size at all rather than a bogus one.
     pub monitors: Vec<MonitorInfo>,
     pub desktop_size: Option<(u32, u32)>,
+    // whether our windows can be translucent (see window::has_transparency): measured in `resumed`
+    // like the monitors, it decides what we ask of the server in `hello` and which windows get
+    // created with an alpha channel.
+    pub transparency: bool,
+    // the metadata keys of window_properties.rs this platform can apply, also measured in `resumed`
+    pub platform_metadata: &'static [&'static str],
+    // `--desktop-scaling`, if given, and the factor it comes to once `resumed` can ask the monitors
+    // (see scaling.rs). `monitors` and everything winit reports stay in local pixels; only what goes
+    // over the wire is scaled.
+    pub desktop_scaling: Option<DesktopScaling>,
+    pub scaling: Scaling,
+    // `Some` when the server's hello says this is a desktop or shadow session, whose one window is
+    // the server's root (see desktop.rs). `--desktop-fullscreen` shows it fullscreen; a resize of it
+    // sends `desktop_size` once DESKTOP_SIZE_DELAY has passed since the last one, which is when
+    // `desktop_resized_at` was set, until then.
+    pub desktop: Option<DesktopSession>,
+    pub desktop_fullscreen: bool,
+    pub desktop_resized_at: Option<Instant>,
+    // the desktop window that has grabbed the keyboard while it is focused, if any
+    pub keyboard_grabbed: Option<u64>,
     // the window whose pointer is currently grabbed at the server's request. The grab is applied
     // through winit and must be explicitly released on pointer-ungrab or before that window is
     // destroyed.
@@ -315,8 +428,8 @@ pub struct XpraClient {
     // loop: a value we just wrote locally is not re-sent to the server, and vice-versa.
     pub last_clipboard: String,
     // the connection string as the user typed it, kept to name the session in the system tray's
-    // tooltip and menu header. Only read on Windows, which is the only platform with a tray.
-    #[cfg_attr(not(windows), allow(dead_code))]
+    // tooltip and menu header (on Windows, the only platform with a tray), and to connect to again
+    // with --reconnect.
     pub target: String,
     // credentials collected by the connection dialog (see connect_dialog.rs), when the client was
     // started without a target on the command line. `username` overrides the one we would otherwise
@@ -324,6 +437,27 @@ pub struct XpraClient {
     // prompting a second time (see process_challenge). Both `None` on the command-line path.
     pub username: Option<String>,
     pub password: Option<String>,
+    // `None` unless --reconnect asked us to survive losing the connection once the session is up:
+    // the windows are then kept, greyed out, while a worker thread tries to connect again (see
+    // start_reconnect), and the server's `new-window`s for the same wids take them back over.
+    pub reconnect: Option<Reconnect>,
+    // which of this session's connections we are on, starting from 0 (see TypedPacket::ConnectionLost)
+    pub connection: u64,
+    // the current connection is gone, whether or not we have heard from the reader thread yet:
+    // nothing more can be written to it.
+    pub connection_lost: bool,
+    // when the session lost its connection, until the server has taken it back up with a
+    // `startup-complete` on a new one: the give-up timeout counts from here.
+    pub lost_at: Option<Instant>,
+    // the reconnect worker's outcome, while it is trying
+    pub reconnect_rx: Option<Receiver<Result<Connection, (ExitCode, String)>>>,
+    // packet encryption, when the target URI asked for it (see net::crypto). `cipher_params` are
+    // ours, for what the server sends us - picked when the reader thread starts, which owns the
+    // decrypting end - and sent in every hello; `cipher_out` encrypts what we send, once the
+    // server's hello has told us how.
+    pub encryption: Option<Encryption>,
+    pub cipher_params: Option<CipherParams>,
+    pub cipher_out: Option<Cipher>,
     // the shared memory area the server writes pixels into when it runs on this same host (see
     // mmap.rs). Created before the connection, offered in our `hello` and confirmed - or not - by
     // the server's reply; the decode thread holds the other `Arc`. `None` when mmap is switched
@@ -361,6 +495,13 @@ pub(crate) fn client_packet(packet_type: &str, message: &str) -> Packet {
     }
 }
 
+// "connection-lost" also says which connection was lost, see TypedPacket::ConnectionLost
+fn connection_lost_packet(connection: u64, message: &str) -> Packet {
+    let mut packet = client_packet("connection-lost", message);
+    packet.main.push(Yaml::Integer(connection as i64));
+    packet
+}
+
 // xpra's `disconnect_is_an_error` (`net/common.py`): disconnect reasons are free-form strings
 // (`ConnectionMessage` in `net/constants.py`), and an error is anything that says "error", or any
 // timeout other than the idle one.
@@ -368,42 +509,129 @@ fn disconnect_is_an_error(reason: &str) -> bool {
     reason.contains("error") || (reason.contains("timeout") && reason != "idle timeout")
 }
 
+// Decode a plain-text clipboard payload. xpra sends 8-bit text with wire encoding "bytes"; the
+// other encodings ("integers"/"atoms") aren't text - skip them.
+fn clipboard_text(data: ClipboardData) -> Option<String> {
+    if data.encoding != "bytes" {
+        debug!("ignoring clipboard data with wire encoding {:?}", data.encoding);
+        return None;
+    }
+    if data.data.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&data.data).into_owned())
+}
+
+// Decode one draw, on a decode pool worker with `video_decoders` of its own: the result goes back as
+// a client-side packet with the draw's layout, the pixels (or the error message) in place of the
+// compressed data.
+fn decode_draw(draw: &mut Draw, video_decoders: &mut VideoDecoders, mmap: Option<&MmapArea>) -> Packet {
+    let (wid, w, h, seq) = (draw.wid, draw.w, draw.h, draw.seq);
+    let coding = draw.coding.as_str();
+    debug!("wid {:#x} got {:?}x{:?} {:?} draw packet", wid, w, h, coding);
+
+    let t0 = Instant::now();
+    // Ok(Some(pixels)) = a frame is ready; Ok(None) = input consumed but no frame yet
+    // (decoder warm-up) -- we must still ack the sequence; Err = decode failure.
+    let result: Result<Option<Vec<u8>>, String> = if VIDEO_ENCODINGS.contains(&coding) {
+        // a window that switches to another video encoding starts a new stream, with a new decoder
+        let decoder = match video_decoders.entry(wid) {
+            Entry::Occupied(entry) if entry.get().coding() == coding => Ok(entry.into_mut()),
+            Entry::Occupied(mut entry) => VideoDecoder::new(coding).map(|d| {
+                entry.insert(d);
+                entry.into_mut()
+            }),
+            Entry::Vacant(entry) => VideoDecoder::new(coding).map(|d| entry.insert(d)),
+        };
+        decoder.and_then(|decoder| decoder.decode(&draw.data, w, h, draw.full_range()))
+    } else if coding == "scroll" {
+        // nothing to decode: it only comes through here to stay in order with the draws queued
+        // before it, whose pixels it moves (see XpraWindow::scroll)
+        Ok(None)
+    } else if coding == "rgb24" || coding == "rgb32" {
+        draw_decoder::decode_rgb(draw).map(Some)
+    } else if coding == "mmap" {
+        match mmap {
+            Some(area) => read_mmap_draw(draw, area).map(Some),
+            // the server only sends these once it has verified our area, so this cannot happen -
+            // but it must not be painted as if it were pixel data.
+            None => Err("received an mmap draw without an mmap area".to_string()),
+        }
+    } else {
+        draw_decoder::decode(&draw.coding, std::mem::take(&mut draw.data)).map(Some)
+    };
+    let decode_elapsed = t0.elapsed();
+    trace!("perf: draw packet: {:?}x{:?} {:?} decoded in {:?}", w, h, coding, decode_elapsed);
+    let mut main = vec![
+        Yaml::String("draw-decoded".to_string()),
+        Yaml::Integer(wid as i64),
+        Yaml::Integer(draw.x as i64),
+        Yaml::Integer(draw.y as i64),
+        Yaml::Integer(w as i64),
+        Yaml::Integer(h as i64),
+        Yaml::String(draw.coding.clone()),
+        Yaml::Null,
+        Yaml::Integer(seq as i64),
+    ];
+    let mut raw = HashMap::new();
+    let mut decode_time_us = None;
+    match result {
+        Err(message) => {
+            error!("draw decoding error for {:?} sequence {:?}: {:?}", coding, seq, message);
+            main[0] = Yaml::String("decoding-failed".to_string());
+            main[7] = Yaml::String(message);
+        }
+        Ok(pixels) => {
+            // an empty payload (None) means "no frame this time": the UI thread will ack the
+            // sequence without painting.
+            if coding == "scroll" {
+                main[7] = scrolls_yaml(&draw.scrolls);
+            } else {
+                raw.insert(7, pixels.unwrap_or_default());
+            }
+            decode_time_us = Some(decode_elapsed.as_micros() as i64);
+        }
+    }
+    Packet { main, raw, decode_time_us }
+}
+
 // Read one `mmap` draw packet: instead of pixel data it carries (offset, length) pairs into the
-// shared memory area the server has been writing frames into (see mmap.rs). Runs on the decode
-// thread, in place of a decoder, and hands back the same tightly packed BGRX buffer the real
+// shared memory area the server has been writing frames into (see mmap.rs). Runs on a decode
+// worker, in place of a decoder, and hands back the same tightly packed BGRX buffer the real
 // decoders produce.
-fn read_mmap_draw(packet: &Packet, area: &MmapArea) -> Result<Vec<u8>, String> {
-    let options = packet.main.get(10);
-    // the chunk list rides in the packet options; the server also leaves a copy in the packet's
-    // data field, which is the only place older clients look (paint_mmap, xpra
-    // client/gui/window/backing.py), so fall back to it.
-    let list = match options.and_then(|o| yaml_hash(o, "chunks")).or_else(|| packet.main.get(7)) {
+fn read_mmap_draw(draw: &Draw, area: &MmapArea) -> Result<Vec<u8>, String> {
+    let list = match draw.mmap_chunks() {
         Some(list) => list,
         None => return Err("mmap draw packet without a chunk list".to_string()),
     };
     let chunks = mmap::parse_chunks(list)?;
-    let pixels = read_mmap_pixels(packet, area, options, &chunks);
+    let pixels = read_mmap_pixels(draw, area, &chunks);
     // Whatever happened above: the server does not reclaim this part of the ring until we move
     // `data_start` past it, so dropping a draw without releasing it would stall the session.
     area.release(&chunks);
     pixels
 }
 
-fn read_mmap_pixels(packet: &Packet, area: &MmapArea, options: Option<&Yaml>,
-                    chunks: &[(usize, usize)]) -> Result<Vec<u8>, String> {
-    // we advertise `encoding.rgb_formats = ["BGRX"]` and nothing else, so this is what the server
-    // writes - BGRA would do just as well (we ignore the alpha byte), anything else would not.
-    let rgb_format = options.map(|o| yaml_hash_str(o, "rgb_format".to_string())).unwrap_or_default();
-    if !rgb_format.is_empty() && !rgb_format.starts_with("BGR") {
-        return Err(format!("unsupported mmap pixel format {:?}", rgb_format));
-    }
-    // The source stride, which for a damage sub-rectangle is the whole window's rather than w*4.
-    // This is the only place a draw packet's rowstride is read: every other encoding we handle
-    // produces tightly packed output.
-    let rowstride = packet.main.get(9).map(yaml_i32).unwrap_or(0).max(0) as usize;
-    let w = packet.get_i32(4).max(0) as usize;
-    let h = packet.get_i32(5).max(0) as usize;
-    area.read_image(chunks, w, h, rowstride)
+fn read_mmap_pixels(draw: &Draw, area: &MmapArea, chunks: &[(usize, usize)]) -> Result<Vec<u8>, String> {
+    // one of the `encoding.rgb_formats` we advertise, BGRX whenever the server has a choice, which
+    // needs no conversion at all; older servers do not say, and write BGRX
+    let rgb_format = match yaml_hash_str(&draw.options, "rgb_format".to_string()) {
+        format if format.is_empty() => "BGRX".to_string(),
+        format => format,
+    };
+    let bpp = draw_decoder::bytes_per_pixel(&rgb_format)?;
+    let (w, h) = (draw.w as usize, draw.h as usize);
+    // The source stride, which for a damage sub-rectangle is the whole window's rather than w*bpp.
+    let mut pixels = area.read_image(chunks, w, h, draw.rowstride as usize, bpp)?;
+    match rgb_format.as_str() {
+        "BGRA" => Ok(pixels),
+        // as draw_decoder::to_bgrx, but in place: the padding byte becomes opaque alpha
+        "BGRX" => {
+            pixels::to_bgra_in_place(&mut pixels, Order::Bgra, Alpha::Opaque);
+            Ok(pixels)
+        }
+        _ => Ok(draw_decoder::to_bgrx(&pixels, &rgb_format, w, h, w * bpp)),
+    }
 }
 
 fn connection_error(e: &io::Error) -> String {
@@ -468,6 +696,17 @@ fn server_backwards_compatible(hello: &Yaml) -> Option<bool> {
     has_packet_type("window-ack").then(|| has_packet_type("damage-sequence"))
 }
 
+// The packet encoder to send with: our most preferred one that the server lists in its own
+// `encoders`. The server decodes each packet according to the flags in its header, so this is
+// purely our choice - it picks its own encoder for the packets it sends us from our list.
+fn server_packet_encoder(hello: &Yaml) -> Option<PacketEncoder> {
+    let Yaml::Array(encoders) = &hello["encoders"] else {
+        return None;
+    };
+    PACKET_ENCODERS.into_iter().find(|encoder| encoders.iter()
+        .any(|value| matches!(value, Yaml::String(s) if s == encoder.name())))
+}
+
 impl fmt::Debug for XpraClient {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("XpraClient")
@@ -478,7 +717,7 @@ impl fmt::Debug for XpraClient {
 
 impl XpraClient {
 
-    pub fn new(stream: Connection, proxy: EventLoopProxy<Packet>, decode_sender: Sender<Packet>,
+    pub fn new(stream: Connection, proxy: EventLoopProxy<Packet>, decode_sender: Sender<DecodeRequest>,
                log_sink: LogSink, target: String, mmap: Option<Arc<MmapArea>>) -> Self {
         #[cfg(windows)]
         let audio_worker = match AudioWorker::start(proxy.clone()) {
@@ -494,6 +733,8 @@ impl XpraClient {
             hello_sent: false,
             server_version: "".to_string(),
             server_backwards_compatible: true,
+            packet_encoder: PacketEncoder::Yaml,
+            compress_packets: false,
             windows: HashMap::new(),
             id_map: HashMap::new(),
             stream,
@@ -505,8 +746,17 @@ impl XpraClient {
             start: Instant::now(),
             last_client_latency_ms: -1,
             current_cursor: None,
+            window_icons: WindowIcons::new(),
             monitors: Vec::new(),
             desktop_size: None,
+            transparency: false,
+            platform_metadata: &[],
+            desktop_scaling: None,
+            scaling: Scaling::NONE,
+            desktop: None,
+            desktop_fullscreen: false,
+            desktop_resized_at: None,
+            keyboard_grabbed: None,
             pointer_grabbed: None,
             auth_dialog: None,
             pending_challenge: None,
@@ -518,6 +768,14 @@ impl XpraClient {
             target,
             username: None,
             password: None,
+            reconnect: None,
+            connection: 0,
+            connection_lost: false,
+            lost_at: None,
+            reconnect_rx: None,
+            encryption: None,
+            cipher_params: None,
+            cipher_out: None,
             mmap,
             #[cfg(windows)]
             tray: None,
@@ -547,6 +805,12 @@ impl XpraClient {
         if self.startup_complete { ExitCode::ConnectionLost } else { ExitCode::ConnectionFailed }
     }
 
+    // Only a session that was up is worth getting back: a connection that never got that far was
+    // refused for a reason that trying again will not change.
+    fn can_reconnect(&self) -> bool {
+        self.reconnect.is_some() && self.startup_complete && self.exit_code.is_none()
+    }
+
     // Send our `hello`. `reply` is `Some((challenge_response, client_salt))` on the second hello,
     // once we've answered a server `challenge` (see process_challenge); `None` on the first one.
     pub fn send_hello(&mut self, reply: Option<(String, String)>) {
@@ -560,41 +824,43 @@ impl XpraClient {
         // matches against (and what its password prompt names).
         let env_username = env::var("USERNAME").or_else(|_| env::var("USER")).unwrap_or_default();
         let username = self.username.clone().unwrap_or(env_username);
-        // h264 is decoded via Media Foundation, which is Windows-only:
-        #[cfg_attr(not(windows), allow(unused_mut))]
-        let mut encodings = vec!["jpeg", "png", "webp"];
+        // rgb24 and rgb32 are raw pixels, lz4 or zlib compressed (see draw_decoder::decode_rgb).
+        // scroll moves what is already on screen instead of repainting it (see XpraWindow::scroll):
+        // the server only looks for scrolled regions if the client lists it (`supports_scrolling`,
+        // xpra server/window/video.py).
+        let mut encodings = vec!["jpeg", "png", "webp", "rgb24", "rgb32", "scroll"];
         // The nested "encoding" caps dict (read server-side as hello["encoding"], see xpra's
-        // server/source/encoding.py). For a video encoding to be offered at all, the server needs
-        // `full_csc_modes[<enc>]` to list at least one colourspace its encoder can produce that we
-        // can decode. Media Foundation's H.264 decoder only handles 8-bit 4:2:0 up to High profile,
-        // so we advertise *only* YUV420P (never 422/444) and pin the profile to "high".
-        #[cfg_attr(not(windows), allow(unused_mut))]
+        // server/source/encoding.py).
         let mut encoding_caps = json!({
             // read server-side as hello["encoding"]["window-icon"]; without it the server
             // sends no "window-icon" packets at all (it only ships icons as png).
             "window-icon": ["png"],
+            // the raw pixel layouts we accept, for rgb24/rgb32 draws and for what the server
+            // writes into the mmap area (rgb_reformat, xpra server/window/compress.py). This is
+            // *not* optional: the server defaults to ("RGB",) alone, three bytes per pixel, which
+            // would cost it a conversion for every draw. BGRX comes first: it is X11's native
+            // little-endian layout, so the server usually ends up doing no conversion at all. The
+            // alpha formats only go with `transparency` below.
+            "rgb_formats": draw_decoder::RGB_FORMATS.iter()
+                .filter(|format| self.transparency || !draw_decoder::has_alpha(format))
+                .collect::<Vec<_>>(),
+            // whether to keep the alpha channel of windows that have one (`has-alpha`): in png,
+            // webp and the alpha rgb_formats above. Without it the server flattens them onto
+            // black for us, which is all we could show anyway on a display that cannot blend them
+            // (see window::has_transparency).
+            "transparency": self.transparency,
+            // and how we take their pixels compressed: the server only compresses rgb draws with
+            // the algorithms the client names here
+            "rgb_lz4": true,
+            "rgb_zlib": true,
         });
-        #[cfg(windows)]
-        {
-            encodings.push("h264");
-            encoding_caps["full_csc_modes"] = json!({ "h264": ["YUV420P"] });
-            encoding_caps["h264"] = json!({ "YUV420P.profile": "high" });
-        }
+        // plus whichever video encodings this platform and build can decode (see video.rs)
+        encodings.extend(video::encoding_caps(&mut encoding_caps));
         // the picture encodings we can decode (parse_encoding_caps, xpra server/source/encoding.py).
-        // The two lists are identical here: every encoding we advertise is one draw_decoder.rs
-        // handles directly, none is a container for another.
+        // The two lists are identical here: every encoding we advertise is one we handle directly,
+        // none is a container for another.
         encoding_caps["options"] = json!(encodings);
         encoding_caps["core"] = json!(encodings);
-        if self.mmap.is_some() {
-            // the raw pixel layouts we accept, which is what the server writes into the mmap area
-            // (mmap_encode, xpra server/window/compress.py). This is *not* optional: the server
-            // defaults to ("RGB",) - three bytes per pixel - which window::paint cannot render.
-            // BGRX is both what it happens to want and X11's native little-endian layout, so the
-            // server ends up doing no conversion at all. We list no alpha format: our framebuffer
-            // is opaque 0x00RRGGBB, and advertising only BGRX makes the server flatten any window
-            // that has an alpha channel for us.
-            encoding_caps["rgb_formats"] = json!(["BGRX"]);
-        }
         // The nested "display" caps dict (xpra server/source/display.py, DisplayConnection). Sending
         // it is what instantiates that subsystem server-side at all: `is_needed` looks for this key
         // and, failing that, for the pre-6.5 spelling where these attributes were flattened into the
@@ -616,9 +882,21 @@ impl XpraClient {
         // of that screen (`do_parse_screen_info` / `configure_best_screen_size`, xpra
         // server/subsystem/display.py), so that remote windows are laid out for a desktop we can
         // actually show them on.
+        //
+        // With `--desktop-scaling`, that is the size the desktop comes to in server pixels - smaller
+        // than the real one by the factor, which is the whole point: the server lays its screen out
+        // for that - with the real size next to it, as xpra's own scaling client sends it. The
+        // factor itself (`desktop-scaling`, x and y) and whether our windows are scaled by it
+        // (`window-scaling`, always all of them or none) only go into the server's client info.
         if let Some((w, h)) = self.desktop_size {
-            display_caps["desktop_size"] = json!([w, h]);
+            let scaling = self.scaling;
+            display_caps["desktop_size"] = json!([scaling.size_to_server(w), scaling.size_to_server(h)]);
+            if scaling.is_scaled() {
+                display_caps["desktop_size.unscaled"] = json!([w, h]);
+            }
         }
+        display_caps["desktop-scaling"] = json!([self.scaling.factor, self.scaling.factor]);
+        display_caps["window-scaling"] = json!(self.scaling.is_scaled());
         // ... and its breakdown into individual monitors. An X11 server whose dummy driver has
         // RandR 1.6 goes one better than resizing: it reproduces this layout as real virtual
         // monitors (`mirror_client_monitor_layout` -> `set_crtc_config`, xpra
@@ -632,10 +910,12 @@ impl XpraClient {
         // (`get_normalized_monitor_definitions`).
         if !self.monitors.is_empty() {
             let mut monitors = json!({});
+            let scaling = self.scaling;
             for (index, monitor) in self.monitors.iter().enumerate() {
                 let (x, y, w, h) = monitor.geometry;
                 let mut mdef = json!({
-                    "geometry": [x, y, w, h],
+                    "geometry": [scaling.to_server(x), scaling.to_server(y),
+                                 scaling.size_to_server(w), scaling.size_to_server(h)],
                     "primary": monitor.primary,
                 });
                 if !monitor.name.is_empty() {
@@ -648,6 +928,12 @@ impl XpraClient {
             }
             display_caps["monitors"] = monitors;
         }
+        // the window metadata we apply everywhere, and what window_properties.rs can on this platform
+        let supported_metadata: Vec<&str> = [
+            "title", "size-constraints", "fullscreen", "maximized", "iconic",
+            "decorations", "above", "below", "has-alpha", "transparent",
+            "transient-for", "modal", "window-type", "group-leader", "role",
+        ].into_iter().chain(self.platform_metadata.iter().copied()).collect();
         let mut packet = json!(["hello", {
             "version": VERSION,
             // Needed to distinguish the legacy draw acknowledgement layout from `window-ack`.
@@ -655,17 +941,17 @@ impl XpraClient {
             "wants": ["packet-types"],
             // the packet encoders we can read, negotiated against the server's own list
             // (enable_encoder_from_caps, xpra net/protocol/socket_handler.py).
-            "encoders": ["yaml"],
+            "encoders": PACKET_ENCODERS.map(PacketEncoder::name),
             // out-of-band chunks: let the server send large binary items (pixel data, window
-            // icons, cursors, clipboard payloads) as their own packets instead of base64-inlining
-            // them in the YAML payload - which costs a third more bytes and a decode pass on our
-            // side. See net::io's read_packet for the reassembly.
+            // icons, cursors, clipboard payloads) as their own packets instead of inlining them
+            // in the packet payload - base64 in YAML costs a third more bytes and a decode pass
+            // on our side. See net::io's read_packet for the reassembly.
             "chunks": true,
-            // packet compression: advertise lz4 (the only algorithm we decompress, see net::io)
-            // and a non-zero level so the server actually compresses its packets to us - it falls
-            // back to "none" when compression_level is 0 (xpra server/core.py). This is inbound
-            // only; our own outgoing packets are small input events, sent uncompressed.
-            "compressors": ["lz4"],
+            // packet compression: advertise what we decompress (see net::io) and a non-zero level
+            // so the server actually compresses its packets to us - it falls back to "none" when
+            // compression_level is 0 (xpra server/core.py). Our own packets are mostly small input
+            // events, only the larger ones get compressed, see write_packet.
+            "compressors": COMPRESSORS,
             "compression_level": 1,
             "windows": true,
             "keyboard": true,
@@ -692,10 +978,7 @@ impl XpraClient {
             // advertise only the window metadata keys we actually apply. Without this list, the
             // server assumes the broad legacy default and sends properties this client ignores.
             "metadata": {
-                "supported": [
-                    "title", "size-constraints", "fullscreen", "maximized", "iconic",
-                    "decorations", "above", "below",
-                ],
+                "supported": supported_metadata,
             },
             // desktop notifications: shown as balloons on the system tray icon on Windows, logged
             // everywhere else (see process_notify_show). The server gates notification sending on
@@ -745,6 +1028,14 @@ impl XpraClient {
         if let Some(area) = &self.mmap {
             packet[1]["mmap"] = json!({ "read": area.caps() });
         }
+        // how the server is to encrypt what it sends us (see start_read_loop). The same parameters
+        // go into the second hello that answers a challenge: nothing has been encrypted with them
+        // yet at that point.
+        if let Some(Value::Object(cipher_caps)) = self.cipher_params.as_ref().map(CipherParams::caps) {
+            for (key, value) in cipher_caps {
+                packet[1][key] = value;
+            }
+        }
         // Audio probing happened before this hello was built. Advertise only the asynchronous
         // request here; the decoder list is sent later in `audio-capabilities`.
         #[cfg(windows)]
@@ -764,10 +1055,10 @@ impl XpraClient {
     // the monitor the point falls in and its offset within it (see `monitor_relative_position` for
     // why the server needs it). `None` when the point is on no known monitor, in which case the
     // caller leaves the key out and the server keeps using the absolute coordinates it was sent
-    // alongside.
+    // alongside. Given a local point, like the monitor list, but what goes out is in server pixels.
     fn monitor_descriptor(&self, x: i32, y: i32) -> Option<Value> {
         let (index, mx, my) = monitor_relative_position(&self.monitors, x, y)?;
-        Some(json!({ "index": index, "position": [mx, my] }))
+        Some(json!({ "index": index, "position": [self.scaling.to_server(mx), self.scaling.to_server(my)] }))
     }
 
     // The same descriptor for a *window* origin. A window's top-left corner is regularly outside
@@ -798,7 +1089,8 @@ impl XpraClient {
         match current {
             Some(index) => {
                 let (mx, my, _, _) = self.monitors[index].geometry;
-                Some(json!({ "index": index, "position": [x - mx, y - my] }))
+                let position = [self.scaling.to_server(x - mx), self.scaling.to_server(y - my)];
+                Some(json!({ "index": index, "position": position }))
             }
             None => self.monitor_descriptor(x, y),
         }
@@ -815,18 +1107,29 @@ impl XpraClient {
         props
     }
 
+    // Pointer positions, like window geometries below, are given in local pixels and sent in the
+    // server's (see scaling.rs) - except in a desktop session, whose one window gives them on the
+    // server's root already (see XpraWindow::absolute_position), with no local monitor to be on.
+    fn pointer_location(&self, x: i32, y: i32) -> ([i32; 2], Value) {
+        if self.desktop.is_some() {
+            return ([x, y], json!({}));
+        }
+        ([self.scaling.to_server(x), self.scaling.to_server(y)], self.pointer_props(x, y))
+    }
+
     fn send_pointer_position(&mut self, wid: u64, x: i32, y: i32) {
         let device_id = 0;
         let sequence = 0;
-        let packet = json!(["pointer-motion", device_id, sequence, wid, [x, y], self.pointer_props(x, y)]);
+        let (position, props) = self.pointer_location(x, y);
+        let packet = json!(["pointer-motion", device_id, sequence, wid, position, props]);
         self.write_json(packet);
     }
 
     fn send_pointer_button(&mut self, wid: u64, button: i8, pressed: bool, x: i32, y: i32) {
         let device_id = 0;
         let sequence = 0;
-        let props = self.pointer_props(x, y);
-        let packet = json!(["pointer-button", device_id, sequence, wid, button, pressed, [x, y], props]);
+        let (position, props) = self.pointer_location(x, y);
+        let packet = json!(["pointer-button", device_id, sequence, wid, button, pressed, position, props]);
         self.write_json(packet);
     }
 
@@ -868,9 +1171,18 @@ impl XpraClient {
     // appended rather than sent as a null placeholder. It goes through `resolve_monitor_geometry`
     // there and replaces the x,y of the geometry that follows it. The x,y we are given here is the
     // position `process_new_common` asked winit to place the window at, so it is a local position -
-    // which is what the descriptor has to describe.
+    // which is what the descriptor has to describe. The geometry itself goes out in server pixels.
     fn send_window_map(&mut self, wid: u64, x: i32, y: i32, w: u32, h: u32) {
-        let mut packet = json!(["window-map", wid, x, y, w, h, {}, {}]);
+        // a desktop session's root is not on any of our monitors, and stays the size it is on the
+        // server: the server only needs to know that it is shown
+        if let Some(window) = self.windows.get(&wid)
+            && window.desktop.is_some() {
+            let packet = json!(["window-map", wid, 0, 0, window.width, window.height, {}, {}]);
+            self.write_json(packet);
+            return;
+        }
+        let (sx, sy, sw, sh) = self.scaled_geometry(x, y, w, h);
+        let mut packet = json!(["window-map", wid, sx, sy, sw, sh, {}, {}]);
         if let Some(monitor) = self.window_monitor_descriptor(wid, x, y) {
             // json! builds an array here, so this cannot fail.
             if let Some(fields) = packet.as_array_mut() {
@@ -887,7 +1199,8 @@ impl XpraClient {
     // form of that origin - omitting "state"/"properties" is the same as the empty dicts the old
     // packet had to carry.
     fn send_window_configure(&mut self, wid: u64, x: i32, y: i32, w: u32, h: u32) {
-        let mut config = json!({ "geometry": [x, y, w, h] });
+        let (sx, sy, sw, sh) = self.scaled_geometry(x, y, w, h);
+        let mut config = json!({ "geometry": [sx, sy, sw, sh] });
         if let Some(monitor) = self.window_monitor_descriptor(wid, x, y) {
             config["monitor"] = monitor;
         }
@@ -895,6 +1208,39 @@ impl XpraClient {
         self.write_json(packet);
     }
 
+    // A local window geometry in server pixels
+    fn scaled_geometry(&self, x: i32, y: i32, w: u32, h: u32) -> (i32, i32, u32, u32) {
+        let scaling = self.scaling;
+        (scaling.to_server(x), scaling.to_server(y), scaling.size_to_server(w), scaling.size_to_server(h))
+    }
+
+    // ["desktop_size", w, h]: the size we would like the server's screen to be, which is that of a
+    // desktop session's window in server pixels. The server picks the closest it can do and
+    // resizes the root to it (see process_window_resized), or leaves it as it is.
+    fn send_desktop_size(&mut self) {
+        let Some(resized_at) = self.desktop_resized_at else {
+            return;
+        };
+        // resized again since the timer was started: wait for that one
+        let elapsed = resized_at.elapsed();
+        if elapsed < DESKTOP_SIZE_DELAY {
+            self.start_desktop_size_timer(DESKTOP_SIZE_DELAY - elapsed);
+            return;
+        }
+        self.desktop_resized_at = None;
+        let scaling = self.scaling;
+        // nothing to ask for when the window is the size of the root already
+        let Some((w, h)) = self.windows.values().find(|window| window.desktop.is_some()).and_then(|window| {
+            let size = window.window.inner_size();
+            let size = (scaling.size_to_server(size.width.max(1)), scaling.size_to_server(size.height.max(1)));
+            (size != (window.width, window.height)).then_some(size)
+        }) else {
+            return;
+        };
+        debug!("requesting a {}x{} desktop", w, h);
+        self.write_json(json!(["desktop_size", w, h]));
+    }
+
     fn send_window_close(&mut self, wid: u64) {
         let packet = json!(["window-close", wid]);
         self.write_json(packet);
@@ -989,19 +1335,23 @@ impl XpraClient {
 
     fn write_json(&mut self, packet: Value) {
         // once we're on the way out, drop outgoing packets instead of failing on every one:
-        // the event loop is winding down but still delivers queued input events.
-        if self.exit_code.is_some() {
+        // the event loop is winding down but still delivers queued input events. The same goes
+        // for the time it takes to reconnect: nothing typed at a greyed out window is replayed.
+        if self.exit_code.is_some() || self.connection_lost {
             return;
         }
-        let packet_str = packet.to_string();
-        let packet_data = packet_str.as_bytes();
-        if let Err(e) = write_packet(&mut self.stream, packet_data) {
+        let packet_data = encode_packet(self.packet_encoder, &packet);
+        if let Err(e) = write_packet(&mut self.stream, self.packet_encoder, self.compress_packets,
+                                     self.cipher_out.as_mut(), &packet_data) {
             // the server went away mid-write (broken pipe / reset): shut down cleanly rather
             // than panicking. The reader thread may not have noticed yet, so tell the UI thread
             // ourselves - `user_event` is the only place that can reach the `ActiveEventLoop`.
             error!("failed to send packet to the server: {}", e);
-            self.exit_code = Some(self.connection_lost_code());
-            let _ = self.proxy.send_event(client_packet("connection-lost", &e.to_string()));
+            if !self.can_reconnect() {
+                self.exit_code = Some(self.connection_lost_code());
+            }
+            self.connection_lost = true;
+            let _ = self.proxy.send_event(connection_lost_packet(self.connection, &e.to_string()));
         }
     }
 
@@ -1009,19 +1359,37 @@ impl XpraClient {
     pub fn start_read_loop(&mut self) {
         let proxy = self.proxy.clone();
         let mut stream = self.stream.try_clone().unwrap();
+        // With encryption on, everything the server sends once it has our hello comes encrypted -
+        // bar a `challenge` (which it sends before it has set up its end) and a `disconnect`
+        // refusing us. Anything else in the clear, or anything in the clear once encrypted packets
+        // have started flowing, could be anyone's: treat it as garbage.
+        let mut cipher_in = None;
+        if let Some(encryption) = &self.encryption {
+            let params = CipherParams::new(encryption.mode);
+            match Cipher::decryptor(&params, &encryption.secret) {
+                Ok(cipher) => cipher_in = Some(cipher),
+                Err(e) => error!("failed to set up decryption: {}", e),
+            }
+            self.cipher_params = Some(params);
+        }
+        let encrypted = self.encryption.is_some();
+        let mut encrypted_started = false;
+        let connection = self.connection;
         thread::Builder::new().name("reader".to_string()).spawn(move || loop {
             let t0 = Instant::now();
-            let raw = match read_packet(&mut stream) {
+            let raw = match read_packet(&mut stream, cipher_in.as_mut()) {
                 Ok(raw) => raw,
                 Err(e) => {
                     // the server closed the connection (or died): hand the reason to the UI
                     // thread, which logs it and exits the event loop.
                     debug!("read loop terminated: {}", e);
-                    let _ = proxy.send_event(client_packet("connection-lost", &connection_error(&e)));
+                    let _ = proxy.send_event(connection_lost_packet(connection, &connection_error(&e)));
                     break;
                 }
             };
             let read_elapsed = t0.elapsed();
+            let raw_encrypted = raw.encrypted;
+            encrypted_started |= raw_encrypted;
             // payload + out-of-band chunks: with `chunks` enabled the bulk of a draw packet
             // (the pixel data) arrives as a chunk rather than in the payload.
             let payload_len = raw.size();
@@ -1032,7 +1400,14 @@ impl XpraClient {
                     break;
                 }
             };
-            if packet.get_str(0) == "draw" {
+            let packet_type = packet.packet_type();
+            if encrypted && !raw_encrypted
+                && (encrypted_started || !matches!(packet_type.as_str(), "challenge" | "disconnect")) {
+                let message = format!("unencrypted {:?} packet on an encrypted connection", packet_type);
+                let _ = proxy.send_event(client_packet("invalid-packet", &message));
+                break;
+            }
+            if packet_type == "draw" {
                 trace!("perf: draw packet: {:?} bytes read (network) in {:?}", payload_len, read_elapsed);
             }
             if proxy.send_event(packet).is_err() {
@@ -1057,230 +1432,138 @@ impl XpraClient {
         }).unwrap();
     }
 
-    pub fn start_draw_decode_loop(proxy: EventLoopProxy<Packet>, receiver: Receiver<Packet>,
-                                  mmap: Option<Arc<MmapArea>>) {
-        thread::Builder::new().name("decode".to_string()).spawn(move || {
-            info!("decoding thread started");
-            // Per-window H.264 decoders (Windows / Media Foundation). H.264 is inter-frame
-            // predicted, so unlike the stateless jpeg/png path each window keeps a persistent,
-            // stateful decoder. These COM objects live only on this thread.
-            #[cfg(windows)]
-            let mut h264_decoders: HashMap<u64, super::mediafoundation::H264Decoder> = HashMap::new();
-            loop {
-                let mut packet = match receiver.recv() {
-                    Ok(packet) => packet,
-                    // the UI thread dropped its sender: the client is shutting down.
-                    Err(_) => {
-                        debug!("decoding thread stopping");
-                        break;
-                    }
-                };
-                // window teardown (lost-window) or video stream end (eos) forwarded from the UI
-                // thread: release this window's h264 decoder so a following stream restarts from a
-                // keyframe (Windows). Both drain the draw queue first (see the dispatch side).
-                let ptype = packet.get_str(0);
-                if ptype == "lost-window" || ptype == "eos" {
-                    #[cfg(windows)]
-                    {
-                        let key = packet.get_u64(1);
-                        if h264_decoders.remove(&key).is_some() {
-                            debug!("released h264 decoder for {:?} on window {:#x}", ptype, key);
-                        }
-                    }
-                    continue;
-                }
-                let wid = packet.get_i64(1);
-                let w = packet.get_i32(4);
-                let h = packet.get_i32(5);
-                let coding = packet.get_str(6);
-                // an mmap draw has no pixel data in the packet at all - field 7 holds a copy of
-                // its chunk list, which read_mmap_draw picks up from the options instead.
-                let data = if coding == "mmap" { Vec::new() } else { packet.get_bytes(7) };
-                let seq = packet.get_i64(8);
-                debug!("wid {:#x} got {:?}x{:?} {:?} draw packet", wid, w, h, coding);
-
-                let mut main = packet.main.to_vec();
-                let mut raw = HashMap::new();
-                let t0 = Instant::now();
-                // Ok(Some(pixels)) = a frame is ready; Ok(None) = input consumed but no frame yet
-                // (decoder warm-up) -- we must still ack the sequence; Err = decode failure.
-                let result: Result<Option<Vec<u8>>, String> = if coding == "h264" {
-                    #[cfg(windows)]
-                    {
-                        let key = wid as u64;
-                        // colour range signalled per-stream by the encoder; absent in steady state
-                        // (xpra omits it once settled), so None means "unchanged" to the decoder.
-                        let full_range = packet.get_hash_bool(10, "full-range".to_string());
-                        let ensured = if h264_decoders.contains_key(&key) {
-                            Ok(())
-                        } else {
-                            super::mediafoundation::H264Decoder::new()
-                                .map(|d| { h264_decoders.insert(key, d); })
-                        };
-                        ensured.and_then(|()| {
-                            h264_decoders.get_mut(&key).unwrap()
-                                .decode(&data, w.max(0) as u32, h.max(0) as u32, full_range)
-                        })
-                    }
-                    #[cfg(not(windows))]
-                    {
-                        Err("h264 decoding is only supported on Windows".to_string())
-                    }
-                } else if coding == "mmap" {
-                    match &mmap {
-                        Some(area) => read_mmap_draw(&packet, area).map(Some),
-                        // the server only sends these once it has verified our area, so this
-                        // cannot happen - but it must not be painted as if it were pixel data.
-                        None => Err("received an mmap draw without an mmap area".to_string()),
-                    }
-                } else {
-                    draw_decoder::decode(&coding, data).map(Some)
-                };
-                let decode_elapsed = t0.elapsed();
-                trace!("perf: draw packet: {:?}x{:?} {:?} decoded in {:?}", w, h, coding, decode_elapsed);
-                let mut decode_time_us = None;
-                match result {
-                    Err(message) => {
-                        error!("draw decoding error for {:?} sequence {:?}: {:?}", coding, seq, message);
-                        main[0] = Yaml::String("decoding-failed".to_string());
-                        main[7] = Yaml::String(message.to_string());
-                    }
-                    Ok(pixels) => {
-                        // an empty payload (None) means "no frame this time": the UI thread will
-                        // ack the sequence without painting.
-                        raw.insert(7, pixels.unwrap_or_default());
-                        main[0] = Yaml::String("draw-decoded".to_string());
-                        decode_time_us = Some(decode_elapsed.as_micros() as i64);
-                    }
-                }
-                let patched_packet = Packet { main, raw, decode_time_us };
-                if proxy.send_event(patched_packet).is_err() {
-                    break;
-                }
-            }
+    // The root of a desktop session has been resized locally: ask the server for a screen that size
+    // once it has kept it for DESKTOP_SIZE_DELAY, from a timer thread like the ping's. A resize
+    // while the timer runs only pushes the deadline back (see send_desktop_size).
+    fn schedule_desktop_size(&mut self) {
+        if self.desktop_resized_at.replace(Instant::now()).is_none() {
+            self.start_desktop_size_timer(DESKTOP_SIZE_DELAY);
+        }
+    }
+
+    fn start_desktop_size_timer(&self, delay: Duration) {
+        let proxy = self.proxy.clone();
+        thread::Builder::new().name("desktop-size".to_string()).spawn(move || {
+            thread::sleep(delay);
+            let _ = proxy.send_event(client_packet("send-desktop-size", ""));
         }).unwrap();
     }
 
+    // Decode draws off the UI thread, on the pool in decode_pool.rs, which hands each result back to
+    // the UI thread as a client-side packet.
+    pub fn start_draw_decode_loop(proxy: EventLoopProxy<Packet>, receiver: Receiver<DecodeRequest>,
+                                  mmap: Option<Arc<MmapArea>>) {
+        let decode = move |draw: &mut Draw, video_decoders: &mut VideoDecoders| decode_draw(draw, video_decoders, mmap.as_deref());
+        decode_pool::start(receiver, decode_pool::default_workers(), decode, move |packet| proxy.send_event(packet).is_ok());
+    }
 
-    fn do_process_packet(&mut self, event_loop: &ActiveEventLoop, packet_type: &str, packet: Packet) {
-        let mut p = packet;
-        match packet_type {
-            "hello" => {
-                assert!(p.len() > 1);
-                self.process_hello(event_loop, &p.main[1]);
-            }
+
+    fn do_process_packet(&mut self, event_loop: &ActiveEventLoop, packet: TypedPacket) {
+        match packet {
+            TypedPacket::Hello(hello) => self.process_hello(event_loop, &hello),
             #[cfg(windows)]
-            AUDIO_CAPABILITIES_PACKET => {
-                if p.len() > 1 {
-                    self.process_audio_capabilities(&p.main[1]);
-                } else {
-                    warn!("ignoring malformed audio-capabilities packet");
-                }
-            }
+            TypedPacket::AudioCapabilities(capabilities) => self.process_audio_capabilities(&capabilities),
             // `sound-data` is the incoming compatibility alias only. All packets we emit use the
             // canonical `audio-*` names.
             #[cfg(windows)]
-            packet_type if audio::is_audio_data_type(packet_type) => self.process_audio_data(&mut p),
+            TypedPacket::AudioData(incoming) => self.process_audio_data(incoming),
             #[cfg(windows)]
-            "audio-latency" => self.report_audio_latency(p.get_u32(1)),
+            TypedPacket::AudioLatency(total_ms) => self.report_audio_latency(total_ms),
             #[cfg(windows)]
-            "audio-worker-failed" => self.disable_audio(&p.get_str(1)),
-            "encodings" => debug!("got server encodings: {:?}", p.main[1]),
-            "startup-complete" => {
+            TypedPacket::AudioWorkerFailed(error) => self.disable_audio(&error),
+            TypedPacket::Encodings(encodings) => debug!("got server encodings: {:?}", encodings),
+            TypedPacket::StartupComplete => {
                 info!("startup complete!");
                 // the session is up: start pinging the server so it can track our latency.
                 if !self.startup_complete {
                     self.startup_complete = true;
                     self.start_ping_loop();
                 }
+                if self.lost_at.take().is_some() {
+                    self.drop_stale_windows();
+                }
             }
-            "new-window" => self.process_new_common(event_loop, &p, false),
-            "new-override-redirect" => self.process_new_common(event_loop, &p, true),
-            "window-move-resize" => self.process_window_move_resize(&p),
-            "configure-override-redirect" => self.process_window_move_resize(&p),
-            "initiate-moveresize" => self.process_initiate_moveresize(&p),
-            "raise-window" => self.process_raise_window(&p),
-            "show-desktop" => self.process_show_desktop(&p),
-            "pointer-position" => self.process_pointer_position(&p),
-            "pointer-grab" => self.process_pointer_grab(&p),
-            "pointer-ungrab" => self.process_pointer_ungrab(&p),
-            "lost-window" => {
-                self.process_lost_window(&p);
-                // forward to the decode thread so it can drop this window's persistent h264
-                // decoder; routed through the same channel as draws, so any still-queued draws
-                // for this window drain before the decoder is released.
-                #[cfg(windows)]
-                { let _ = self.decode_sender.send(p); }
-            }
-            "eos" => {
-                // video stream ended: forward to the decode thread to drop this window's h264
+            TypedPacket::NewWindow(window) => self.process_new_common(event_loop, window),
+            TypedPacket::MoveResize(move_resize) => self.process_window_move_resize(move_resize),
+            TypedPacket::InitiateMoveResize { wid, direction } => self.process_initiate_moveresize(wid, direction),
+            TypedPacket::WindowResized { wid, w, h } => self.process_window_resized(wid, w, h),
+            TypedPacket::RaiseWindow { wid } => self.process_raise_window(wid),
+            TypedPacket::ShowDesktop { show } => self.process_show_desktop(show),
+            TypedPacket::PointerPosition(position) => self.process_pointer_position(position),
+            TypedPacket::PointerGrab { wid } => self.process_pointer_grab(wid),
+            TypedPacket::PointerUngrab { wid } => self.process_pointer_ungrab(wid),
+            TypedPacket::LostWindow { wid } => {
+                self.process_lost_window(wid);
+                // forward to the decode pool so it can drop this window's persistent video
+                // decoder; routed through the same channel and worker as its draws, so any
+                // still-queued draws for this window drain before the decoder is released.
+                let _ = self.decode_sender.send(DecodeRequest::Release { wid, reason: "lost-window" });
+            }
+            TypedPacket::Eos { wid } => {
+                // video stream ended: forward to the decode pool to drop this window's video
                 // decoder, over the draw channel so any queued draws for the old stream drain first.
-                #[cfg(windows)]
-                { let _ = self.decode_sender.send(p); }
+                let _ = self.decode_sender.send(DecodeRequest::Release { wid, reason: "eos" });
             }
             // ["setting-change", setting, value]: server-pushed session settings we don't act on
             // (xpra's own client no-ops most of these); log rather than warn about "unhandled".
-            "setting-change" => debug!("ignoring setting-change: {:?}", p.get_str(1)),
-            "bell" => self.process_bell(&p),
-            "cursor" => self.process_cursor(event_loop, &mut p),
-            "notify_show" => self.process_notify_show(&p),
-            "notify_close" => self.process_notify_close(&p),
-            "window-icon" => self.process_window_icon(&mut p),
-            "window-metadata" => self.process_window_metadata(&p),
-            "server-event" => self.process_server_event(&p),
-            "draw" => {
-                if self.decode_sender.send(p).is_err() {
+            TypedPacket::SettingChange { setting } => debug!("ignoring setting-change: {:?}", setting),
+            TypedPacket::Bell { pitch, duration } => self.process_bell(pitch, duration),
+            TypedPacket::Cursor(cursor) => self.process_cursor(event_loop, cursor),
+            TypedPacket::NotifyShow(notification) => self.process_notify_show(notification),
+            TypedPacket::NotifyClose { nid } => self.process_notify_close(nid),
+            TypedPacket::WindowIcon(icon) => self.process_window_icon(icon),
+            TypedPacket::WindowMetadata { wid, metadata } => self.process_window_metadata(wid, &metadata),
+            TypedPacket::ServerEvent { event_type, args } => self.process_server_event(&event_type, &args),
+            TypedPacket::Draw(draw) => {
+                if self.decode_sender.send(DecodeRequest::Draw(Box::new(draw))).is_err() {
                     error!("cannot decode: the decoding thread has stopped");
                 }
             }
-            "draw-decoded" => self.process_draw_decoded(&mut p),
+            TypedPacket::DrawDecoded(decoded) => self.process_draw_decoded(decoded),
             // both are client-side packet types the decode thread synthesizes for a draw it could
             // not turn into pixels; either way the sequence still has to be acked, or the server's
             // damage bookkeeping for that window stops making progress.
-            "draw-failed" | "decoding-failed" => self.process_draw_failed(&p),
-            "ping" => self.process_ping(&p),
+            TypedPacket::DrawFailed(failed) => self.process_draw_failed(failed),
+            TypedPacket::Ping { echotime, sid } => self.process_ping(echotime, sid),
             // our own periodic ping, fired by the ping timer thread (start_ping_loop); "send-ping"
             // is a client-side packet type like "draw-decoded", not something on the wire.
-            "send-ping" => self.send_ping(),
+            TypedPacket::SendPing => self.send_ping(),
+            // a desktop session's window has kept its new size for long enough (see
+            // schedule_desktop_size): client-side only, like "send-ping"
+            TypedPacket::SendDesktopSize => self.send_desktop_size(),
             // one of our own log records, handed here by the remote logger (remote_logging.rs) to
             // be turned into a wire `logging` packet; "send-log" is client-side only, like above.
-            "send-log" => self.send_log(p.get_i64(1), p.get_str(2)),
+            TypedPacket::SendLog { level, message } => self.send_log(level, message),
             // the server's echo of one of our pings: measures the client->server round-trip.
-            "ping_echo" => self.process_ping_echo(&p),
-            "challenge" => self.process_challenge(event_loop, &mut p),
+            TypedPacket::PingEcho { echoed_time } => self.process_ping_echo(echoed_time),
+            TypedPacket::Challenge(challenge) => self.process_challenge(event_loop, challenge),
             // ["challenge-password", pw] / ["challenge-cancel"]: synthesized locally by the
             // pinentry worker thread (see prompt_password_pinentry), delivered on the UI thread so
             // it can compute the response and re-send hello / quit - a challenge equivalent of the
             // decode thread's "draw-decoded".
-            "challenge-password" => {
-                let password = p.get_str(1);
-                self.answer_challenge(&password);
-            }
-            "challenge-cancel" => self.cancel_auth(event_loop),
+            TypedPacket::ChallengePassword(password) => self.answer_challenge(&password),
+            TypedPacket::ChallengeCancel => self.cancel_auth(event_loop),
             // pinentry could not run (e.g. no display); fall back to the built-in dialog. The
             // prompt text rides along in field 1.
-            "challenge-fallback-dialog" => {
-                let prompt_text = p.get_str(1);
-                self.show_auth_dialog(event_loop, prompt_text);
-            }
+            TypedPacket::ChallengeFallbackDialog(prompt_text) => self.show_auth_dialog(event_loop, prompt_text),
             // clipboard (plain text). The server toggles syncing, takes/gives ownership via
             // token/data, and pulls/pushes contents; see the process_clipboard_* handlers below and
             // clipboard.rs. "clipboard-changed" is our own synthesized type, posted by the clipboard
             // thread when the local OS clipboard changed - the analogue of "send-ping"/"draw-decoded".
-            "set-clipboard-enabled" => {
-                self.clipboard_enabled = p.get_bool(1);
+            TypedPacket::SetClipboardEnabled(enabled) => {
+                self.clipboard_enabled = enabled;
                 debug!("clipboard sync {}", if self.clipboard_enabled { "enabled" } else { "disabled" });
             }
-            "clipboard-token" => self.process_clipboard_token(&mut p),
-            "clipboard-request" => self.process_clipboard_request(&p),
-            "clipboard-contents" => self.process_clipboard_contents(&mut p),
-            "clipboard-contents-none" => debug!("clipboard-contents-none"),
-            "clipboard-pending-requests" => {} // server-side request count; nothing to render
-            "clipboard-changed" => self.process_clipboard_changed(&p),
+            TypedPacket::ClipboardToken(data) => self.process_clipboard_token(data),
+            TypedPacket::ClipboardRequest { request_id, target } => self.process_clipboard_request(request_id, target),
+            TypedPacket::ClipboardContents(data) => self.process_clipboard_contents(data),
+            TypedPacket::ClipboardContentsNone => debug!("clipboard-contents-none"),
+            TypedPacket::ClipboardPendingRequests => {} // server-side request count; nothing to render
+            TypedPacket::ClipboardChanged(text) => self.process_clipboard_changed(text),
             // ["tray-exit"]: the "Exit" item of the Windows system tray menu (see tray.rs). A
             // client-side packet type like "send-ping": the tray's window procedure runs on the UI
             // thread but has no `ActiveEventLoop`, so it posts this and the quit happens here.
-            "tray-exit" => {
+            TypedPacket::TrayExit => {
                 info!("exit requested from the system tray");
                 // say goodbye the way xpra's own client does (`disconnect` is now `connection-close`).
                 // This has to come before quit(), which sets exit_code and turns write_json into a
@@ -1288,55 +1571,175 @@ impl XpraClient {
                 self.write_json(json!(["connection-close", "client exit"]));
                 self.quit(event_loop, ExitCode::Ok);
             }
-            "disconnect" => self.process_disconnect(event_loop, &p),
-            "connection-lost" => {
+            TypedPacket::Disconnect(info) => self.process_disconnect(event_loop, info),
+            TypedPacket::ConnectionLost { message, connection } => {
+                if connection != self.connection {
+                    debug!("ignoring the loss of an earlier connection: {}", message);
+                    return;
+                }
                 // synthesized locally (see `client_packet`): the write path has already logged
                 // the error that got it here, so only log if this is the first we hear of it.
-                if self.exit_code.is_none() {
-                    warn!("connection lost: {}", p.get_str(1));
+                if self.exit_code.is_none() && self.reconnect_rx.is_none() {
+                    warn!("connection lost: {}", message);
+                }
+                if self.can_reconnect() {
+                    self.start_reconnect();
+                    return;
                 }
                 let exit_code = self.connection_lost_code();
                 self.quit(event_loop, exit_code);
             }
-            "invalid-packet" => {
-                error!("invalid packet received: {}", p.get_str(1));
-                // garbage on a connection that never became a session usually means we're not
-                // talking to an xpra server at all, so report that rather than a packet failure:
-                let exit_code = if self.startup_complete {
-                    ExitCode::PacketFailure
-                } else {
-                    ExitCode::ConnectionFailed
-                };
-                self.quit(event_loop, exit_code);
+            TypedPacket::ReconnectResult => self.finish_reconnect(event_loop),
+            TypedPacket::InvalidPacket(message) => self.process_invalid_packet(event_loop, &message),
+            TypedPacket::Unknown(other) => warn!("unhandled packet type {:?}", other),
+        }
+    }
+
+    // The connection is gone but the session may not be: keep its windows, greyed out, and try to
+    // connect to the same server again on a worker thread - like main.rs's connect worker, which
+    // this reuses the `connect` of, it posts a client-side packet ("reconnect-result") to hand the
+    // outcome back. Only errors that say the server is unreachable are worth retrying; anything
+    // else (a changed certificate, a bad ssh setup, ...) ends the session straight away.
+    fn start_reconnect(&mut self) {
+        self.connection_lost = true;
+        let Some(reconnect) = &self.reconnect else {
+            return;
+        };
+        if self.reconnect_rx.is_some() {
+            return;
+        }
+        let lost_at = *self.lost_at.get_or_insert_with(Instant::now);
+        let deadline = lost_at + reconnect.timeout;
+        let connect = reconnect.connect.clone();
+        info!("reconnecting to {} (giving up after {:?})", self.target, reconnect.timeout);
+        let (tx, rx) = channel();
+        self.reconnect_rx = Some(rx);
+        let proxy = self.proxy.clone();
+        thread::Builder::new().name("reconnect".to_string()).spawn(move || {
+            let mut delay = RECONNECT_DELAY;
+            let result = loop {
+                thread::sleep(delay.min(deadline.saturating_duration_since(Instant::now())));
+                match connect() {
+                    Ok(connection) => break Ok(connection),
+                    Err((ExitCode::ConnectionFailed | ExitCode::SshFailure, message)) if Instant::now() < deadline => {
+                        info!("reconnection failed, trying again in {:?}: {}", delay, message);
+                    }
+                    Err((ExitCode::ConnectionFailed | ExitCode::SshFailure, message)) => {
+                        break Err((ExitCode::ConnectionLost, format!("giving up reconnecting: {}", message)));
+                    }
+                    Err(error) => break Err(error),
+                }
+                delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+            };
+            let _ = tx.send(result);
+            let _ = proxy.send_event(client_packet("reconnect-result", ""));
+        }).unwrap();
+
+        // what the server showed us is out of date from here on
+        self.release_pointer_grab();
+        let popups: Vec<u64> = self.windows.values()
+            .filter(|window| window.override_redirect)
+            .map(|window| window.wid)
+            .collect();
+        for wid in popups {
+            self.process_lost_window(wid);
+        }
+        for window in self.windows.values_mut() {
+            window.mark_stale();
+        }
+        #[cfg(windows)]
+        {
+            if let Some(worker) = &self.audio_worker {
+                let _ = worker.end(self.audio_protocol.sequence);
             }
-            other => warn!("unhandled packet type {:?}", other),
+            self.audio_protocol = AudioProtocol::default();
+            self.audio_sync_reporter = LatencyReporter::default();
         }
     }
 
+    // Pick the session up on the new connection the way it started on the first one - hello and
+    // all - bar what only happens once per process (the ping and clipboard threads, the monitors).
+    fn finish_reconnect(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(rx) = self.reconnect_rx.take() else {
+            return;
+        };
+        let connection = match rx.try_recv() {
+            Ok(Ok(connection)) => connection,
+            Ok(Err((exit_code, message))) => {
+                error!("{}", message);
+                self.quit(event_loop, exit_code);
+                return;
+            }
+            Err(e) => {
+                error!("no reconnection result: {e}");
+                self.quit(event_loop, ExitCode::ConnectionLost);
+                return;
+            }
+        };
+        info!("reconnected to {}", self.target);
+        self.stream = connection;
+        self.connection += 1;
+        self.connection_lost = false;
+        // everything the server's hello told us about the old connection
+        self.server_backwards_compatible = true;
+        self.packet_encoder = PacketEncoder::Yaml;
+        self.compress_packets = false;
+        self.cipher_params = None;
+        self.cipher_out = None;
+        self.pending_challenge = None;
+        // the area's file was unlinked once the first server had it mapped (see process_mmap_caps),
+        // so there is nothing left to offer a second time
+        self.mmap = None;
+        self.start_read_loop();
+        self.send_hello(None);
+    }
+
+    // the server's `startup-complete` after a reconnect: it has sent back every window it still
+    // has, so those that are still greyed out are gone
+    fn drop_stale_windows(&mut self) {
+        let stale: Vec<u64> = self.windows.values()
+            .filter(|window| window.stale)
+            .map(|window| window.wid)
+            .collect();
+        for wid in stale {
+            debug!("window {:#x} did not come back after reconnecting", wid);
+            self.process_lost_window(wid);
+        }
+    }
+
+    // Synthesized by the read loop for what it could not decode, and by user_event for a packet
+    // that decoded but does not parse as its type - a truncated draw, a metadata that is not a
+    // dictionary, ...: either way the session cannot be trusted to continue.
+    fn process_invalid_packet(&mut self, event_loop: &ActiveEventLoop, message: &str) {
+        error!("invalid packet received: {}", message);
+        // garbage on a connection that never became a session usually means we're not
+        // talking to an xpra server at all, so report that rather than a packet failure:
+        let exit_code = if self.startup_complete {
+            ExitCode::PacketFailure
+        } else {
+            ExitCode::ConnectionFailed
+        };
+        self.quit(event_loop, exit_code);
+    }
+
     // ["server-event", event_type, *args]: informational server lifecycle events. Advertising
     // `events: true` in hello enables these; they complement rather than replace the dedicated
     // protocol packets, so log them without changing client state.
-    fn process_server_event(&self, packet: &Packet) {
-        if packet.len() < 2 {
-            warn!("ignoring malformed server-event packet with no event type");
-            return;
-        }
-        let event_type = packet.get_str(1);
+    fn process_server_event(&self, event_type: &str, args: &[Yaml]) {
         if event_type.is_empty() {
             warn!("ignoring malformed server-event packet with an invalid event type");
             return;
         }
         info!("server event: {}", event_type);
-        if packet.len() > 2 {
-            debug!("server event {:?} arguments: {:?}", event_type, &packet.main[2..]);
+        if !args.is_empty() {
+            debug!("server event {:?} arguments: {:?}", event_type, args);
         }
     }
 
     // ["disconnect", reason, *info] - see xpra's `server_disconnect_exit_code` in
     // `client/base/client.py`: most disconnects are the server saying goodbye (exit code `OK`);
     // the exceptions are authentication failures and anything whose reason reads as an error.
-    fn process_disconnect(&mut self, event_loop: &ActiveEventLoop, packet: &Packet) {
-        let info: Vec<String> = (1..packet.len()).map(|i| packet.get_str(i as u8)).collect();
+    fn process_disconnect(&mut self, event_loop: &ActiveEventLoop, info: Vec<String>) {
         let reason = info.first().cloned().unwrap_or_default();
         let message = info.join(", ");
         let exit_code = if info.iter().any(|i| i == "authentication failed") {
@@ -1355,17 +1758,14 @@ impl XpraClient {
     // ["challenge", server_salt, cipher, digest, salt_digest, prompt]: the server wants a password.
     // We only implement the hmac+sha256 password digest (see send_hello / net::sha256); the reply
     // is a second hello carrying the challenge response. Mirrors xpra's client/base/challenge.py.
-    fn process_challenge(&mut self, event_loop: &ActiveEventLoop, packet: &mut Packet) {
+    fn process_challenge(&mut self, event_loop: &ActiveEventLoop, challenge: Challenge) {
         if self.pending_challenge.is_some() || self.auth_dialog.is_some() {
             // we answer a single challenge; a repeat means our answer was rejected, and the server
             // will also send a disconnect ("authentication failed") that ends the session.
             warn!("ignoring repeated challenge");
             return;
         }
-        let server_salt = packet.get_bytes(1);
-        let digest = packet.get_str(3);
-        let salt_digest = if packet.len() >= 5 { packet.get_str(4) } else { "xor".to_string() };
-        let prompt = if packet.len() >= 6 { packet.get_str(5) } else { "password".to_string() };
+        let Challenge { server_salt, digest, salt_digest, prompt } = challenge;
         if server_salt.is_empty() {
             error!("authentication challenge has no server salt");
             self.quit(event_loop, ExitCode::AuthenticationFailed);
@@ -1373,8 +1773,8 @@ impl XpraClient {
         }
         // we advertised only hmac+sha256 for both digests, so that is all the server should pick.
         // Anything else (including the xor/des digests, which xpra only allows over an encrypted
-        // link we don't have) we cannot answer - fail cleanly rather than hang until the server's
-        // authentication timeout.
+        // link) we cannot answer - fail cleanly rather than hang until the server's authentication
+        // timeout.
         if digest != "hmac+sha256" || salt_digest != "hmac+sha256" {
             error!("server requested an unsupported challenge digest ({digest:?}/{salt_digest:?})");
             self.quit(event_loop, ExitCode::AuthenticationFailed);
@@ -1383,9 +1783,10 @@ impl XpraClient {
         self.pending_challenge = Some(server_salt);
 
         // password source 1: the connection dialog, when the user filled its (optional) password
-        // field - they have already been asked, so do not ask again.
+        // field - they have already been asked, so do not ask again. With --reconnect, this is
+        // also the password that answered the first connection's challenge.
         if let Some(pw) = self.password.clone().filter(|pw| !pw.is_empty()) {
-            info!("authenticating with the password from the connection dialog");
+            info!("authenticating with the password given earlier");
             self.answer_challenge(&pw);
             return;
         }
@@ -1427,6 +1828,10 @@ impl XpraClient {
         let response = hmac_sha256_hex(password.as_bytes(), salt.as_bytes());
         debug!("answering authentication challenge");
         self.send_hello(Some((response, client_salt)));
+        // the server will ask again after a reconnect, and the user should not have to
+        if self.reconnect.is_some() && self.password.is_none() {
+            self.password = Some(password.to_string());
+        }
     }
 
     fn show_auth_dialog(&mut self, event_loop: &ActiveEventLoop, prompt_text: String) {
@@ -1488,6 +1893,9 @@ impl XpraClient {
     }
 
     fn process_hello(&mut self, event_loop: &ActiveEventLoop, hello: &Yaml) {
+        if !self.process_encryption_caps(event_loop, hello) {
+            return;
+        }
         self.process_mmap_caps(event_loop, hello);
         match &hello {
             Yaml::Hash(hash) => {
@@ -1508,6 +1916,18 @@ impl XpraClient {
                         self.server_backwards_compatible,
                     );
                 }
+                self.desktop = DesktopSession::from_hello(hello);
+                if let Some(session) = self.desktop {
+                    info!("{} session, {}", session.name(),
+                          if session.resize_screen { "resized to fit the window" } else { "scaled to fit the window" });
+                }
+                if let Some(encoder) = server_packet_encoder(hello) {
+                    debug!("using packet encoder {}", encoder.name());
+                    self.packet_encoder = encoder;
+                }
+                self.compress_packets = hash.get(&Yaml::String("compressors".to_string()))
+                    .and_then(Yaml::as_vec)
+                    .is_some_and(|compressors| compressors.contains(&Yaml::String("lz4".to_string())));
                 // The server advertises whether it accepts forwarded client logs as
                 // `remote-logging: {receive, send}` (xpra server/subsystem/logging.py). When it
                 // receives, drop our proxy into the shared sink so the global logger starts
@@ -1585,6 +2005,33 @@ impl XpraClient {
         }
     }
 
+    // With encryption on, the server's hello - which arrived encrypted, the reader thread makes
+    // sure of that - says how to encrypt what we send it. A server that doesn't is one that has
+    // no encryption to offer, and carrying on in the clear is not what the user asked for.
+    fn process_encryption_caps(&mut self, event_loop: &ActiveEventLoop, hello: &Yaml) -> bool {
+        let Some(encryption) = &self.encryption else {
+            return true;
+        };
+        let cipher = match CipherParams::from_hello(hello) {
+            Ok(Some(params)) => Cipher::encryptor(&params, &encryption.secret)
+                .map(|cipher| (cipher, params.mode)),
+            Ok(None) => Err("the server did not enable encryption".to_string()),
+            Err(e) => Err(e),
+        };
+        match cipher {
+            Ok((cipher, mode)) => {
+                info!("packet encryption enabled: sending with {:?}, receiving with {:?}", mode, encryption.mode);
+                self.cipher_out = Some(cipher);
+                true
+            }
+            Err(message) => {
+                error!("encryption: {}", message);
+                self.quit(event_loop, ExitCode::Encryption);
+                false
+            }
+        }
+    }
+
     // Windows speaker forwarding -------------------------------------------------------------
 
     #[cfg(windows)]
@@ -1607,17 +2054,10 @@ impl XpraClient {
     }
 
     #[cfg(windows)]
-    fn process_audio_data(&mut self, packet: &mut Packet) {
+    fn process_audio_data(&mut self, incoming: IncomingAudio) {
         if !self.audio_protocol.negotiated || self.audio_worker.is_none() {
             return;
         }
-        let incoming = match IncomingAudio::parse(packet) {
-            Ok(incoming) => incoming,
-            Err(error) => {
-                warn!("ignoring malformed audio-data packet: {error}");
-                return;
-            }
-        };
         if !self.audio_protocol.accepts_sequence(incoming.metadata.sequence) {
             debug!(
                 "ignoring audio data for old sequence {:?} (current is {})",
@@ -1823,16 +2263,17 @@ impl XpraClient {
     // the token (fields 3..8 of the legacy layout: target, dtype, dformat, wire_encoding,
     // wire_data), so we write it straight to the local clipboard. A bare token carries no data - we
     // pull it with a clipboard-request instead.
-    fn process_clipboard_token(&mut self, packet: &mut Packet) {
+    fn process_clipboard_token(&mut self, data: Option<ClipboardData>) {
         if !self.clipboard_enabled {
             return;
         }
-        if packet.len() >= 8 {
-            if let Some(text) = self.clipboard_text(packet, 6, 7) {
-                self.set_local_clipboard(text);
+        match data {
+            Some(data) => {
+                if let Some(text) = clipboard_text(data) {
+                    self.set_local_clipboard(text);
+                }
             }
-        } else {
-            self.send_clipboard_request();
+            None => self.send_clipboard_request(),
         }
     }
 
@@ -1840,9 +2281,7 @@ impl XpraClient {
     // local text, which the clipboard thread's poll keeps in `last_clipboard`. We only serve plain
     // text, so a request for anything else (a TARGETS enumeration, an image, ...) gets "none" - and
     // we echo the requested text target back as the reply's dtype.
-    fn process_clipboard_request(&mut self, packet: &Packet) {
-        let request_id = packet.get_u64(1);
-        let target = packet.get_str(3);
+    fn process_clipboard_request(&mut self, request_id: u64, target: String) {
         let is_text = matches!(target.as_str(),
             "UTF8_STRING" | "TEXT" | "STRING" | "text/plain;charset=utf-8" | "text/plain");
         if !self.clipboard_enabled || !is_text || self.last_clipboard.is_empty() {
@@ -1855,25 +2294,22 @@ impl XpraClient {
 
     // The server's reply to a clipboard-request we made for a bare token: the pulled text. Layout
     // has no target field - request_id, selection, dtype, dformat, wire_encoding, wire_data.
-    fn process_clipboard_contents(&mut self, packet: &mut Packet) {
+    fn process_clipboard_contents(&mut self, data: Option<ClipboardData>) {
         if !self.clipboard_enabled {
             return;
         }
-        if packet.len() >= 7 {
-            if let Some(text) = self.clipboard_text(packet, 5, 6) {
-                self.set_local_clipboard(text);
-            }
+        if let Some(text) = data.and_then(clipboard_text) {
+            self.set_local_clipboard(text);
         }
     }
 
     // The clipboard thread saw the local clipboard change (a local copy): claim the clipboard on
     // the remote side by sending a token carrying the new text. The `last_clipboard` guard drops a
     // value we ourselves just wrote from a remote paste, so it doesn't bounce back to the server.
-    fn process_clipboard_changed(&mut self, packet: &Packet) {
+    fn process_clipboard_changed(&mut self, text: String) {
         if !self.clipboard_enabled {
             return;
         }
-        let text = packet.get_str(1);
         if text.is_empty() || text == self.last_clipboard {
             return;
         }
@@ -1881,22 +2317,6 @@ impl XpraClient {
         self.send_clipboard_data(&text);
     }
 
-    // Decode a plain-text clipboard payload. xpra sends 8-bit text with wire encoding "bytes" (the
-    // only text encoding - clipboard/core.py); the bytes ride as a YAML !!binary scalar, which
-    // get_bytes base64-decodes. Non-text encodings ("integers"/"atoms") aren't text - skip them.
-    fn clipboard_text(&self, packet: &mut Packet, enc_index: u8, data_index: u8) -> Option<String> {
-        let encoding = packet.get_str(enc_index);
-        if encoding != "bytes" {
-            debug!("ignoring clipboard data with wire encoding {:?}", encoding);
-            return None;
-        }
-        let bytes = packet.get_bytes(data_index);
-        if bytes.is_empty() {
-            return None;
-        }
-        Some(String::from_utf8_lossy(&bytes).into_owned())
-    }
-
     // Put text on the local OS clipboard (via the clipboard thread) and remember it, so the
     // thread's poll doesn't report our own write back as a local change (which would loop it
     // straight back to the server).
@@ -1907,32 +2327,88 @@ impl XpraClient {
         }
     }
 
-    fn process_new_common(&mut self, event_loop: &ActiveEventLoop, packet: &Packet, override_redirect: bool) {
-        let wid = packet.get_u64(1);
+    fn process_new_common(&mut self, event_loop: &ActiveEventLoop, new_window: NewWindow) {
+        let NewWindow { wid, x, y, w, h, override_redirect, .. } = new_window;
         debug!("new-window {:#x}, override-redirect={:?}", wid, override_redirect);
-        let x = packet.get_i32(2);
-        let y = packet.get_i32(3);
-        let w = packet.get_u32(4);
-        let h = packet.get_u32(5);
-        let metadata = WindowMetadataUpdate::parse(&packet.main[6]);
+        // in a desktop or shadow session, the server's root window: `w`x`h` is the size of its screen
+        let desktop = self.desktop.filter(|_| !override_redirect);
+        let root = (w, h);
+        // from here on, the geometry is the one the window has locally
+        let scaling = self.scaling;
+        let (x, y, w, h) = (scaling.to_local(x), scaling.to_local(y), scaling.size_to_local(w), scaling.size_to_local(h));
+        let metadata = WindowMetadataUpdate::parse(&new_window.metadata);
         let title = metadata.title.clone().unwrap_or_default();
         // override-redirect windows are never decorated; otherwise honour the metadata flag
         // (absent means decorated, as in xpra's own client - see `client/gui/window_base.py`)
         let decorated = !override_redirect
-            && metadata.decorations.unwrap_or(true);
+            && (metadata.decorations.unwrap_or(true) || desktop.is_some());
+        // translucent popups, rounded menus, shaped notifications: created with an alpha channel
+        // of their own, on a display that can blend it - the server sends the others opaque
+        let alpha = self.transparency
+            && (metadata.has_alpha.unwrap_or(false) || metadata.transparent.unwrap_or(false));
+
+        // a window we kept through a reconnect: take it back over as it is, where the user may have
+        // moved it since, and tell the server so.
+        if let Some(window) = self.windows.get_mut(&wid)
+            && window.stale {
+            info!("new-window {:#x} : {:?} (after reconnecting)", wid, title);
+            window.stale = false;
+            window.window.set_title(&title);
+            Self::apply_window_metadata(window, metadata);
+            // the server's screen may have changed size while we were away
+            if window.desktop.is_some() {
+                window.resize_framebuffer(root.0, root.1);
+            }
+            let (x, y, w, h) = window.get_geometry();
+            self.send_window_map(wid, x, y, w, h);
+            return;
+        }
 
-        #[allow(unused_mut)]
         let mut attrs = Window::default_attributes()
             .with_title(&title)
             .with_position(PhysicalPosition::new(x, y))
             .with_inner_size(PhysicalSize::new(w.max(1), h.max(1)))
             .with_decorations(decorated)
-            .with_resizable(!override_redirect);
+            .with_resizable(!override_redirect)
+            .with_transparent(alpha);
         #[cfg(target_os = "linux")]
         {
             use winit::platform::x11::WindowAttributesExtX11;
             attrs = attrs.with_override_redirect(override_redirect);
+            // WM_CLASS - or the app_id, on Wayland - must be there before the window is mapped
+            if let Some((instance, class)) = &metadata.class_instance {
+                attrs = attrs.with_name(class, instance);
+            }
+            // what the window manager counts the increments from, which winit can only be told now
+            if let Some((w, h)) = metadata.size_constraints.and_then(|constraints| constraints.base) {
+                attrs = attrs.with_base_size(PhysicalSize::new(scaling.size_to_local(w), scaling.size_to_local(h)));
+            }
+        }
+        // the root goes wherever the window manager puts it, at a size that fits the screen
+        if desktop.is_some() {
+            let monitor = event_loop.primary_monitor().or_else(|| event_loop.available_monitors().next())
+                .map(|monitor| (monitor.size().width, monitor.size().height));
+            let (w, h) = desktop::window_size((w, h), monitor);
+            attrs.position = None;
+            attrs = attrs.with_inner_size(PhysicalSize::new(w, h));
+            if self.desktop_fullscreen {
+                attrs = attrs.with_fullscreen(Some(Fullscreen::Borderless(None)));
+            }
+        }
+        // dialogs over the window they belong to - when we have it - and the window type
+        let hints = WindowHints::new(
+            wid,
+            metadata.transient_for,
+            metadata.group_leader,
+            metadata.modal.unwrap_or(false),
+            metadata.window_type.as_deref().map(WindowType::parse).unwrap_or_default(),
+            metadata.role.clone(),
+        );
+        let owner = hints.owner.and_then(|owner| self.windows.get(&owner)).map(|owner| owner.window.clone());
+        if let (Some(owner_wid), None) = (hints.owner, &owner) {
+            debug!("new-window {:#x}: transient for an unknown window {:#x}", wid, owner_wid);
         }
+        let attrs = hints.attributes(attrs, owner.as_deref());
 
         let window = match event_loop.create_window(attrs) {
             Ok(window) => Rc::new(window),
@@ -1941,15 +2417,23 @@ impl XpraClient {
                 return;
             }
         };
+        hints.apply(&window, owner.as_deref());
         info!("new-window {:#x} : {:?}", wid, title);
 
-        // start the window off with the current session cursor (see process_cursor):
+        // start the window off with the current session cursor (see process_cursor), and with the
+        // icon it had before, if it is one coming back, or ours:
         if let Some(cursor) = self.current_cursor.clone() {
             window.set_cursor(cursor);
         }
+        if let Some(icon) = self.window_icons.get(wid) {
+            icons::apply(&window, icon);
+        }
 
         let context = self.softbuffer_ctx.as_ref().expect("softbuffer context not initialized");
-        let mut xpra_window = XpraWindow::new(wid, window.clone(), context, w, h, override_redirect);
+        let mut xpra_window = XpraWindow::new(wid, window.clone(), context, override_redirect, alpha, scaling);
+        if let Some(session) = desktop {
+            xpra_window.set_desktop(session, root.0, root.1);
+        }
         Self::apply_window_metadata(&mut xpra_window, metadata);
         xpra_window.mapped = true;
         self.id_map.insert(window.id(), wid);
@@ -1960,8 +2444,8 @@ impl XpraClient {
         }
     }
 
-    fn process_window_move_resize(&mut self, packet: &Packet) {
-        let wid = packet.get_u64(1);
+    fn process_window_move_resize(&mut self, move_resize: MoveResize) {
+        let MoveResize { wid, x, y, w, h } = move_resize;
         let window = match self.windows.get_mut(&wid) {
             Some(window) => window,
             None => {
@@ -1969,11 +2453,13 @@ impl XpraClient {
                 return;
             }
         };
-        let x = packet.get_i32(2);
-        let y = packet.get_i32(3);
-        let w = packet.get_u32(4);
-        let h = packet.get_u32(5);
-
+        // a desktop session's root is where the user put it: only its size is the server's
+        if window.desktop.is_some() {
+            window.resize_framebuffer(w, h);
+            return;
+        }
+        let scaling = self.scaling;
+        let (x, y, w, h) = (scaling.to_local(x), scaling.to_local(y), scaling.size_to_local(w), scaling.size_to_local(h));
         if let Some(outer) = window.to_outer_position(x, y) {
             window.window.set_outer_position(outer);
         } else {
@@ -1982,6 +2468,22 @@ impl XpraClient {
         let _ = window.window.request_inner_size(PhysicalSize::new(w.max(1), h.max(1)));
     }
 
+    // ["window-resized", wid, w, h, ...]: the server resized a window itself - which, in a desktop
+    // session, is how the root's new size comes back after a `desktop_size` (see send_desktop_size).
+    fn process_window_resized(&mut self, wid: u64, w: u32, h: u32) {
+        let Some(window) = self.windows.get_mut(&wid) else {
+            error!("cannot resize: window {:#x} not found", wid);
+            return;
+        };
+        if window.desktop.is_some() {
+            window.resize_framebuffer(w, h);
+        } else {
+            let scaling = self.scaling;
+            let size = PhysicalSize::new(scaling.size_to_local(w).max(1), scaling.size_to_local(h).max(1));
+            let _ = window.window.request_inner_size(size);
+        }
+    }
+
     // ["initiate-moveresize", wid, x_root, y_root, direction, button, source_indication]
     // The server forwards a window's _NET_WM_MOVERESIZE request (an app calling the EWMH hint,
     // e.g. dragging its own client-side titlebar) so we can start an interactive move/resize
@@ -1993,9 +2495,7 @@ impl XpraClient {
     // (9/10) and cancel (11) have no winit equivalent and are ignored. These only take effect
     // while the initiating pointer button is still held (the WM adopts the pointer grab), so a
     // request whose grab has already been released gets silently dropped by the WM.
-    fn process_initiate_moveresize(&mut self, packet: &Packet) {
-        let wid = packet.get_u64(1);
-        let direction = packet.get_u32(4);
+    fn process_initiate_moveresize(&mut self, wid: u64, direction: u32) {
         let window = match self.windows.get(&wid) {
             Some(window) => window,
             None => {
@@ -2031,8 +2531,7 @@ impl XpraClient {
     // ["raise-window", wid]: bring the window to the front. Also arrives as the server's fallback
     // for restack requests, since we don't advertise the "window.restack" capability. Like xpra's
     // own client, skip it if the window already has focus; focus_window() is a no-op on Wayland.
-    fn process_raise_window(&mut self, packet: &Packet) {
-        let wid = packet.get_u64(1);
+    fn process_raise_window(&mut self, wid: u64) {
         let window = match self.windows.get(&wid) {
             Some(window) => window,
             None => {
@@ -2051,9 +2550,8 @@ impl XpraClient {
     // desktop, so the portable equivalent within winit is to minimize (show=true) or restore
     // (show=false) our own windows; `set_minimized` works on X11, Wayland and Windows. Only arrives
     // when the server session enables it (gated by `show_desktop_allowed` server-side). A short
-    // packet defaults to restore rather than indexing past the end (panic=abort).
-    fn process_show_desktop(&mut self, packet: &Packet) {
-        let show = packet.len() >= 2 && packet.get_bool(1);
+    // packet defaults to restore.
+    fn process_show_desktop(&mut self, show: bool) {
         debug!("show-desktop: {}", show);
         for window in self.windows.values() {
             window.window.set_minimized(show);
@@ -2064,31 +2562,16 @@ impl XpraClient {
     // side. Shadow / desktop-forwarding sessions poll the real pointer and push its position here
     // so the client can draw a "remote pointer" overlay (xpra's own show_pointer_overlay). We
     // render no such overlay, so just log it; rx/ry (root-relative) are omitted by pre-v5 senders.
-    // Guarded against a short packet (panic=abort).
-    fn process_pointer_position(&self, packet: &Packet) {
-        if packet.len() < 4 {
-            debug!("pointer-position: {:?}", &packet.main[1..]);
-            return;
-        }
-        let wid = packet.get_u64(1);
-        let (x, y) = (packet.get_i32(2), packet.get_i32(3));
-        let (rx, ry) = if packet.len() >= 6 {
-            (packet.get_i32(4), packet.get_i32(5))
-        } else {
-            (-1, -1)
-        };
+    fn process_pointer_position(&self, position: PointerPosition) {
+        let PointerPosition { wid, x, y, relative } = position;
+        let (rx, ry) = relative.unwrap_or((-1, -1));
         debug!("pointer-position: {},{} ({},{} relative to window {:#x})", x, y, rx, ry, wid);
     }
 
     // ["pointer-grab", wid]: a remote application has grabbed its pointer. Prefer confining the
     // cursor to the forwarded window; some winit backends only implement locking, so use that as
     // the fallback. If another window held the grab, release it first.
-    fn process_pointer_grab(&mut self, packet: &Packet) {
-        if packet.len() < 2 {
-            warn!("ignoring malformed pointer-grab packet with no window id");
-            return;
-        }
-        let wid = packet.get_u64(1);
+    fn process_pointer_grab(&mut self, wid: u64) {
         if self.pointer_grabbed == Some(wid) {
             return;
         }
@@ -2116,9 +2599,9 @@ impl XpraClient {
 
     // ["pointer-ungrab", wid]: the wid is informational; the local windowing API has one active
     // pointer grab for the application, so release whichever forwarded window currently owns it.
-    fn process_pointer_ungrab(&mut self, packet: &Packet) {
-        if packet.len() >= 2 {
-            debug!("pointer-ungrab requested for window {}", packet.get_i64(1));
+    fn process_pointer_ungrab(&mut self, wid: Option<u64>) {
+        if let Some(wid) = wid {
+            debug!("pointer-ungrab requested for window {:#x}", wid);
         }
         self.release_pointer_grab();
     }
@@ -2140,27 +2623,23 @@ impl XpraClient {
     // cursor shape. xpra sends one cursor for the whole session (not per-window), so we apply it to
     // every window and remember it for windows created later. A 2-item ["cursor", ""] packet resets
     // to the default. We only advertised the "png" encoding, so pixels decode like a window icon.
-    fn process_cursor(&mut self, event_loop: &ActiveEventLoop, packet: &mut Packet) {
+    fn process_cursor(&mut self, event_loop: &ActiveEventLoop, cursor: Option<Cursor>) {
         // an empty (2-item) packet means "use the default cursor":
-        if packet.len() <= 2 {
+        let Some(Cursor { encoding, xhot, yhot, pixels }) = cursor else {
             self.current_cursor = None;
             for window in self.windows.values() {
                 window.window.set_cursor(CursorIcon::Default);
             }
             return;
-        }
+        };
         // the encoding may be prefixed "default:" (also marks it as the session default); either
         // way we just render it, so strip the prefix:
-        let encoding = packet.get_str(1);
         let encoding = encoding.rsplit(':').next().unwrap_or(&encoding);
         if encoding != "png" {
             debug!("ignoring cursor with unsupported encoding {:?}", encoding);
             return;
         }
-        let xhot = packet.get_u32(6);
-        let yhot = packet.get_u32(7);
-        let data = packet.get_bytes(9);
-        let (w, h, rgba) = match draw_decoder::decode_png_rgba(&data) {
+        let (w, h, rgba) = match draw_decoder::decode_png_rgba(&pixels) {
             Ok(decoded) => decoded,
             Err(e) => {
                 debug!("failed to decode cursor: {}", e);
@@ -2195,10 +2674,8 @@ impl XpraClient {
     // they are only logged, like xpra's own headless fallback: there is no portable notifier
     // without a D-Bus dependency, which this client avoids. The log line is kept on every platform
     // since it is also what reaches the server through remote logging.
-    fn process_notify_show(&mut self, packet: &Packet) {
-        let app_name = packet.get_str(3);
-        let summary = packet.get_str(6);
-        let body = packet.get_str(7);
+    fn process_notify_show(&mut self, notification: Notification) {
+        let Notification { app_name, summary, body, .. } = notification;
         if app_name.is_empty() {
             info!("notification: {summary}");
         } else {
@@ -2209,14 +2686,13 @@ impl XpraClient {
         }
         #[cfg(windows)]
         if let Some(tray) = &mut self.tray {
-            tray.show_notification(packet.get_u64(2), &app_name, &summary, &body);
+            tray.show_notification(notification.nid, &app_name, &summary, &body);
         }
     }
 
     // ["notify_close", nid]: the server withdrawing a notification, by the same id `notify_show`
     // carried. Only the Windows balloon can actually be taken back; the log line already went out.
-    fn process_notify_close(&mut self, packet: &Packet) {
-        let notification_id = packet.get_u64(1);
+    fn process_notify_close(&mut self, notification_id: u64) {
         debug!("notification {notification_id} closed");
         #[cfg(windows)]
         if let Some(tray) = &mut self.tray {
@@ -2227,49 +2703,47 @@ impl XpraClient {
     // ["bell", wid, device, percent, pitch, duration, bell_class, bell_id, bell_name]: the server
     // forwarding a window's bell (e.g. a terminal's ^G). We advertised "bell" support in the hello,
     // without which the server never sends this. Only pitch/duration are used (see ring_bell).
-    fn process_bell(&mut self, packet: &Packet) {
-        let pitch = packet.get_i32(4);
-        let duration = packet.get_i32(5);
+    fn process_bell(&mut self, pitch: i32, duration: i32) {
         ring_bell(pitch, duration);
     }
 
     // ["window-icon", wid, w, h, encoding, pixels]: the titlebar/taskbar icon. The server only
     // ever ships icons as png (see xpra's windowicon.py), which we advertised support for in the
-    // hello; a "default"/empty payload just means "keep the default icon". A bad icon logs and
-    // leaves the current one in place - purely cosmetic, never fatal.
-    fn process_window_icon(&mut self, packet: &mut Packet) {
-        let wid = packet.get_u64(1);
-        let encoding = packet.get_str(4);
-        if encoding != "png" {
-            debug!("ignoring window-icon for {:#x} with unsupported encoding {:?}", wid, encoding);
-            return;
-        }
-        let data = packet.get_bytes(5);
-        let (w, h, rgba) = match draw_decoder::decode_png_rgba(&data) {
-            Ok(decoded) => decoded,
-            Err(e) => {
-                debug!("failed to decode window icon for {:#x}: {}", wid, e);
-                return;
-            }
-        };
-        let icon = match Icon::from_rgba(rgba, w, h) {
-            Ok(icon) => icon,
-            Err(e) => {
-                debug!("invalid window icon for {:#x}: {:?}", wid, e);
+    // hello; its "default" placeholder means the window has no icon of its own, and gets ours. A bad
+    // icon logs and leaves the current one in place - purely cosmetic, never fatal. Either way the
+    // icon is remembered for the window, should it be created again (see icons.rs).
+    fn process_window_icon(&mut self, icon: WindowIcon) {
+        let WindowIcon { wid, encoding, pixels } = icon;
+        match encoding.as_str() {
+            "png" => match draw_decoder::decode_png_rgba(&pixels) {
+                Ok((w, h, rgba)) => self.window_icons.set(wid, IconImage { w, h, rgba }),
+                Err(e) => {
+                    debug!("failed to decode window icon for {:#x}: {}", wid, e);
+                    return;
+                }
+            },
+            "default" => self.window_icons.reset(wid),
+            _ => {
+                debug!("ignoring window-icon for {:#x} with unsupported encoding {:?}", wid, encoding);
                 return;
             }
-        };
-        match self.windows.get(&wid) {
-            Some(window) => window.window.set_window_icon(Some(icon)),
-            None => error!("cannot set icon: window {:#x} not found", wid),
+        }
+        match (self.windows.get(&wid), self.window_icons.get(wid)) {
+            (Some(window), Some(icon)) => icons::apply(&window.window, icon),
+            (None, _) => debug!("window-icon for {:#x}, which is not mapped", wid),
+            _ => {}
         }
     }
 
-    fn process_lost_window(&mut self, packet: &Packet) {
-        let wid = packet.get_u64(1);
+    fn process_lost_window(&mut self, wid: u64) {
         if self.pointer_grabbed == Some(wid) {
             self.release_pointer_grab();
         }
+        // and the keyboard, which a root coming back with the same wid would otherwise never grab
+        if self.keyboard_grabbed.take_if(|grabbed| *grabbed == wid).is_some()
+            && let Some(window) = self.windows.get(&wid) {
+            desktop::grab_keyboard(&window.window, false);
+        }
         if let Some(window) = self.windows.remove(&wid) {
             self.id_map.remove(&window.window.id());
         } else {
@@ -2277,9 +2751,7 @@ impl XpraClient {
         }
     }
 
-    fn process_window_metadata(&mut self, packet: &Packet) {
-        let wid = packet.get_u64(1);
-        let metadata = &packet.main[2];
+    fn process_window_metadata(&mut self, wid: u64, metadata: &Yaml) {
         info!("window-metadata for {:#x}: {:?}", wid, metadata);
         let window = match self.windows.get_mut(&wid) {
             Some(window) => window,
@@ -2298,19 +2770,24 @@ impl XpraClient {
         if let Some(decorations) = update.decorations {
             window.window.set_decorations(decorations && !window.override_redirect);
         }
+        // only a hint to winit, which cannot give a window that was created opaque an alpha
+        // channel - but it lets a translucent one go opaque, and back
+        if let Some(transparent) = update.transparent {
+            window.window.set_transparent(transparent && window.alpha);
+        }
         if let Some(constraints) = update.size_constraints {
-            window.window.set_min_inner_size(
-                constraints.minimum.map(|(w, h)| PhysicalSize::new(w, h)),
-            );
-            window.window.set_max_inner_size(
-                constraints.maximum.map(|(w, h)| PhysicalSize::new(w, h)),
-            );
-            window.window.set_resize_increments(
-                constraints.increment.map(|(w, h)| PhysicalSize::new(w, h)),
-            );
+            let scaling = window.scaling;
+            let local = |(w, h): (u32, u32)| PhysicalSize::new(scaling.size_to_local(w), scaling.size_to_local(h));
+            window.window.set_min_inner_size(constraints.minimum.map(local));
+            window.window.set_max_inner_size(constraints.maximum.map(local));
+            // X11 and macOS make the user's resizes go by these steps themselves - from the base size
+            // given when the window was created, on X11 - and the others are snapped once they are
+            // done (see size_hints.rs and handle_window_event)
+            window.window.set_resize_increments(constraints.increment.map(local));
             let fixed_size = constraints.minimum.is_some()
                 && constraints.minimum == constraints.maximum;
             window.window.set_resizable(!window.override_redirect && !fixed_size);
+            window.size_hints = constraints;
         }
         if let Some(fullscreen) = update.fullscreen {
             window.window.set_fullscreen(
@@ -2346,19 +2823,29 @@ impl XpraClient {
             };
             window.window.set_window_level(level);
         }
+        if let Some(opacity) = update.opacity {
+            window_properties::set_opacity(&window.window, opacity);
+        }
+        for (state, on) in [(State::Sticky, update.sticky), (State::SkipTaskbar, update.skip_taskbar),
+                            (State::SkipPager, update.skip_pager)] {
+            if let Some(on) = on {
+                window_properties::set_state(&window.window, state, on);
+            }
+        }
+        if let Some(workspace) = update.workspace.filter(|&workspace| workspace != WORKSPACE_UNSET) {
+            window_properties::set_workspace(&window.window, workspace);
+        }
+        if let Some((instance, class)) = &update.class_instance {
+            window_properties::set_class(&window.window, instance, class);
+        }
+        if let Some(shadow) = update.shadow {
+            window_properties::set_shadow(&window.window, shadow);
+        }
     }
 
-    fn process_draw_decoded(&mut self, packet: &mut Packet) {
-        let p = packet;
-        let wid = p.get_u64(1);
-        let x = p.get_i32(2);
-        let y = p.get_i32(3);
-        let w = p.get_u32(4);
-        let h = p.get_u32(5);
-        let coding = p.get_str(6);
-        let pixels = p.get_bytes(7);
-        let seq = p.get_u64(8);
-        let decode_time_us = p.decode_time_us.unwrap_or(0) as i128;
+    fn process_draw_decoded(&mut self, decoded: DrawDecoded) {
+        let DrawDecoded { wid, x, y, w, h, coding, pixels, scrolls, seq, decode_time_us } = decoded;
+        let decode_time_us = decode_time_us as i128;
 
         let window = match self.windows.get_mut(&wid) {
             Some(window) => window,
@@ -2370,7 +2857,9 @@ impl XpraClient {
         };
         trace!("drawing {:?} on {:#x}", coding, wid);
         // an empty payload is a decoder warm-up frame (h264): ack it, but there's nothing to paint.
-        if !pixels.is_empty() {
+        if coding == "scroll" {
+            window.scroll(seq, &scrolls);
+        } else if !pixels.is_empty() {
             window.paint(seq, x, y, w, h, &coding, &pixels);
         }
 
@@ -2378,19 +2867,12 @@ impl XpraClient {
         self.send_draw_ack(seq, wid, w, h, decode_time_us, message);
     }
 
-    fn process_draw_failed(&mut self, packet: &Packet) {
-        let p = packet;
-        let wid = p.get_u64(1);
-        let w = p.get_u32(4);
-        let h = p.get_u32(5);
-        let message = p.get_str(7);
-        let seq = p.get_u64(8);
+    fn process_draw_failed(&mut self, failed: DrawFailed) {
+        let DrawFailed { wid, w, h, message, seq } = failed;
         self.send_draw_ack(seq, wid, w, h, -1, message);
     }
 
-    fn process_ping(&mut self, packet: &Packet) {
-        let echotime = packet.get_u64(1);
-        let sid = if packet.len() >= 4 { packet.get_str(3) } else { "".to_string() };
+    fn process_ping(&mut self, echotime: u64, sid: String) {
         debug!("got ping, sending echo time={:?}", echotime);
         self.send_ping_echo(echotime, sid);
     }
@@ -2398,8 +2880,7 @@ impl XpraClient {
     // The server's echo of a `ping` we sent (see send_ping): field 1 is the monotonic timestamp we
     // stamped it with, so `now - echoed` is the client->server round-trip. We keep it to report in
     // the ping_echo replies we send back to the server (send_ping_echo).
-    fn process_ping_echo(&mut self, packet: &Packet) {
-        let echoedtime = packet.get_i64(1);
+    fn process_ping_echo(&mut self, echoedtime: i64) {
         let rtt = self.start.elapsed().as_millis() as i64 - echoedtime;
         if rtt >= 0 {
             self.last_client_latency_ms = rtt;
@@ -2414,11 +2895,36 @@ impl XpraClient {
                 if is_focused && !override_redirect {
                     self.send_focus(wid);
                 }
+                // a desktop session's window has the keyboard shortcuts for as long as it is focused
+                if let Some(window) = self.windows.get(&wid)
+                    && window.desktop.is_some()
+                    && is_focused != (self.keyboard_grabbed == Some(wid)) {
+                    desktop::grab_keyboard(&window.window, is_focused);
+                    self.keyboard_grabbed = is_focused.then_some(wid);
+                }
             }
             WindowEvent::Moved(_) | WindowEvent::Resized(_) => {
                 if let Some(window) = self.windows.get_mut(&wid) {
-                    let size = window.window.inner_size();
+                    let mut size = window.window.inner_size();
+                    // a size the window's size-constraints do not allow: ask for the closest one that
+                    // they do, and go on with that if the platform resizes the window there and then.
+                    // Otherwise another Resized follows once it has.
+                    if let Some(constrained) = window.constrained_size(size) {
+                        debug!("window {:#x}: resized to {}x{}, snapping to {}x{}", wid, size.width, size.height,
+                               constrained.width, constrained.height);
+                        if let Some(applied) = window.window.request_inner_size(constrained) {
+                            size = applied;
+                        }
+                    }
                     window.resize(size.width, size.height);
+                    // where the root of a desktop session is on our screen is of no interest to
+                    // the server, but its size may be
+                    if let Some(session) = window.desktop {
+                        if session.resize_screen && matches!(event, WindowEvent::Resized(_)) {
+                            self.schedule_desktop_size();
+                        }
+                        return;
+                    }
                     let (x, y, w, h) = window.get_geometry();
                     debug!("updated window geometry: {:?},{:?},{:?},{:?}", x, y, w, h);
                     self.send_window_configure(wid, x, y, w, h);
@@ -2520,6 +3026,13 @@ impl ApplicationHandler<Packet> for XpraClient {
             // that answers an authentication challenge reports the same layout.
             self.monitors = local_monitors(event_loop);
             self.desktop_size = total_display_size(&self.monitors);
+            self.transparency = has_transparency(event_loop);
+            self.platform_metadata = window_properties::supported(event_loop);
+            debug!("translucent windows {}", if self.transparency { "supported" } else { "not supported" });
+            if let Some(desktop_scaling) = self.desktop_scaling {
+                self.scaling = desktop_scaling.resolve(event_loop);
+                info!("desktop scaling: {}", self.scaling.factor);
+            }
             match self.desktop_size {
                 Some((w, h)) => info!("local display size: {w}x{h}"),
                 None => warn!("no local display size to report to the server"),
@@ -2536,16 +3049,10 @@ impl ApplicationHandler<Packet> for XpraClient {
     }
 
     fn user_event(&mut self, event_loop: &ActiveEventLoop, packet: Packet) {
-        if packet.len() == 0 {
-            error!("empty packet!");
-            return;
-        }
-        let packet_type = packet.get_str(0);
-        if packet_type.is_empty() {
-            error!("malformed packet");
-            return;
+        match TypedPacket::parse(packet) {
+            Ok(packet) => self.do_process_packet(event_loop, packet),
+            Err(e) => self.process_invalid_packet(event_loop, &e),
         }
-        self.do_process_packet(event_loop, &packet_type, packet);
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
@@ -2631,8 +3138,10 @@ fn key_to_xpra_keyname(key: &Key) -> String {
 #[cfg(test)]
 mod tests {
     use super::{
-        draw_ack_packet, server_backwards_compatible, WindowMetadataUpdate, WindowSizeConstraints,
+        draw_ack_packet, server_backwards_compatible, server_packet_encoder, WindowMetadataUpdate,
+        SizeHints,
     };
+    use xpra::net::serde::PacketEncoder;
     use serde_json::json;
     use yaml_rust2::YamlLoader;
 
@@ -2652,11 +3161,27 @@ mod tests {
                 iconic: 0,
                 above: true,
                 below: false,
+                has-alpha: true,
                 size-constraints: {
                     minimum-size: [320, 200],
                     maximum-size: [1920, 1080],
-                    increment: [8, 16]
-                }
+                    base-size: [4, 8],
+                    increment: [8, 16],
+                    minimum-aspect: [4, 3],
+                    maximum-aspect: [16, 0]
+                },
+                transient-for: 3,
+                modal: true,
+                window-type: ["DIALOG", "NORMAL"],
+                group-leader: 0,
+                role: "GtkFileChooserDialog",
+                opacity: -1,
+                sticky: 1,
+                skip-taskbar: true,
+                skip-pager: false,
+                workspace: 2,
+                class-instance: ["xterm", "XTerm"],
+                shadow: false
             }"#,
         );
         assert_eq!(
@@ -2669,11 +3194,28 @@ mod tests {
                 iconic: Some(false),
                 above: Some(true),
                 below: Some(false),
-                size_constraints: Some(WindowSizeConstraints {
+                has_alpha: Some(true),
+                transparent: None,
+                size_constraints: Some(SizeHints {
                     minimum: Some((320, 200)),
                     maximum: Some((1920, 1080)),
+                    base: Some((4, 8)),
                     increment: Some((8, 16)),
+                    minimum_aspect: Some((4, 3)),
+                    maximum_aspect: None,
                 }),
+                transient_for: Some(3),
+                modal: Some(true),
+                window_type: Some(vec!["DIALOG".to_string(), "NORMAL".to_string()]),
+                group_leader: None,
+                role: Some("GtkFileChooserDialog".to_string()),
+                opacity: Some(None),
+                sticky: Some(true),
+                skip_taskbar: Some(true),
+                skip_pager: Some(false),
+                workspace: Some(2),
+                class_instance: Some(("xterm".to_string(), "XTerm".to_string())),
+                shadow: Some(false),
             }
         );
     }
@@ -2694,7 +3236,7 @@ mod tests {
         assert_eq!(metadata.decorations, None);
         assert_eq!(
             metadata.size_constraints,
-            Some(WindowSizeConstraints::default())
+            Some(SizeHints::default())
         );
     }
 
@@ -2728,4 +3270,15 @@ mod tests {
         assert_eq!(server_backwards_compatible(&modern[0]), Some(false));
         assert_eq!(server_backwards_compatible(&unspecified[0]), None);
     }
+
+    #[test]
+    fn packet_encoder_follows_our_preference() {
+        let hello = |yaml: &str| YamlLoader::load_from_str(yaml).unwrap().remove(0);
+
+        assert_eq!(server_packet_encoder(&hello("{encoders: [yaml, rencodeplus]}")),
+                   Some(PacketEncoder::RencodePlus));
+        assert_eq!(server_packet_encoder(&hello("{encoders: [yaml]}")), Some(PacketEncoder::Yaml));
+        assert_eq!(server_packet_encoder(&hello("{encoders: [rencode]}")), None);
+        assert_eq!(server_packet_encoder(&hello("{version: 6.4}")), None);
+    }
 }
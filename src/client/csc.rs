@@ -0,0 +1,109 @@
+// Colourspace conversion ("csc", as xpra calls it) for the software video decoders: openh264,
+// libvpx and dav1d all hand back planar YUV, which we turn into the same tightly packed BGRX the
+// picture decoders produce, so `window::paint` needs no video-specific path. (Media Foundation on
+// Windows does this itself, in its Video Processor.)
+//
+// Only 8-bit planar formats come through here: YUV420P, where each chroma sample covers 2x2 pixels,
+// and YUV444P, where there is one per pixel - which is all we advertise in `full_csc_modes` - and
+// YUV422P, a chroma sample per 2x1 pixels, which dav1d may output regardless.
+
+// The planes of a decoded picture, as the decoder left them: `strides` may be wider than the
+// picture, and the chroma planes are subsampled by `chroma_shift` (x, y) - (1, 1) for YUV420P,
+// (1, 0) for YUV422P and (0, 0) for YUV444P.
+pub struct YuvImage<'a> {
+    pub planes: [&'a [u8]; 3],
+    pub strides: [usize; 3],
+    pub chroma_shift: (u32, u32),
+}
+
+// The Kr/Kb derived coefficients of a YUV -> RGB matrix, in 16.16 fixed point, for full range
+// chroma: R = Y + rv*V, G = Y - gu*U - gv*V, B = Y + bu*U
+pub struct Matrix {
+    rv: i32,
+    gu: i32,
+    gv: i32,
+    bu: i32,
+}
+
+pub const BT601: Matrix = Matrix { rv: 91881, gu: 22554, gv: 46802, bu: 116130 };
+pub const BT709: Matrix = Matrix { rv: 103206, gu: 12276, gv: 30679, bu: 121609 };
+
+// For a stream that does not say which matrix it was encoded with (xpra's encoders do not tell us):
+// BT.709 for HD pictures and BT.601 for smaller ones, which is also what Media Foundation's Video
+// Processor picks on Windows.
+pub fn default_matrix(height: u32) -> &'static Matrix {
+    if height >= 720 { &BT709 } else { &BT601 }
+}
+
+// The top-left `w`x`h` of `image` as BGRX. The caller has checked that the picture is that large.
+pub fn yuv_to_bgrx(image: &YuvImage, w: usize, h: usize, matrix: &Matrix, full_range: bool) -> Vec<u8> {
+    let [y_plane, u_plane, v_plane] = image.planes;
+    let [y_stride, u_stride, v_stride] = image.strides;
+    let (x_shift, y_shift) = image.chroma_shift;
+    let mut pixels = vec![0u8; w * h * 4];
+    for (row, out) in pixels.chunks_exact_mut(w * 4).enumerate() {
+        let y_row = &y_plane[row * y_stride..];
+        let u_row = &u_plane[(row >> y_shift) * u_stride..];
+        let v_row = &v_plane[(row >> y_shift) * v_stride..];
+        for (col, px) in out.chunks_exact_mut(4).enumerate() {
+            let chroma = col >> x_shift;
+            let (r, g, b) = yuv_to_rgb(matrix, full_range, y_row[col], u_row[chroma], v_row[chroma]);
+            px.copy_from_slice(&[b, g, r, 0xFF]);
+        }
+    }
+    pixels
+}
+
+fn yuv_to_rgb(matrix: &Matrix, full_range: bool, y: u8, u: u8, v: u8) -> (u8, u8, u8) {
+    let (u, v) = (u as i32 - 128, v as i32 - 128);
+    // limited ("studio") range puts black at 16, white at 235 and the chroma in 16..240: stretch
+    // all three back out to the full 0..255 first
+    let (y, u, v) = if full_range {
+        ((y as i32) << 16, u, v)
+    } else {
+        ((y as i32 - 16) * 76309, u * 255 / 224, v * 255 / 224)
+    };
+    let clamp = |value: i32| ((value + 0x8000) >> 16).clamp(0, 255) as u8;
+    (
+        clamp(y + matrix.rv * v),
+        clamp(y - matrix.gu * u - matrix.gv * v),
+        clamp(y + matrix.bu * u),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours_are_converted_in_both_ranges() {
+        assert_eq!(yuv_to_rgb(&BT601, true, 0, 128, 128), (0, 0, 0));
+        assert_eq!(yuv_to_rgb(&BT601, true, 255, 128, 128), (255, 255, 255));
+        assert_eq!(yuv_to_rgb(&BT601, false, 16, 128, 128), (0, 0, 0));
+        assert_eq!(yuv_to_rgb(&BT601, false, 235, 128, 128), (255, 255, 255));
+        // pure red, as a full range encoder writes it with either matrix
+        assert_eq!(yuv_to_rgb(&BT601, true, 76, 85, 255), (254, 0, 0));
+        assert_eq!(yuv_to_rgb(&BT709, true, 54, 99, 255), (254, 0, 0));
+    }
+
+    #[test]
+    fn planes_are_subsampled_and_strided() {
+        // a 4x2 picture in 6 byte wide rows (and 4 byte wide chroma rows), one 2x2 chroma block
+        // white and the other red
+        let y = [255, 255, 76, 76, 9, 9, 255, 255, 76, 76];
+        let (u, v) = ([128, 85, 9, 9], [128, 255, 9, 9]);
+        let (w, h) = (4, 2);
+        let yuv420 = YuvImage { planes: [&y, &u, &v], strides: [6, 4, 4], chroma_shift: (1, 1) };
+        let pixels = yuv_to_bgrx(&yuv420, w, h, &BT601, true);
+        let (white, red) = ([255, 255, 255, 255], [0, 0, 254, 255]);
+        let row = [white, white, red, red].concat();
+        assert_eq!(pixels, [row.clone(), row].concat());
+
+        // one chroma sample per pixel: a single red pixel
+        let (u, v) = ([128, 128, 128, 85], [128, 128, 128, 255]);
+        let y = [255, 255, 255, 76];
+        let yuv444 = YuvImage { planes: [&y, &u, &v], strides: [4, 4, 4], chroma_shift: (0, 0) };
+        let pixels = yuv_to_bgrx(&yuv444, w, 1, &BT601, true);
+        assert_eq!(pixels, [white, white, white, red].concat());
+    }
+}
@@ -0,0 +1,89 @@
+// The parts of a window that changed since it was last presented, in surface pixels: what
+// XpraWindow::draw_screen copies into softbuffer's buffer and passes on to `present_with_damage`,
+// so that a blinking cursor in a large window costs a few hundred bytes rather than the whole frame.
+//
+// Every paint, scroll and rescale between two redraws adds its rectangle here, and winit folds all
+// the redraws requested in one event loop turn into a single `RedrawRequested`, which takes them
+// all at once. Rectangles that overlap or touch are merged as they come in, which is what a run of
+// small draws along a line of text tends to be, and past `MAX_RECTS` the lot becomes its bounding
+// box: copying a few pixels too many is cheaper than keeping track of a hundred rectangles.
+
+// `(x0, y0, x1, y1)`, the end exclusive - as scale_rect takes them
+pub type Rect = (u32, u32, u32, u32);
+
+const MAX_RECTS: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct Damage {
+    rects: Vec<Rect>,
+}
+
+impl Damage {
+    pub fn add(&mut self, mut rect: Rect) {
+        if is_empty(&rect) {
+            return;
+        }
+        while let Some(index) = self.rects.iter().position(|other| touches(&rect, other)) {
+            rect = union(&rect, &self.rects.swap_remove(index));
+        }
+        self.rects.push(rect);
+        if self.rects.len() > MAX_RECTS {
+            let bounds = self.rects.iter().fold(rect, |bounds, other| union(&bounds, other));
+            self.rects = vec![bounds];
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn take(&mut self) -> Vec<Rect> {
+        std::mem::take(&mut self.rects)
+    }
+}
+
+pub fn is_empty(rect: &Rect) -> bool {
+    rect.0 >= rect.2 || rect.1 >= rect.3
+}
+
+pub fn area(rect: &Rect) -> usize {
+    (rect.2 - rect.0) as usize * (rect.3 - rect.1) as usize
+}
+
+fn touches(a: &Rect, b: &Rect) -> bool {
+    a.0 <= b.2 && b.0 <= a.2 && a.1 <= b.3 && b.1 <= a.3
+}
+
+fn union(a: &Rect, b: &Rect) -> Rect {
+    (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touching_rectangles_are_merged() {
+        let mut damage = Damage::default();
+        damage.add((0, 0, 10, 10));
+        damage.add((10, 0, 20, 10));
+        damage.add((50, 50, 60, 60));
+        damage.add((5, 5, 5, 9));
+        assert_eq!(damage.rects, [(0, 0, 20, 10), (50, 50, 60, 60)]);
+        // one that bridges the two takes them both in
+        damage.add((15, 5, 55, 55));
+        assert_eq!(damage.take(), [(0, 0, 60, 60)]);
+        assert!(damage.is_empty());
+    }
+
+    #[test]
+    fn too_many_rectangles_become_their_bounds() {
+        let mut damage = Damage::default();
+        for i in 0..=MAX_RECTS as u32 {
+            damage.add((i * 10, i * 10, i * 10 + 5, i * 10 + 5));
+        }
+        let last = MAX_RECTS as u32 * 10;
+        assert_eq!(damage.rects, [(0, 0, last + 5, last + 5)]);
+        assert_eq!(area(&damage.rects[0]), ((last + 5) * (last + 5)) as usize);
+    }
+}
@@ -0,0 +1,242 @@
+// The draw decoding pool behind XpraClient::start_draw_decode_loop: the UI thread hands it draws in
+// the order the server sent them, and gets them back as `draw-decoded` (or `decoding-failed`)
+// client-side packets, ready to paint and ack.
+//
+// Decoding is spread over a few worker threads, so that one large paint - a full window webp, a
+// video keyframe - no longer holds up the draws of every other window behind it. Not every draw
+// can go to whichever worker is free, though:
+//  * the video encodings need the stateful decoder of their window's stream (see video.rs), which
+//    lives on one worker only, picked by wid, so every draw and `Release` of a window goes there
+//  * mmap draws are read from a ring the server writes in order, and hand each part of it back as
+//    they go (MmapArea::release), so they all go to one worker, which takes them in that order
+//  * the rest - the picture encodings, rgb and scroll - go to the worker with the fewest draws
+//    queued
+// Whichever way a draw went, what comes out for a window comes out in the order its draws came in:
+// a `results` thread holds back each one until the ones queued before it for the same window are
+// out (see `collect`). Painting depends on that - a later draw must cover an earlier one, and a
+// scroll moves what was painted before it - and so do the server's damage acks. Draws of different
+// windows are independent, and are delivered as soon as they are ready.
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use log::{debug, info};
+use xpra::net::packet::Packet;
+
+use super::packets::Draw;
+use super::video::{VideoDecoder, VIDEO_ENCODINGS};
+
+// More workers than this only buys idle threads: there are rarely more windows than that updating
+// at once, and dav1d decodes on threads of its own.
+const MAX_WORKERS: usize = 4;
+
+// What the UI thread hands the decode pool, in order: the draws, and the end of a window's video
+// stream, which releases its video decoder.
+pub enum DecodeRequest {
+    Draw(Box<Draw>),
+    Release { wid: u64, reason: &'static str },
+}
+
+// Each worker's own video decoders, by wid
+pub type VideoDecoders = HashMap<u64, VideoDecoder>;
+
+enum Job {
+    Draw { ticket: u64, draw: Box<Draw> },
+    Release { wid: u64, reason: &'static str },
+}
+
+// What the `results` thread hears about, from the dispatcher and the workers in turn. `ticket`s
+// number the draws across all windows, in the order they came in.
+enum Outcome {
+    Queued { wid: u64, ticket: u64 },
+    Decoded { wid: u64, ticket: u64, packet: Packet },
+}
+
+struct Worker {
+    jobs: Sender<Job>,
+    // the draws sent to it that it has not finished decoding yet
+    queued: Arc<AtomicUsize>,
+}
+
+pub fn default_workers() -> usize {
+    thread::available_parallelism().map_or(1, |count| count.get()).min(MAX_WORKERS)
+}
+
+// Start `workers` decoding threads, with the dispatcher and results threads around them. `decode`
+// turns one draw into its packet - taking its data, if it needs to - with the video decoders of
+// the worker it runs on; `deliver` sends a packet on, and returns false once there is no one left
+// to send it to. Everything stops when the UI thread drops its end of `requests`, or when
+// `deliver` fails.
+pub fn start<D, S>(requests: Receiver<DecodeRequest>, workers: usize, decode: D, deliver: S)
+where
+    D: Fn(&mut Draw, &mut VideoDecoders) -> Packet + Clone + Send + 'static,
+    S: FnMut(Packet) -> bool + Send + 'static,
+{
+    let (outcomes, results) = channel::<Outcome>();
+    let workers: Vec<Worker> = (0..workers.max(1))
+        .map(|index| start_worker(index, decode.clone(), outcomes.clone()))
+        .collect();
+    info!("decoding with {} threads", workers.len());
+    thread::Builder::new().name("decode-results".to_string()).spawn(move || collect(results, deliver)).unwrap();
+    thread::Builder::new().name("decode".to_string()).spawn(move || dispatch(requests, &workers, outcomes)).unwrap();
+}
+
+fn start_worker<D>(index: usize, decode: D, outcomes: Sender<Outcome>) -> Worker
+where
+    D: Fn(&mut Draw, &mut VideoDecoders) -> Packet + Send + 'static,
+{
+    let (jobs, receiver) = channel::<Job>();
+    let queued = Arc::new(AtomicUsize::new(0));
+    let pending = queued.clone();
+    thread::Builder::new().name(format!("decode-{index}")).spawn(move || {
+        let mut video_decoders = VideoDecoders::new();
+        for job in receiver {
+            match job {
+                Job::Draw { ticket, mut draw } => {
+                    let packet = decode(&mut draw, &mut video_decoders);
+                    pending.fetch_sub(1, Ordering::Relaxed);
+                    if outcomes.send(Outcome::Decoded { wid: draw.wid, ticket, packet }).is_err() {
+                        break;
+                    }
+                }
+                // window teardown (lost-window) or video stream end (eos) forwarded from the UI
+                // thread: release this window's video decoder so a following stream restarts from
+                // a keyframe. Queued behind the window's last draws, which drain first.
+                Job::Release { wid, reason } => {
+                    if let Some(decoder) = video_decoders.remove(&wid) {
+                        debug!("released {} decoder for {:?} on window {:#x}", decoder.coding(), reason, wid);
+                    }
+                }
+            }
+        }
+    }).unwrap();
+    Worker { jobs, queued }
+}
+
+fn dispatch(requests: Receiver<DecodeRequest>, workers: &[Worker], outcomes: Sender<Outcome>) {
+    info!("decoding thread started");
+    let mut next_ticket = 0u64;
+    // the UI thread dropped its sender when this ends: the client is shutting down.
+    for request in requests {
+        let (index, job) = match request {
+            DecodeRequest::Draw(draw) => {
+                let index = route(workers, &draw);
+                let ticket = next_ticket;
+                next_ticket += 1;
+                // announced before the worker has it, so that its result can never arrive first
+                if outcomes.send(Outcome::Queued { wid: draw.wid, ticket }).is_err() {
+                    break;
+                }
+                workers[index].queued.fetch_add(1, Ordering::Relaxed);
+                (index, Job::Draw { ticket, draw })
+            }
+            DecodeRequest::Release { wid, reason } => (video_worker(workers, wid), Job::Release { wid, reason }),
+        };
+        if workers[index].jobs.send(job).is_err() {
+            break;
+        }
+    }
+    debug!("decoding thread stopping");
+}
+
+// The worker a draw goes to, see the top of this file
+fn route(workers: &[Worker], draw: &Draw) -> usize {
+    let coding = draw.coding.as_str();
+    if VIDEO_ENCODINGS.contains(&coding) {
+        video_worker(workers, draw.wid)
+    } else if coding == "mmap" {
+        0
+    } else {
+        (0..workers.len()).min_by_key(|&index| workers[index].queued.load(Ordering::Relaxed)).unwrap_or(0)
+    }
+}
+
+fn video_worker(workers: &[Worker], wid: u64) -> usize {
+    (wid % workers.len() as u64) as usize
+}
+
+// Deliver each window's packets in ticket order. Every window with draws in flight has the queue
+// of their tickets, oldest first, and a packet goes out once its own ticket is at the front -
+// along with those of the next ones that were ready and waiting for it.
+fn collect<S>(results: Receiver<Outcome>, mut deliver: S)
+where
+    S: FnMut(Packet) -> bool,
+{
+    let mut in_flight: HashMap<u64, VecDeque<u64>> = HashMap::new();
+    let mut ready: HashMap<u64, Packet> = HashMap::new();
+    for outcome in results {
+        match outcome {
+            Outcome::Queued { wid, ticket } => in_flight.entry(wid).or_default().push_back(ticket),
+            Outcome::Decoded { wid, ticket, packet } => {
+                ready.insert(ticket, packet);
+                let Some(tickets) = in_flight.get_mut(&wid) else {
+                    continue;
+                };
+                while let Some(packet) = tickets.front().and_then(|ticket| ready.remove(ticket)) {
+                    tickets.pop_front();
+                    if !deliver(packet) {
+                        return;
+                    }
+                }
+                if tickets.is_empty() {
+                    in_flight.remove(&wid);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use yaml_rust2::Yaml;
+
+    fn draw(wid: u64, seq: u64, coding: &str) -> Box<Draw> {
+        Box::new(Draw::for_tests(&format!("{wid}, 0, 0, 1, 1, {coding}, ~, {seq}, 4, {{}}"), vec![0; 4]))
+    }
+
+    #[test]
+    fn each_window_gets_its_draws_back_in_order() {
+        let (requests, receiver) = channel();
+        let (delivered, packets) = channel();
+        // the earlier draws of a window take the longest, so they finish last
+        let decode = |draw: &mut Draw, _: &mut VideoDecoders| {
+            thread::sleep(Duration::from_millis(5 * (8 - draw.seq % 8)));
+            let main = vec![Yaml::Integer(draw.wid as i64), Yaml::Integer(draw.seq as i64)];
+            Packet { main, raw: HashMap::new(), decode_time_us: None }
+        };
+        start(receiver, 4, decode, move |packet| delivered.send(packet).is_ok());
+        for seq in 0..24 {
+            let coding = if seq % 3 == 0 { "png" } else { "jpeg" };
+            requests.send(DecodeRequest::Draw(draw(1 + seq % 2, seq, coding))).unwrap();
+        }
+        let mut seen: HashMap<i64, Vec<i64>> = HashMap::new();
+        for _ in 0..24 {
+            let packet = packets.recv_timeout(Duration::from_secs(10)).unwrap();
+            let [Yaml::Integer(wid), Yaml::Integer(seq)] = packet.main[..] else {
+                unreachable!()
+            };
+            seen.entry(wid).or_default().push(seq);
+        }
+        assert_eq!(seen[&1], (0..24).filter(|seq| seq % 2 == 0).collect::<Vec<_>>());
+        assert_eq!(seen[&2], (0..24).filter(|seq| seq % 2 == 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn video_and_mmap_draws_stay_on_one_worker() {
+        let workers: Vec<Worker> = (0..3).map(|_| Worker {
+            jobs: channel().0,
+            queued: Arc::new(AtomicUsize::new(0)),
+        }).collect();
+        workers[0].queued.store(5, Ordering::Relaxed);
+        workers[2].queued.store(1, Ordering::Relaxed);
+        assert_eq!(route(&workers, &draw(7, 1, "h264")), 1);
+        assert_eq!(route(&workers, &draw(7, 2, "vp9")), 1);
+        assert_eq!(route(&workers, &draw(8, 3, "av1")), 2);
+        assert_eq!(route(&workers, &draw(7, 4, "mmap")), 0);
+        assert_eq!(route(&workers, &draw(7, 5, "webp")), 1);
+    }
+}
@@ -0,0 +1,174 @@
+// Desktop and shadow sessions. A server that runs a whole desktop (`xpra desktop`) or mirrors one
+// that is already there (`xpra shadow`) sends a single window, its root, instead of the windows of
+// the applications on it, and says so in its hello: `desktop` or `shadow`, which xpra's own client
+// reads to show that window the way a remote desktop viewer would. So do we: a normal, decorated and
+// resizable window - fullscreen with `--desktop-fullscreen` - whatever the root's metadata says,
+// which is sized to fit on the local monitor rather than placed where the root is on the server's
+// screen.
+//
+// When the user resizes it, a server that can change the size of its screen (`resize_screen`: a
+// desktop server with RandR, rarely a shadow one - the screen it mirrors is a real one) is asked to
+// take the new size with `desktop_size`, once the window has stopped changing size for a moment,
+// and answers with the root's new size. Until it has - and for good when it cannot resize, or picks
+// the closest size it has instead - the root is scaled to fit the window, the same way
+// `--desktop-scaling` scales windows (see XpraWindow::rescale). A window whose root cannot be
+// resized keeps the root's aspect ratio, so that the picture is not stretched, unless it is
+// maximized or fullscreen. Pointer positions are mapped back to the root's pixels.
+//
+// The root stands for the whole remote desktop, so while it has the focus the keyboard shortcuts
+// of the local one - Alt+Tab, the window manager's - belong to it too: on X11, the keyboard is
+// grabbed from the local window manager for as long as the window is focused. winit has no such
+// thing, and Wayland only allows it through a protocol (keyboard-shortcuts-inhibit) winit does not
+// speak. On Windows the system shortcuts only reach a window through a low-level keyboard hook,
+// and on macOS through the accessibility APIs, neither of which this client installs.
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use log::debug;
+use winit::window::Window;
+use yaml_rust2::Yaml;
+
+use xpra::net::packet::{yaml_hash, yaml_hash_bool};
+
+// how long the window has to keep its size before the server is asked to resize its screen: every
+// step of an interactive resize would otherwise be one, and a RandR mode change is not cheap
+pub const DESKTOP_SIZE_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DesktopSession {
+    pub shadow: bool,
+    // whether `desktop_size` changes the size of the server's screen
+    pub resize_screen: bool,
+}
+
+impl DesktopSession {
+    // `None` for a seamless session. `resize_screen` is a capability of the server's display, which
+    // older servers send at the top level of their hello and newer ones in its `display` dict; a
+    // server that sends neither is assumed to resize a desktop and not a shadow.
+    pub fn from_hello(hello: &Yaml) -> Option<Self> {
+        let flag = |key: &str| yaml_hash_bool(hello, key.to_string()).unwrap_or(false);
+        let shadow = flag("shadow");
+        if !shadow && !flag("desktop") {
+            return None;
+        }
+        let resize_screen = ["resize_screen", "resize-screen"].iter().find_map(|&key| {
+            yaml_hash_bool(hello, key.to_string())
+                .or_else(|| yaml_hash(hello, "display").and_then(|display| yaml_hash_bool(display, key.to_string())))
+        }).unwrap_or(!shadow);
+        Some(DesktopSession { shadow, resize_screen })
+    }
+
+    pub fn name(self) -> &'static str {
+        if self.shadow { "shadow" } else { "desktop" }
+    }
+}
+
+// The size to show a `root` at: its own, unless that does not fit on the monitor,
+// in which case the largest that does with the same aspect ratio.
+pub fn window_size(root: (u32, u32), monitor: Option<(u32, u32)>) -> (u32, u32) {
+    let (w, h) = (root.0.max(1), root.1.max(1));
+    let Some((mw, mh)) = monitor.filter(|&(mw, mh)| mw > 0 && mh > 0) else {
+        return (w, h);
+    };
+    if w <= mw && h <= mh {
+        return (w, h);
+    }
+    // whichever side fits more tightly decides
+    if w as u64 * mh as u64 > h as u64 * mw as u64 {
+        (mw, ((h as u64 * mw as u64 / w as u64) as u32).max(1))
+    } else {
+        (((w as u64 * mh as u64 / h as u64) as u32).max(1), mh)
+    }
+}
+
+// A position within a window of `window` size, on the `root` it shows scaled to fit
+pub fn to_root(x: f64, y: f64, window: (u32, u32), root: (u32, u32)) -> (i32, i32) {
+    let map = |value: f64, window: u32, root: u32| {
+        ((value * root as f64 / window.max(1) as f64) as i32).clamp(0, root.saturating_sub(1) as i32)
+    };
+    (map(x, window.0, root.0), map(y, window.1, root.1))
+}
+
+// Grab the keyboard for `window`, or let go of it (see above). Only X11 has a way: elsewhere this
+// does nothing.
+pub fn grab_keyboard(window: &Window, grab: bool) {
+    #[cfg(target_os = "linux")]
+    if let Err(e) = grab_x11_keyboard(window, grab) {
+        debug!("cannot {} the keyboard: {e}", if grab { "grab" } else { "ungrab" });
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (window, grab);
+}
+
+// The grab has to come from the connection winit reads its key events from: one of our own (see
+// window_properties::with_x11) would get them all instead. That is an Xlib display, which x11rb
+// cannot drive, so the grab goes through Xlib itself - the copy of libX11 winit has already loaded.
+#[cfg(target_os = "linux")]
+fn grab_x11_keyboard(window: &Window, grab: bool) -> Result<(), String> {
+    use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle};
+    use x11_dl::xlib::{self, Xlib};
+    thread_local! {
+        // loaded on the UI thread, the only one that has windows to grab for
+        static XLIB: Option<Xlib> = Xlib::open().map_err(|e| debug!("cannot load Xlib: {e}")).ok();
+    }
+    let display = window.display_handle().map(|handle| handle.as_raw());
+    let handle = window.window_handle().map(|handle| handle.as_raw());
+    let (Ok(RawDisplayHandle::Xlib(display)), Ok(RawWindowHandle::Xlib(handle))) = (display, handle) else {
+        // Wayland
+        return Ok(());
+    };
+    let display = display.display.ok_or("winit has no Xlib display")?.as_ptr() as *mut xlib::Display;
+    XLIB.with(|xlib| {
+        let xlib = xlib.as_ref().ok_or("Xlib is not available")?;
+        // SAFETY: the display is winit's, open for as long as the window is, and used from the
+        // thread winit uses it from
+        unsafe {
+            if grab {
+                // owner_events: key events for our own windows still go to them, as without a grab
+                let status = (xlib.XGrabKeyboard)(display, handle.window, xlib::True, xlib::GrabModeAsync,
+                                                  xlib::GrabModeAsync, xlib::CurrentTime);
+                if status != xlib::GrabSuccess {
+                    return Err(format!("the grab failed with status {status}"));
+                }
+            } else {
+                (xlib.XUngrabKeyboard)(display, xlib::CurrentTime);
+            }
+            (xlib.XFlush)(display);
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust2::YamlLoader;
+
+    fn hello(yaml: &str) -> Option<DesktopSession> {
+        DesktopSession::from_hello(&YamlLoader::load_from_str(yaml).unwrap()[0])
+    }
+
+    #[test]
+    fn sessions_are_told_apart_by_the_hello() {
+        assert_eq!(hello("{version: '6.0'}"), None);
+        assert_eq!(hello("{desktop: false}"), None);
+        assert_eq!(hello("{desktop: true}"), Some(DesktopSession { shadow: false, resize_screen: true }));
+        assert_eq!(hello("{shadow: true}"), Some(DesktopSession { shadow: true, resize_screen: false }));
+        // what the server says about its screen wins over the defaults, wherever it says it
+        assert_eq!(hello("{desktop: true, resize_screen: false}").map(|s| s.resize_screen), Some(false));
+        assert_eq!(hello("{shadow: true, display: {resize-screen: true}}").map(|s| s.resize_screen), Some(true));
+    }
+
+    #[test]
+    fn roots_are_shown_whole() {
+        // at their own size when they fit, and shrunk to the monitor keeping their shape otherwise
+        assert_eq!(window_size((1280, 1024), Some((1920, 1080))), (1280, 1024));
+        assert_eq!(window_size((3840, 2160), Some((1920, 1200))), (1920, 1080));
+        assert_eq!(window_size((1024, 2048), Some((1920, 1080))), (540, 1080));
+        assert_eq!(window_size((3840, 2160), None), (3840, 2160));
+        // and the pointer goes where it points on them, never past their edges
+        assert_eq!(to_root(960.0, 540.0, (1920, 1080), (3840, 2160)), (1920, 1080));
+        assert_eq!(to_root(1919.9, -3.0, (1920, 1080), (3840, 2160)), (3839, 0));
+        assert_eq!(to_root(10.0, 10.0, (100, 100), (100, 100)), (10, 10));
+    }
+}
@@ -1,5 +1,22 @@
+use std::borrow::Cow;
+
 use log::{debug, trace};
 
+use xpra::net::packet::{yaml_hash_i32, yaml_hash_str};
+use super::packets::Draw;
+use super::pixels::{self, Alpha, Order};
+
+// The raw pixel layouts we accept, as advertised in our hello's `encoding.rgb_formats`: the server
+// converts anything else to one of these before sending an rgb24/rgb32 draw or writing into the
+// mmap area (rgb_reformat, xpra server/window/compress.py), and it prefers them in this order. The
+// ones with an alpha channel are only worth advertising on a display that can show it (see
+// `has_alpha` and window::has_transparency).
+pub const RGB_FORMATS: [&str; 5] = ["BGRX", "BGRA", "RGBX", "RGBA", "RGB"];
+
+pub fn has_alpha(rgb_format: &str) -> bool {
+    rgb_format.ends_with('A')
+}
+
 pub fn decode(coding: &String, data: Vec<u8>) -> Result<Vec<u8>, String> {
     debug!("decode {:?}: {:?} bytes", coding, data.len());
     trace!("data={:?}", data);
@@ -63,6 +80,96 @@ pub fn decode(coding: &String, data: Vec<u8>) -> Result<Vec<u8>, String> {
     Err(format!("unsupported encoding {coding}"))
 }
 
+// An rgb24 or rgb32 draw: raw pixels in one of RGB_FORMATS, each row `rowstride` bytes apart, and
+// lz4 or zlib compressed as a whole when the options say so. Far cheaper for us than a png of the
+// same pixels, which is why servers use it for small and for lossless regions.
+pub fn decode_rgb(draw: &Draw) -> Result<Vec<u8>, String> {
+    let rgb_format = match yaml_hash_str(&draw.options, "rgb_format".to_string()) {
+        // what the server means when it does not say (it always does, in practice)
+        format if format.is_empty() => if draw.coding == "rgb24" { "RGB".to_string() } else { "BGRX".to_string() },
+        format => format,
+    };
+    let bpp = bytes_per_pixel(&rgb_format)?;
+    let (w, h) = (draw.w as usize, draw.h as usize);
+    if w == 0 || h == 0 {
+        return Err(format!("empty {} draw packet: {}x{}", draw.coding, w, h));
+    }
+    let width_bytes = w.checked_mul(bpp).ok_or("rgb draw packet width overflows")?;
+    let stride = if draw.rowstride == 0 { width_bytes } else { draw.rowstride as usize };
+    if stride < width_bytes {
+        return Err(format!("rowstride {} is smaller than {}x{} bytes", stride, w, bpp));
+    }
+    let size = stride.checked_mul(h).ok_or("rgb draw packet size overflows")?;
+    let pixels = decompress_rgb(draw, size)?;
+    // the last row only needs `width_bytes`, not a whole stride:
+    let required = (h - 1) * stride + width_bytes;
+    if pixels.len() < required {
+        return Err(format!("{} bytes of {} pixel data for a {}x{} image with a stride of {} ({} needed)",
+                           pixels.len(), rgb_format, w, h, stride, required));
+    }
+    Ok(to_bgrx(&pixels, &rgb_format, w, h, stride))
+}
+
+pub fn bytes_per_pixel(rgb_format: &str) -> Result<usize, String> {
+    match rgb_format {
+        "BGRX" | "BGRA" | "RGBX" | "RGBA" => Ok(4),
+        "RGB" => Ok(3),
+        other => Err(format!("unsupported pixel format {:?}", other)),
+    }
+}
+
+// The pixels of an rgb draw, decompressed if the options name a compressor (with its level, as in
+// xpra's `compressed_wrapper`). Neither may expand past the `size` of the image.
+fn decompress_rgb(draw: &Draw, size: usize) -> Result<Cow<'_, [u8]>, String> {
+    let data = draw.data.as_slice();
+    if yaml_hash_i32(&draw.options, "lz4".to_string()) > 0 {
+        // the 4-byte little-endian size prefix and raw block of xpra's lz4 framing, as for packets
+        // (see net/io.rs); lz4_flex allocates whatever the prefix says, so check it first
+        let Some(prefix) = data.first_chunk::<4>() else {
+            return Err("lz4 pixel data without a size prefix".to_string());
+        };
+        let decompressed = u32::from_le_bytes(*prefix) as usize;
+        if decompressed > size {
+            return Err(format!("lz4 pixel data expands to {} bytes, more than the {} of the image",
+                               decompressed, size));
+        }
+        return lz4_flex::block::decompress_size_prepended(data)
+            .map(Cow::Owned)
+            .map_err(|e| format!("lz4 decompression of the pixel data failed: {e}"));
+    }
+    if yaml_hash_i32(&draw.options, "zlib".to_string()) > 0 {
+        return miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, size)
+            .map(Cow::Owned)
+            .map_err(|e| format!("zlib decompression of the pixel data failed: {e}"));
+    }
+    Ok(Cow::Borrowed(data))
+}
+
+// Tightly packed BGRA from `h` rows of `w` pixels in `rgb_format`, `stride` bytes apart: the alpha
+// of the formats that have one, opaque for the others - whatever the server left in their padding
+// byte. The caller has checked that `pixels` holds them all.
+pub fn to_bgrx(pixels: &[u8], rgb_format: &str, w: usize, h: usize, stride: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(w * h * 4);
+    for row in pixels.chunks(stride).take(h) {
+        if rgb_format == "RGB" {
+            for px in row[..w * 3].chunks_exact(3) {
+                out.extend_from_slice(&[px[2], px[1], px[0], 0xFF]);
+            }
+        } else {
+            out.extend_from_slice(&row[..w * 4]);
+        }
+    }
+    let (order, alpha) = match rgb_format {
+        "BGRX" => (Order::Bgra, Alpha::Opaque),
+        "RGBA" => (Order::Rgba, Alpha::Keep),
+        "RGBX" => (Order::Rgba, Alpha::Opaque),
+        // BGRA, and RGB which is already converted
+        _ => return out,
+    };
+    pixels::to_bgra_in_place(&mut out, order, alpha);
+    out
+}
+
 // Decode a png into (width, height, RGBA8) with proper error handling - used for window icons,
 // which arrive on the UI thread (unlike draws, which run on the decode thread). Every fallible
 // spng call is turned into an error so malformed image data cannot abort the process.
@@ -85,7 +192,52 @@ pub fn decode_png_rgba(data: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
 
 #[cfg(test)]
 mod tests {
-    use super::decode;
+    use super::{decode, decode_rgb};
+    use crate::client::packets::Draw;
+
+    // a draw of window 1, sequence 1, at (0, 0)
+    fn draw(w: u32, h: u32, coding: &str, data: Vec<u8>, rowstride: usize, options: &str) -> Draw {
+        Draw::for_tests(&format!("1, 0, 0, {w}, {h}, {coding}, ~, 1, {rowstride}, {options}"), data)
+    }
+
+    #[test]
+    fn rgb_draws_are_destrided_and_converted_to_bgrx() {
+        // 2x2 pixels in rows 8 bytes apart, each row padded with junk
+        let rgb = vec![1, 2, 3, 4, 5, 6, 99, 99, 7, 8, 9, 10, 11, 12];
+        let expected = vec![3, 2, 1, 0xFF, 6, 5, 4, 0xFF, 9, 8, 7, 0xFF, 12, 11, 10, 0xFF];
+        assert_eq!(decode_rgb(&draw(2, 2, "rgb24", rgb.clone(), 8, "{}")).unwrap(), expected);
+        assert_eq!(decode_rgb(&draw(2, 2, "rgb24", rgb.clone(), 8, "{rgb_format: RGB}")).unwrap(), expected);
+        let rgba: Vec<u8> = (1..=16).collect();
+        let bgra = vec![3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 16];
+        assert_eq!(decode_rgb(&draw(2, 2, "rgb32", rgba.clone(), 0, "{rgb_format: RGBA}")).unwrap(), bgra);
+        assert_eq!(decode_rgb(&draw(2, 2, "rgb32", rgba.clone(), 8, "{rgb_format: BGRA}")).unwrap(), rgba);
+        // without alpha, the padding byte comes out opaque whatever the server left in it
+        let bgrx = vec![1, 2, 3, 0xFF, 5, 6, 7, 0xFF, 9, 10, 11, 0xFF, 13, 14, 15, 0xFF];
+        assert_eq!(decode_rgb(&draw(2, 2, "rgb32", rgba.clone(), 8, "{rgb_format: BGRX}")).unwrap(), bgrx);
+        assert_eq!(decode_rgb(&draw(2, 2, "rgb32", rgba.clone(), 8, "{rgb_format: RGBX}")).unwrap(),
+                   [3, 2, 1, 0xFF, 7, 6, 5, 0xFF, 11, 10, 9, 0xFF, 15, 14, 13, 0xFF]);
+        // a stride narrower than a row, pixel data short of the last row, and an unknown layout
+        for (data, rowstride, options) in [(rgb.clone(), 5, "{}"), (rgb[..13].to_vec(), 8, "{}"),
+                                           (rgb.clone(), 8, "{rgb_format: XRGB}")] {
+            assert!(decode_rgb(&draw(2, 2, "rgb24", data, rowstride, options)).is_err(), "{options}");
+        }
+    }
+
+    #[test]
+    fn rgb_draws_are_decompressed() {
+        let bgra: Vec<u8> = (0..64).collect();
+        let lz4 = lz4_flex::block::compress_prepend_size(&bgra);
+        assert_eq!(decode_rgb(&draw(4, 4, "rgb32", lz4, 0, "{rgb_format: BGRA, lz4: 1}")).unwrap(), bgra);
+        let zlib = miniz_oxide::deflate::compress_to_vec_zlib(&bgra, 6);
+        assert_eq!(decode_rgb(&draw(4, 4, "rgb32", zlib, 0, "{rgb_format: BGRA, zlib: 6}")).unwrap(), bgra);
+        // neither may expand past the size of the image
+        let bomb = vec![0u8; 1024 * 1024];
+        for (data, options) in [(lz4_flex::block::compress_prepend_size(&bomb), "{lz4: 1}"),
+                                (miniz_oxide::deflate::compress_to_vec_zlib(&bomb, 6), "{zlib: 6}")] {
+            let error = decode_rgb(&draw(4, 4, "rgb32", data, 0, options)).unwrap_err();
+            assert!(error.contains("pixel data"), "unexpected error: {error}");
+        }
+    }
 
     #[test]
     fn malformed_jpeg_returns_an_error() {
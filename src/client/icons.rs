@@ -0,0 +1,251 @@
+// Window icons: the ones the server sends us in `window-icon` packets (png, see
+// XpraClient::process_window_icon), and the xpra icon every window starts with until its own
+// arrives - the same one the Windows executable carries (assets/xpra.ico, see build.rs).
+//
+// Icons are kept by wid for as long as the session lasts, lost windows included: the server does
+// not send an icon again just because a window came back, and windows do come back under the same
+// wid - popups re-created after a reconnect (see XpraClient::start_reconnect), or a window the
+// application unmapped and mapped again.
+use std::collections::{HashMap, VecDeque};
+
+use log::{debug, error};
+use winit::window::{Icon, Window};
+
+use super::draw_decoder;
+
+// What is kept of an icon, at most: the largest a taskbar or a window switcher shows them.
+// Larger ones are scaled down to this as they come in.
+const MAX_SIZE: u32 = 128;
+
+// Past this many windows' icons, the oldest make way
+const MAX_CACHED: usize = 64;
+
+const DEFAULT_ICON: &[u8] = include_bytes!("../../assets/xpra.ico");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IconImage {
+    pub w: u32,
+    pub h: u32,
+    // non-premultiplied, as winit's Icon takes it
+    pub rgba: Vec<u8>,
+}
+
+pub struct WindowIcons {
+    icons: HashMap<u64, IconImage>,
+    // the wids in `icons`, oldest first
+    order: VecDeque<u64>,
+    default: Option<IconImage>,
+}
+
+impl WindowIcons {
+    pub fn new() -> Self {
+        let default = from_ico(DEFAULT_ICON)
+            .map_err(|e| error!("cannot load the default window icon: {e}"))
+            .ok();
+        WindowIcons { icons: HashMap::new(), order: VecDeque::new(), default }
+    }
+
+    // The window's own icon from now on
+    pub fn set(&mut self, wid: u64, image: IconImage) {
+        if self.icons.insert(wid, fit(&image, MAX_SIZE)).is_none() {
+            self.order.push_back(wid);
+        }
+        while self.order.len() > MAX_CACHED {
+            if let Some(oldest) = self.order.pop_front() {
+                self.icons.remove(&oldest);
+            }
+        }
+    }
+
+    // Back to the default icon
+    pub fn reset(&mut self, wid: u64) {
+        if self.icons.remove(&wid).is_some() {
+            self.order.retain(|&other| other != wid);
+        }
+    }
+
+    // The icon to show for a window
+    pub fn get(&self, wid: u64) -> Option<&IconImage> {
+        self.icons.get(&wid).or(self.default.as_ref())
+    }
+}
+
+// Give a window its icon, at the sizes the platform shows it: Windows takes one for the titlebar
+// (SM_CXSMICON, 16 pixels at 100%) and another for the taskbar and alt-tab (SM_CXICON, 32), and
+// stretches whatever it gets to those - winit's `set_window_icon` only sets the first, which left the
+// taskbar showing the client's own icon for every window.
+#[cfg(windows)]
+pub fn apply(window: &Window, image: &IconImage) {
+    use winit::platform::windows::WindowExtWindows;
+    let size = |points: f64| (points * window.scale_factor()).round() as u32;
+    window.set_window_icon(to_icon(image, size(16.0)));
+    window.set_taskbar_icon(to_icon(image, size(32.0)));
+}
+
+// X11's _NET_WM_ICON holds the one image, which the window manager and the taskbar scale themselves.
+// winit has no window icons on Wayland or macOS, and ignores it there.
+#[cfg(not(windows))]
+pub fn apply(window: &Window, image: &IconImage) {
+    window.set_window_icon(to_icon(image, MAX_SIZE));
+}
+
+fn to_icon(image: &IconImage, size: u32) -> Option<Icon> {
+    let IconImage { w, h, rgba } = fit(image, size);
+    Icon::from_rgba(rgba, w, h)
+        .map_err(|e| debug!("invalid window icon {w}x{h}: {e:?}"))
+        .ok()
+}
+
+// `image`, scaled down to fit in `max` x `max` pixels if it does not, keeping its aspect ratio. Each
+// pixel is the average of those it covers, weighted by their alpha: the colour of a fully
+// transparent pixel means nothing, and must not darken the edges of the shape.
+pub fn fit(image: &IconImage, max: u32) -> IconImage {
+    let (w, h) = (image.w, image.h);
+    if w <= max && h <= max {
+        return image.clone();
+    }
+    let longest = w.max(h) as u64;
+    let (fw, fh) = (((w as u64 * max as u64) / longest).max(1) as u32, ((h as u64 * max as u64) / longest).max(1) as u32);
+    // the source pixels `i` of `len` covers, out of `src_len`
+    let span = |i: u32, len: u32, src_len: u32| {
+        let start = (i as u64 * src_len as u64 / len as u64) as u32;
+        (start, ((((i + 1) as u64 * src_len as u64) / len as u64) as u32).max(start + 1))
+    };
+    let mut rgba = Vec::with_capacity((fw * fh * 4) as usize);
+    for y in 0..fh {
+        let (y0, y1) = span(y, fh, h);
+        for x in 0..fw {
+            let (x0, x1) = span(x, fw, w);
+            let mut sum = [0u64; 4];
+            for row in y0..y1 {
+                let start = ((row * w + x0) * 4) as usize;
+                for px in image.rgba[start..start + ((x1 - x0) * 4) as usize].chunks_exact(4) {
+                    let a = px[3] as u64;
+                    sum[0] += px[0] as u64 * a;
+                    sum[1] += px[1] as u64 * a;
+                    sum[2] += px[2] as u64 * a;
+                    sum[3] += a;
+                }
+            }
+            let count = ((x1 - x0) * (y1 - y0)) as u64;
+            let colour = |c: u64| (c + sum[3] / 2).checked_div(sum[3]).unwrap_or(0) as u8;
+            rgba.extend([colour(sum[0]), colour(sum[1]), colour(sum[2]), ((sum[3] + count / 2) / count) as u8]);
+        }
+    }
+    IconImage { w: fw, h: fh, rgba }
+}
+
+// The largest image of a Windows .ico: either a png, or a 32 bits per pixel bitmap - bottom-up BGRA
+// rows after a BITMAPINFOHEADER, followed by the 1 bit mask older Windows needed, which the alpha
+// channel makes redundant.
+fn from_ico(data: &[u8]) -> Result<IconImage, String> {
+    let u16_at = |offset: usize| data.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |offset: usize| data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let count = match (u16_at(0), u16_at(2), u16_at(4)) {
+        (Some(0), Some(1), Some(count)) => count as usize,
+        _ => return Err("not an icon file".to_string()),
+    };
+    // ICONDIRENTRYs: width, height (0 for 256), colours, reserved, planes, bits per pixel, size, offset
+    let largest = (0..count)
+        .map(|index| 6 + index * 16)
+        .filter(|&entry| u16_at(entry + 6) == Some(32))
+        .max_by_key(|&entry| data.get(entry).map_or(0, |&w| if w == 0 { 256 } else { w as u32 }))
+        .ok_or("no 32 bit image in the icon file")?;
+    let (size, offset) = match (u32_at(largest + 8), u32_at(largest + 12)) {
+        (Some(size), Some(offset)) => (size as usize, offset as usize),
+        _ => return Err("truncated icon directory".to_string()),
+    };
+    let image = data.get(offset..offset.saturating_add(size)).ok_or("truncated icon image")?;
+    if image.starts_with(b"\x89PNG") {
+        let (w, h, rgba) = draw_decoder::decode_png_rgba(image)?;
+        return Ok(IconImage { w, h, rgba });
+    }
+    let header = |offset: usize| image.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let (Some(header_size), Some(w), Some(double_h)) = (header(0), header(4), header(8)) else {
+        return Err("truncated bitmap header".to_string());
+    };
+    // the height counts the mask too
+    let (w, h) = (w as usize, double_h as usize / 2);
+    if w == 0 || h == 0 || w > 256 || h > 256 {
+        return Err(format!("invalid icon size {w}x{h}"));
+    }
+    let pixels = image.get(header_size as usize..)
+        .and_then(|pixels| pixels.get(..w * h * 4))
+        .ok_or("truncated bitmap")?;
+    let rgba = pixels.chunks_exact(w * 4).rev()
+        .flat_map(|row| row.chunks_exact(4).flat_map(|px| [px[2], px[1], px[0], px[3]]))
+        .collect();
+    Ok(IconImage { w: w as u32, h: h as u32, rgba })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(w: u32, h: u32, px: [u8; 4]) -> IconImage {
+        IconImage { w, h, rgba: px.repeat((w * h) as usize) }
+    }
+
+    #[test]
+    fn the_default_icon_is_the_largest_in_the_ico() {
+        let icon = from_ico(DEFAULT_ICON).unwrap();
+        assert_eq!((icon.w, icon.h, icon.rgba.len()), (64, 64, 64 * 64 * 4));
+        // a round logo: transparent in the corners, opaque in the middle
+        assert_eq!(icon.rgba[3], 0);
+        assert_eq!(icon.rgba[(32 * 64 + 32) * 4 + 3], 0xFF);
+        assert!(from_ico(b"\x89PNG\r\n").is_err());
+        assert!(from_ico(&DEFAULT_ICON[..100]).is_err());
+    }
+
+    #[test]
+    fn bitmaps_are_flipped_into_rgba() {
+        // a 2x2 icon: its bottom row first, in BGRA, then a mask
+        let mut ico = vec![0, 0, 1, 0, 1, 0, 2, 2, 0, 0, 1, 0, 32, 0];
+        ico.extend(64u32.to_le_bytes());
+        ico.extend(22u32.to_le_bytes());
+        let mut header = [0u8; 40];
+        header[0..4].copy_from_slice(&40u32.to_le_bytes());
+        header[4..8].copy_from_slice(&2u32.to_le_bytes());
+        header[8..12].copy_from_slice(&4u32.to_le_bytes());
+        ico.extend(header);
+        ico.extend([[3, 2, 1, 255], [6, 5, 4, 128], [9, 8, 7, 64], [12, 11, 10, 0]].concat());
+        ico.extend([0u8; 8]);
+        let icon = from_ico(&ico).unwrap();
+        assert_eq!((icon.w, icon.h), (2, 2));
+        assert_eq!(icon.rgba, [[7, 8, 9, 64], [10, 11, 12, 0], [1, 2, 3, 255], [4, 5, 6, 128]].concat());
+    }
+
+    #[test]
+    fn large_icons_are_scaled_to_fit() {
+        let small = solid(48, 48, [1, 2, 3, 4]);
+        assert_eq!(fit(&small, 64), small);
+        // the aspect ratio is kept
+        let wide = fit(&solid(256, 64, [10, 20, 30, 255]), 32);
+        assert_eq!((wide.w, wide.h), (32, 8));
+        assert_eq!(wide.rgba, solid(32, 8, [10, 20, 30, 255]).rgba);
+        let thin = fit(&solid(1000, 3, [0; 4]), 16);
+        assert_eq!((thin.w, thin.h), (16, 1));
+        // a red square on a transparent black background: its edge stays red, only fainter
+        let mut image = solid(4, 4, [0, 0, 0, 0]);
+        image.rgba[..4].copy_from_slice(&[255, 0, 0, 255]);
+        assert_eq!(fit(&image, 2).rgba[..4], [255, 0, 0, 64]);
+    }
+
+    #[test]
+    fn icons_are_cached_by_wid() {
+        let mut icons = WindowIcons::new();
+        let default = icons.get(1).cloned().unwrap();
+        icons.set(1, solid(256, 256, [1, 2, 3, 255]));
+        assert_eq!(icons.get(1), Some(&solid(MAX_SIZE, MAX_SIZE, [1, 2, 3, 255])));
+        assert_eq!(icons.get(2), Some(&default));
+        icons.reset(1);
+        assert_eq!(icons.get(1), Some(&default));
+        // the oldest make way
+        for wid in 0..=MAX_CACHED as u64 {
+            icons.set(wid, solid(1, 1, [wid as u8, 0, 0, 255]));
+        }
+        assert_eq!(icons.get(0), Some(&default));
+        assert_eq!(icons.get(1), Some(&solid(1, 1, [1, 0, 0, 255])));
+        assert_eq!(icons.icons.len(), MAX_CACHED);
+    }
+}
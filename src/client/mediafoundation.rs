@@ -9,7 +9,8 @@
 // GPU-accelerated.
 //
 // One `H264Decoder` is stateful and lives per xpra window (H.264 is inter-frame predicted), on the
-// decode thread only -- these COM objects never cross threads, so nothing here needs to be `Send`.
+// window's decode worker only -- these COM objects never cross threads, so nothing here needs to be
+// `Send`.
 //
 // NOTE: this compiles and the pipeline is wired per the MF docs, but it has NOT been verified
 // against a live xpra server yet. The most likely things to need tuning against real frames are
@@ -47,8 +48,8 @@ const MF_LOW_LATENCY: GUID = GUID::from_u128(0x9c27891a_ed7a_40e1_88e8_b22727a02
 static MF_STARTUP: Once = Once::new();
 
 fn ensure_mf_started() {
-    // COM must be initialised on this (the decode) thread; MFStartup is process-wide. Neither is
-    // ever torn down -- the decode thread lives for the whole process.
+    // COM must be initialised on this (a decode worker's) thread; MFStartup is process-wide.
+    // Neither is ever torn down -- the decode workers live for the whole process.
     unsafe {
         let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
     }
@@ -2,8 +2,9 @@
 //
 // When the server runs on the same host as this client, sending pixels down the socket - encoded
 // with jpeg/webp/h264 and decoded again here - is pure waste. Instead we create a file, map it
-// shared, and hand its path to the server in our `hello`; the server then writes raw BGRX frames
-// straight into it and its `draw` packets carry only (offset, length) pairs into that area.
+// shared, and hand its path to the server in our `hello`; the server then writes raw frames (BGRX,
+// when it has a choice - see draw_decoder::RGB_FORMATS) straight into it and its `draw` packets
+// carry only (offset, length) pairs into that area.
 // See xpra's own `net/mmap/{common,io,objects}.py`, `client/subsystem/mmap.py` and
 // `server/source/mmap.py`, and `docs/Subsystems/MMAP.md` upstream.
 //
@@ -269,11 +270,11 @@ impl MmapArea {
         }
     }
 
-    // Copy one image out of the area, de-striding it into a tightly packed w*h*4 buffer - the same
-    // shape turbojpeg, spng and libwebp hand back, so the rest of the draw path is unchanged.
-    pub fn read_image(&self, chunks: &[(usize, usize)], w: usize, h: usize, rowstride: usize)
+    // Copy one image of `bpp` bytes per pixel out of the area, de-striding it into a tightly packed
+    // w*h*bpp buffer - for BGRX, the same shape turbojpeg, spng and libwebp hand back.
+    pub fn read_image(&self, chunks: &[(usize, usize)], w: usize, h: usize, rowstride: usize, bpp: usize)
             -> Result<Vec<u8>, String> {
-        destride(self.bytes(), chunks, w, h, rowstride)
+        destride(self.bytes(), chunks, w, h, rowstride, bpp)
     }
 
     // Move `data_start` past the chunks we have just consumed, which is what lets the server reuse
@@ -346,20 +347,19 @@ pub fn parse_chunks(value: &Yaml) -> Result<Vec<(usize, usize)>, String> {
 }
 
 
-// Copy a tightly packed w*h*4 image out of the logical byte stream formed by concatenating
+// Copy a tightly packed w*h*bpp image out of the logical byte stream formed by concatenating
 // `chunks`. `rowstride` is the *source* stride, which for a damage sub-rectangle is the stride of
-// the whole window rather than w*4: xpra hands out zero-copy sub-images that keep their parent's
-// stride (XImageWrapper.get_sub_image). Every other encoding this client decodes produces tightly
-// packed output, which is why the draw packet's rowstride field is only ever read here.
-fn destride(area: &[u8], chunks: &[(usize, usize)], w: usize, h: usize, rowstride: usize)
+// the whole window rather than w*bpp: xpra hands out zero-copy sub-images that keep their parent's
+// stride (XImageWrapper.get_sub_image) - as do rgb24/rgb32 draws, see draw_decoder::decode_rgb.
+fn destride(area: &[u8], chunks: &[(usize, usize)], w: usize, h: usize, rowstride: usize, bpp: usize)
         -> Result<Vec<u8>, String> {
     if w == 0 || h == 0 {
         return Err(format!("empty mmap draw packet: {}x{}", w, h));
     }
-    let width_bytes = w.checked_mul(4).ok_or("mmap draw packet width overflows")?;
+    let width_bytes = w.checked_mul(bpp).ok_or("mmap draw packet width overflows")?;
     let stride = if rowstride == 0 { width_bytes } else { rowstride };
     if stride < width_bytes {
-        return Err(format!("mmap rowstride {} is smaller than {}x4 bytes", stride, w));
+        return Err(format!("mmap rowstride {} is smaller than {}x{} bytes", stride, w, bpp));
     }
     let mut available = 0usize;
     for (offset, length) in chunks {
@@ -496,14 +496,14 @@ mod tests {
     #[test]
     fn destride_tightly_packed() {
         let src = area(4096);
-        let out = destride(&src, &[(8, 2 * 3 * 4)], 3, 2, 12).unwrap();
+        let out = destride(&src, &[(8, 2 * 3 * 4)], 3, 2, 12, 4).unwrap();
         assert_eq!(out, src[8..8 + 24].to_vec());
     }
 
     #[test]
     fn destride_zero_rowstride_means_packed() {
         let src = area(4096);
-        let out = destride(&src, &[(8, 24)], 3, 2, 0).unwrap();
+        let out = destride(&src, &[(8, 24)], 3, 2, 0, 4).unwrap();
         assert_eq!(out, src[8..32].to_vec());
     }
 
@@ -513,7 +513,7 @@ mod tests {
     fn destride_wider_stride() {
         let src = area(4096);
         let (w, h, stride) = (2usize, 3usize, 40usize);
-        let out = destride(&src, &[(8, (h - 1) * stride + w * 4)], w, h, stride).unwrap();
+        let out = destride(&src, &[(8, (h - 1) * stride + w * 4)], w, h, stride, 4).unwrap();
         assert_eq!(out.len(), w * h * 4);
         for row in 0..h {
             let from = 8 + row * stride;
@@ -521,6 +521,15 @@ mod tests {
         }
     }
 
+    // RGB, the only layout without a fourth byte:
+    #[test]
+    fn destride_three_bytes_per_pixel() {
+        let src = area(4096);
+        let (w, h, stride) = (3usize, 2usize, 12usize);
+        let out = destride(&src, &[(8, stride + w * 3)], w, h, stride, 3).unwrap();
+        assert_eq!(out, [&src[8..17], &src[20..29]].concat());
+    }
+
     // the server wraps the ring around and splits the image in two at an arbitrary byte offset -
     // one that can fall in the middle of a row:
     #[test]
@@ -530,7 +539,7 @@ mod tests {
         let total = w * h * 4;
         let split = 22;
         let chunks = [(1000, split), (8, total - split)];
-        let out = destride(&src, &chunks, w, h, 0).unwrap();
+        let out = destride(&src, &chunks, w, h, 0, 4).unwrap();
         let mut expected = src[1000..1000 + split].to_vec();
         expected.extend_from_slice(&src[8..8 + total - split]);
         assert_eq!(out, expected);
@@ -540,13 +549,13 @@ mod tests {
     fn destride_rejects_bad_chunks() {
         let src = area(4096);
         // not enough bytes for the image:
-        assert!(destride(&src, &[(8, 16)], 4, 4, 0).is_err());
+        assert!(destride(&src, &[(8, 16)], 4, 4, 0, 4).is_err());
         // reaching past the end of the area:
-        assert!(destride(&src, &[(4000, 256)], 4, 4, 0).is_err());
+        assert!(destride(&src, &[(4000, 256)], 4, 4, 0, 4).is_err());
         // overlapping the control header:
-        assert!(destride(&src, &[(4, 256)], 4, 4, 0).is_err());
+        assert!(destride(&src, &[(4, 256)], 4, 4, 0, 4).is_err());
         // a stride narrower than the image:
-        assert!(destride(&src, &[(8, 256)], 4, 4, 8).is_err());
+        assert!(destride(&src, &[(8, 256)], 4, 4, 8, 4).is_err());
     }
 
     #[test]
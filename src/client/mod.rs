@@ -1,18 +1,38 @@
 pub mod auth_dialog;
+pub mod certificate_dialog;
 pub mod audio;
+#[cfg(feature = "av1")]
+pub mod av1;
 pub mod client;
 pub mod clipboard;
 pub mod connect_dialog;
+#[cfg(any(feature = "openh264", feature = "vpx", feature = "av1"))]
+pub mod csc;
+pub mod damage;
+pub mod decode_pool;
+pub mod desktop;
 pub mod draw_decoder;
 pub mod font;
+pub mod icons;
 pub mod mmap;
+pub mod packets;
 pub mod paint;
 pub mod pinentry;
+pub mod pixels;
 pub mod remote_logging;
+pub mod scaling;
+pub mod size_hints;
 #[cfg(windows)]
 pub mod mediafoundation;
+#[cfg(all(not(windows), feature = "openh264"))]
+pub mod openh264;
 #[cfg(windows)]
 pub mod windows_audio;
 #[cfg(windows)]
 pub mod tray;
+pub mod video;
+#[cfg(feature = "vpx")]
+pub mod vpx;
 pub mod window;
+pub mod window_hints;
+pub mod window_properties;
@@ -0,0 +1,89 @@
+// Software H.264 video decoder built on Cisco's openh264, for the platforms without Media Foundation
+// (see mediafoundation.rs for the Windows one). Only compiled with the `openh264` cargo feature.
+//
+// Pipeline: xpra sends h264 as an Annex-B elementary stream with in-band SPS/PPS, which openh264
+// takes as is, one draw packet at a time. It outputs planar YUV420P, which csc.rs converts to the
+// same tightly packed BGRX the other decoders produce, so `window::paint` treats it like jpeg/webp.
+//
+// One `H264Decoder` is stateful and lives per xpra window (H.264 is inter-frame predicted), on the
+// window's decode worker only (see video.rs).
+use ::openh264::decoder::{Decoder, DecoderConfig, Flush};
+use ::openh264::formats::YUVSource;
+use ::openh264::OpenH264API;
+use log::trace;
+
+use super::csc::{default_matrix, yuv_to_bgrx, YuvImage};
+
+pub struct H264Decoder {
+    decoder: Decoder,
+    full_range: bool, // colour range of the YUV (from xpra's `full-range`), as in mediafoundation.rs:
+                      // only sent on transitions and keyframes, and xpra's encoders default to full.
+}
+
+impl H264Decoder {
+    pub fn new() -> Result<Self, String> {
+        // Flush every frame out as soon as it is decoded rather than holding a reorder window:
+        // xpra's stream has no B-frames, so there is nothing to reorder, and without this the first
+        // picture only comes out a few frames late.
+        let config = DecoderConfig::new().flush_after_decode(Flush::Flush);
+        let decoder = Decoder::with_api_config(OpenH264API::from_source(), config)
+            .map_err(|e| format!("failed to create openh264 decoder: {e}"))?;
+        Ok(H264Decoder { decoder, full_range: true })
+    }
+
+    // Decode one encoded frame. Returns Ok(Some(bgrx)) when a frame is ready (exactly w*h*4 bytes),
+    // Ok(None) when the input was consumed but no frame is available yet (decoder warm-up) -- the
+    // caller must still ack the draw sequence -- or Err on failure.
+    pub fn decode(&mut self, data: &[u8], w: u32, h: u32, full_range: Option<bool>)
+        -> Result<Option<Vec<u8>>, String>
+    {
+        if w == 0 || h == 0 {
+            return Err("invalid zero frame size".to_string());
+        }
+        if let Some(fr) = full_range {
+            self.full_range = fr;
+        }
+        let full_range = self.full_range;
+        let Some(yuv) = self.decoder.decode(data).map_err(|e| format!("openh264 decoding failed: {e}"))? else {
+            return Ok(None);
+        };
+        let (frame_w, frame_h) = yuv.dimensions();
+        trace!("openh264 decoded a {}x{} frame for a {}x{} draw", frame_w, frame_h, w, h);
+        // the encoder pads odd sizes up to even ones (and its own macroblock alignment is cropped
+        // away by the SPS), so the picture can be a little larger than the draw, never smaller:
+        if frame_w < w as usize || frame_h < h as usize {
+            return Err(format!("decoded a {frame_w}x{frame_h} frame for a {w}x{h} draw"));
+        }
+        let image = YuvImage {
+            planes: [yuv.y(), yuv.u(), yuv.v()],
+            strides: yuv.strides().into(),
+            chroma_shift: (1, 1),
+        };
+        Ok(Some(yuv_to_bgrx(&image, w as usize, h as usize, default_matrix(h), full_range)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::openh264::encoder::Encoder;
+    use ::openh264::formats::YUVBuffer;
+
+    #[test]
+    fn frames_are_decoded_to_bgrx() {
+        // an odd sized draw of a frame encoded at the next even size, as xpra's encoders send them
+        let (w, h) = (63u32, 47u32);
+        let mut yuv = YUVBuffer::new(64, 48);
+        yuv.read_rgb8(::openh264::formats::RgbSliceU8::new(&[40, 120, 200].repeat(64 * 48), (64, 48)));
+        let mut encoder = Encoder::new().unwrap();
+        let stream = encoder.encode(&yuv).unwrap().to_vec();
+        let mut decoder = H264Decoder::new().unwrap();
+        assert_eq!(decoder.decode(&stream, 0, h, None), Err("invalid zero frame size".to_string()));
+        let pixels = decoder.decode(&stream, w, h, Some(false)).unwrap().expect("a frame");
+        assert_eq!(pixels.len(), (w * h * 4) as usize);
+        for px in pixels.chunks_exact(4) {
+            let expected = [200, 120, 40, 0xFF];
+            assert!(px.iter().zip(expected).all(|(&got, want)| got.abs_diff(want) <= 8), "{px:?}");
+        }
+    }
+}
@@ -0,0 +1,606 @@
+// The packets the client acts on, parsed once from the generic `Packet` (net/packet.rs) into
+// typed structs before they are dispatched (XpraClient::do_process_packet). Field layouts follow
+// the python server's senders; each parser only reads the fields we use, but every field it does
+// read is checked, so a truncated or garbled packet is an error naming the packet type and the
+// field - which the client turns into an `invalid-packet` disconnect - rather than a panic on the
+// UI thread or a silently zeroed value.
+//
+// Besides what the server sends, this covers the client-side packet types the worker threads
+// synthesize to hand results to the UI thread ("draw-decoded", "send-ping", "challenge-password",
+// ... - see `client_packet`), since they travel on the same event loop channel.
+
+use yaml_rust2::Yaml;
+
+use xpra::net::packet::{yaml_hash, yaml_hash_bool, Packet};
+#[cfg(windows)]
+use super::audio::{self, IncomingAudio, AUDIO_CAPABILITIES_PACKET};
+
+
+#[derive(Debug)]
+pub enum TypedPacket {
+    Hello(Yaml),
+    #[cfg(windows)]
+    AudioCapabilities(Yaml),
+    // `audio-data`, or its incoming alias `sound-data`
+    #[cfg(windows)]
+    AudioData(IncomingAudio),
+    // from the audio worker thread (windows_audio.rs)
+    #[cfg(windows)]
+    AudioLatency(u32),
+    #[cfg(windows)]
+    AudioWorkerFailed(String),
+    Encodings(Yaml),
+    StartupComplete,
+    // `new-window` and `new-override-redirect`
+    NewWindow(NewWindow),
+    // `window-move-resize` and `configure-override-redirect`
+    MoveResize(MoveResize),
+    InitiateMoveResize { wid: u64, direction: u32 },
+    WindowResized { wid: u64, w: u32, h: u32 },
+    RaiseWindow { wid: u64 },
+    ShowDesktop { show: bool },
+    PointerPosition(PointerPosition),
+    PointerGrab { wid: u64 },
+    PointerUngrab { wid: Option<u64> },
+    LostWindow { wid: u64 },
+    Eos { wid: u64 },
+    SettingChange { setting: String },
+    Bell { pitch: i32, duration: i32 },
+    // None: back to the default cursor
+    Cursor(Option<Cursor>),
+    NotifyShow(Notification),
+    NotifyClose { nid: u64 },
+    WindowIcon(WindowIcon),
+    WindowMetadata { wid: u64, metadata: Yaml },
+    ServerEvent { event_type: String, args: Vec<Yaml> },
+    Draw(Draw),
+    DrawDecoded(DrawDecoded),
+    // `draw-failed` and `decoding-failed`
+    DrawFailed(DrawFailed),
+    Ping { echotime: u64, sid: String },
+    SendPing,
+    SendDesktopSize,
+    SendLog { level: i64, message: String },
+    PingEcho { echoed_time: i64 },
+    Challenge(Challenge),
+    ChallengePassword(String),
+    ChallengeCancel,
+    ChallengeFallbackDialog(String),
+    SetClipboardEnabled(bool),
+    // None: a bare token, without the clipboard contents
+    ClipboardToken(Option<ClipboardData>),
+    ClipboardRequest { request_id: u64, target: String },
+    ClipboardContents(Option<ClipboardData>),
+    ClipboardContentsNone,
+    ClipboardPendingRequests,
+    ClipboardChanged(String),
+    TrayExit,
+    // the reason and any extra info strings, in order
+    Disconnect(Vec<String>),
+    // `connection` numbers the connections of the session, which only ever has more than one with
+    // --reconnect: the reader thread of a previous one may be late to notice that it is gone
+    ConnectionLost { message: String, connection: u64 },
+    ReconnectResult,
+    InvalidPacket(String),
+    // anything else: logged, not fatal, as the server may well send packets we don't handle
+    Unknown(String),
+}
+
+// ["new-window", wid, x, y, w, h, metadata, client_properties]
+#[derive(Debug)]
+pub struct NewWindow {
+    pub wid: u64,
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+    pub metadata: Yaml,
+    pub override_redirect: bool,
+}
+
+// ["window-move-resize", wid, x, y, w, h, ...]
+#[derive(Debug)]
+pub struct MoveResize {
+    pub wid: u64,
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+// ["pointer-position", wid, x, y, rx, ry]: rx/ry are omitted by pre-v5 senders.
+#[derive(Debug)]
+pub struct PointerPosition {
+    pub wid: u64,
+    pub x: i32,
+    pub y: i32,
+    pub relative: Option<(i32, i32)>,
+}
+
+// ["cursor", encoding, x, y, w, h, xhot, yhot, serial, pixels, name, ...sizes]
+#[derive(Debug)]
+pub struct Cursor {
+    pub encoding: String,
+    pub xhot: u32,
+    pub yhot: u32,
+    pub pixels: Vec<u8>,
+}
+
+// ["notify_show", dbus_id, nid, app_name, replaces_nid, app_icon, summary, body, ...]
+#[derive(Debug)]
+pub struct Notification {
+    // only the Windows tray balloons can be closed again, by this id
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub nid: u64,
+    pub app_name: String,
+    pub summary: String,
+    pub body: String,
+}
+
+// ["window-icon", wid, w, h, encoding, pixels]: the pixels are only read for the png icons we
+// can use, the server's "default" placeholder carries something else.
+#[derive(Debug)]
+pub struct WindowIcon {
+    pub wid: u64,
+    pub encoding: String,
+    pub pixels: Vec<u8>,
+}
+
+// ["draw", wid, x, y, w, h, coding, data, seq, rowstride, options]
+#[derive(Debug)]
+pub struct Draw {
+    pub wid: u64,
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+    pub coding: String,
+    // empty for an mmap draw, whose pixels are in the shared area (see `mmap_chunks`), and for a
+    // scroll draw, which has none at all
+    pub data: Vec<u8>,
+    mmap_data: Option<Yaml>,
+    // the rectangles a `scroll` draw moves, empty for every other encoding
+    pub scrolls: Vec<Scroll>,
+    pub seq: u64,
+    pub rowstride: u32,
+    // always a dictionary, empty when the server sent none
+    pub options: Yaml,
+}
+
+impl Draw {
+    // The (offset, length) list of an mmap draw. It rides in the options; the server also leaves a
+    // copy in the data field, which is the only place older clients look (paint_mmap, xpra
+    // client/gui/window/backing.py), so fall back to it.
+    pub fn mmap_chunks(&self) -> Option<&Yaml> {
+        yaml_hash(&self.options, "chunks").or(self.mmap_data.as_ref())
+    }
+
+    // colour range signalled per-stream by a video encoder; absent in steady state (xpra omits it
+    // once settled), so None means "unchanged" to the decoder.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn full_range(&self) -> Option<bool> {
+        yaml_hash_bool(&self.options, "full-range".to_string())
+    }
+
+    // `["draw", <fields>]` parsed the way one from the server is, with `data` out-of-band: for the
+    // tests of whatever decodes or routes draws
+    #[cfg(test)]
+    pub fn for_tests(fields: &str, data: Vec<u8>) -> Draw {
+        let yaml = format!("[draw, {fields}]");
+        let Some(Yaml::Array(main)) = yaml_rust2::YamlLoader::load_from_str(&yaml).unwrap().pop() else {
+            panic!("not a list of fields: {fields}");
+        };
+        match TypedPacket::parse(Packet { main, raw: std::collections::HashMap::from([(7, data)]), decode_time_us: None }) {
+            Ok(TypedPacket::Draw(draw)) => draw,
+            other => panic!("not a draw: {:?}", other),
+        }
+    }
+}
+
+// One rectangle of a `scroll` draw: the `w`x`h` area at (x, y) of what is already on screen moves
+// by (dx, dy), in window coordinates. xpra's server sends these instead of repainting when it finds
+// lines of the new frame in the previous one (scroll_data, xpra server/window/video.py), which is
+// what scrolling a terminal or a browser page looks like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scroll {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+    pub dx: i32,
+    pub dy: i32,
+}
+
+// The decode thread's result for a draw: the pixels (tightly packed BGRX) to paint, or none for a
+// decoder warm-up frame, which still has to be acked - or, for a scroll draw, the rectangles to
+// move, which it passes through untouched.
+#[derive(Debug)]
+pub struct DrawDecoded {
+    pub wid: u64,
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+    pub coding: String,
+    pub pixels: Vec<u8>,
+    pub scrolls: Vec<Scroll>,
+    pub seq: u64,
+    pub decode_time_us: i64,
+}
+
+#[derive(Debug)]
+pub struct DrawFailed {
+    pub wid: u64,
+    pub w: u32,
+    pub h: u32,
+    pub message: String,
+    pub seq: u64,
+}
+
+// ["challenge", server_salt, cipher, digest, salt_digest, prompt]
+#[derive(Debug)]
+pub struct Challenge {
+    pub server_salt: Vec<u8>,
+    pub digest: String,
+    pub salt_digest: String,
+    pub prompt: String,
+}
+
+// The (wire_encoding, wire_data) pair of a clipboard token or contents packet. xpra sends text as
+// "bytes" (the only text encoding - clipboard/core.py); the data of the other encodings
+// ("integers"/"atoms") isn't text, and isn't read.
+#[derive(Debug)]
+pub struct ClipboardData {
+    pub encoding: String,
+    pub data: Vec<u8>,
+}
+
+impl ClipboardData {
+    fn parse(packet: &mut Packet, enc_index: u8, data_index: u8) -> Result<Self, String> {
+        let encoding = packet.field_str(enc_index, "wire_encoding")?;
+        let data = if encoding == "bytes" {
+            packet.field_bytes(data_index, "wire_data")?
+        } else {
+            Vec::new()
+        };
+        Ok(ClipboardData { encoding, data })
+    }
+}
+
+
+impl TypedPacket {
+    pub fn parse(packet: Packet) -> Result<Self, String> {
+        let mut p = packet;
+        if p.is_empty() {
+            return Err("empty packet".to_string());
+        }
+        let packet_type = p.field_str(0, "type")?;
+        let typed = match packet_type.as_str() {
+            "hello" => TypedPacket::Hello(p.field_hash(1, "capabilities")?.clone()),
+            #[cfg(windows)]
+            AUDIO_CAPABILITIES_PACKET => TypedPacket::AudioCapabilities(p.field(1, "capabilities")?.clone()),
+            #[cfg(windows)]
+            t if audio::is_audio_data_type(t) => TypedPacket::AudioData(IncomingAudio::parse(&mut p)?),
+            #[cfg(windows)]
+            "audio-latency" => TypedPacket::AudioLatency(p.field_int(1, "latency")?),
+            #[cfg(windows)]
+            "audio-worker-failed" => TypedPacket::AudioWorkerFailed(p.field_str(1, "error")?),
+            "encodings" => TypedPacket::Encodings(p.field(1, "encodings")?.clone()),
+            "startup-complete" => TypedPacket::StartupComplete,
+            "new-window" | "new-override-redirect" => TypedPacket::NewWindow(NewWindow {
+                wid: p.field_int(1, "wid")?,
+                x: p.field_int(2, "x")?,
+                y: p.field_int(3, "y")?,
+                w: p.field_int(4, "width")?,
+                h: p.field_int(5, "height")?,
+                metadata: p.field_hash(6, "metadata")?.clone(),
+                override_redirect: packet_type == "new-override-redirect",
+            }),
+            "window-move-resize" | "configure-override-redirect" => TypedPacket::MoveResize(MoveResize {
+                wid: p.field_int(1, "wid")?,
+                x: p.field_int(2, "x")?,
+                y: p.field_int(3, "y")?,
+                w: p.field_int(4, "width")?,
+                h: p.field_int(5, "height")?,
+            }),
+            "window-resized" => TypedPacket::WindowResized {
+                wid: p.field_int(1, "wid")?,
+                w: p.field_int(2, "width")?,
+                h: p.field_int(3, "height")?,
+            },
+            "initiate-moveresize" => TypedPacket::InitiateMoveResize {
+                wid: p.field_int(1, "wid")?,
+                direction: p.field_int(4, "direction")?,
+            },
+            "raise-window" => TypedPacket::RaiseWindow { wid: p.field_int(1, "wid")? },
+            // a short packet means restore
+            "show-desktop" => TypedPacket::ShowDesktop {
+                show: p.has(1) && p.field_bool(1, "show")?,
+            },
+            "pointer-position" => TypedPacket::PointerPosition(PointerPosition {
+                wid: p.field_int(1, "wid")?,
+                x: p.field_int(2, "x")?,
+                y: p.field_int(3, "y")?,
+                relative: if p.has(5) {
+                    Some((p.field_int(4, "rx")?, p.field_int(5, "ry")?))
+                } else {
+                    None
+                },
+            }),
+            "pointer-grab" => TypedPacket::PointerGrab { wid: p.field_int(1, "wid")? },
+            "pointer-ungrab" => TypedPacket::PointerUngrab {
+                wid: if p.has(1) { Some(p.field_int(1, "wid")?) } else { None },
+            },
+            "lost-window" => TypedPacket::LostWindow { wid: p.field_int(1, "wid")? },
+            "eos" => TypedPacket::Eos { wid: p.field_int(1, "wid")? },
+            "setting-change" => TypedPacket::SettingChange { setting: p.field_str(1, "setting")? },
+            "bell" => TypedPacket::Bell {
+                pitch: p.field_int(4, "pitch")?,
+                duration: p.field_int(5, "duration")?,
+            },
+            // a 2-item ["cursor", ""] packet resets to the default
+            "cursor" if p.len() <= 2 => TypedPacket::Cursor(None),
+            "cursor" => TypedPacket::Cursor(Some(Cursor {
+                encoding: p.field_str(1, "encoding")?,
+                xhot: p.field_int(6, "xhot")?,
+                yhot: p.field_int(7, "yhot")?,
+                pixels: p.field_bytes(9, "pixels")?,
+            })),
+            "notify_show" => TypedPacket::NotifyShow(Notification {
+                nid: p.field_int(2, "nid")?,
+                app_name: p.field_str(3, "app_name")?,
+                summary: p.field_str(6, "summary")?,
+                body: p.field_str(7, "body")?,
+            }),
+            "notify_close" => TypedPacket::NotifyClose { nid: p.field_int(1, "nid")? },
+            "window-icon" => {
+                let wid = p.field_int(1, "wid")?;
+                let encoding = p.field_str(4, "encoding")?;
+                let pixels = if encoding == "png" { p.field_bytes(5, "pixels")? } else { Vec::new() };
+                TypedPacket::WindowIcon(WindowIcon { wid, encoding, pixels })
+            }
+            "window-metadata" => TypedPacket::WindowMetadata {
+                wid: p.field_int(1, "wid")?,
+                metadata: p.field_hash(2, "metadata")?.clone(),
+            },
+            "server-event" => TypedPacket::ServerEvent {
+                event_type: p.field_str(1, "event_type")?,
+                args: p.main.get(2..).unwrap_or_default().to_vec(),
+            },
+            "draw" => TypedPacket::Draw(parse_draw(&mut p)?),
+            "draw-decoded" => {
+                let coding = p.field_str(6, "coding")?;
+                let (pixels, scrolls) = if coding == "scroll" {
+                    (Vec::new(), parse_scrolls(p.field(7, "scrolls")?)?)
+                } else {
+                    (p.field_bytes(7, "pixels")?, Vec::new())
+                };
+                TypedPacket::DrawDecoded(DrawDecoded {
+                    wid: p.field_int(1, "wid")?,
+                    x: p.field_int(2, "x")?,
+                    y: p.field_int(3, "y")?,
+                    w: p.field_int(4, "width")?,
+                    h: p.field_int(5, "height")?,
+                    coding,
+                    pixels,
+                    scrolls,
+                    seq: p.field_int(8, "sequence")?,
+                    decode_time_us: p.decode_time_us.unwrap_or(0),
+                })
+            }
+            "draw-failed" | "decoding-failed" => TypedPacket::DrawFailed(DrawFailed {
+                wid: p.field_int(1, "wid")?,
+                w: p.field_int(4, "width")?,
+                h: p.field_int(5, "height")?,
+                message: p.field_str(7, "message")?,
+                seq: p.field_int(8, "sequence")?,
+            }),
+            "ping" => TypedPacket::Ping {
+                echotime: p.field_int(1, "echotime")?,
+                sid: if p.has(3) { p.field_str(3, "sid")? } else { String::new() },
+            },
+            "send-ping" => TypedPacket::SendPing,
+            "send-desktop-size" => TypedPacket::SendDesktopSize,
+            "send-log" => TypedPacket::SendLog {
+                level: p.field_int(1, "level")?,
+                message: p.field_str(2, "message")?,
+            },
+            "ping_echo" => TypedPacket::PingEcho { echoed_time: p.field_int(1, "echoedtime")? },
+            "challenge" => TypedPacket::Challenge(Challenge {
+                server_salt: p.field_bytes(1, "server_salt")?,
+                digest: p.field_str(3, "digest")?,
+                salt_digest: if p.has(4) { p.field_str(4, "salt_digest")? } else { "xor".to_string() },
+                prompt: if p.has(5) { p.field_str(5, "prompt")? } else { "password".to_string() },
+            }),
+            "challenge-password" => TypedPacket::ChallengePassword(p.field_str(1, "password")?),
+            "challenge-cancel" => TypedPacket::ChallengeCancel,
+            "challenge-fallback-dialog" => TypedPacket::ChallengeFallbackDialog(p.field_str(1, "prompt")?),
+            "set-clipboard-enabled" => TypedPacket::SetClipboardEnabled(p.field_bool(1, "enabled")?),
+            // fields 3..8 of the legacy layout: target, dtype, dformat, wire_encoding, wire_data
+            "clipboard-token" => TypedPacket::ClipboardToken(if p.len() >= 8 {
+                Some(ClipboardData::parse(&mut p, 6, 7)?)
+            } else {
+                None
+            }),
+            "clipboard-request" => TypedPacket::ClipboardRequest {
+                request_id: p.field_int(1, "request_id")?,
+                target: p.field_str(3, "target")?,
+            },
+            // no target field: request_id, selection, dtype, dformat, wire_encoding, wire_data
+            "clipboard-contents" => TypedPacket::ClipboardContents(if p.len() >= 7 {
+                Some(ClipboardData::parse(&mut p, 5, 6)?)
+            } else {
+                None
+            }),
+            "clipboard-contents-none" => TypedPacket::ClipboardContentsNone,
+            "clipboard-pending-requests" => TypedPacket::ClipboardPendingRequests,
+            "clipboard-changed" => TypedPacket::ClipboardChanged(p.field_str(1, "text")?),
+            "tray-exit" => TypedPacket::TrayExit,
+            "disconnect" => TypedPacket::Disconnect(
+                (1..p.len()).map(|i| p.field_str(i as u8, "info")).collect::<Result<_, _>>()?,
+            ),
+            "connection-lost" => TypedPacket::ConnectionLost {
+                message: p.field_str(1, "message")?,
+                connection: p.field_int(2, "connection")?,
+            },
+            "reconnect-result" => TypedPacket::ReconnectResult,
+            "invalid-packet" => TypedPacket::InvalidPacket(p.field_str(1, "message")?),
+            _ => TypedPacket::Unknown(packet_type),
+        };
+        Ok(typed)
+    }
+}
+
+fn parse_draw(p: &mut Packet) -> Result<Draw, String> {
+    let coding = p.field_str(6, "coding")?;
+    // an mmap draw has no pixel data in the packet at all - field 7 holds a copy of its chunk
+    // list instead (see Draw::mmap_chunks) - and a scroll draw has the list of rectangles to move
+    let (data, mmap_data, scrolls) = if coding == "mmap" {
+        (Vec::new(), p.main.get(7).cloned(), Vec::new())
+    } else if coding == "scroll" {
+        let scrolls = parse_scrolls(p.field(7, "scrolls")?).map_err(|e| format!("invalid \"draw\" packet: {e}"))?;
+        (Vec::new(), None, scrolls)
+    } else {
+        (p.field_bytes(7, "data")?, None, Vec::new())
+    };
+    Ok(Draw {
+        wid: p.field_int(1, "wid")?,
+        x: p.field_int(2, "x")?,
+        y: p.field_int(3, "y")?,
+        w: p.field_int(4, "width")?,
+        h: p.field_int(5, "height")?,
+        coding,
+        data,
+        mmap_data,
+        scrolls,
+        seq: p.field_int(8, "sequence")?,
+        // the source stride, which for a damage sub-rectangle is the whole window's: only the raw
+        // pixels of mmap and rgb24/rgb32 draws use it, everything else decodes tightly packed
+        rowstride: if p.has(9) { p.field_int(9, "rowstride")? } else { 0 },
+        options: if p.has(10) {
+            p.field_hash(10, "options")?.clone()
+        } else {
+            Yaml::Hash(Default::default())
+        },
+    })
+}
+
+// The (x, y, w, h, dx, dy) tuples of a scroll draw
+pub fn parse_scrolls(value: &Yaml) -> Result<Vec<Scroll>, String> {
+    let Yaml::Array(array) = value else {
+        return Err("scroll draw without a list of rectangles".to_string());
+    };
+    array.iter().map(|scroll| {
+        let fields = match scroll {
+            Yaml::Array(fields) if fields.len() == 6 => fields,
+            _ => return Err(format!("invalid scroll rectangle {:?}", scroll)),
+        };
+        let mut values = [0i64; 6];
+        for (value, field) in values.iter_mut().zip(fields) {
+            *value = match field {
+                Yaml::Integer(i) if i32::try_from(*i).is_ok() => *i,
+                _ => return Err(format!("invalid scroll rectangle {:?}", scroll)),
+            };
+        }
+        let [x, y, w, h, dx, dy] = values.map(|value| value as i32);
+        // where the rectangle goes has to be somewhere we can draw
+        if w < 0 || h < 0 || x.checked_add(dx).is_none() || y.checked_add(dy).is_none() {
+            return Err(format!("invalid scroll rectangle {:?}", scroll));
+        }
+        Ok(Scroll { x, y, w: w as u32, h: h as u32, dx, dy })
+    }).collect()
+}
+
+// ... and back, for the decode thread to hand them to the UI thread in a "draw-decoded" packet
+pub fn scrolls_yaml(scrolls: &[Scroll]) -> Yaml {
+    Yaml::Array(scrolls.iter().map(|s| {
+        Yaml::Array([s.x, s.y, s.w as i32, s.h as i32, s.dx, s.dy].map(|v| Yaml::Integer(v as i64)).to_vec())
+    }).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_scrolls, scrolls_yaml, Scroll, TypedPacket};
+    use std::collections::HashMap;
+    use xpra::net::packet::Packet;
+    use yaml_rust2::YamlLoader;
+
+    fn packet(yaml: &str) -> Packet {
+        let main = match YamlLoader::load_from_str(yaml).unwrap().remove(0) {
+            yaml_rust2::Yaml::Array(main) => main,
+            other => panic!("not a list: {:?}", other),
+        };
+        Packet { main, raw: HashMap::new(), decode_time_us: None }
+    }
+
+    fn parse(yaml: &str) -> Result<TypedPacket, String> {
+        TypedPacket::parse(packet(yaml))
+    }
+
+    #[test]
+    fn parses_known_packets_into_structs() {
+        match parse("[new-override-redirect, 3, -10, 20, 640, 480, {title: menu}]").unwrap() {
+            TypedPacket::NewWindow(window) => {
+                assert_eq!((window.wid, window.x, window.y, window.w, window.h), (3, -10, 20, 640, 480));
+                assert!(window.override_redirect);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let mut draw = packet("[draw, 1, 0, 0, 4, 2, png, aGVsbG8=, 7, 0, {full-range: true}]");
+        draw.raw.insert(7, b"pixels".to_vec());
+        match TypedPacket::parse(draw).unwrap() {
+            TypedPacket::Draw(draw) => {
+                assert_eq!((draw.wid, draw.seq, draw.coding.as_str()), (1, 7, "png"));
+                // the out-of-band chunk wins over the (base64) payload field
+                assert_eq!(draw.data, b"pixels");
+                assert_eq!(draw.full_range(), Some(true));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("[draw, 1, 0, 0, 100, 40, scroll, [[0, 20, 100, 20, 0, -10], [0, 0, 50, 5, 3, 0]], 8]").unwrap() {
+            TypedPacket::Draw(draw) => {
+                assert!(draw.data.is_empty());
+                assert_eq!(draw.scrolls, [Scroll { x: 0, y: 20, w: 100, h: 20, dx: 0, dy: -10 },
+                                          Scroll { x: 0, y: 0, w: 50, h: 5, dx: 3, dy: 0 }]);
+                // through the decode thread and back
+                assert_eq!(parse_scrolls(&scrolls_yaml(&draw.scrolls)).unwrap(), draw.scrolls);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse("[challenge, c2FsdA==, '', hmac+sha256]").unwrap() {
+            TypedPacket::Challenge(challenge) => {
+                assert_eq!(challenge.server_salt, b"salt");
+                assert_eq!(challenge.salt_digest, "xor");
+                assert_eq!(challenge.prompt, "password");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(parse("[window-resized, 1, 1920, 1080, 3]").unwrap(),
+                         TypedPacket::WindowResized { wid: 1, w: 1920, h: 1080 }));
+        assert!(matches!(parse("[cursor, '']").unwrap(), TypedPacket::Cursor(None)));
+        assert!(matches!(parse("[clipboard-token, CLIPBOARD]").unwrap(), TypedPacket::ClipboardToken(None)));
+        assert!(matches!(parse("[some-future-packet, 1]").unwrap(), TypedPacket::Unknown(t) if t == "some-future-packet"));
+    }
+
+    #[test]
+    fn malformed_packets_name_the_type_and_field() {
+        assert_eq!(parse("[window-metadata, 5]").unwrap_err(),
+                   "invalid \"window-metadata\" packet: field 2 (metadata) is missing");
+        assert_eq!(parse("[draw, 1, 0, 0, wide, 2, png, '', 7]").unwrap_err(),
+                   "invalid \"draw\" packet: field 4 (width) should be an integer, not a string");
+        assert_eq!(parse("[draw, 1, 0, 0, 4, 2, scroll, [[0, 0, -4, 2, 0, 1]], 7]").unwrap_err(),
+                   "invalid \"draw\" packet: invalid scroll rectangle Array([Integer(0), Integer(0), Integer(-4), Integer(2), Integer(0), Integer(1)])");
+        assert!(parse("[draw, 1, 0, 0, 4, 2, scroll, [[2147483647, 0, 4, 2, 1, 0]], 7]").is_err());
+        assert!(parse("[draw, 1, 0, 0, 4, 2, scroll, [[0, -2147483648, 4, 2, 0, -1]], 7]").is_err());
+        assert_eq!(parse("[lost-window, -1]").unwrap_err(),
+                   "invalid \"lost-window\" packet: field 1 (wid) is out of range: -1");
+        assert_eq!(parse("[window-icon, 1, 16, 16, png, '!!']").unwrap_err(),
+                   "invalid \"window-icon\" packet: field 5 (pixels) is not valid base64: Invalid symbol 33, offset 0.");
+        assert_eq!(parse("[hello]").unwrap_err(),
+                   "invalid \"hello\" packet: field 1 (capabilities) is missing");
+        assert_eq!(parse("[1, 2]").unwrap_err(),
+                   "invalid \"\" packet: field 0 (type) should be a string, not an integer");
+        assert_eq!(TypedPacket::parse(Packet::new()).unwrap_err(), "empty packet");
+    }
+}
@@ -0,0 +1,195 @@
+// Pixel conversion, a row at a time: the 4 byte pixels the decoders hand us into the u32s
+// softbuffer presents (XpraWindow::paint), and the byte orders a server may send raw pixels in into
+// the BGRA everything else takes (draw_decoder::to_bgrx and the mmap path). A 1080p paint is two
+// million pixels, so this is where most of a draw's time goes once it is decoded.
+//
+// Pixels are loaded as little endian u32s, which makes BGRA 0xAARRGGBB - softbuffer's own layout,
+// with the alpha where window.rs wants it - and RGBA 0xAABBGGRR. From there every conversion is a
+// few shifts and masks on the whole pixel, which `wide`'s portable vectors do 8 pixels at a time
+// (SSE2 or AVX2 on x86, NEON on ARM, plain arrays where there is neither), and the very same
+// arithmetic on a single u32 does for the last few pixels of a row.
+use std::ops::{Add, BitAnd, BitOr, Mul, Shl, Shr};
+
+use wide::bytemuck;
+use wide::u32x8;
+
+const LANES: usize = 8;
+
+// The byte order of the source pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Bgra,
+    Rgba,
+}
+
+// What becomes of the fourth byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alpha {
+    Keep,
+    // zeroed, for softbuffer's 0x00RRGGBB
+    Clear,
+    // 0xFF
+    Opaque,
+    // kept, with every colour multiplied by it: X11's ARGB visuals, and the compositing managers
+    // that blend them, take them that way (as xpra's own client hands them to cairo)
+    Premultiply,
+}
+
+// Convert `dst.len()` pixels from `src` into `dst`, as BGRA loaded as u32s (see the top of this file).
+pub fn to_u32_row(src: &[u8], dst: &mut [u32], order: Order, alpha: Alpha) {
+    let (src_chunks, src_rest) = src[..dst.len() * 4].as_chunks::<{ LANES * 4 }>();
+    let (dst_chunks, dst_rest) = dst.as_chunks_mut::<LANES>();
+    for (to, from) in dst_chunks.iter_mut().zip(src_chunks) {
+        *to = convert(load(from), order, alpha).to_array();
+    }
+    for (to, from) in dst_rest.iter_mut().zip(src_rest.chunks_exact(4)) {
+        *to = convert(u32::from_le_bytes([from[0], from[1], from[2], from[3]]), order, alpha);
+    }
+}
+
+// The same on bytes, leaving BGRA in place of the source pixels
+pub fn to_bgra_in_place(pixels: &mut [u8], order: Order, alpha: Alpha) {
+    let (chunks, rest) = pixels.as_chunks_mut::<{ LANES * 4 }>();
+    for chunk in chunks {
+        let words = convert(load(chunk), order, alpha).to_array();
+        #[cfg(target_endian = "big")]
+        let words = words.map(u32::to_le);
+        *chunk = bytemuck::cast(words);
+    }
+    for px in rest.chunks_exact_mut(4) {
+        let value = convert(u32::from_le_bytes([px[0], px[1], px[2], px[3]]), order, alpha);
+        px.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+// The release profile optimizes for size (opt-level "z"), which stops inlining all but the smallest
+// functions: without the `inline(always)`s below, every vector of pixels would go through a few
+// calls to build its constants and to load it, and come out slower than the scalar loop. For the
+// same reason the constants are built at compile time, the loads and stores are of whole arrays,
+// and there is no closure in `convert`.
+#[inline(always)]
+fn load(bytes: &[u8; LANES * 4]) -> u32x8 {
+    let words: [u32; LANES] = bytemuck::cast(*bytes);
+    // a no-op on the little endian machines this runs on in practice
+    #[cfg(target_endian = "big")]
+    let words = words.map(u32::from_le);
+    u32x8::new(words)
+}
+
+// A single pixel, or a vector of them
+trait Pixels: Copy + Add<Output = Self> + Mul<Output = Self> + BitAnd<Output = Self> + BitOr<Output = Self>
+    + Shl<u32, Output = Self> + Shr<u32, Output = Self> {
+    // `V` in every lane
+    fn splat<const V: u32>() -> Self;
+}
+
+impl Pixels for u32 {
+    #[inline(always)]
+    fn splat<const V: u32>() -> Self {
+        V
+    }
+}
+
+impl Pixels for u32x8 {
+    #[inline(always)]
+    fn splat<const V: u32>() -> Self {
+        const { u32x8::splat(V) }
+    }
+}
+
+#[inline(always)]
+fn convert<P: Pixels>(v: P, order: Order, alpha: Alpha) -> P {
+    let v = match order {
+        Order::Bgra => v,
+        // swap the red and blue bytes
+        Order::Rgba => (v & P::splat::<0xFF00_FF00>()) | ((v >> 16) & P::splat::<0xFF>())
+            | ((v & P::splat::<0xFF>()) << 16),
+    };
+    match alpha {
+        Alpha::Keep => v,
+        Alpha::Clear => v & P::splat::<0x00FF_FFFF>(),
+        Alpha::Opaque => v | P::splat::<0xFF00_0000>(),
+        Alpha::Premultiply => {
+            let a = v >> 24;
+            (v & P::splat::<0xFF00_0000>()) | scale(v & P::splat::<0x00FF_00FF>(), a)
+                | (scale((v >> 8) & P::splat::<0xFF>(), a) << 8)
+        }
+    }
+}
+
+// (c * a + 127) / 255 on two channels at once, each in 16 bits of its own: x / 255 is
+// (x + (x >> 8) + 1) >> 8 for every x up to 255 * 255 + 127, and neither step carries into the next
+// channel
+#[inline(always)]
+fn scale<P: Pixels>(channels: P, a: P) -> P {
+    let x = channels * a + P::splat::<0x007F_007F>();
+    ((x + ((x >> 8) & P::splat::<0x00FF_00FF>()) + P::splat::<0x0001_0001>()) >> 8) & P::splat::<0x00FF_00FF>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what XpraWindow::paint used to do one pixel at a time
+    fn premultiplied(r: u8, g: u8, b: u8, a: u8) -> u32 {
+        let a = a as u32;
+        let scale = |c: u8| (c as u32 * a + 127) / 255;
+        a << 24 | scale(r) << 16 | scale(g) << 8 | scale(b)
+    }
+
+    fn rgb(r: u8, g: u8, b: u8) -> u32 {
+        (r as u32) << 16 | (g as u32) << 8 | (b as u32)
+    }
+
+    #[test]
+    fn alpha_is_premultiplied() {
+        let bgra = |r: u8, g: u8, b: u8, a: u8| u32::from_le_bytes([b, g, r, a]);
+        let premultiply = |px: u32| convert(px, Order::Bgra, Alpha::Premultiply);
+        assert_eq!(premultiply(bgra(0xFF, 0x80, 0x00, 0xFF)), 0xFFFF_8000);
+        assert_eq!(premultiply(bgra(0xFF, 0x80, 0x00, 0x80)), 0x8080_4000);
+        assert_eq!(premultiply(bgra(0xFF, 0xFF, 0xFF, 0x00)), 0);
+        assert_eq!(convert(bgra(0x12, 0x34, 0x56, 0x78), Order::Bgra, Alpha::Clear), 0x0012_3456);
+    }
+
+    #[test]
+    fn premultiplying_matches_dividing() {
+        // every colour at every alpha, in each channel, through the vector and the scalar paths alike:
+        // 8 + 3 pixels a row
+        for a in 0..=255u8 {
+            let src: Vec<u8> = (0..=255u8).flat_map(|c| [c, c.wrapping_mul(7), c.wrapping_add(85), a]).collect();
+            for row in src.chunks(11 * 4) {
+                let mut bgra = vec![0u32; row.len() / 4];
+                to_u32_row(row, &mut bgra, Order::Bgra, Alpha::Premultiply);
+                let mut rgba = vec![0u32; row.len() / 4];
+                to_u32_row(row, &mut rgba, Order::Rgba, Alpha::Premultiply);
+                for (i, px) in row.chunks_exact(4).enumerate() {
+                    assert_eq!(bgra[i], premultiplied(px[2], px[1], px[0], px[3]), "{px:?}");
+                    assert_eq!(rgba[i], premultiplied(px[0], px[1], px[2], px[3]), "{px:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rows_of_any_length_are_converted() {
+        let src: Vec<u8> = (0..=255u8).cycle().step_by(13).take(4 * 37).collect();
+        for len in [0, 1, 7, 8, 9, 16, 37] {
+            let row = &src[..len * 4];
+            let pixels = || row.chunks_exact(4);
+            let mut dst = vec![0u32; len];
+            to_u32_row(row, &mut dst, Order::Bgra, Alpha::Clear);
+            assert_eq!(dst, pixels().map(|px| rgb(px[2], px[1], px[0])).collect::<Vec<_>>());
+            to_u32_row(row, &mut dst, Order::Rgba, Alpha::Opaque);
+            assert_eq!(dst, pixels().map(|px| 0xFF00_0000 | rgb(px[0], px[1], px[2])).collect::<Vec<_>>());
+            to_u32_row(row, &mut dst, Order::Bgra, Alpha::Keep);
+            assert_eq!(dst, pixels().map(|px| u32::from_le_bytes([px[0], px[1], px[2], px[3]])).collect::<Vec<_>>());
+
+            let mut bytes = row.to_vec();
+            to_bgra_in_place(&mut bytes, Order::Rgba, Alpha::Keep);
+            assert_eq!(bytes, pixels().flat_map(|px| [px[2], px[1], px[0], px[3]]).collect::<Vec<_>>());
+            let mut bytes = row.to_vec();
+            to_bgra_in_place(&mut bytes, Order::Bgra, Alpha::Opaque);
+            assert_eq!(bytes, pixels().flat_map(|px| [px[0], px[1], px[2], 0xFF]).collect::<Vec<_>>());
+        }
+    }
+}
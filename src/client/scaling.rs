@@ -0,0 +1,194 @@
+// Client-side desktop scaling (`--desktop-scaling`): on a high density display, remote windows laid
+// out for 96dpi come out tiny when every server pixel is one of ours. So - as xpra's own client does
+// - we tell the server our desktop is smaller than it is, by the scaling factor, and blow everything
+// it sends back up to the real size: window geometries, pointer positions and the pixels themselves.
+//
+// The server only ever sees scaled coordinates; the client converts at the edge, where a packet is
+// read or written (`to_local` and `to_server`). Each window keeps its framebuffer at the server's
+// resolution, so that draws, scroll draws and acks work in the coordinates the server used, plus a
+// copy the size of the local window that `scale_rect` resamples the damaged part of after each
+// change (see XpraWindow::rescale).
+
+use winit::event_loop::ActiveEventLoop;
+
+// The range of factors we take: past these, a window either needs a desktop the size of a wall, or
+// puts more than 16 local pixels behind every server one.
+const MIN_FACTOR: f64 = 0.25;
+const MAX_FACTOR: f64 = 4.0;
+
+// `--desktop-scaling=auto|FACTOR`, as given on the command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DesktopScaling {
+    // the scale factor the OS applies to the primary monitor (or to the first one, where there is
+    // no primary): what the user has already chosen for the local applications
+    Auto,
+    Factor(f64),
+}
+
+impl DesktopScaling {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value == "auto" {
+            return Ok(DesktopScaling::Auto);
+        }
+        match value.parse::<f64>() {
+            Ok(factor) if (MIN_FACTOR..=MAX_FACTOR).contains(&factor) => Ok(DesktopScaling::Factor(factor)),
+            _ => Err(format!("{value:?}: --desktop-scaling takes 'auto' or a factor from {MIN_FACTOR} to {MAX_FACTOR}")),
+        }
+    }
+
+    pub fn resolve(self, event_loop: &ActiveEventLoop) -> Scaling {
+        let factor = match self {
+            DesktopScaling::Factor(factor) => factor,
+            DesktopScaling::Auto => event_loop.primary_monitor()
+                .or_else(|| event_loop.available_monitors().next())
+                .map(|monitor| monitor.scale_factor())
+                .unwrap_or(1.0)
+                .clamp(MIN_FACTOR, MAX_FACTOR),
+        };
+        Scaling { factor }
+    }
+}
+
+// The factor in effect for a session: how many local pixels make one server pixel, on both axes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scaling {
+    pub factor: f64,
+}
+
+impl Scaling {
+    pub const NONE: Scaling = Scaling { factor: 1.0 };
+
+    pub fn is_scaled(self) -> bool {
+        self.factor != 1.0
+    }
+
+    // a server coordinate or offset as a local one
+    pub fn to_local(self, value: i32) -> i32 {
+        (value as f64 * self.factor).round() as i32
+    }
+
+    // ... and back
+    pub fn to_server(self, value: i32) -> i32 {
+        (value as f64 / self.factor).round() as i32
+    }
+
+    // The same for sizes, which never go below one pixel either way
+    pub fn size_to_local(self, size: u32) -> u32 {
+        ((size as f64 * self.factor).round() as u32).max(1)
+    }
+
+    pub fn size_to_server(self, size: u32) -> u32 {
+        ((size as f64 / self.factor).round() as u32).max(1)
+    }
+
+    // Whole factors keep every server pixel a sharp square of local ones, which is what text needs;
+    // fractional ones would then make some of them a pixel wider than their neighbours, which shows
+    // more than the blur of interpolating between them does.
+    pub fn filter(self) -> Filter {
+        if self.factor.fract() == 0.0 { Filter::Nearest } else { Filter::Bilinear }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+// Resample the part of a `src_w` pixels wide framebuffer that covers the `(x0, y0)..(x1, y1)`
+// rectangle of the `dst_w` wide one - whatever the two sizes are, so both factors can differ a little
+// from the nominal one (window sizes are rounded) without the picture drifting. Pixels are mapped by
+// their centres, and the channels are interpolated as they are: premultiplied alpha (see window.rs)
+// is what makes that right for translucent pixels too.
+pub fn scale_rect(src: &[u32], src_w: u32, dst: &mut [u32], dst_w: u32,
+                  (x0, y0, x1, y1): (u32, u32, u32, u32), filter: Filter) {
+    if src_w == 0 || dst_w == 0 {
+        return;
+    }
+    let (src_h, dst_h) = ((src.len() / src_w as usize) as u32, (dst.len() / dst_w as usize) as u32);
+    let (x1, y1) = (x1.min(dst_w), y1.min(dst_h));
+    if x1 <= x0 || y1 <= y0 || src_h == 0 {
+        return;
+    }
+    // the source position of a destination column or row, in 24.8 fixed point
+    let sample = |d: u32, src_len: u32, dst_len: u32| -> i64 {
+        ((d as f64 + 0.5) * src_len as f64 / dst_len as f64 * 256.0) as i64 - 128
+    };
+    let columns: Vec<i64> = (x0..x1).map(|x| sample(x, src_w, dst_w)).collect();
+    let (last_col, last_row) = (src_w as i64 - 1, src_h as i64 - 1);
+    for y in y0..y1 {
+        let row = &mut dst[(y * dst_w) as usize..][x0 as usize..x1 as usize];
+        let sy = sample(y, src_h, dst_h);
+        match filter {
+            Filter::Nearest => {
+                let src_row = &src[(((sy + 128) >> 8).clamp(0, last_row) * src_w as i64) as usize..];
+                for (px, &sx) in row.iter_mut().zip(&columns) {
+                    *px = src_row[((sx + 128) >> 8).clamp(0, last_col) as usize];
+                }
+            }
+            Filter::Bilinear => {
+                let top = (sy >> 8).clamp(0, last_row);
+                let bottom = ((sy >> 8) + 1).clamp(0, last_row);
+                let fy = (sy & 0xFF) as u32;
+                let (top, bottom) = (&src[(top * src_w as i64) as usize..], &src[(bottom * src_w as i64) as usize..]);
+                for (px, &sx) in row.iter_mut().zip(&columns) {
+                    let left = (sx >> 8).clamp(0, last_col) as usize;
+                    let right = ((sx >> 8) + 1).clamp(0, last_col) as usize;
+                    let fx = (sx & 0xFF) as u32;
+                    *px = lerp(lerp(top[left], top[right], fx), lerp(bottom[left], bottom[right], fx), fy);
+                }
+            }
+        }
+    }
+}
+
+// `a` to `b` by `f`/256, on all four channels at once: two at a time, each in 16 bits of its own
+fn lerp(a: u32, b: u32, f: u32) -> u32 {
+    let blend = |a: u32, b: u32| ((a * (256 - f) + b * f) >> 8) & 0x00FF_00FF;
+    blend(a & 0x00FF_00FF, b & 0x00FF_00FF) | blend((a >> 8) & 0x00FF_00FF, (b >> 8) & 0x00FF_00FF) << 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn factors_are_parsed_and_bounded() {
+        assert_eq!(DesktopScaling::parse("auto"), Ok(DesktopScaling::Auto));
+        assert_eq!(DesktopScaling::parse("1.5"), Ok(DesktopScaling::Factor(1.5)));
+        assert_eq!(DesktopScaling::parse("2"), Ok(DesktopScaling::Factor(2.0)));
+        for bad in ["", "0", "-2", "8", "2x", "NaN", "AUTO"] {
+            assert!(DesktopScaling::parse(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn coordinates_round_trip() {
+        let scaling = Scaling { factor: 1.5 };
+        assert_eq!((scaling.to_local(101), scaling.to_local(-7)), (152, -11));
+        for value in [-1000, -7, 0, 1, 101, 1919] {
+            assert_eq!(scaling.to_server(scaling.to_local(value)), value);
+        }
+        assert_eq!((scaling.size_to_local(1), scaling.size_to_server(1)), (2, 1));
+        assert_eq!(Scaling { factor: 2.0 }.filter(), Filter::Nearest);
+        assert_eq!(scaling.filter(), Filter::Bilinear);
+    }
+
+    #[test]
+    fn pixels_are_upscaled() {
+        let src = [0x00_000000, 0xFF_FFFFFF, 0xFF_FF0000, 0x80_000080];
+        let mut dst = [0u32; 16];
+        scale_rect(&src, 2, &mut dst, 4, (0, 0, 4, 4), Filter::Nearest);
+        let (a, b, c, d) = (src[0], src[1], src[2], src[3]);
+        assert_eq!(dst, [a, a, b, b, a, a, b, b, c, c, d, d, c, c, d, d]);
+        // only the rectangle asked for
+        let mut dst = [7u32; 16];
+        scale_rect(&src, 2, &mut dst, 4, (2, 2, 9, 9), Filter::Nearest);
+        assert_eq!(dst, [7, 7, 7, 7, 7, 7, 7, 7, 7, 7, d, d, 7, 7, d, d]);
+        // bilinear: the corners keep their own colour, the middle ones blend their neighbours'
+        let src = [0x00_000000, 0xFF_FFFFFF];
+        let mut dst = [0u32; 4];
+        scale_rect(&src, 2, &mut dst, 4, (0, 0, 4, 1), Filter::Bilinear);
+        assert_eq!(dst, [0x00_000000, 0x3F_3F3F3F, 0xBF_BFBFBF, 0xFF_FFFFFF]);
+    }
+}
@@ -0,0 +1,150 @@
+// The sizes a window may take, from its `size-constraints` metadata - the WM_NORMAL_HINTS of the
+// application on the server's side: a minimum and a maximum size, steps to grow and shrink by (a
+// terminal's character cell) counted from a base size, and a range of aspect ratios (a video
+// player's). winit hands the minimum, the maximum and the steps to the platforms that can enforce
+// them while the user drags a window's edge (X11 and macOS for the steps), but the others let a
+// window take any size, which the application then has to make do with: half a character cell of
+// black at the edge of a terminal, or a letterboxed video. So a window that comes out of a resize
+// the wrong size is snapped back (see XpraClient::handle_window_event), the way GTK's
+// gdk_window_constrain_size - which xpra's own client relies on - does it.
+//
+// All of it is in server pixels, which is what the application counts its cells in: with
+// `--desktop-scaling`, sizes go to the server's pixels and back around `constrain`.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SizeHints {
+    pub minimum: Option<(u32, u32)>,
+    pub maximum: Option<(u32, u32)>,
+    pub base: Option<(u32, u32)>,
+    pub increment: Option<(u32, u32)>,
+    // width : height, as a pair of integers - which keeps the arithmetic below exact
+    pub minimum_aspect: Option<(u32, u32)>,
+    pub maximum_aspect: Option<(u32, u32)>,
+}
+
+impl SizeHints {
+    // The size closest to `(w, h)` that these hints allow, rounding down to a whole number of steps
+    pub fn constrain(&self, w: u32, h: u32) -> (u32, u32) {
+        // as in ICCCM, each of the base and minimum sizes stands in for the other when it is missing
+        let (base_w, base_h) = self.base.or(self.minimum).unwrap_or((0, 0));
+        let (min_w, min_h) = self.minimum.or(self.base).unwrap_or((1, 1));
+        let (max_w, max_h) = self.maximum.unwrap_or((u32::MAX, u32::MAX));
+        let (max_w, max_h) = (max_w.max(min_w), max_h.max(min_h));
+        let (inc_w, inc_h) = self.increment.map_or((1, 1), |(w, h)| (w.max(1), h.max(1)));
+        // whole steps from the base, within the limits
+        let steps = |size: u32, base: u32, min: u32, max: u32, inc: u32| {
+            let mut size = base.saturating_add(size.clamp(min, max).saturating_sub(base) / inc * inc);
+            if size < min {
+                size = base.saturating_add((min - base).div_ceil(inc) * inc);
+            }
+            if size > max {
+                size = base.saturating_add(max.saturating_sub(base) / inc * inc);
+            }
+            size
+        };
+        let mut w = steps(w, base_w, min_w, max_w, inc_w);
+        let mut h = steps(h, base_h, min_h, max_h, inc_h);
+        // `excess / divisor` pixels, rounded down to a whole number of `inc`
+        let floor = |excess: u64, divisor: u64, inc: u32| (excess / (divisor * inc as u64)) as u32 * inc;
+        let aspect = |aspect: Option<(u32, u32)>| aspect.filter(|&(n, d)| n > 0 && d > 0).map(|(n, d)| (n as u64, d as u64));
+        // too narrow (w / h < n / d): lose some height, or failing that gain some width - a step at
+        // a time
+        if let Some((n, d)) = aspect(self.minimum_aspect)
+            && w as u64 * d < n * h as u64 {
+            let excess = n * h as u64 - w as u64 * d;
+            let delta = floor(excess, n, inc_h);
+            if h - delta >= min_h {
+                h -= delta;
+            } else {
+                let delta = floor(excess, d, inc_w);
+                if w.saturating_add(delta) <= max_w {
+                    w += delta;
+                }
+            }
+        }
+        // too wide: the other way around
+        if let Some((n, d)) = aspect(self.maximum_aspect)
+            && w as u64 * d > n * h as u64 {
+            let excess = w as u64 * d - n * h as u64;
+            let delta = floor(excess, d, inc_w);
+            if w - delta >= min_w {
+                w -= delta;
+            } else {
+                let delta = floor(excess, n, inc_h);
+                if h.saturating_add(delta) <= max_h {
+                    h += delta;
+                }
+            }
+        }
+        (w, h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an 80x24 terminal: 8x16 cells, and 4 pixels of border on each side
+    const TERMINAL: SizeHints = SizeHints {
+        minimum: Some((8 + 8 * 10, 8 + 16 * 2)),
+        maximum: None,
+        base: Some((8, 8)),
+        increment: Some((8, 16)),
+        minimum_aspect: None,
+        maximum_aspect: None,
+    };
+
+    #[test]
+    fn sizes_are_snapped_to_whole_steps_from_the_base() {
+        let cells = |columns: u32, rows: u32| (8 + 8 * columns, 8 + 16 * rows);
+        assert_eq!(TERMINAL.constrain(648, 392), cells(80, 24));
+        assert_eq!(TERMINAL.constrain(655, 407), cells(80, 24));
+        assert_eq!(TERMINAL.constrain(656, 408), cells(81, 25));
+        // never below the minimum, which is a whole number of steps up from the base anyway
+        assert_eq!(TERMINAL.constrain(10, 10), cells(10, 2));
+        // a minimum that is not: the next step up from it
+        let hints = SizeHints { minimum: Some((100, 30)), ..TERMINAL };
+        assert_eq!(hints.constrain(10, 10), cells(12, 2));
+        // no base: the minimum stands in for it
+        let hints = SizeHints { base: None, ..TERMINAL };
+        assert_eq!(hints.constrain(100, 50), (96, 40));
+        assert_eq!(hints.constrain(97, 56), (96, 56));
+        // an increment of 0 is no increment at all
+        let hints = SizeHints { increment: Some((0, 0)), ..SizeHints::default() };
+        assert_eq!(hints.constrain(123, 45), (123, 45));
+    }
+
+    #[test]
+    fn sizes_are_kept_within_the_limits() {
+        let hints = SizeHints { minimum: Some((100, 50)), maximum: Some((400, 300)), ..SizeHints::default() };
+        assert_eq!(hints.constrain(10, 1000), (100, 300));
+        assert_eq!(hints.constrain(250, 150), (250, 150));
+        // the largest whole number of steps that fits under the maximum
+        let hints = SizeHints { maximum: Some((650, 400)), ..TERMINAL };
+        assert_eq!(hints.constrain(2000, 2000), (648, 392));
+        // a maximum below the minimum gives way to it
+        let hints = SizeHints { minimum: Some((200, 100)), maximum: Some((100, 50)), ..SizeHints::default() };
+        assert_eq!(hints.constrain(300, 300), (200, 100));
+        assert_eq!(SizeHints::default().constrain(0, 0), (1, 1));
+    }
+
+    #[test]
+    fn aspect_ratios_are_kept_within_their_range() {
+        let video = SizeHints { minimum_aspect: Some((16, 9)), maximum_aspect: Some((16, 9)), ..SizeHints::default() };
+        // too tall, and the height gives; too wide, and the width does
+        assert_eq!(video.constrain(1280, 1000), (1280, 720));
+        assert_eq!(video.constrain(1600, 720), (1280, 720));
+        assert_eq!(video.constrain(1280, 720), (1280, 720));
+        // unless the minimum height is in the way: then the width makes up the difference
+        let hints = SizeHints { minimum: Some((100, 900)), ..video };
+        assert_eq!(hints.constrain(1280, 1000), (1777, 1000));
+        // a range only moves sizes that are outside it
+        let range = SizeHints { minimum_aspect: Some((1, 1)), maximum_aspect: Some((2, 1)), ..SizeHints::default() };
+        assert_eq!(range.constrain(300, 200), (300, 200));
+        assert_eq!(range.constrain(100, 200), (100, 100));
+        assert_eq!(range.constrain(500, 200), (400, 200));
+        // with steps, by whole steps
+        let hints = SizeHints { increment: Some((10, 10)), ..range };
+        assert_eq!(hints.constrain(100, 205), (100, 100));
+    }
+}
@@ -0,0 +1,122 @@
+// The video encodings, as opposed to the picture ones in draw_decoder.rs: inter-frame predicted, so
+// unlike a jpeg each draw only makes sense on top of the ones before it, and every window keeps a
+// stateful decoder for as long as its stream lasts. Each of these lives on one decode worker only
+// (the Media Foundation ones are COM objects), the one decode_pool.rs sends all of its window's draws
+// to, and is released on `lost-window`, on `eos` and when a window switches to another video
+// encoding.
+//
+// Which decoders exist depends on the platform and on cargo features:
+//  * h264: Media Foundation on Windows (mediafoundation.rs), openh264 elsewhere (`openh264`)
+//  * vp8 and vp9: libvpx (`vpx`)
+//  * av1: dav1d (`av1`)
+// A build without any of them still has this type, just without variants, so that the decode
+// thread needs no `cfg` of its own.
+use serde_json::{json, Value};
+
+#[cfg(windows)]
+use super::mediafoundation::H264Decoder;
+#[cfg(all(not(windows), feature = "openh264"))]
+use super::openh264::H264Decoder;
+#[cfg(feature = "vpx")]
+use super::vpx::VpxDecoder;
+#[cfg(feature = "av1")]
+use super::av1::Av1Decoder;
+
+// every video encoding xpra may send us, whether this build can decode it or not
+pub const VIDEO_ENCODINGS: [&str; 4] = ["h264", "vp8", "vp9", "av1"];
+
+pub enum VideoDecoder {
+    #[cfg(any(windows, feature = "openh264"))]
+    H264(H264Decoder),
+    #[cfg(feature = "vpx")]
+    Vpx(VpxDecoder),
+    #[cfg(feature = "av1")]
+    Av1(Av1Decoder),
+}
+
+impl VideoDecoder {
+    pub fn new(coding: &str) -> Result<Self, String> {
+        match coding {
+            #[cfg(any(windows, feature = "openh264"))]
+            "h264" => H264Decoder::new().map(VideoDecoder::H264),
+            #[cfg(feature = "vpx")]
+            "vp8" | "vp9" => VpxDecoder::new(coding).map(VideoDecoder::Vpx),
+            #[cfg(feature = "av1")]
+            "av1" => Av1Decoder::new().map(VideoDecoder::Av1),
+            // we never advertise these, so a server should not be sending them
+            #[cfg(not(any(windows, feature = "openh264")))]
+            "h264" => Err("h264 decoding needs a build with the openh264 feature".to_string()),
+            #[cfg(not(feature = "vpx"))]
+            "vp8" | "vp9" => Err(format!("{coding} decoding needs a build with the vpx feature")),
+            #[cfg(not(feature = "av1"))]
+            "av1" => Err("av1 decoding needs a build with the av1 feature".to_string()),
+            other => Err(format!("{other:?} is not a video encoding")),
+        }
+    }
+
+    pub fn coding(&self) -> &'static str {
+        match *self {
+            #[cfg(any(windows, feature = "openh264"))]
+            VideoDecoder::H264(_) => "h264",
+            #[cfg(feature = "vpx")]
+            VideoDecoder::Vpx(ref decoder) => decoder.coding(),
+            #[cfg(feature = "av1")]
+            VideoDecoder::Av1(_) => "av1",
+        }
+    }
+
+    // see H264Decoder::decode: Ok(Some(bgrx)) for a frame, Ok(None) for a draw to ack without
+    // painting anything.
+    pub fn decode(&mut self, data: &[u8], w: u32, h: u32, full_range: Option<bool>)
+        -> Result<Option<Vec<u8>>, String>
+    {
+        #[cfg(not(any(windows, feature = "openh264", feature = "vpx", feature = "av1")))]
+        let _ = (data, w, h, full_range);
+        match *self {
+            #[cfg(any(windows, feature = "openh264"))]
+            VideoDecoder::H264(ref mut decoder) => decoder.decode(data, w, h, full_range),
+            #[cfg(feature = "vpx")]
+            VideoDecoder::Vpx(ref mut decoder) => decoder.decode(data, w, h, full_range),
+            #[cfg(feature = "av1")]
+            VideoDecoder::Av1(ref mut decoder) => decoder.decode(data, w, h, full_range),
+        }
+    }
+}
+
+// The video encodings this build decodes, with their settings added to the hello's "encoding"
+// `caps`. For a video encoding to be offered at all, the server needs `full_csc_modes[<enc>]` to
+// list at least one colourspace its encoder can produce that we can decode (xpra's
+// server/source/encoding.py).
+#[cfg_attr(not(any(windows, feature = "openh264", feature = "vpx", feature = "av1")), allow(unused_variables, unused_mut))]
+pub fn encoding_caps(caps: &mut Value) -> Vec<&'static str> {
+    let mut encodings = Vec::new();
+    let mut csc_modes = json!({});
+    // Media Foundation's H.264 decoder only handles 8-bit 4:2:0 up to High profile, so we advertise
+    // *only* YUV420P (never 422/444) and pin the profile to "high". openh264 decodes even less -
+    // Constrained Baseline and Main, still 4:2:0 - so it pins "main".
+    #[cfg(any(windows, feature = "openh264"))]
+    {
+        encodings.push("h264");
+        csc_modes["h264"] = json!(["YUV420P"]);
+        let profile = if cfg!(windows) { "high" } else { "main" };
+        caps["h264"] = json!({ "YUV420P.profile": profile });
+    }
+    // libvpx decodes every VP8 stream (which is always 4:2:0), and VP9's profile 1 4:4:4 as well as
+    // its profile 0 4:2:0, at 8 bits - csc.rs converts both
+    #[cfg(feature = "vpx")]
+    {
+        encodings.extend(["vp8", "vp9"]);
+        csc_modes["vp8"] = json!(["YUV420P"]);
+        csc_modes["vp9"] = json!(["YUV420P", "YUV444P"]);
+    }
+    // dav1d decodes all three AV1 profiles, of which we take the 8-bit 4:2:0 and 4:4:4 ones
+    #[cfg(feature = "av1")]
+    {
+        encodings.push("av1");
+        csc_modes["av1"] = json!(["YUV420P", "YUV444P"]);
+    }
+    if !encodings.is_empty() {
+        caps["full_csc_modes"] = csc_modes;
+    }
+    encodings
+}
@@ -0,0 +1,156 @@
+// VP8 and VP9 video decoders built on libvpx. Only compiled with the `vpx` cargo feature, which
+// links the system libvpx (found with pkg-config, through env-libvpx-sys).
+//
+// Each draw packet carries exactly one compressed frame, which libvpx decodes synchronously: there
+// is no reordering and nothing to flush, so every decode call hands back the frame it was given,
+// as planar YUV that csc.rs converts to BGRX. Like the h264 decoders, one `VpxDecoder` is stateful
+// and lives per xpra window, on one decode worker only (see video.rs).
+use std::ffi::CStr;
+use std::mem::MaybeUninit;
+use std::os::raw::c_char;
+use std::ptr;
+
+use log::trace;
+use vpx_sys::*;
+
+use super::csc::{default_matrix, yuv_to_bgrx, YuvImage, BT601, BT709};
+
+pub struct VpxDecoder {
+    coding: &'static str,
+    // boxed: libvpx keeps pointers into its context, so it must not move once initialised
+    ctx: Box<vpx_codec_ctx_t>,
+    full_range: Option<bool>, // xpra's `full-range`, which wins over what the stream itself says
+}
+
+impl VpxDecoder {
+    pub fn new(coding: &str) -> Result<Self, String> {
+        let (coding, iface) = match coding {
+            "vp8" => ("vp8", unsafe { vpx_codec_vp8_dx() }),
+            "vp9" => ("vp9", unsafe { vpx_codec_vp9_dx() }),
+            other => return Err(format!("{other:?} is not a libvpx encoding")),
+        };
+        let mut ctx = Box::new(unsafe { MaybeUninit::<vpx_codec_ctx_t>::zeroed().assume_init() });
+        // `vpx_codec_dec_init` is a macro around this, passing the ABI version we were built for
+        let err = unsafe {
+            vpx_codec_dec_init_ver(&mut *ctx, iface, ptr::null(), 0, VPX_DECODER_ABI_VERSION as i32)
+        };
+        if err != vpx_codec_err_t::VPX_CODEC_OK {
+            return Err(format!("failed to create {coding} decoder: {}", unsafe { c_str(vpx_codec_err_to_string(err)) }));
+        }
+        Ok(VpxDecoder { coding, ctx, full_range: None })
+    }
+
+    pub fn coding(&self) -> &'static str {
+        self.coding
+    }
+
+    // Decode one encoded frame. Returns Ok(Some(bgrx)) with the frame (exactly w*h*4 bytes), Ok(None)
+    // for a frame that is not meant to be shown - which the caller must still ack - or Err on failure.
+    pub fn decode(&mut self, data: &[u8], w: u32, h: u32, full_range: Option<bool>)
+        -> Result<Option<Vec<u8>>, String>
+    {
+        if w == 0 || h == 0 {
+            return Err("invalid zero frame size".to_string());
+        }
+        if full_range.is_some() {
+            self.full_range = full_range;
+        }
+        let ctx: *mut vpx_codec_ctx_t = &mut *self.ctx;
+        let err = unsafe { vpx_codec_decode(ctx, data.as_ptr(), data.len() as u32, ptr::null_mut(), 0) };
+        if err != vpx_codec_err_t::VPX_CODEC_OK {
+            let error = self.error();
+            return Err(format!("{} decoding failed: {}", self.coding, error));
+        }
+        // one frame in, at most one out: keep the last one, should there ever be more
+        let mut iter: vpx_codec_iter_t = ptr::null();
+        let mut image: Option<&vpx_image_t> = None;
+        while let Some(next) = unsafe { vpx_codec_get_frame(ctx, &mut iter).as_ref() } {
+            image = Some(next);
+        }
+        let Some(image) = image else {
+            return Ok(None);
+        };
+        trace!("{} decoded a {}x{} {:?} frame for a {}x{} draw", self.coding, image.d_w, image.d_h, image.fmt, w, h);
+        let chroma_shift = match image.fmt {
+            vpx_img_fmt::VPX_IMG_FMT_I420 => (1, 1),
+            vpx_img_fmt::VPX_IMG_FMT_I444 => (0, 0),
+            // we only advertise YUV420P and YUV444P, see video.rs
+            other => return Err(format!("unsupported {} frame format {:?}", self.coding, other)),
+        };
+        if image.d_w < w || image.d_h < h {
+            return Err(format!("decoded a {}x{} frame for a {w}x{h} draw", image.d_w, image.d_h));
+        }
+        // VP9 signals its colourspace and range in the bitstream; VP8 always leaves them unknown
+        // (and, in practice, limited)
+        let matrix = match image.cs {
+            vpx_color_space::VPX_CS_BT_709 => &BT709,
+            vpx_color_space::VPX_CS_BT_601 | vpx_color_space::VPX_CS_SMPTE_170 => &BT601,
+            _ => default_matrix(h),
+        };
+        let full_range = self.full_range.unwrap_or(image.range == vpx_color_range::VPX_CR_FULL_RANGE);
+        let rows = |plane: usize| {
+            let shift = if plane == 0 { 0 } else { chroma_shift.1 };
+            let stride = image.stride[plane] as usize;
+            let len = stride * ((image.d_h as usize + (1 << shift) - 1) >> shift);
+            unsafe { std::slice::from_raw_parts(image.planes[plane], len) }
+        };
+        let yuv = YuvImage {
+            planes: [rows(0), rows(1), rows(2)],
+            strides: [0, 1, 2].map(|plane| image.stride[plane] as usize),
+            chroma_shift,
+        };
+        Ok(Some(yuv_to_bgrx(&yuv, w as usize, h as usize, matrix, full_range)))
+    }
+
+    fn error(&mut self) -> String {
+        let (error, detail) = unsafe { (vpx_codec_error(&mut *self.ctx), vpx_codec_error_detail(&mut *self.ctx)) };
+        match unsafe { c_str(detail) } {
+            "" => unsafe { c_str(error) }.to_string(),
+            detail => format!("{}: {}", unsafe { c_str(error) }, detail),
+        }
+    }
+}
+
+impl Drop for VpxDecoder {
+    fn drop(&mut self) {
+        unsafe { vpx_codec_destroy(&mut *self.ctx) };
+    }
+}
+
+// libvpx's static error strings, or "" for none
+unsafe fn c_str<'a>(s: *const c_char) -> &'a str {
+    if s.is_null() {
+        return "";
+    }
+    unsafe { CStr::from_ptr(s) }.to_str().unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 16x16 keyframe of rgb(40, 120, 200): the VP8 bitstream of a lossy webp picture, which is
+    // one VP8 keyframe wrapped in a RIFF container, encoded by libwebp.
+    const VP8_FRAME: [u8; 50] = [
+        0xd0, 0x01, 0x00, 0x9d, 0x01, 0x2a, 0x10, 0x00, 0x10, 0x00, 0x00, 0xc0, 0x12, 0x25, 0xa0, 0x02,
+        0x74, 0xba, 0x01, 0xf8, 0x00, 0x03, 0xb0, 0x00, 0xfe, 0xf0, 0x9b, 0x43, 0xff, 0x8c, 0xa9, 0xf1,
+        0x21, 0x7e, 0x24, 0x2f, 0xfc, 0x65, 0x4f, 0xfd, 0xf7, 0xc3, 0xde, 0xf8, 0x7b, 0xdf, 0x0f, 0xf2,
+        0x50, 0x00,
+    ];
+
+    #[test]
+    fn frames_are_decoded_to_bgrx() {
+        assert!(VpxDecoder::new("h264").is_err());
+        // an odd sized draw, cropped from the frame the encoder padded it to
+        let (w, h) = (15u32, 13u32);
+        let mut decoder = VpxDecoder::new("vp8").unwrap();
+        assert_eq!(decoder.decode(&VP8_FRAME, 0, h, None), Err("invalid zero frame size".to_string()));
+        let pixels = decoder.decode(&VP8_FRAME, w, h, None).unwrap().expect("a frame");
+        assert_eq!(pixels.len(), (w * h * 4) as usize);
+        for px in pixels.chunks_exact(4) {
+            let expected = [200, 120, 40, 0xFF];
+            assert!(px.iter().zip(expected).all(|(&got, want)| got.abs_diff(want) <= 8), "{px:?}");
+        }
+        assert!(decoder.decode(&[0xFF; 64], w, h, None).is_err());
+    }
+}
@@ -3,54 +3,138 @@ use std::rc::Rc;
 use std::time::Instant;
 
 use log::{debug, error, trace};
-use softbuffer::{Context, Surface};
-use winit::dpi::PhysicalPosition;
-use winit::event_loop::OwnedDisplayHandle;
+use softbuffer::{Context, Rect, Surface};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::event_loop::{ActiveEventLoop, OwnedDisplayHandle};
 use winit::window::Window;
 
+use super::damage::{self, Damage};
+use super::desktop::{self, DesktopSession};
+use super::packets::Scroll;
+use super::pixels::{self, Alpha, Order};
+use super::scaling::{scale_rect, Scaling};
+use super::size_hints::SizeHints;
+use super::video::VIDEO_ENCODINGS;
+
 
 pub struct XpraWindow {
     pub wid: u64,
     pub window: Rc<Window>,
     pub surface: Surface<OwnedDisplayHandle, Rc<Window>>,
+    // softbuffer's 0x00RRGGBB pixels - or, for a window with `alpha`, premultiplied 0xAARRGGBB -
+    // in server pixels: `width` and `height` are the window's size as the server sees it
     pub framebuffer: Vec<u32>,
     pub width: u32,
     pub height: u32,
+    // `--desktop-scaling` (see scaling.rs): when it is in effect, `scaled` is the framebuffer
+    // resampled to the size of the surface, which is what we present
+    pub scaling: Scaling,
+    scaled: Vec<u32>,
+    // the root window of a desktop or shadow session (see desktop.rs): its framebuffer stays the
+    // size of the server's screen, and `scaled` is that resampled to whatever size the window has
+    pub desktop: Option<DesktopSession>,
+    surface_size: (u32, u32),
+    // what changed since the last present, and what had changed for that one - which the buffer
+    // after it is also missing, on a platform that alternates between two (see draw_screen)
+    dirty: Damage,
+    presented: Vec<damage::Rect>,
     pub mapped: bool,
     pub override_redirect: bool,
+    // the window was created translucent, for a `has-alpha` window on a display that can show it
+    // (see has_transparency). softbuffer documents the top byte of its pixels as unused, but on
+    // X11 it hands the buffer to the server as it is, and the 32-bit visual winit picks for a
+    // transparent window takes that byte as (premultiplied) alpha.
+    pub alpha: bool,
     // remembered window-level metadata. Updates often contain just one of "above" / "below",
     // so retain both values to derive the effective winit WindowLevel after each partial update.
     pub above: bool,
     pub below: bool,
+    // the window's size-constraints, in server pixels (see size_hints.rs)
+    pub size_hints: SizeHints,
     pub paint_debug: bool,
     // absolute position of the pointer as of the last CursorMoved event:
     // button and wheel events don't carry a position of their own.
     pub last_cursor: (i32, i32),
+    // the connection this window came from was lost, and it is waiting - greyed out - for the
+    // server to send it back once we have reconnected (see client::start_reconnect).
+    pub stale: bool,
 }
 
 
 impl XpraWindow {
 
-    pub fn new(wid: u64, window: Rc<Window>, context: &Context<OwnedDisplayHandle>, width: u32, height: u32, override_redirect: bool) -> Self {
+    // Sized after the window winit has just created for it, which is the size we asked for in local
+    // pixels, unless the platform had other ideas.
+    pub fn new(wid: u64, window: Rc<Window>, context: &Context<OwnedDisplayHandle>, override_redirect: bool,
+               alpha: bool, scaling: Scaling) -> Self {
         let mut surface = Surface::new(context, window.clone()).expect("failed to create softbuffer surface");
-        let rw = width.max(1);
-        let rh = height.max(1);
+        let size = window.inner_size();
+        let rw = size.width.max(1);
+        let rh = size.height.max(1);
         surface.resize(NonZeroU32::new(rw).unwrap(), NonZeroU32::new(rh).unwrap())
             .expect("failed to size softbuffer surface");
+        let (width, height) = (scaling.size_to_server(rw), scaling.size_to_server(rh));
         XpraWindow {
             wid,
             window,
             surface,
-            framebuffer: vec![0u32; (rw * rh) as usize],
-            width: rw,
-            height: rh,
+            framebuffer: vec![0u32; (width * height) as usize],
+            width,
+            height,
+            scaling,
+            scaled: if scaling.is_scaled() { vec![0u32; (rw * rh) as usize] } else { Vec::new() },
+            desktop: None,
+            surface_size: (rw, rh),
+            dirty: Damage::default(),
+            presented: Vec::new(),
             mapped: false,
             override_redirect,
+            alpha,
             above: false,
             below: false,
+            size_hints: SizeHints::default(),
             paint_debug: cfg!(debug_assertions),
             last_cursor: (0, 0),
+            stale: false,
+        }
+    }
+
+    // Make this the root window of a desktop or shadow session, whose screen is `width`x`height`
+    pub fn set_desktop(&mut self, session: DesktopSession, width: u32, height: u32) {
+        self.desktop = Some(session);
+        let (sw, sh) = self.surface_size;
+        self.scaled = vec![0u32; (sw * sh) as usize];
+        self.resize_framebuffer(width, height);
+    }
+
+    // The server's screen has changed size, and with it the root window of a desktop session: it
+    // repaints all of it anyway, so the old contents go.
+    pub fn resize_framebuffer(&mut self, width: u32, height: u32) {
+        let (width, height) = (width.max(1), height.max(1));
+        if (width, height) == (self.width, self.height) {
+            return;
         }
+        debug!("resize framebuffer wid={:#x} to {:?}x{:?}", self.wid, width, height);
+        self.width = width;
+        self.height = height;
+        self.framebuffer = vec![0u32; (width * height) as usize];
+        self.damage(0, 0, width, height);
+    }
+
+    // whether what we present is `scaled` rather than the framebuffer itself
+    fn is_scaled(&self) -> bool {
+        self.desktop.is_some() || self.scaling.is_scaled()
+    }
+
+    // grey out the last contents we had, halfway to mid-grey, until the server repaints them
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+        for px in self.framebuffer.iter_mut() {
+            // premultiplied, the grey we add has to be scaled by the pixel's own alpha
+            let alpha = if self.alpha { *px >> 24 } else { 0xFF };
+            *px = (*px & 0xFF00_0000) | (((*px >> 1) & 0x007F_7F7F) + ((alpha + 1) >> 2) * 0x0001_0101);
+        }
+        self.damage(0, 0, self.width, self.height);
     }
 
     pub fn paint(&mut self, seq: u64, x: i32, y: i32, w: u32, h: u32, coding: &String, pixels: &Vec<u8>) {
@@ -60,44 +144,102 @@ impl XpraWindow {
             error!("pixel data is too small! got {:?} bytes, expected {:?}", pixels.len(), expected);
             return;
         }
-        // convert the decoded bytes into softbuffer's 0x00RRGGBB u32 pixels,
-        // and composite them into our persistent framebuffer at (x,y):
-        let to_pixel: fn(&[u8]) -> u32 = if coding == "jpeg" || coding == "h264" || coding == "webp"
-                || coding == "mmap" {
-            // turbojpeg outputs BGRA, and so do WebPDecodeBGRA, the Media Foundation h264 path
-            // (RGB32) and the shared memory area (we ask the server for BGRX, see send_hello):
-            |px: &[u8]| (px[2] as u32) << 16 | (px[1] as u32) << 8 | (px[0] as u32)
+        // convert the decoded bytes into softbuffer's u32 pixels, a row at a time (see pixels.rs), and
+        // composite them into our persistent framebuffer at (x,y). turbojpeg outputs BGRA, and so do
+        // WebPDecodeBGRA, the video decoders (Media Foundation's RGB32 and csc.rs' conversion) and
+        // draw_decoder::to_bgrx, for raw pixels from rgb draws and from the shared memory area; spng
+        // outputs RGBA8.
+        let bgra = coding == "jpeg" || coding == "webp" || coding == "mmap"
+            || coding == "rgb24" || coding == "rgb32" || VIDEO_ENCODINGS.contains(&coding.as_str());
+        // Of those, only png, webp and the raw pixels carry an alpha channel the server filled in
+        // (and only once we have told it we want one, see `transparency` in send_hello). The others
+        // leave that byte at 0xFF at best: paint them fully opaque.
+        let keep_alpha = self.alpha && matches!(coding.as_str(), "png" | "webp" | "rgb32" | "mmap");
+        let order = if bgra { Order::Bgra } else { Order::Rgba };
+        let alpha = if keep_alpha {
+            Alpha::Premultiply
+        } else if self.alpha {
+            Alpha::Opaque
         } else {
-            // spng outputs RGBA8:
-            |px: &[u8]| (px[0] as u32) << 16 | (px[1] as u32) << 8 | (px[2] as u32)
+            Alpha::Clear
         };
         let t0 = Instant::now();
-        for row in 0..h {
-            let dst_y = y + row as i32;
-            if dst_y < 0 || dst_y as u32 >= self.height {
-                continue;
-            }
-            let src_row_start = (row as usize) * (w as usize) * 4;
-            for col in 0..w {
-                let dst_x = x + col as i32;
-                if dst_x < 0 || dst_x as u32 >= self.width {
-                    continue;
-                }
-                let src_off = src_row_start + (col as usize) * 4;
-                let px = to_pixel(&pixels[src_off..src_off + 4]);
-                let dst_off = (dst_y as u32) as usize * self.width as usize + dst_x as usize;
-                self.framebuffer[dst_off] = px;
+        // the columns and rows of the draw that land inside the window
+        let (width, height) = (self.width as i64, self.height as i64);
+        let (x0, x1) = ((x as i64).clamp(0, width), (x as i64 + w as i64).clamp(0, width));
+        let (y0, y1) = ((y as i64).clamp(0, height), (y as i64 + h as i64).clamp(0, height));
+        if x0 < x1 {
+            let columns = (x1 - x0) as usize;
+            for dst_y in y0..y1 {
+                let src_start = ((dst_y - y as i64) * w as i64 + (x0 - x as i64)) as usize * 4;
+                let dst_start = (dst_y * width + x0) as usize;
+                pixels::to_u32_row(&pixels[src_start..], &mut self.framebuffer[dst_start..dst_start + columns],
+                                   order, alpha);
             }
         }
         trace!("perf: paint wid={:#x} {:?}x{:?} converted in {:?}", self.wid, w, h, t0.elapsed());
         if self.paint_debug {
             self.draw_debug_border(x, y, w, h);
         }
-        self.window.request_redraw();
+        self.damage(x, y, w, h);
+    }
+
+    // A `scroll` draw: move rectangles of what we already show, rather than repaint them
+    pub fn scroll(&mut self, seq: u64, scrolls: &[Scroll]) {
+        debug!("scroll({seq}, {:?} rectangles)", scrolls.len());
+        let t0 = Instant::now();
+        scroll_framebuffer(&mut self.framebuffer, self.width, self.height, scrolls);
+        trace!("perf: scroll wid={:#x} {:?} rectangles moved in {:?}", self.wid, scrolls.len(), t0.elapsed());
+        for scroll in scrolls {
+            let (x, y) = (scroll.x + scroll.dx, scroll.y + scroll.dy);
+            if self.paint_debug {
+                self.draw_debug_border(x, y, scroll.w, scroll.h);
+            }
+            self.damage(x, y, scroll.w, scroll.h);
+        }
+    }
+
+    // The (x, y, w, h) rectangle of the framebuffer has changed: have it presented on the next
+    // redraw, along with whatever else changes before then (see damage.rs).
+    fn damage(&mut self, x: i32, y: i32, w: u32, h: u32) {
+        let rect = if self.is_scaled() {
+            self.rescale(x, y, w, h)
+        } else {
+            let clip = |value: i32, size: u32| (value as i64).clamp(0, size as i64) as u32;
+            (clip(x, self.width), clip(y, self.height),
+             clip(x.saturating_add_unsigned(w), self.width), clip(y.saturating_add_unsigned(h), self.height))
+        };
+        if damage::is_empty(&rect) {
+            return;
+        }
+        if self.dirty.is_empty() {
+            self.window.request_redraw();
+        }
+        self.dirty.add(rect);
+    }
+
+    // Bring the part of `scaled` that shows the (x, y, w, h) rectangle of the framebuffer up to date,
+    // with a pixel of margin: bilinear filtering blends the local pixels along its edges with the
+    // server pixels just outside it. Returns the part of the surface that covers.
+    fn rescale(&mut self, x: i32, y: i32, w: u32, h: u32) -> damage::Rect {
+        let (sw, sh) = self.surface_size;
+        let to_surface = |value: i64, server: u32, surface: u32| {
+            (value * surface as i64 / server as i64).clamp(0, surface as i64) as u32
+        };
+        let (x, y, w, h) = (x as i64, y as i64, w as i64, h as i64);
+        let x0 = to_surface(x - 1, self.width, sw);
+        let y0 = to_surface(y - 1, self.height, sh);
+        // rounded up, so that the last local pixel the rectangle reaches into is included
+        let x1 = (to_surface(x + w + 1, self.width, sw) + 1).min(sw);
+        let y1 = (to_surface(y + h + 1, self.height, sh) + 1).min(sh);
+        let t0 = Instant::now();
+        scale_rect(&self.framebuffer, self.width, &mut self.scaled, sw, (x0, y0, x1, y1), self.scaling.filter());
+        trace!("perf: rescale wid={:#x} {:?}x{:?} resampled in {:?}", self.wid, x1.saturating_sub(x0), y1.saturating_sub(y0), t0.elapsed());
+        (x0, y0, x1, y1)
     }
 
     fn draw_debug_border(&mut self, x: i32, y: i32, w: u32, h: u32) {
-        let color: u32 = 0x00FF0000;
+        let color: u32 = if self.alpha { 0xFFFF0000 } else { 0x00FF0000 };
         let x0 = x.max(0) as u32;
         let y0 = y.max(0) as u32;
         let x1 = ((x + w as i32).max(0) as u32).min(self.width);
@@ -124,6 +266,7 @@ impl XpraWindow {
 
     pub fn draw_screen(&mut self) {
         trace!("draw_screen wid={:#x}", self.wid);
+        let scaled = self.is_scaled();
         let mut buffer = match self.surface.buffer_mut() {
             Ok(buffer) => buffer,
             Err(e) => {
@@ -131,25 +274,64 @@ impl XpraWindow {
                 return;
             }
         };
-        if buffer.len() != self.framebuffer.len() {
+        let pixels = if scaled { &self.scaled } else { &self.framebuffer };
+        if buffer.len() != pixels.len() {
             // surface hasn't been resized to match our framebuffer yet, skip this present:
             return;
         }
+        // What changed since the last present: the damage, or - for a redraw we did not ask for,
+        // the platform's, after the window was uncovered - all of it. Only that needs copying into
+        // a buffer that already holds the last frame (age 1); one that holds the frame before
+        // (age 2, a platform with two buffers) also misses what changed for the last one, and any
+        // other gets the whole frame.
+        let (sw, sh) = self.surface_size;
+        let whole = vec![(0, 0, sw, sh)];
+        let changed = match self.dirty.take() {
+            damage if damage.is_empty() => whole.clone(),
+            damage => damage,
+        };
+        let rects = match buffer.age() {
+            1 => changed.clone(),
+            2 => {
+                let mut rects = Damage::default();
+                for rect in changed.iter().chain(&self.presented) {
+                    rects.add(*rect);
+                }
+                rects.take()
+            }
+            _ => whole,
+        };
         let t0 = Instant::now();
-        buffer.copy_from_slice(&self.framebuffer);
+        let width = sw as usize;
+        for &(x0, y0, x1, y1) in &rects {
+            for row in y0 as usize..y1 as usize {
+                let range = row * width + x0 as usize..row * width + x1 as usize;
+                buffer[range.clone()].copy_from_slice(&pixels[range]);
+            }
+        }
         let copy_elapsed = t0.elapsed();
+        let bytes: usize = rects.iter().map(damage::area).sum::<usize>() * 4;
+        let damage_rects: Vec<Rect> = rects.iter().filter_map(|&(x0, y0, x1, y1)| Some(Rect {
+            x: x0,
+            y: y0,
+            width: NonZeroU32::new(x1 - x0)?,
+            height: NonZeroU32::new(y1 - y0)?,
+        })).collect();
         let t1 = Instant::now();
-        let result = buffer.present();
-        trace!("perf: draw_screen wid={:#x} copy={:?} present={:?}", self.wid, copy_elapsed, t1.elapsed());
+        let result = buffer.present_with_damage(&damage_rects);
+        trace!("perf: draw_screen wid={:#x} {} rects, {} of {} bytes copied in {:?}, present={:?}",
+               self.wid, rects.len(), bytes, pixels.len() * 4, copy_elapsed, t1.elapsed());
+        self.presented = changed;
         if let Err(e) = result {
             error!("failed to present softbuffer buffer: {:?}", e);
         }
     }
 
+    // The window's new size in local pixels, as winit reports it
     pub fn resize(&mut self, width: u32, height: u32) {
         let rw = width.max(1);
         let rh = height.max(1);
-        if rw == self.width && rh == self.height {
+        if (rw, rh) == self.surface_size {
             return;
         }
         debug!("resize wid={:#x} to {:?}x{:?}", self.wid, rw, rh);
@@ -159,12 +341,50 @@ impl XpraWindow {
                 return;
             }
         }
-        self.width = rw;
-        self.height = rh;
-        self.framebuffer = vec![0u32; (rw * rh) as usize];
+        self.surface_size = (rw, rh);
+        // whatever was pending is for the old size: the new buffer gets the whole frame
+        self.dirty = Damage::default();
+        self.presented.clear();
+        // a desktop session's root stays the size it is on the server, and is scaled to the new size
+        if self.desktop.is_some() {
+            self.scaled = vec![0u32; (rw * rh) as usize];
+            self.damage(0, 0, self.width, self.height);
+            return;
+        }
+        self.width = self.scaling.size_to_server(rw);
+        self.height = self.scaling.size_to_server(rh);
+        self.framebuffer = vec![0u32; (self.width * self.height) as usize];
+        if self.scaling.is_scaled() {
+            self.scaled = vec![0u32; (rw * rh) as usize];
+        }
         self.window.request_redraw();
     }
 
+    // The size the window should have instead of `size`, if its size hints do not allow that one.
+    // A maximized or fullscreen window takes the size it is given: so does GTK's, and asking for
+    // another one would take it out of that state on some platforms.
+    pub fn constrained_size(&self, size: PhysicalSize<u32>) -> Option<PhysicalSize<u32>> {
+        if self.override_redirect || self.window.is_maximized() || self.window.fullscreen().is_some() {
+            return None;
+        }
+        // the root of a desktop session that cannot be resized keeps its shape, at any size
+        if let Some(session) = self.desktop
+            && !session.resize_screen {
+            let aspect = Some((self.width, self.height));
+            let hints = SizeHints { minimum_aspect: aspect, maximum_aspect: aspect, ..SizeHints::default() };
+            let (w, h) = (size.width.max(1), size.height.max(1));
+            let (cw, ch) = hints.constrain(w, h);
+            return ((cw, ch) != (w, h)).then(|| PhysicalSize::new(cw, ch));
+        }
+        let scaling = self.scaling;
+        let (w, h) = (scaling.size_to_server(size.width.max(1)), scaling.size_to_server(size.height.max(1)));
+        let (cw, ch) = self.size_hints.constrain(w, h);
+        if (cw, ch) == (w, h) {
+            return None;
+        }
+        Some(PhysicalSize::new(scaling.size_to_local(cw), scaling.size_to_local(ch)))
+    }
+
     pub fn get_geometry(&self) -> (i32, i32, u32, u32) {
         let size = self.window.inner_size();
         let w = size.width.max(1);
@@ -177,7 +397,11 @@ impl XpraWindow {
     // xpra expects, using the same window origin as get_geometry() (which is what
     // window-map / window-configure told the server) so the two stay consistent -
     // on Wayland both fall back to (0,0) and the server sees window-relative values.
+    // On a desktop session's root, it is the position on the root, in server pixels.
     pub fn absolute_position(&self, position: PhysicalPosition<f64>) -> (i32, i32) {
+        if self.desktop.is_some() {
+            return desktop::to_root(position.x, position.y, self.surface_size, (self.width, self.height));
+        }
         let origin = self.window.inner_position().unwrap_or(PhysicalPosition::new(0, 0));
         (origin.x + position.x as i32, origin.y + position.y as i32)
     }
@@ -191,3 +415,123 @@ impl XpraWindow {
         Some(PhysicalPosition::new(inner_x + (outer.x - inner.x), inner_y + (outer.y - inner.y)))
     }
 }
+
+// Whether this display can show a window with per-pixel alpha at all, which is what our hello's
+// `transparency` tells the server: without it, it flattens translucent windows onto black for us.
+// That takes an X11 session with a compositing manager - one owns the `_NET_WM_CM_S<screen>`
+// selection, which is how GTK answers the same question for xpra's own client
+// (gdk_screen_is_composited). Elsewhere softbuffer presents opaque pixels whatever we put in the
+// top byte: an Xrgb8888 buffer on Wayland, a plain BitBlt on Windows, and an image without alpha
+// on macOS.
+pub fn has_transparency(event_loop: &ActiveEventLoop) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        use winit::platform::x11::ActiveEventLoopExtX11;
+        if event_loop.is_x11() {
+            return match x11_compositing_manager() {
+                Ok(composited) => composited,
+                Err(e) => {
+                    debug!("cannot tell whether a compositing manager is running: {e}");
+                    false
+                }
+            };
+        }
+    }
+    let _ = event_loop;
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn x11_compositing_manager() -> Result<bool, Box<dyn std::error::Error>> {
+    use x11rb::protocol::xproto::ConnectionExt;
+    let (connection, screen) = x11rb::connect(None)?;
+    let name = format!("_NET_WM_CM_S{screen}");
+    // only_if_exists: no atom, no manager that ever claimed the selection
+    let atom = connection.intern_atom(true, name.as_bytes())?.reply()?.atom;
+    if atom == 0 {
+        return Ok(false);
+    }
+    Ok(connection.get_selection_owner(atom)?.reply()?.owner != 0)
+}
+
+// Apply the rectangles of a scroll draw to a `width`x`height` framebuffer. Every one of them moves
+// what was on screen *before* the packet, as xpra's own client does it (do_paint_scroll, xpra
+// client/gtk3/cairo_backing_base.py) - not what an earlier rectangle of the same packet has already
+// moved there, which matters when one's destination is another's source. So with more than one,
+// they all read from a copy of the old contents; a lone rectangle - the common case, and the one
+// with the most pixels - moves in place, walking its rows away from the direction it moves in so
+// that no row is overwritten before it has been copied (and `copy_within` takes care of overlap
+// within a row). Whatever falls outside the framebuffer, on either end, is clipped.
+fn scroll_framebuffer(framebuffer: &mut [u32], width: u32, height: u32, scrolls: &[Scroll]) {
+    let (width, height) = (width as i64, height as i64);
+    let old = if scrolls.len() > 1 { Some(framebuffer.to_vec()) } else { None };
+    for scroll in scrolls {
+        let (x, y, w, h) = (scroll.x as i64, scroll.y as i64, scroll.w as i64, scroll.h as i64);
+        let (dx, dy) = (scroll.dx as i64, scroll.dy as i64);
+        // the columns and rows that are on screen both where they come from and where they go
+        let x0 = x.max(0).max(-dx);
+        let x1 = (x + w).min(width).min(width - dx);
+        let y0 = y.max(0).max(-dy);
+        let y1 = (y + h).min(height).min(height - dy);
+        if x1 <= x0 || y1 <= y0 {
+            continue;
+        }
+        let len = (x1 - x0) as usize;
+        let rows: Box<dyn Iterator<Item = i64>> = if dy > 0 { Box::new((y0..y1).rev()) } else { Box::new(y0..y1) };
+        for row in rows {
+            let src = (row * width + x0) as usize;
+            let dst = ((row + dy) * width + x0 + dx) as usize;
+            match old {
+                Some(ref old) => framebuffer[dst..dst + len].copy_from_slice(&old[src..src + len]),
+                None => framebuffer.copy_within(src..src + len, dst),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scroll(x: i32, y: i32, w: u32, h: u32, dx: i32, dy: i32) -> Scroll {
+        Scroll { x, y, w, h, dx, dy }
+    }
+
+    // a 4x4 framebuffer where each pixel holds its own (row, column) as 0xRC
+    fn framebuffer() -> Vec<u32> {
+        (0..4).flat_map(|row| (0..4).map(move |col| row << 4 | col)).collect()
+    }
+
+    #[test]
+    fn overlapping_scrolls_move_in_place() {
+        // scroll the whole window up by one line, as a terminal does: the last row stays put
+        let mut fb = framebuffer();
+        scroll_framebuffer(&mut fb, 4, 4, &[scroll(0, 1, 4, 3, 0, -1)]);
+        assert_eq!(fb, [0x10, 0x11, 0x12, 0x13, 0x20, 0x21, 0x22, 0x23,
+                        0x30, 0x31, 0x32, 0x33, 0x30, 0x31, 0x32, 0x33]);
+        // and back down, which only comes out right walking the rows bottom up
+        let mut fb = framebuffer();
+        scroll_framebuffer(&mut fb, 4, 4, &[scroll(0, 0, 4, 3, 0, 1)]);
+        assert_eq!(fb, [0x00, 0x01, 0x02, 0x03, 0x00, 0x01, 0x02, 0x03,
+                        0x10, 0x11, 0x12, 0x13, 0x20, 0x21, 0x22, 0x23]);
+        // sideways within the rows, with the part that would land off screen clipped
+        let mut fb = framebuffer();
+        scroll_framebuffer(&mut fb, 4, 4, &[scroll(0, 0, 4, 1, 2, 0)]);
+        assert_eq!(fb[..4], [0x00, 0x01, 0x00, 0x01]);
+        assert_eq!(fb[4..], framebuffer()[4..]);
+    }
+
+    #[test]
+    fn scrolls_of_one_packet_all_read_the_old_contents() {
+        // row 0 moves to row 1 and row 1 to row 2: row 2 gets what row 1 was, not row 0
+        let mut fb = framebuffer();
+        scroll_framebuffer(&mut fb, 4, 4, &[scroll(0, 0, 4, 1, 0, 1), scroll(0, 1, 4, 1, 0, 1)]);
+        assert_eq!(fb, [0x00, 0x01, 0x02, 0x03, 0x00, 0x01, 0x02, 0x03,
+                        0x10, 0x11, 0x12, 0x13, 0x30, 0x31, 0x32, 0x33]);
+        // rectangles that lie entirely off screen, or move there, change nothing
+        let mut fb = framebuffer();
+        scroll_framebuffer(&mut fb, 4, 4, &[scroll(-8, 0, 4, 4, 1, 0), scroll(0, 0, 4, 4, 0, 9), scroll(0, 0, 4, 4, 0, 0)]);
+        assert_eq!(fb, framebuffer());
+    }
+}
@@ -0,0 +1,212 @@
+// Where a window stands among the others, from the metadata that comes with its `new-window`: the
+// window it is a transient of (`transient-for`, a dialog's owner), whether it is `modal`, what kind
+// of window it is (`window-type`, X11's _NET_WM_WINDOW_TYPE names without their prefix), its
+// `group-leader`, and its `role` (WM_WINDOW_ROLE, which window managers match their rules against).
+// xpra's own client hands these to GTK, which turns them into the same X11 properties, or into an
+// owned window on Windows. winit only goes part of the way: the window types on X11 and the owner
+// window on Windows. On X11 the rest is set with x11rb, on the window winit created, before it is
+// shown. Wayland and macOS have nothing to take any of it.
+//
+// All of them are only read when a window is created (as `has-alpha` is): applications set them
+// before mapping a window, and window managers only look at them then.
+use log::debug;
+use winit::window::{Window, WindowAttributes};
+
+#[cfg(target_os = "linux")]
+use super::window_properties::{atom, with_x11, x11_state, x11_window, X11Result};
+
+// xpra's `window-type` names
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WindowType {
+    #[default]
+    Normal,
+    Dialog,
+    Utility,
+    Splash,
+    Toolbar,
+    Menu,
+    DropdownMenu,
+    PopupMenu,
+    Tooltip,
+    Notification,
+    Combo,
+    Dnd,
+    Dock,
+    Desktop,
+}
+
+impl WindowType {
+    // The first of `names` we know: like _NET_WM_WINDOW_TYPE, the list goes from the most specific
+    // type to the ones to fall back on, such as KDE's "_KDE_NET_WM_WINDOW_TYPE_OVERRIDE" before
+    // "NORMAL".
+    pub fn parse(names: &[String]) -> Self {
+        names.iter().find_map(|name| {
+            Some(match name.strip_prefix("_NET_WM_WINDOW_TYPE_").unwrap_or(name) {
+                "NORMAL" => WindowType::Normal,
+                "DIALOG" => WindowType::Dialog,
+                "UTILITY" => WindowType::Utility,
+                "SPLASH" | "SPLASHSCREEN" => WindowType::Splash,
+                "TOOLBAR" => WindowType::Toolbar,
+                "MENU" => WindowType::Menu,
+                "DROPDOWN_MENU" => WindowType::DropdownMenu,
+                "POPUP_MENU" => WindowType::PopupMenu,
+                "TOOLTIP" => WindowType::Tooltip,
+                "NOTIFICATION" => WindowType::Notification,
+                "COMBO" => WindowType::Combo,
+                "DND" => WindowType::Dnd,
+                "DOCK" => WindowType::Dock,
+                "DESKTOP" => WindowType::Desktop,
+                _ => return None,
+            })
+        }).unwrap_or_default()
+    }
+
+    // The windows that have no place in the taskbar, as opposed to the application windows
+    // and their dialogs. X11 window managers already go by the window type.
+    #[cfg(any(windows, test))]
+    fn skips_taskbar(self) -> bool {
+        !matches!(self, WindowType::Normal | WindowType::Dialog)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct WindowHints {
+    // the wid of the window this one belongs to, and is kept above
+    pub owner: Option<u64>,
+    pub modal: bool,
+    pub window_type: WindowType,
+    pub role: Option<String>,
+}
+
+impl WindowHints {
+    pub fn new(wid: u64, transient_for: Option<u64>, group_leader: Option<u64>, modal: bool,
+               window_type: WindowType, role: Option<String>) -> Self {
+        // A dialog without a transient-for is one for its whole group (what ICCCM's WM_TRANSIENT_FOR
+        // pointing at the root window means), and the group leader is the closest thing to an
+        // owner we have for it. A window is never its own.
+        let owner = transient_for
+            .or(group_leader.filter(|_| window_type == WindowType::Dialog || modal))
+            .filter(|&owner| owner != wid);
+        WindowHints { owner, modal, window_type, role }
+    }
+
+    // Add what winit can do itself to the attributes of the window to create, given the one it
+    // belongs to - if that one still exists. A window that also needs `apply` is created hidden.
+    pub fn attributes(&self, attrs: WindowAttributes, owner: Option<&Window>) -> WindowAttributes {
+        #[cfg(target_os = "linux")]
+        let attrs = {
+            use winit::platform::x11::{WindowAttributesExtX11, WindowType as X11WindowType};
+            let x11_type = match self.window_type {
+                WindowType::Normal => X11WindowType::Normal,
+                WindowType::Dialog => X11WindowType::Dialog,
+                WindowType::Utility => X11WindowType::Utility,
+                WindowType::Splash => X11WindowType::Splash,
+                WindowType::Toolbar => X11WindowType::Toolbar,
+                WindowType::Menu => X11WindowType::Menu,
+                WindowType::DropdownMenu => X11WindowType::DropdownMenu,
+                WindowType::PopupMenu => X11WindowType::PopupMenu,
+                WindowType::Tooltip => X11WindowType::Tooltip,
+                WindowType::Notification => X11WindowType::Notification,
+                WindowType::Combo => X11WindowType::Combo,
+                WindowType::Dnd => X11WindowType::Dnd,
+                WindowType::Dock => X11WindowType::Dock,
+                WindowType::Desktop => X11WindowType::Desktop,
+            };
+            let attrs = attrs.with_x11_window_type(vec![x11_type]);
+            if self.x11_properties(owner) { attrs.with_visible(false) } else { attrs }
+        };
+        // An owned window stays above its owner, is minimized along with it, and is not in the
+        // taskbar: what a dialog, modal or not, should do. The other types have no Win32
+        // equivalent, only that most of them do not belong in the taskbar either.
+        #[cfg(windows)]
+        let attrs = {
+            use winit::platform::windows::WindowAttributesExtWindows;
+            use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+            let owner = owner.and_then(|owner| match owner.window_handle().map(|handle| handle.as_raw()) {
+                Ok(RawWindowHandle::Win32(handle)) => Some(handle.hwnd.get()),
+                _ => None,
+            });
+            let attrs = match owner {
+                Some(hwnd) => attrs.with_owner_window(hwnd),
+                None => attrs,
+            };
+            attrs.with_skip_taskbar(self.window_type.skips_taskbar())
+        };
+        #[cfg(not(any(target_os = "linux", windows)))]
+        let _ = owner;
+        attrs
+    }
+
+    // The rest, once the window exists: on X11, its WM_TRANSIENT_FOR, the modal state and its
+    // role - and then it can be shown.
+    pub fn apply(&self, window: &Window, owner: Option<&Window>) {
+        #[cfg(target_os = "linux")]
+        if self.x11_properties(owner) {
+            if let Err(e) = self.set_x11_properties(window, owner) {
+                debug!("cannot set the X11 properties of a {:?} window: {e}", self.window_type);
+            }
+            window.set_visible(true);
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (window, owner);
+    }
+
+    #[cfg(target_os = "linux")]
+    fn x11_properties(&self, owner: Option<&Window>) -> bool {
+        owner.is_some() || self.modal || self.role.is_some()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_x11_properties(&self, window: &Window, owner: Option<&Window>) -> X11Result {
+        use x11rb::protocol::xproto::{AtomEnum, PropMode};
+        use x11rb::wrapper::ConnectionExt as _;
+        with_x11(window, |connection, xid| {
+            if let Some(owner) = owner.and_then(x11_window) {
+                connection.change_property32(PropMode::REPLACE, xid, AtomEnum::WM_TRANSIENT_FOR, AtomEnum::WINDOW, &[owner])?;
+            }
+            if self.modal {
+                x11_state(connection, window, xid, "_NET_WM_STATE_MODAL", true)?;
+            }
+            if let Some(role) = &self.role {
+                let atom = atom(connection, "WM_WINDOW_ROLE")?;
+                connection.change_property8(PropMode::REPLACE, xid, atom, AtomEnum::STRING, role.as_bytes())?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn the_first_known_window_type_wins() {
+        assert_eq!(WindowType::parse(&names(&["DIALOG"])), WindowType::Dialog);
+        assert_eq!(WindowType::parse(&names(&["_KDE_NET_WM_WINDOW_TYPE_OVERRIDE", "NORMAL"])), WindowType::Normal);
+        assert_eq!(WindowType::parse(&names(&["_NET_WM_WINDOW_TYPE_UTILITY", "DIALOG"])), WindowType::Utility);
+        assert_eq!(WindowType::parse(&names(&["DROPDOWN_MENU"])), WindowType::DropdownMenu);
+        assert_eq!(WindowType::parse(&names(&["whatever"])), WindowType::Normal);
+        assert_eq!(WindowType::parse(&[]), WindowType::Normal);
+        assert!(WindowType::Utility.skips_taskbar() && !WindowType::Dialog.skips_taskbar());
+    }
+
+    #[test]
+    fn dialogs_belong_to_their_transient_for_or_group_leader() {
+        let hints = |transient_for, group_leader, modal, window_type| {
+            WindowHints::new(5, transient_for, group_leader, modal, window_type, None).owner
+        };
+        assert_eq!(hints(Some(1), Some(2), false, WindowType::Dialog), Some(1));
+        assert_eq!(hints(None, Some(2), false, WindowType::Dialog), Some(2));
+        assert_eq!(hints(None, Some(2), true, WindowType::Normal), Some(2));
+        // a plain window of the same group is not a transient of its leader
+        assert_eq!(hints(None, Some(2), false, WindowType::Normal), None);
+        assert_eq!(hints(Some(3), None, false, WindowType::Utility), Some(3));
+        // nor is the leader itself
+        assert_eq!(hints(None, Some(5), false, WindowType::Dialog), None);
+    }
+}
@@ -0,0 +1,288 @@
+// The window metadata winit has no API for, or only has on some platforms: `opacity`, `sticky`,
+// `skip-taskbar`, `skip-pager`, `workspace`, `class-instance` and `shadow`. Unlike the hints of
+// window_hints.rs these change while the window is shown - a window moved to another workspace
+// or made sticky on the server's side - so every one of them is applied whenever it comes in.
+//
+// On X11 they are the EWMH and ICCCM properties xpra's server read them from in the first place:
+// _NET_WM_WINDOW_OPACITY, the STICKY / SKIP_TASKBAR / SKIP_PAGER states of _NET_WM_STATE,
+// _NET_WM_DESKTOP and WM_CLASS, set with x11rb on the window winit created. A mapped window's state
+// and desktop belong to the window manager, which has to be asked for a change with a client
+// message to the root window. On Windows the closest are a layered window's alpha, winit's
+// skip-taskbar, the AppUserModelID the taskbar groups windows by, and winit's shadow for
+// undecorated windows; macOS only has the shadow. The server is only told about those a platform
+// can apply (see `supported`).
+use log::debug;
+use winit::event_loop::ActiveEventLoop;
+use winit::window::Window;
+
+// `workspace` when the window is on none in particular (xpra's WORKSPACE_UNSET): nothing to apply
+pub const WORKSPACE_UNSET: u32 = 0xFFFF;
+
+// The metadata keys of this file that can be applied here, for the `metadata.supported` list of
+// the hello
+pub fn supported(event_loop: &ActiveEventLoop) -> &'static [&'static str] {
+    #[cfg(target_os = "linux")]
+    {
+        use winit::platform::x11::ActiveEventLoopExtX11;
+        if event_loop.is_x11() {
+            return &["opacity", "sticky", "skip-taskbar", "skip-pager", "workspace", "class-instance"];
+        }
+        // on Wayland, the class is the window's app_id - which can only be given when it is created
+        &["class-instance"]
+    }
+    #[cfg(windows)]
+    {
+        let _ = event_loop;
+        &["opacity", "skip-taskbar", "class-instance", "shadow"]
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let _ = event_loop;
+        &["shadow"]
+    }
+    #[cfg(not(any(target_os = "linux", windows, target_os = "macos")))]
+    {
+        let _ = event_loop;
+        &[]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Sticky,
+    SkipTaskbar,
+    SkipPager,
+}
+
+// `opacity` is _NET_WM_WINDOW_OPACITY's 0 to 0xFFFFFFFF, and -1 (or nothing) for an opaque window
+pub fn set_opacity(window: &Window, opacity: Option<u32>) {
+    #[cfg(target_os = "linux")]
+    log_error("opacity", with_x11(window, |connection, xid| {
+        use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, PropMode};
+        use x11rb::wrapper::ConnectionExt as _;
+        let atom = atom(connection, "_NET_WM_WINDOW_OPACITY")?;
+        match opacity {
+            Some(opacity) => connection.change_property32(PropMode::REPLACE, xid, atom, AtomEnum::CARDINAL, &[opacity])?,
+            None => connection.delete_property(xid, atom)?,
+        };
+        Ok(())
+    }));
+    // a layered window is blended with what is behind it as a whole, at the alpha given
+    #[cfg(windows)]
+    log_error("opacity", win32_window(window).and_then(|hwnd| unsafe {
+        use windows::Win32::Foundation::COLORREF;
+        use windows::Win32::UI::WindowsAndMessaging::{
+            GetWindowLongPtrW, SetLayeredWindowAttributes, SetWindowLongPtrW, GWL_EXSTYLE, LWA_ALPHA, WS_EX_LAYERED,
+        };
+        let style = GetWindowLongPtrW(hwnd, GWL_EXSTYLE);
+        match opacity {
+            Some(opacity) => {
+                SetWindowLongPtrW(hwnd, GWL_EXSTYLE, style | WS_EX_LAYERED.0 as isize);
+                SetLayeredWindowAttributes(hwnd, COLORREF(0), (opacity >> 24) as u8, LWA_ALPHA)?;
+            }
+            None => {
+                SetWindowLongPtrW(hwnd, GWL_EXSTYLE, style & !(WS_EX_LAYERED.0 as isize));
+            }
+        }
+        Ok(())
+    }));
+    #[cfg(not(any(target_os = "linux", windows)))]
+    let _ = (window, opacity);
+}
+
+pub fn set_state(window: &Window, state: State, on: bool) {
+    #[cfg(target_os = "linux")]
+    log_error("state", with_x11(window, |connection, xid| {
+        let name = match state {
+            State::Sticky => "_NET_WM_STATE_STICKY",
+            State::SkipTaskbar => "_NET_WM_STATE_SKIP_TASKBAR",
+            State::SkipPager => "_NET_WM_STATE_SKIP_PAGER",
+        };
+        x11_state(connection, window, xid, name, on)
+    }));
+    #[cfg(windows)]
+    if state == State::SkipTaskbar {
+        use winit::platform::windows::WindowExtWindows;
+        window.set_skip_taskbar(on);
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    let _ = (window, state, on);
+}
+
+// the desktop number, or 0xFFFFFFFF for all of them
+pub fn set_workspace(window: &Window, workspace: u32) {
+    #[cfg(target_os = "linux")]
+    log_error("workspace", with_x11(window, |connection, xid| {
+        use x11rb::protocol::xproto::{AtomEnum, PropMode};
+        use x11rb::wrapper::ConnectionExt as _;
+        let atom = atom(connection, "_NET_WM_DESKTOP")?;
+        if window.is_visible() == Some(false) {
+            connection.change_property32(PropMode::REPLACE, xid, atom, AtomEnum::CARDINAL, &[workspace])?;
+            return Ok(());
+        }
+        x11_message(connection, xid, atom, [workspace, 1, 0, 0, 0])
+    }));
+    #[cfg(not(target_os = "linux"))]
+    let _ = (window, workspace);
+}
+
+// `class-instance` is WM_CLASS, instance first. On Linux, winit is also given it when it creates
+// the window (see XpraClient::process_new_common), which on Wayland is the only time it counts.
+pub fn set_class(window: &Window, instance: &str, class: &str) {
+    #[cfg(target_os = "linux")]
+    log_error("class", with_x11(window, |connection, xid| {
+        use x11rb::protocol::xproto::{AtomEnum, PropMode};
+        use x11rb::wrapper::ConnectionExt as _;
+        let value = format!("{instance}\0{class}\0");
+        connection.change_property8(PropMode::REPLACE, xid, AtomEnum::WM_CLASS, AtomEnum::STRING, value.as_bytes())?;
+        Ok(())
+    }));
+    // windows with the same AppUserModelID share a taskbar button, and are grouped apart from those
+    // of the client itself and of the other applications
+    #[cfg(windows)]
+    log_error("class", win32_window(window).and_then(|hwnd| unsafe {
+        use windows::core::PROPVARIANT;
+        use windows::Win32::Storage::EnhancedStorage::PKEY_AppUserModel_ID;
+        use windows::Win32::System::Com::StructuredStorage::{PropVariantChangeType, PVCHF_DEFAULT};
+        use windows::Win32::System::Variant::VT_LPWSTR;
+        use windows::Win32::UI::Shell::PropertiesSystem::{IPropertyStore, SHGetPropertyStoreForWindow};
+        let store: IPropertyStore = SHGetPropertyStoreForWindow(hwnd)?;
+        // the shell wants a plain wide string, where windows-rs makes a BSTR of a &str
+        let mut value = PROPVARIANT::default();
+        PropVariantChangeType(&mut value, &PROPVARIANT::from(app_user_model_id(instance, class).as_str()),
+                              PVCHF_DEFAULT, VT_LPWSTR)?;
+        store.SetValue(&PKEY_AppUserModel_ID, &value)?;
+        store.Commit()?;
+        Ok(())
+    }));
+    #[cfg(not(any(target_os = "linux", windows)))]
+    let _ = (window, instance, class);
+}
+
+pub fn set_shadow(window: &Window, shadow: bool) {
+    // only drawn for undecorated windows: a decorated one always has the frame's own
+    #[cfg(windows)]
+    {
+        use winit::platform::windows::WindowExtWindows;
+        window.set_undecorated_shadow(shadow);
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use winit::platform::macos::WindowExtMacOS;
+        window.set_has_shadow(shadow);
+    }
+    #[cfg(not(any(windows, target_os = "macos")))]
+    let _ = (window, shadow);
+}
+
+// "Xpra.<class>": at most 128 characters, and no spaces
+#[cfg(any(windows, test))]
+fn app_user_model_id(instance: &str, class: &str) -> String {
+    let name = if class.is_empty() { instance } else { class };
+    let name: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    let mut id = format!("Xpra.{name}");
+    id.truncate(128);
+    id
+}
+
+#[cfg(any(target_os = "linux", windows))]
+fn log_error<E: std::fmt::Display>(what: &str, result: Result<(), E>) {
+    if let Err(e) = result {
+        debug!("cannot set the window {what}: {e}");
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub type X11Result = Result<(), Box<dyn std::error::Error>>;
+
+// Run `f` with our own connection to the X server and the window's id, then wait for the server
+// to have handled it all: winit maps windows and asks the window manager for changes on a
+// connection of its own, which must not overtake ours. Nothing to do for a window that is not on X11.
+#[cfg(target_os = "linux")]
+pub fn with_x11(window: &Window, f: impl FnOnce(&x11rb::rust_connection::RustConnection, u32) -> X11Result) -> X11Result {
+    use std::sync::OnceLock;
+    use x11rb::wrapper::ConnectionExt as _;
+    static CONNECTION: OnceLock<Option<x11rb::rust_connection::RustConnection>> = OnceLock::new();
+    let Some(xid) = x11_window(window) else {
+        return Ok(());
+    };
+    let connection = CONNECTION.get_or_init(|| {
+        x11rb::connect(None)
+            .map(|(connection, _)| connection)
+            .map_err(|e| debug!("cannot connect to the X server: {e}"))
+            .ok()
+    }).as_ref().ok_or("no connection to the X server")?;
+    f(connection, xid)?;
+    connection.sync()?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn x11_window(window: &Window) -> Option<u32> {
+    use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    match window.window_handle().ok()?.as_raw() {
+        RawWindowHandle::Xlib(handle) => Some(handle.window as u32),
+        RawWindowHandle::Xcb(handle) => Some(handle.window.get()),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn atom(connection: &impl x11rb::connection::Connection, name: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    use x11rb::protocol::xproto::ConnectionExt as _;
+    Ok(connection.intern_atom(false, name.as_bytes())?.reply()?.atom)
+}
+
+// Add or remove a _NET_WM_STATE: in the property itself until the window is mapped, by asking the
+// window manager after that
+#[cfg(target_os = "linux")]
+pub fn x11_state(connection: &impl x11rb::connection::Connection, window: &Window, xid: u32, name: &str, on: bool) -> X11Result {
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _, PropMode};
+    use x11rb::wrapper::ConnectionExt as _;
+    let state = atom(connection, "_NET_WM_STATE")?;
+    let value = atom(connection, name)?;
+    if window.is_visible() == Some(false) {
+        let reply = connection.get_property(false, xid, state, AtomEnum::ATOM, 0, 1024)?.reply()?;
+        let mut states: Vec<u32> = reply.value32().into_iter().flatten().filter(|&other| other != value).collect();
+        if on {
+            states.push(value);
+        }
+        connection.change_property32(PropMode::REPLACE, xid, state, AtomEnum::ATOM, &states)?;
+        return Ok(());
+    }
+    // _NET_WM_STATE_REMOVE / _ADD, the state, no second one, and a normal application as the source
+    x11_message(connection, xid, state, [on as u32, value, 0, 1, 0])
+}
+
+#[cfg(target_os = "linux")]
+fn x11_message(connection: &impl x11rb::connection::Connection, xid: u32, message: u32, data: [u32; 5]) -> X11Result {
+    use x11rb::protocol::xproto::{ClientMessageEvent, ConnectionExt as _, EventMask};
+    let root = connection.get_geometry(xid)?.reply()?.root;
+    let event = ClientMessageEvent::new(32, xid, message, data);
+    connection.send_event(false, root, EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY, event)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn win32_window(window: &Window) -> windows::core::Result<windows::Win32::Foundation::HWND> {
+    use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
+    match window.window_handle().map(|handle| handle.as_raw()) {
+        Ok(RawWindowHandle::Win32(handle)) => Ok(windows::Win32::Foundation::HWND(handle.hwnd.get() as *mut _)),
+        _ => Err(windows::core::Error::from(windows::Win32::Foundation::E_HANDLE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_user_model_ids_are_made_of_the_class() {
+        assert_eq!(app_user_model_id("xterm", "XTerm"), "Xpra.XTerm");
+        assert_eq!(app_user_model_id("libreoffice", ""), "Xpra.libreoffice");
+        assert_eq!(app_user_model_id("x", "Google Chrome"), "Xpra.Google-Chrome");
+        assert_eq!(app_user_model_id("x", &"a".repeat(200)).len(), 128);
+    }
+}
@@ -7,6 +7,8 @@ pub enum ExitCode {
     Ok = 0,
     // the connection dropped after the session was established
     ConnectionLost = 1,
+    // packet encryption was asked for but cannot be had: no key, or a server that won't encrypt
+    Encryption = 6,
     Failure = 7,
     SshFailure = 8,
     // an unusable / unparseable packet from an established session
@@ -1,6 +1,7 @@
 extern crate alloc;
 
 use std::env;
+use std::fs;
 use std::net::TcpStream;
 #[cfg(unix)]
 use std::os::unix::net::UnixStream;
@@ -9,7 +10,8 @@ use std::process;
 use std::sync::Arc;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
-use log::{debug, error, info, LevelFilter};
+use std::time::Duration;
+use log::{debug, error, info, warn, LevelFilter};
 use softbuffer::Context;
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
@@ -18,15 +20,24 @@ use winit::window::WindowId;
 use xpra::CLIENT_VERSION;
 use xpra::exit_codes::ExitCode;
 use xpra::net::connection::Connection;
+use xpra::net::crypto::{CipherMode, Encryption};
+use xpra::net::known_hosts::KnownHosts;
+use xpra::net::proxy::{self, Proxy};
 use xpra::net::packet::Packet;
 use xpra::net::uri::{host_only, parse_target, Scheme, Target};
-use xpra::net::{ssh, tls, websocket};
+use xpra::net::{sessions, ssh, tls, websocket};
+use xpra::net::tls::{format_fingerprint, SharedTlsStream, TlsOptions};
+use xpra::net::websocket::{Upgrade, WebSocketOptions};
 
 mod client;
-use client::client::{client_packet, XpraClient};
+use client::auth_dialog::DialogAction;
+use client::certificate_dialog::CertificateDialog;
+use client::client::{client_packet, Reconnect, XpraClient};
 use client::connect_dialog::{ConnectAction, ConnectDetails, ConnectDialog};
+use client::decode_pool::DecodeRequest;
 use client::mmap::MmapArea;
 use client::remote_logging::{self, LogSink};
+use client::scaling::DesktopScaling;
 
 
 fn main() {
@@ -56,32 +67,74 @@ Targets:
   HOST:PORT                           plain tcp, the same as tcp://HOST:PORT/
   tcp://HOST:PORT/                    plain tcp
   ssl://HOST:PORT/                    tcp with TLS
-  ws://HOST:PORT/                     websocket over http
-  wss://HOST:PORT/                    websocket over https
-  ssh://[USER@]HOST[:PORT]/[DISPLAY]  tunnel through the system 'ssh' (port 22 by default)
+  ws://[USER:PASS@]HOST:PORT/         websocket over http, with Basic auth as USER
+  wss://[USER:PASS@]HOST:PORT/        websocket over https
+  ssh://[USER@]HOST[:PORT]/[DISPLAY]  tunnel through the system 'ssh' (port 22 by
+                                      default), DISPLAY being 10 or :10
   socket:///ABSOLUTE/PATH             Unix-domain socket (Unix only)
   /ABSOLUTE/PATH                      shorthand for socket:///ABSOLUTE/PATH (Unix only)
+  :DISPLAY, local:DISPLAY             the session on this display of this machine, found
+                                      in the xpra socket directories (Unix only)
+
+Add ?encryption=AES-GCM (or AES-CBC, AES-CTR) to a URI to encrypt the packets with
+a key taken from &keyfile=PATH, or else from the session password.
 
 ssl:// and wss:// verify the server's certificate chain and hostname against the
-system trust store. There is no way to trust a private CA yet, so a self-signed
-certificate needs --ssl-insecure.
+system trust store, or against --ssl-ca-certs. A certificate that fails this is
+shown to you to trust or not, by its fingerprint, and once trusted it is the only
+one that server may present (see the ssl-known-hosts file in the xpra config
+directory). Each --ssl-* or --ws-* option that takes a value can also be given to
+a single target as ?ssl-ca-certs=FILE, and so on.
 
 Options:
   -h, --help                          show this help and exit
       --version                       show the version and exit
+      --list-sessions                 list the sessions running on this machine, with
+                                      their state and server version, and exit
       --ssl-insecure                  connect to an ssl:// or wss:// server without
                                       verifying its certificate or hostname
+      --ssl-ca-certs=FILE             verify the server against the CA certificates
+                                      in this PEM file instead of the system's
+      --ssl-cert=FILE                 the client certificate, for servers that want
+                                      one: PEM, or a PKCS#12 bundle with its key
+      --ssl-key=FILE                  the PKCS#8 PEM key of --ssl-cert, when it is
+                                      not in the certificate file itself
+      --ssl-server-hostname=NAME      the name to send and verify the server's
+                                      certificate against, instead of the host's
+      --ssl-fingerprint=HEX           trust only the certificate with this SHA-256
+                                      fingerprint, and remember it for the server
+      --ws-header=NAME:VALUE          add this header to the websocket upgrade request
+                                      (may be given more than once)
+      --ws-token=TOKEN                authenticate the websocket upgrade request with
+                                      this Bearer token
+      --proxy=URL                     reach a tcp://, ssl://, ws:// or wss:// server
+                                      through http://[USER:PASS@]HOST[:PORT], or a
+                                      socks5:// or socks5h:// proxy
+      --reconnect[=SECONDS]           when the connection to a running session drops,
+                                      keep the windows and try to connect again, for
+                                      up to SECONDS (300 by default)
+      --desktop-scaling=auto|FACTOR   make the remote windows FACTOR times larger
+                                      (0.25 to 4), or as large as the display's own
+                                      scaling makes local ones with 'auto'
+      --desktop-fullscreen            show the remote desktop of a desktop or shadow
+                                      session fullscreen
 
 Environment:
   XPRA_PASSWORD     the session password, used to answer the server's authentication
                     challenge without prompting
   PINENTRY_PROGRAM  the pinentry binary to prompt with, when there is no XPRA_PASSWORD
+  XPRA_SSL_KEY_PASSWORD
+                    the password of a PKCS#12 --ssl-cert bundle
   XPRA_MMAP         'no' to switch off shared memory picture transfers, or the path of
                     the backing file to use for them (Linux only, on by default)
   XPRA_MMAP_DIR     the directory to create the shared memory file in (the temporary
                     directory by default)
   XPRA_MMAP_SIZE    the size of the shared memory area, with an optional K/M/G suffix
                     (128M by default, 64M minimum)
+  https_proxy, http_proxy, all_proxy
+                    the proxy for ssl:// and wss://, for ws://, and for any of them and
+                    tcp:// (as --proxy, which wins over them)
+  no_proxy          the hosts and domains to connect to directly, comma-separated
   NO_COLOR          never colour the log output
 
 See rust-xpra(1), or https://github.com/Xpra-org/rust-xpra, for the full documentation.
@@ -101,11 +154,29 @@ fn program_name(args: &[String]) -> &str {
 struct Options {
     // the target as the user typed it; `None` means the connection dialog collects one instead.
     target: Option<String>,
-    // `--ssl-insecure`: connect to an `ssl://`/`wss://` server without verifying its certificate
-    // chain or hostname. Off by default - see net::tls.
-    ssl_insecure: bool,
+    // `--ssl-insecure`, `--ssl-ca-certs=FILE`, ...: how to verify an `ssl://`/`wss://` server,
+    // and the client certificate to show it. The system trust store and no client certificate by
+    // default - see net::tls.
+    ssl: TlsOptions,
+    // `--ws-header=NAME:VALUE`, `--ws-token=TOKEN`: what to add to a websocket upgrade request
+    ws: WebSocketOptions,
+    // `--reconnect[=SECONDS]`: how long to keep trying to get a lost session back, if at all
+    reconnect: Option<Duration>,
+    // `--list-sessions`: print the sessions running on this machine instead of connecting to one
+    list_sessions: bool,
+    // `--proxy=URL`: the HTTP or SOCKS5 proxy to reach the server through, in place of whichever
+    // one the environment names (see net::proxy)
+    proxy: Option<Proxy>,
+    // `--desktop-scaling=auto|FACTOR`: how much larger to show the remote windows than the server
+    // draws them, if at all (see client::scaling)
+    desktop_scaling: Option<DesktopScaling>,
+    // `--desktop-fullscreen`: show the one window of a desktop or shadow session fullscreen
+    desktop_fullscreen: bool,
 }
 
+// `--reconnect` on its own: long enough to ride out a suspended laptop or a router restart.
+const DEFAULT_RECONNECT_TIMEOUT: Duration = Duration::from_secs(300);
+
 // Options and the target may come in either order, and there is at most one target. Unlike the
 // rest of the command line this is strict about spelling: an unrecognized `-...` argument is an
 // error rather than something to connect to, so a mistyped option can never be read as a hostname.
@@ -115,7 +186,40 @@ fn parse_args(args: &[String]) -> Result<Options, String> {
         match arg.as_str() {
             // dealt with before this runs, but they are still valid arguments:
             "-h" | "--help" | "--version" => {}
-            "--ssl-insecure" => options.ssl_insecure = true,
+            "--ssl-insecure" => options.ssl.insecure = true,
+            "--list-sessions" => options.list_sessions = true,
+            "--desktop-fullscreen" => options.desktop_fullscreen = true,
+            _ if arg.starts_with("--proxy=") => {
+                let url = &arg["--proxy=".len()..];
+                if url.is_empty() {
+                    return Err(format!("{:?} needs a value", "proxy"));
+                }
+                options.proxy = Some(Proxy::parse(url)?);
+            }
+            "--reconnect" => options.reconnect = Some(DEFAULT_RECONNECT_TIMEOUT),
+            _ if arg.starts_with("--reconnect=") => {
+                let seconds = arg["--reconnect=".len()..].parse::<u64>().ok().filter(|s| *s > 0)
+                    .ok_or_else(|| format!("{:?}: --reconnect takes a number of seconds", arg))?;
+                options.reconnect = Some(Duration::from_secs(seconds));
+            }
+            _ if arg.starts_with("--desktop-scaling=") => {
+                options.desktop_scaling = Some(DesktopScaling::parse(&arg["--desktop-scaling=".len()..])?);
+            }
+            _ if arg.starts_with("--ssl-") && arg.contains('=') => {
+                let (name, value) = arg[2..].split_once('=').unwrap_or_default();
+                // `--ssl-insecure=yes` included: a flag takes no value
+                if !tls::OPTIONS.contains(&name) {
+                    return Err(format!("unrecognized option {:?}", arg));
+                }
+                options.ssl.set(name, value)?;
+            }
+            _ if arg.starts_with("--ws-") && arg.contains('=') => {
+                let (name, value) = arg[2..].split_once('=').unwrap_or_default();
+                if !websocket::OPTIONS.contains(&name) {
+                    return Err(format!("unrecognized option {:?}", arg));
+                }
+                options.ws.set(name, value)?;
+            }
             _ if arg.starts_with('-') => return Err(format!("unrecognized option {:?}", arg)),
             _ => match &options.target {
                 Some(first) => return Err(format!("more than one target: {:?} and {:?}", first, arg)),
@@ -123,9 +227,28 @@ fn parse_args(args: &[String]) -> Result<Options, String> {
             },
         }
     }
+    if options.list_sessions && let Some(target) = &options.target {
+        return Err(format!("--list-sessions takes no target, not {:?}", target));
+    }
     Ok(options)
 }
 
+// `--list-sessions`: every session socket in the standard directories (see net::sessions), with
+// the state of its server and, for a live one that will tell us, its version - `xpra list` with
+// the version of `xpra version` added, one line each.
+fn list_sessions() -> ExitCode {
+    let sessions = sessions::sessions_in(&sessions::socket_dirs());
+    if sessions.is_empty() {
+        println!("no xpra sessions found");
+    }
+    for session in sessions {
+        let (state, version) = sessions::probe(&session.path);
+        let version = version.map(|version| format!("xpra {version}")).unwrap_or_else(|| "-".to_string());
+        println!(":{:<8}{:<9}{:<14}{}", session.display, state.name(), version, session.path.display());
+    }
+    ExitCode::Ok
+}
+
 fn run(log_sink: LogSink) -> ExitCode {
     let args: Vec<String> = env::args().collect();
     let program = program_name(&args);
@@ -151,10 +274,14 @@ fn run(log_sink: LogSink) -> ExitCode {
             return ExitCode::ArgumentMismatch;
         }
     };
-    let ssl_insecure = options.ssl_insecure;
+    if options.list_sessions {
+        return list_sessions();
+    }
     // with a target on the command line we connect before doing anything else, so that a bad
     // address is reported (and exited on) without ever opening a window. With no argument, the
-    // connection dialog collects one instead - see AppState below.
+    // connection dialog collects one instead - see AppState below. The one thing that needs a
+    // window first is a certificate to trust, which is asked about once the event loop runs.
+    let mut untrusted = None;
     let session = match &options.target {
         Some(target_str) => {
             let target = match parse_target(target_str) {
@@ -165,12 +292,24 @@ fn run(log_sink: LogSink) -> ExitCode {
                     return ExitCode::ArgumentMismatch;
                 }
             };
-            match connect(&target, ssl_insecure) {
-                Ok(connection) => Some((connection, target_str.clone())),
+            let encryption = match encryption(&target, None) {
+                Ok(encryption) => encryption,
                 Err((exit_code, message)) => {
                     error!("{}", message);
                     return exit_code;
                 }
+            };
+            match connect(&target, &options.ssl, options.proxy.as_ref(), &options.ws) {
+                Ok(connection) => Some((connection, target_str.clone(), encryption)),
+                Err(ConnectError::Failed(exit_code, message)) => {
+                    error!("{}", message);
+                    return exit_code;
+                }
+                Err(ConnectError::Untrusted(certificate)) => {
+                    let details = ConnectDetails { uri: target_str.clone(), username: None, password: None };
+                    untrusted = Some((details, certificate));
+                    None
+                }
             }
         }
         None => None,
@@ -186,18 +325,19 @@ fn run(log_sink: LogSink) -> ExitCode {
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
 
     // this channel is used for sending 'draw' packets from the UI thread to the decode thread:
-    let (decode_tx, decode_rx) = channel::<Packet>();
+    let (decode_tx, decode_rx) = channel::<DecodeRequest>();
     let proxy = event_loop.create_proxy();
     // the mmap area, if we can have one: offered to the server in the hello, and read from by the
     // decode thread in place of a decoder for the `mmap` encoding.
     let mmap = MmapArea::create().map(Arc::new);
     XpraClient::start_draw_decode_loop(proxy.clone(), decode_rx, mmap.clone());
 
-    let mut app = App::new(proxy, decode_tx, log_sink, mmap, ssl_insecure);
-    if let Some((connection, target)) = session {
+    let mut app = App::new(proxy, decode_tx, log_sink, mmap, options);
+    app.untrusted = untrusted;
+    if let Some((connection, target, encryption)) = session {
         // args[1] as typed, rather than the parsed target: it is what the user will recognise in
         // the system tray's tooltip and menu header (see client/tray.rs).
-        app.state = AppState::Session(app.new_client(connection, target, None, None));
+        app.state = AppState::Session(app.new_client(connection, target, None, None, encryption));
     }
     if let Err(e) = event_loop.run_app(&mut app) {
         error!("event loop error: {}", e);
@@ -219,19 +359,33 @@ struct App {
     // its window state; `None` once the session owns it (or until `resumed` runs).
     context: Option<Context<OwnedDisplayHandle>>,
     proxy: EventLoopProxy<Packet>,
-    decode_sender: Sender<Packet>,
+    decode_sender: Sender<DecodeRequest>,
     log_sink: LogSink,
     // the shared memory area for mmap picture transfers (client/mmap.rs), created once for the
     // process and shared with the decode thread. It depends on nothing but its own size, so it is
     // ready before we know whether the target came from the command line or from the dialog.
     mmap: Option<Arc<MmapArea>>,
-    // `--ssl-insecure`, applied to whatever the dialog ends up connecting to (the flag is given
-    // before the protocol is picked, so `connect` is what rejects it on a non-TLS target).
-    ssl_insecure: bool,
-    // the connection attempt started from the dialog: what the user asked for, and the channel the
-    // worker thread hands the outcome back on (see start_connect / finish_connect).
-    pending: Option<ConnectDetails>,
-    connect_rx: Option<Receiver<Result<Connection, (ExitCode, String)>>>,
+    // the `--ssl-*` options, applied to whatever the dialog ends up connecting to (they are given
+    // before the protocol is picked, so `connect` is what rejects them on a non-TLS target).
+    ssl: TlsOptions,
+    // the `--ws-*` options, likewise (only ws:// and wss:// take them)
+    ws: WebSocketOptions,
+    // `--proxy`, likewise - `None` leaves the choice to the environment, target by target
+    proxy_server: Option<Proxy>,
+    // `--reconnect`'s give-up timeout, for whatever session we end up with
+    reconnect: Option<Duration>,
+    // `--desktop-scaling` and `--desktop-fullscreen`, likewise
+    desktop_scaling: Option<DesktopScaling>,
+    desktop_fullscreen: bool,
+    // the connection attempt started from the dialog: what the user asked for (and the encryption
+    // that implies), and the channel the worker thread hands the outcome back on (see
+    // start_connect / finish_connect).
+    pending: Option<(ConnectDetails, Option<Encryption>)>,
+    connect_rx: Option<Receiver<Result<Connection, ConnectError>>>,
+    // a server certificate that failed verification, awaiting the user's verdict in the
+    // certificate dialog - and what to connect to again, with it pinned, if they trust it.
+    untrusted: Option<(ConnectDetails, UntrustedCertificate)>,
+    certificate_dialog: Option<CertificateDialog>,
     // set when the dialog is cancelled or cannot be shown; the session's own exit code wins.
     exit_code: Option<ExitCode>,
 }
@@ -248,8 +402,10 @@ enum AppState {
 const CONNECT_RESULT: &str = "connect-result";
 
 impl App {
-    fn new(proxy: EventLoopProxy<Packet>, decode_sender: Sender<Packet>, log_sink: LogSink,
-           mmap: Option<Arc<MmapArea>>, ssl_insecure: bool) -> Self {
+    // `options` are what the command line says about every connection we make, whoever picks the
+    // target.
+    fn new(proxy: EventLoopProxy<Packet>, decode_sender: Sender<DecodeRequest>, log_sink: LogSink,
+           mmap: Option<Arc<MmapArea>>, options: Options) -> Self {
         App {
             state: AppState::Prompt(None),
             context: None,
@@ -257,9 +413,16 @@ impl App {
             decode_sender,
             log_sink,
             mmap,
-            ssl_insecure,
+            ssl: options.ssl,
+            ws: options.ws,
+            proxy_server: options.proxy,
+            reconnect: options.reconnect,
+            desktop_scaling: options.desktop_scaling,
+            desktop_fullscreen: options.desktop_fullscreen,
             pending: None,
             connect_rx: None,
+            untrusted: None,
+            certificate_dialog: None,
             exit_code: None,
         }
     }
@@ -270,6 +433,7 @@ impl App {
         target: String,
         username: Option<String>,
         password: Option<String>,
+        encryption: Option<Encryption>,
     ) -> XpraClient {
         let mut client = XpraClient::new(
             connection,
@@ -281,6 +445,24 @@ impl App {
         );
         client.username = username;
         client.password = password;
+        client.encryption = encryption;
+        client.desktop_scaling = self.desktop_scaling;
+        client.desktop_fullscreen = self.desktop_fullscreen;
+        client.reconnect = self.reconnect.and_then(|timeout| {
+            let target = parse_target(&client.target).ok()?;
+            let ssl = self.ssl.clone();
+            let ws = self.ws.clone();
+            let proxy_server = self.proxy_server.clone();
+            // the same server, the same way: a certificate the user trusted the first time is in
+            // the known hosts store by now, so one that is not is one that changed
+            let connect = move || connect(&target, &ssl, proxy_server.as_ref(), &ws).map_err(|error| match error {
+                ConnectError::Failed(exit_code, message) => (exit_code, message),
+                ConnectError::Untrusted(certificate) => {
+                    (ExitCode::SslFailure, format!("the certificate of {} is no longer trusted", certificate.address))
+                }
+            });
+            Some(Reconnect { connect: Arc::new(connect), timeout })
+        });
         client
     }
 
@@ -309,20 +491,43 @@ impl App {
         .unwrap_or(ExitCode::Ok)
     }
 
+    // the softbuffer context the dialogs paint through, created by whichever is shown first
+    fn context(&mut self, event_loop: &ActiveEventLoop) -> Option<&Context<OwnedDisplayHandle>> {
+        if self.context.is_none() {
+            match Context::new(event_loop.owned_display_handle()) {
+                Ok(context) => self.context = Some(context),
+                Err(e) => {
+                    error!("failed to create the softbuffer context: {:?}", e);
+                    self.quit(event_loop, ExitCode::InternalError);
+                }
+            }
+        }
+        self.context.as_ref()
+    }
+
     fn show_dialog(&mut self, event_loop: &ActiveEventLoop) {
-        let context = match Context::new(event_loop.owned_display_handle()) {
-            Ok(context) => context,
+        let Some(context) = self.context(event_loop) else {
+            return;
+        };
+        match ConnectDialog::new(event_loop, context) {
+            Ok(dialog) => self.state = AppState::Prompt(Some(dialog)),
             Err(e) => {
-                error!("failed to create the softbuffer context: {:?}", e);
+                error!("{e}");
                 self.quit(event_loop, ExitCode::InternalError);
-                return;
             }
+        }
+    }
+
+    fn show_certificate_dialog(&mut self, event_loop: &ActiveEventLoop) {
+        let Some((_, certificate)) = &self.untrusted else {
+            return;
         };
-        match ConnectDialog::new(event_loop, &context) {
-            Ok(dialog) => {
-                self.context = Some(context);
-                self.state = AppState::Prompt(Some(dialog));
-            }
+        let (address, fingerprint) = (certificate.address.clone(), certificate.fingerprint.clone());
+        let Some(context) = self.context(event_loop) else {
+            return;
+        };
+        match CertificateDialog::new(event_loop, context, address, &fingerprint) {
+            Ok(dialog) => self.certificate_dialog = Some(dialog),
             Err(e) => {
                 error!("{e}");
                 self.quit(event_loop, ExitCode::InternalError);
@@ -330,6 +535,58 @@ impl App {
         }
     }
 
+    fn handle_certificate_dialog_event(&mut self, event_loop: &ActiveEventLoop, event: WindowEvent) {
+        let Some(dialog) = self.certificate_dialog.as_mut() else {
+            return;
+        };
+        let action = match event {
+            WindowEvent::RedrawRequested | WindowEvent::Resized(_) => {
+                dialog.draw();
+                return;
+            }
+            // a key still held from typing the target must not answer the question for the user
+            WindowEvent::KeyboardInput { is_synthetic: true, .. } => return,
+            WindowEvent::KeyboardInput { event: key_event, .. } => dialog.handle_key(&key_event),
+            WindowEvent::CloseRequested => DialogAction::Cancel,
+            _ => return,
+        };
+        match action {
+            DialogAction::None => {}
+            DialogAction::Submit => {
+                self.certificate_dialog = None;
+                if let Some((details, certificate)) = self.untrusted.take() {
+                    info!("trusting the certificate of {} ({})", certificate.address,
+                          format_fingerprint(&certificate.fingerprint));
+                    // connecting with it pinned is also what records it, see `connect` - unless a
+                    // websocket redirect took us to that server, which the next attempt starts
+                    // somewhere else again: it goes straight into the store then.
+                    let redirected = parse_target(&details.uri).is_ok_and(|target| target.address != certificate.address);
+                    if !redirected {
+                        self.start_connect(event_loop, details, Some(certificate.fingerprint));
+                        return;
+                    }
+                    let pinned = KnownHosts::open()
+                        .ok_or_else(|| "there is no xpra config directory".to_string())
+                        .and_then(|store| store.set(&certificate.address, &certificate.fingerprint));
+                    match pinned {
+                        Ok(()) => self.start_connect(event_loop, details, None),
+                        Err(e) => {
+                            let message = format!("the certificate of {} cannot be remembered: {}", certificate.address, e);
+                            self.connect_failed(event_loop, ExitCode::SslFailure, message);
+                        }
+                    }
+                }
+            }
+            DialogAction::Cancel => {
+                self.certificate_dialog = None;
+                if let Some((_, certificate)) = self.untrusted.take() {
+                    let message = format!("the certificate of {} was not trusted", certificate.address);
+                    self.connect_failed(event_loop, ExitCode::SslFailure, message);
+                }
+            }
+        }
+    }
+
     fn handle_dialog_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
         let Some(dialog) = self.dialog() else {
             return;
@@ -369,7 +626,7 @@ impl App {
                 info!("connection cancelled");
                 self.quit(event_loop, ExitCode::Ok);
             }
-            ConnectAction::Connect(details) => self.start_connect(details),
+            ConnectAction::Connect(details) => self.start_connect(event_loop, details, None),
         }
     }
 
@@ -377,13 +634,19 @@ impl App {
     // out, and ssh may be waiting on a host-key confirmation), and the UI thread must keep drawing
     // the dialog. The outcome comes back the way the pinentry and decode threads report theirs -
     // over a channel, with a synthesized client-side packet to wake the UI thread up.
-    fn start_connect(&mut self, details: ConnectDetails) {
+    // `fingerprint` is the certificate the user just chose to trust, if that is why we are here.
+    fn start_connect(&mut self, event_loop: &ActiveEventLoop, details: ConnectDetails, fingerprint: Option<String>) {
         let target = match parse_target(&details.uri) {
             Ok(target) => target,
             Err(message) => {
-                if let Some(dialog) = self.dialog() {
-                    dialog.set_error(message);
-                }
+                self.connect_failed(event_loop, ExitCode::ArgumentMismatch, message);
+                return;
+            }
+        };
+        let encryption = match encryption(&target, details.password.as_deref()) {
+            Ok(encryption) => encryption,
+            Err((exit_code, message)) => {
+                self.connect_failed(event_loop, exit_code, message);
                 return;
             }
         };
@@ -393,35 +656,49 @@ impl App {
         }
         let (tx, rx) = channel();
         self.connect_rx = Some(rx);
-        self.pending = Some(details);
+        self.pending = Some((details, encryption));
         let proxy = self.proxy.clone();
-        let ssl_insecure = self.ssl_insecure;
+        let mut ssl = self.ssl.clone();
+        if fingerprint.is_some() {
+            ssl.fingerprint = fingerprint;
+        }
+        let ws = self.ws.clone();
+        let proxy_server = self.proxy_server.clone();
         thread::Builder::new().name("connect".to_string()).spawn(move || {
-            let _ = tx.send(connect(&target, ssl_insecure));
+            let _ = tx.send(connect(&target, &ssl, proxy_server.as_ref(), &ws));
             let _ = proxy.send_event(client_packet(CONNECT_RESULT, ""));
         }).unwrap();
     }
 
     fn finish_connect(&mut self, event_loop: &ActiveEventLoop) {
-        let (Some(rx), Some(details)) = (self.connect_rx.take(), self.pending.take()) else {
+        let (Some(rx), Some((details, encryption))) = (self.connect_rx.take(), self.pending.take()) else {
             return;
         };
         match rx.try_recv() {
-            Ok(Ok(connection)) => self.start_session(event_loop, connection, details),
-            Ok(Err((_exit_code, message))) => {
-                // unlike the command-line path this is not fatal: report it in the dialog and let
-                // the user correct the details and try again.
-                error!("{message}");
-                if let Some(dialog) = self.dialog() {
-                    dialog.set_error(message);
-                }
+            Ok(Ok(connection)) => self.start_session(event_loop, connection, details, encryption),
+            Ok(Err(ConnectError::Failed(exit_code, message))) => self.connect_failed(event_loop, exit_code, message),
+            Ok(Err(ConnectError::Untrusted(certificate))) => {
+                self.untrusted = Some((details, certificate));
+                self.show_certificate_dialog(event_loop);
             }
             Err(e) => error!("no connection result: {e}"),
         }
     }
 
-    fn start_session(&mut self, event_loop: &ActiveEventLoop, connection: Connection, details: ConnectDetails) {
-        let mut client = self.new_client(connection, details.uri, details.username, details.password);
+    // Unlike the command-line path, a failure from the dialog is not fatal: report it there and let
+    // the user correct the details and try again. Without the dialog - the command line's target,
+    // after asking about its certificate - it ends the process like any other failure to connect.
+    fn connect_failed(&mut self, event_loop: &ActiveEventLoop, exit_code: ExitCode, message: String) {
+        error!("{message}");
+        match self.dialog() {
+            Some(dialog) => dialog.set_error(message),
+            None => self.quit(event_loop, exit_code),
+        }
+    }
+
+    fn start_session(&mut self, event_loop: &ActiveEventLoop, connection: Connection, details: ConnectDetails,
+                     encryption: Option<Encryption>) {
+        let mut client = self.new_client(connection, details.uri, details.username, details.password, encryption);
         // hand over the dialog's softbuffer context rather than making a second one; dropping the
         // dialog below closes its window.
         client.softbuffer_ctx = self.context.take();
@@ -440,6 +717,7 @@ impl ApplicationHandler<Packet> for App {
         match &mut self.state {
             AppState::Session(client) => client.resumed(event_loop),
             AppState::Prompt(Some(_)) => {}
+            AppState::Prompt(None) if self.untrusted.is_some() => self.show_certificate_dialog(event_loop),
             AppState::Prompt(None) => self.show_dialog(event_loop),
         }
     }
@@ -450,7 +728,7 @@ impl ApplicationHandler<Packet> for App {
             return;
         }
         // nothing generates packets before there is a session, bar the connect worker:
-        if packet.len() > 0 && packet.get_str(0) == CONNECT_RESULT {
+        if packet.packet_type() == CONNECT_RESULT {
             self.finish_connect(event_loop);
         } else {
             debug!("ignoring {:?} received before the session started", packet);
@@ -462,53 +740,249 @@ impl ApplicationHandler<Packet> for App {
             client.window_event(event_loop, window_id, event);
             return;
         }
+        if self.certificate_dialog.as_ref().map(|d| d.window.id()) == Some(window_id) {
+            self.handle_certificate_dialog_event(event_loop, event);
+            return;
+        }
         self.handle_dialog_event(event_loop, window_id, event);
     }
 }
 
 
+// The packet encryption the target's `encryption=` option asks for, if any (see net::crypto). The
+// key comes from the `keyfile=` option when there is one, and is the session password otherwise:
+// the one typed in the connection dialog, or XPRA_PASSWORD. Either way it has to be known before
+// the first hello, which already tells the server how to encrypt what it sends us - so, unlike an
+// authentication challenge, this can't fall back to prompting.
+fn encryption(target: &Target, password: Option<&str>) -> Result<Option<Encryption>, (ExitCode, String)> {
+    let keyfile = target.options.get("keyfile");
+    let Some(cipher) = target.options.get("encryption") else {
+        if keyfile.is_some() {
+            return Err((ExitCode::ArgumentMismatch, "'keyfile' only applies with 'encryption'".to_string()));
+        }
+        return Ok(None);
+    };
+    let mode = CipherMode::parse(cipher).map_err(|e| (ExitCode::ArgumentMismatch, e))?;
+    let secret = match keyfile {
+        Some(path) => {
+            let data = fs::read(path).map_err(|e| {
+                (ExitCode::Encryption, format!("failed to read the encryption key file {:?}: {}", path, e))
+            })?;
+            // a key written with `echo` ends in a newline that is not part of it, as xpra knows
+            data.strip_suffix(b"\n").map(|d| d.strip_suffix(b"\r").unwrap_or(d)).unwrap_or(&data).to_vec()
+        }
+        None => password.map(str::to_string)
+            .or_else(|| env::var("XPRA_PASSWORD").ok())
+            .unwrap_or_default()
+            .into_bytes(),
+    };
+    if secret.is_empty() {
+        return Err((ExitCode::Encryption,
+                    "encryption needs a key: a 'keyfile' option, or the session password".to_string()));
+    }
+    Ok(Some(Encryption { mode, secret }))
+}
+
+// The command line's TLS options, with those of the target URI's own `ssl-*` options on top: they
+// are the more specific of the two.
+fn tls_options(target: &Target, command_line: &TlsOptions) -> Result<TlsOptions, (ExitCode, String)> {
+    let mut options = command_line.clone();
+    for (name, value) in target.options.iter().filter(|(name, _)| name.starts_with("ssl-")) {
+        options.set(name, value).map_err(|e| (ExitCode::ArgumentMismatch, e))?;
+    }
+    Ok(options)
+}
+
+// The same for the `--ws-*` options, plus the Basic auth credentials of a `ws://USER:PASSWORD@`
+// target, which only the URI can give.
+fn ws_options(target: &Target, command_line: &WebSocketOptions) -> Result<WebSocketOptions, (ExitCode, String)> {
+    let mut options = command_line.clone();
+    for (name, value) in target.options.iter().filter(|(name, _)| name.starts_with("ws-")) {
+        options.set(name, value).map_err(|e| (ExitCode::ArgumentMismatch, e))?;
+    }
+    if target.username.is_some() && target.scheme != Scheme::Ssh {
+        options.username = target.username.clone();
+        options.password = target.password.clone();
+    }
+    Ok(options)
+}
+
+// Why `connect` gave us no connection.
+#[derive(Debug)]
+enum ConnectError {
+    Failed(ExitCode, String),
+    // not a failure yet: the user has to say whether to trust this certificate (see below)
+    Untrusted(UntrustedCertificate),
+}
+
+impl From<(ExitCode, String)> for ConnectError {
+    fn from((exit_code, message): (ExitCode, String)) -> Self {
+        ConnectError::Failed(exit_code, message)
+    }
+}
+
+// The certificate of an `ssl://`/`wss://` server that failed verification, and that nothing is
+// pinned for: `address` as the target gave it (the known hosts store's key), and the SHA-256
+// fingerprint of the certificate, in lowercase hex.
+#[derive(Debug)]
+struct UntrustedCertificate {
+    address: String,
+    fingerprint: String,
+}
+
 // Failures here mean we never had a session at all, so they map to the "failed to connect"
 // family of exit codes rather than `ConnectionLost`.
-fn connect(target: &Target, ssl_insecure: bool) -> Result<Connection, (ExitCode, String)> {
-    // there is nothing to skip verifying on a connection that has no certificate: say so rather
-    // than let the option pass unnoticed. The dialog reports this the same way it reports a bad
-    // host, since the protocol is only picked once the flag has already been given.
-    if ssl_insecure && !matches!(target.scheme, Scheme::Tls | Scheme::WebSocketTls) {
-        return Err((ExitCode::ArgumentMismatch,
-                    "--ssl-insecure only applies to ssl:// and wss:// connections".to_string()));
-    }
-    let tcp_connect = || {
-        TcpStream::connect(&target.address).map_err(|e| {
+//
+// ssl/wss servers given no other way to verify them (no --ssl-insecure or --ssl-ca-certs) get
+// SSH-style trust on first use: a certificate that the system trust store does not vouch for is
+// shown to the user, who may pin it (see net::known_hosts), and from then on the server must
+// present that same certificate, whatever the trust store says. --ssl-fingerprint pins one without
+// asking, in the store as well.
+//
+// The tcp connection under tcp/ssl/ws/wss goes through `proxy_server` if there is one, or else
+// through the proxy the environment names for the target, if any: TLS and websocket handshakes
+// alike then run inside the tunnel (see net::proxy).
+//
+// A websocket upgrade that is redirected is followed, up to MAX_REDIRECTS times, with a fresh
+// connection to wherever it points (see websocket::redirect_target). What was given for the target
+// - a --ssl-fingerprint, --ssl-server-hostname, the client certificate, --ws-header and --ws-token
+// - stays with its host, and a server elsewhere is verified, or trusted on first use, in its own
+// right.
+fn connect(target: &Target, ssl: &TlsOptions, proxy_server: Option<&Proxy>, ws: &WebSocketOptions)
+           -> Result<Connection, ConnectError> {
+    let mut current = target.clone();
+    // a local display's socket is looked for on every connection, reconnections included: its
+    // server may be a new one by then, listening on a socket of its own
+    if let Some(display) = &target.display {
+        current.address = sessions::find_socket(display).map_err(|e| (ExitCode::ConnectionFailed, e))?
+            .to_string_lossy().into_owned();
+    }
+    let (mut ssl, mut ws) = (ssl.clone(), ws.clone());
+    for _ in 0..=MAX_REDIRECTS {
+        let location = match connect_once(&current, &ssl, proxy_server, &ws)? {
+            Attempt::Connected(connection) => return Ok(connection),
+            Attempt::Redirected(location) => location,
+        };
+        let redirected = websocket::redirect_target(&current, &location)
+            .map_err(|e| (ExitCode::ConnectionFailed, format!("websocket handshake failed: {}", e)))?;
+        info!("the websocket upgrade was redirected to {}", location);
+        if host_only(&redirected.address) != host_only(&current.address) {
+            ssl.fingerprint = None;
+            ssl.cert = None;
+            ssl.key = None;
+            ssl.server_hostname = None;
+            ws.headers.clear();
+            ws.token = None;
+        }
+        current = redirected;
+    }
+    Err(ConnectError::Failed(ExitCode::ConnectionFailed,
+                             format!("websocket handshake failed: more than {} redirects", MAX_REDIRECTS)))
+}
+
+// as many as curl follows by default is far more than a reverse proxy needs: past this, it is a loop
+const MAX_REDIRECTS: usize = 5;
+
+// What one connection attempt came to.
+enum Attempt {
+    Connected(Connection),
+    // the websocket upgrade's `Location`: try again there
+    Redirected(String),
+}
+
+fn connect_once(target: &Target, ssl: &TlsOptions, proxy_server: Option<&Proxy>, ws: &WebSocketOptions)
+                -> Result<Attempt, ConnectError> {
+    let mut ssl = tls_options(target, ssl)?;
+    let ws = ws_options(target, ws)?;
+    if ws.is_set() && !matches!(target.scheme, Scheme::WebSocket | Scheme::WebSocketTls) {
+        return Err(ConnectError::Failed(ExitCode::ArgumentMismatch,
+                                        "the --ws-* options only apply to ws:// and wss:// connections".to_string()));
+    }
+    // there is nothing to verify on a connection that has no certificate: say so rather than let
+    // the option pass unnoticed. The dialog reports this the same way it reports a bad host, since
+    // the protocol is only picked once the flags have already been given.
+    if ssl.is_set() && !matches!(target.scheme, Scheme::Tls | Scheme::WebSocketTls) {
+        let options = if ssl.insecure { "--ssl-insecure only applies" } else { "the --ssl-* options only apply" };
+        return Err(ConnectError::Failed(ExitCode::ArgumentMismatch,
+                                        format!("{} to ssl:// and wss:// connections", options)));
+    }
+    if proxy_server.is_some() && matches!(target.scheme, Scheme::Ssh | Scheme::Socket) {
+        return Err(ConnectError::Failed(ExitCode::ArgumentMismatch,
+                                        "--proxy only applies to tcp://, ssl://, ws:// and wss:// connections".to_string()));
+    }
+    let proxy_server = match proxy_server {
+        Some(proxy_server) => Some(proxy_server.clone()),
+        None => proxy::from_env(target.scheme, &target.address).map_err(|e| (ExitCode::ArgumentMismatch, e))?,
+    };
+    let trust_on_first_use = matches!(target.scheme, Scheme::Tls | Scheme::WebSocketTls)
+        && !ssl.insecure && ssl.ca_certs.is_none();
+    let known_hosts = if trust_on_first_use { KnownHosts::open() } else { None };
+    let mut pinned_in = None;
+    if let Some(store) = &known_hosts && ssl.fingerprint.is_none() {
+        ssl.fingerprint = store.get(&target.address).map_err(|e| (ExitCode::SslFailure, e))?;
+        pinned_in = ssl.fingerprint.as_ref().map(|_| store.path());
+    }
+    let tcp_connect = || match &proxy_server {
+        Some(proxy_server) => proxy_server.connect(&target.address).map_err(|e| (ExitCode::ConnectionFailed, e)),
+        None => TcpStream::connect(&target.address).map_err(|e| {
             (ExitCode::ConnectionFailed, format!("failed to connect to {:?}: {}", target.address, e))
-        })
+        }),
     };
-    let tls_connect = |stream| {
-        tls::connect(stream, host_only(&target.address), ssl_insecure).map_err(|e| {
-            // a self-signed certificate is the likely cause on an xpra server, and there is no way
-            // to trust a private CA yet, so point at the one option that gets past it.
-            let hint = if ssl_insecure { "" } else { " (--ssl-insecure skips verification)" };
-            (ExitCode::SslFailure, format!("tls handshake failed: {}{}", e, hint))
-        })
+    let tls_connect = || -> Result<SharedTlsStream, ConnectError> {
+        let hostname = host_only(&target.address);
+        let error = match tls::connect(tcp_connect()?, hostname, &ssl) {
+            Ok(stream) => {
+                // a pin the user just gave us (on the command line, or in the dialog) is kept
+                if let (Some(store), Some(fingerprint), None) = (&known_hosts, &ssl.fingerprint, pinned_in)
+                    && let Err(e) = store.set(&target.address, fingerprint) {
+                    warn!("the certificate of {} cannot be remembered: {}", target.address, e);
+                }
+                return Ok(stream);
+            }
+            Err(e) => e,
+        };
+        let hint = if let Some(path) = pinned_in {
+            format!(" (if it was replaced on purpose, trust the new one with --ssl-fingerprint, or remove the line \
+                      for {} from {:?})", target.address, path)
+        } else if ssl.fingerprint.is_some() || !trust_on_first_use {
+            String::new()
+        } else {
+            // whatever failed, a server we can complete a handshake with once we stop verifying it
+            // has a certificate the user can look at (if the handshake still fails, verifying it
+            // was not the problem)
+            if let Ok(fingerprint) = tls::peer_fingerprint(tcp_connect()?, hostname) {
+                let address = target.address.clone();
+                return Err(ConnectError::Untrusted(UntrustedCertificate { address, fingerprint }));
+            }
+            // a private CA is the likely cause on an xpra server, so point at the options that
+            // get past it.
+            " (--ssl-ca-certs trusts a private CA, --ssl-insecure skips verification)".to_string()
+        };
+        Err(ConnectError::Failed(ExitCode::SslFailure, format!("tls handshake failed: {}{}", error, hint)))
     };
     // the websocket handshake is generic over the underlying stream (tcp or tls), so it can't
     // be wrapped in a closure the way the other two are:
     let ws_error = |e| (ExitCode::ConnectionFailed, format!("websocket handshake failed: {}", e));
-    match target.scheme {
-        Scheme::Tcp => Ok(Connection::Tcp(tcp_connect()?)),
-        Scheme::Tls => Ok(Connection::Tls(tls_connect(tcp_connect()?)?)),
+    let connection = match target.scheme {
+        Scheme::Tcp => Connection::Tcp(tcp_connect()?),
+        Scheme::Tls => Connection::Tls(tls_connect()?),
         Scheme::WebSocket => {
-            let ws = websocket::connect(tcp_connect()?, &target.address, &target.path).map_err(ws_error)?;
-            Ok(Connection::WebSocket(ws))
+            match websocket::connect(tcp_connect()?, &target.address, &target.path, &ws).map_err(ws_error)? {
+                Upgrade::Connected(ws) => Connection::WebSocket(ws),
+                Upgrade::Redirected(location) => return Ok(Attempt::Redirected(location)),
+            }
         }
         Scheme::WebSocketTls => {
-            let tls_stream = tls_connect(tcp_connect()?)?;
-            let ws = websocket::connect(tls_stream, &target.address, &target.path).map_err(ws_error)?;
-            Ok(Connection::WebSocketTls(ws))
+            let tls_stream = tls_connect()?;
+            match websocket::connect(tls_stream, &target.address, &target.path, &ws).map_err(ws_error)? {
+                Upgrade::Connected(ws) => Connection::WebSocketTls(ws),
+                Upgrade::Redirected(location) => return Ok(Attempt::Redirected(location)),
+            }
         }
         Scheme::Ssh => {
             let ssh_stream = ssh::connect(&target.address, target.username.as_deref(), &target.path)
                 .map_err(|e| (ExitCode::SshFailure, format!("ssh connection failed: {}", e)))?;
-            Ok(Connection::Ssh(ssh_stream))
+            Connection::Ssh(ssh_stream)
         }
         Scheme::Socket => {
             #[cfg(unix)]
@@ -517,15 +991,16 @@ fn connect(target: &Target, ssl_insecure: bool) -> Result<Connection, (ExitCode,
                     (ExitCode::ConnectionFailed,
                      format!("failed to connect to Unix-domain socket {:?}: {}", target.address, e))
                 })?;
-                Ok(Connection::Socket(stream))
+                Connection::Socket(stream)
             }
             #[cfg(not(unix))]
             {
-                Err((ExitCode::ConnectionFailed,
-                     "Unix-domain socket connections are not supported on this platform".to_string()))
+                return Err(ConnectError::Failed(ExitCode::ConnectionFailed,
+                     "Unix-domain socket connections are not supported on this platform".to_string()));
             }
         }
-    }
+    };
+    Ok(Attempt::Connected(connection))
 }
 
 
@@ -556,7 +1031,7 @@ mod tests {
         ] {
             let options = parse(&args).unwrap();
             assert_eq!(options.target.as_deref(), Some("ssl://example.com:10000/"));
-            assert!(options.ssl_insecure);
+            assert!(options.ssl.insecure);
         }
     }
 
@@ -564,13 +1039,13 @@ mod tests {
     fn no_arguments_means_the_dialog_and_verified_certificates() {
         let options = parse(&[]).unwrap();
         assert_eq!(options.target, None);
-        assert!(!options.ssl_insecure);
+        assert!(!options.ssl.insecure);
     }
 
     #[test]
     fn a_target_alone_verifies_certificates() {
         let options = parse(&["wss://example.com:10000/"]).unwrap();
-        assert!(!options.ssl_insecure);
+        assert!(!options.ssl.insecure);
     }
 
     // a mistyped option must never be taken for a hostname to connect to:
@@ -591,12 +1066,159 @@ mod tests {
     #[test]
     fn ssl_insecure_is_rejected_for_socket_targets() {
         let target = parse_target("socket:///tmp/xpra-test.sock").unwrap();
-        let error = match connect(&target, true) {
-            Ok(_) => panic!("socket target unexpectedly accepted --ssl-insecure"),
-            Err(error) => error,
+        let (code, message) = match connect(&target, &TlsOptions { insecure: true, ..TlsOptions::default() }, None, &WebSocketOptions::default()) {
+            Err(ConnectError::Failed(code, message)) => (code, message),
+            _ => panic!("socket target unexpectedly accepted --ssl-insecure"),
+        };
+        assert_eq!(code, ExitCode::ArgumentMismatch);
+        assert!(message.contains("only applies to ssl:// and wss://"), "{message}");
+    }
+
+    #[test]
+    fn ssl_options_take_a_value_and_the_target_overrides_them() {
+        let options = parse(&["--ssl-ca-certs=/etc/ca.pem", "--ssl-cert=/tmp/client.p12",
+                              "--ssl-server-hostname=xpra.internal", "wss://10.0.0.1:443/"]).unwrap();
+        assert_eq!(options.ssl.ca_certs.as_deref(), Some("/etc/ca.pem"));
+        assert_eq!(options.ssl.cert.as_deref(), Some("/tmp/client.p12"));
+        assert_eq!(options.ssl.key, None);
+        assert_eq!(options.ssl.server_hostname.as_deref(), Some("xpra.internal"));
+        assert!(parse(&["--ssl-ca-certs="]).err().unwrap().contains("needs a value"));
+        assert!(parse(&["--ssl-ca-certs", "/etc/ca.pem"]).is_err());
+        assert!(parse(&["--ssl-cafile=/etc/ca.pem"]).is_err());
+        assert!(parse(&["--ssl-fingerprint=AB:CD"]).err().unwrap().contains("not a SHA-256 fingerprint"));
+        let pinned = parse(&[&format!("--ssl-fingerprint={}", ["0F"; 32].join(":"))]).unwrap();
+        assert_eq!(pinned.ssl.fingerprint, Some("0f".repeat(32)));
+
+        let target = parse_target("ssl://host:443/?ssl-ca-certs=/other/ca.pem&ssl-key=/tmp/key.pem").unwrap();
+        let merged = tls_options(&target, &options.ssl).unwrap();
+        assert_eq!(merged.ca_certs.as_deref(), Some("/other/ca.pem"));
+        assert_eq!(merged.key.as_deref(), Some("/tmp/key.pem"));
+        assert_eq!(merged.server_hostname.as_deref(), Some("xpra.internal"));
+
+        for uri in ["tcp://host:10000/?ssl-ca-certs=/etc/ca.pem", "ws://host:10000/"] {
+            let (code, message) = match connect(&parse_target(uri).unwrap(), &merged, None, &WebSocketOptions::default()) {
+                Err(ConnectError::Failed(code, message)) => (code, message),
+                _ => panic!("{uri} unexpectedly accepted the ssl options"),
+            };
+            assert_eq!(code, ExitCode::ArgumentMismatch);
+            assert!(message.contains("--ssl-* options only apply"), "{message}");
+        }
+    }
+
+    #[test]
+    fn proxy_is_parsed_and_refused_for_ssh_and_socket_targets() {
+        let options = parse(&["--proxy=socks5://proxy.corp", "tcp://a:10000/"]).unwrap();
+        assert_eq!(options.proxy.as_ref().map(|proxy| proxy.address.as_str()), Some("proxy.corp:1080"));
+        assert!(parse(&["--proxy="]).err().unwrap().contains("needs a value"));
+        assert!(parse(&["--proxy=ftp://proxy.corp"]).err().unwrap().contains("unsupported proxy protocol"));
+        for uri in ["ssh://host/10", "socket:///tmp/xpra-test.sock"] {
+            let (code, message) = match connect(&parse_target(uri).unwrap(), &TlsOptions::default(), options.proxy.as_ref(),
+                                                 &WebSocketOptions::default()) {
+                Err(ConnectError::Failed(code, message)) => (code, message),
+                _ => panic!("{uri} unexpectedly accepted --proxy"),
+            };
+            assert_eq!(code, ExitCode::ArgumentMismatch);
+            assert!(message.contains("--proxy only applies"), "{message}");
+        }
+    }
+
+    #[test]
+    fn ws_options_apply_to_websockets_only_and_redirects_are_bounded() {
+        let options = parse(&["--ws-header=X-Tenant: 7", "--ws-token=abc", "ws://a:80/"]).unwrap();
+        assert_eq!(options.ws.headers.len(), 1);
+        assert_eq!(options.ws.token.as_deref(), Some("abc"));
+        assert!(parse(&["--ws-headers=X: 1"]).is_err());
+        assert!(parse(&["--ws-header=Upgrade: h2c"]).err().unwrap().contains("handshake's own"));
+        let (code, message) = match connect(&parse_target("tcp://a:10000/").unwrap(), &TlsOptions::default(), None,
+                                            &options.ws) {
+            Err(ConnectError::Failed(code, message)) => (code, message),
+            _ => panic!("a tcp target unexpectedly accepted the ws options"),
         };
+        assert_eq!(code, ExitCode::ArgumentMismatch);
+        assert!(message.contains("--ws-* options only apply"), "{message}");
+
+        // a server that sends every upgrade back to itself
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            for _ in 0..=MAX_REDIRECTS {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut byte = [0u8; 1];
+                while !request.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).unwrap();
+                    request.push(byte[0]);
+                }
+                stream.write_all(b"HTTP/1.1 302 Found\r\nLocation: /again\r\n\r\n").unwrap();
+            }
+        });
+        let target = parse_target(&format!("ws://{address}/")).unwrap();
+        let message = match connect(&target, &TlsOptions::default(), None, &WebSocketOptions::default()) {
+            Err(ConnectError::Failed(_, message)) => message,
+            _ => panic!("the redirect loop was not caught"),
+        };
+        server.join().unwrap();
+        assert!(message.contains("more than 5 redirects"), "{message}");
+    }
+
+    #[test]
+    fn reconnecting_is_opt_in_with_an_optional_timeout() {
+        assert_eq!(parse(&["tcp://a:10000/"]).unwrap().reconnect, None);
+        assert_eq!(parse(&["--reconnect", "tcp://a:10000/"]).unwrap().reconnect, Some(DEFAULT_RECONNECT_TIMEOUT));
+        assert_eq!(parse(&["--reconnect=30"]).unwrap().reconnect, Some(Duration::from_secs(30)));
+        for bad in ["--reconnect=0", "--reconnect=", "--reconnect=5m", "--reconnect=-1"] {
+            assert!(parse(&[bad]).err().unwrap().contains("takes a number of seconds"), "{bad}");
+        }
+        assert!(parse(&["--reconnects"]).is_err());
+    }
+
+    #[test]
+    fn desktop_scaling_is_off_unless_asked_for() {
+        assert_eq!(parse(&["tcp://a:10000/"]).unwrap().desktop_scaling, None);
+        assert_eq!(parse(&["--desktop-scaling=auto"]).unwrap().desktop_scaling, Some(DesktopScaling::Auto));
+        assert_eq!(parse(&["--desktop-scaling=2"]).unwrap().desktop_scaling, Some(DesktopScaling::Factor(2.0)));
+        for bad in ["--desktop-scaling=", "--desktop-scaling=10", "--desktop-scaling"] {
+            assert!(parse(&[bad]).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn desktop_fullscreen_is_a_flag() {
+        assert!(!parse(&["tcp://a:10000/"]).unwrap().desktop_fullscreen);
+        assert!(parse(&["--desktop-fullscreen", "tcp://a:10000/"]).unwrap().desktop_fullscreen);
+        assert!(parse(&["--desktop-fullscreen=yes"]).is_err());
+    }
+
+    #[test]
+    fn listing_sessions_connects_to_none_of_them() {
+        assert!(!parse(&[":10"]).unwrap().list_sessions);
+        assert!(parse(&["--list-sessions"]).unwrap().list_sessions);
+        let error = parse(&["--list-sessions", ":10"]).err().unwrap();
+        assert!(error.contains("--list-sessions takes no target"), "{error}");
+    }
+
+    #[test]
+    fn encryption_options_need_each_other_and_a_key() {
+        let target = |uri: &str| parse_target(uri).unwrap();
+        assert!(encryption(&target("tcp://host:10000/"), None).unwrap().is_none());
+        let error = encryption(&target("tcp://host:10000/?keyfile=/key"), None).err().unwrap();
+        assert_eq!(error.0, ExitCode::ArgumentMismatch);
+        let error = encryption(&target("tcp://host:10000/?encryption=AES-OFB"), Some("pw")).err().unwrap();
         assert_eq!(error.0, ExitCode::ArgumentMismatch);
-        assert!(error.1.contains("only applies to ssl:// and wss://"), "{}", error.1);
+
+        let with_password = encryption(&target("tcp://host:10000/?encryption=aes-cbc"), Some("pw")).unwrap().unwrap();
+        assert_eq!(with_password.mode, CipherMode::Cbc);
+        assert_eq!(with_password.secret, b"pw");
+
+        let keyfile = std::env::temp_dir().join(format!("rust-xpra-test-{}.key", process::id()));
+        fs::write(&keyfile, "secret\n").unwrap();
+        let uri = format!("tcp://host:10000/?encryption=AES-GCM&keyfile={}", keyfile.display());
+        let from_file = encryption(&target(&uri), Some("pw"));
+        let _ = fs::remove_file(&keyfile);
+        assert_eq!(from_file.unwrap().unwrap().secret, b"secret");
+        let error = encryption(&target(&uri), None).err().unwrap();
+        assert_eq!(error.0, ExitCode::Encryption);
     }
 
     // --help and --version act before parse_args runs, but must still parse as valid arguments:
@@ -621,7 +1243,7 @@ mod tests {
         ));
         let listener = UnixListener::bind(&socket_path).unwrap();
         let target = parse_target(socket_path.to_str().unwrap()).unwrap();
-        let mut connection = connect(&target, false).unwrap();
+        let mut connection = connect(&target, &TlsOptions::default(), None, &WebSocketOptions::default()).unwrap();
         let (mut server, _) = listener.accept().unwrap();
 
         connection.write_all(b"client to server").unwrap();
@@ -642,13 +1264,25 @@ mod tests {
         std::fs::remove_file(socket_path).unwrap();
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn local_displays_must_have_a_live_session() {
+        let target = parse_target(":4294967295").unwrap();
+        let (code, message) = match connect(&target, &TlsOptions::default(), None, &WebSocketOptions::default()) {
+            Err(ConnectError::Failed(code, message)) => (code, message),
+            _ => panic!("a display without a session unexpectedly connected"),
+        };
+        assert_eq!(code, ExitCode::ConnectionFailed);
+        assert!(message.contains("no live session found for display :4294967295"), "{message}");
+    }
+
     #[cfg(not(unix))]
     #[test]
     fn socket_target_reports_platform_support_error() {
         let target = parse_target("socket:///tmp/xpra-test.sock").unwrap();
-        let (code, message) = match connect(&target, false) {
-            Ok(_) => panic!("socket target unexpectedly connected on a non-Unix platform"),
-            Err(error) => error,
+        let (code, message) = match connect(&target, &TlsOptions::default(), None, &WebSocketOptions::default()) {
+            Err(ConnectError::Failed(code, message)) => (code, message),
+            _ => panic!("socket target unexpectedly connected on a non-Unix platform"),
         };
         assert_eq!(code, ExitCode::ConnectionFailed);
         assert!(message.contains("not supported on this platform"), "{message}");
@@ -0,0 +1,479 @@
+// xpra's packet encryption (xpra net/crypto.py): AES over the packet payloads, with the key
+// stretched from a shared secret - a key file or the session password - by PBKDF2. It works over
+// any transport, but its point is plain `tcp://` (and `ws://`) on networks where a TLS setup is
+// more trouble than it is worth.
+//
+// Each direction has its own parameters - AES mode, IV, PBKDF2 salt, hash and iteration count -
+// chosen by the receiving end and sent to the peer in its hello: we pick the ones for what the
+// server sends us (see `CipherParams::new`), and the server's hello carries the ones it wants for
+// what we send it. Everything travels through one continuous cipher stream per direction, exactly
+// as python's `cryptography` encryptor does when `update()` is called once per packet: CBC chains
+// from one packet into the next, CTR and GCM carry on with the keystream. xpra never finalizes a
+// GCM stream, so there is no authentication tag and "GCM" is only GCM's counter layout.
+//
+// CBC needs whole blocks, so every packet is padded PKCS#7 style to a multiple of the mode's block
+// size, always by at least one byte. The header's size field gives the unpadded length and the
+// receiver works the padding out for itself (see `Cipher::padded_size`), which is also why the
+// block size is negotiated: xpra uses 32 rather than AES's 16 for CBC.
+use aes::Aes256;
+use aes::cipher::{BlockDecryptMut, BlockEncrypt, BlockEncryptMut, KeyInit, KeyIvInit, StreamCipher};
+use ghash::GHash;
+use ghash::universal_hash::UniversalHash;
+use serde_json::{json, Value};
+use yaml_rust2::Yaml;
+
+use super::packet::{yaml_binary, yaml_i64, yaml_str};
+use super::rand::secure_hex;
+use super::sha1::hmac_sha1;
+use super::sha256::hmac_sha256;
+
+// AES-256: the only key size xpra uses (DEFAULT_KEY_SIZE).
+const KEY_SIZE: usize = 32;
+const CBC_BLOCK_SIZE: usize = 32;
+// what we ask the server to stretch our key with. The iteration count only costs anything once per
+// connection, at either end.
+const KEY_HASH: &str = "SHA256";
+const KEY_STRETCH_ITERATIONS: u32 = 10_000;
+// the most iterations we accept from the server: anything more is no longer a key stretch but a
+// way to keep us busy.
+const MAX_KEY_STRETCH_ITERATIONS: u32 = 1_000_000;
+const PADDING: &str = "PKCS#7";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CipherMode {
+    Gcm,
+    Cbc,
+    Ctr,
+}
+
+impl CipherMode {
+
+    // the `encryption=` spelling, which is also how xpra names its ciphers
+    pub fn parse(name: &str) -> Result<CipherMode, String> {
+        match name.to_ascii_uppercase().as_str() {
+            "AES-GCM" => Ok(CipherMode::Gcm),
+            "AES-CBC" => Ok(CipherMode::Cbc),
+            "AES-CTR" => Ok(CipherMode::Ctr),
+            _ => Err(format!("unsupported encryption {:?}: only 'AES-GCM', 'AES-CBC' and 'AES-CTR' are supported", name)),
+        }
+    }
+
+    fn mode_name(self) -> &'static str {
+        match self {
+            CipherMode::Gcm => "GCM",
+            CipherMode::Cbc => "CBC",
+            CipherMode::Ctr => "CTR",
+        }
+    }
+
+    fn block_size(self) -> usize {
+        match self {
+            CipherMode::Cbc => CBC_BLOCK_SIZE,
+            CipherMode::Gcm | CipherMode::Ctr => 0,
+        }
+    }
+}
+
+
+// What the user asked for (`encryption=` on the target URI): the mode both directions use - we ask
+// for it, and a server may pick another for its own - and the secret both keys are stretched from.
+pub struct Encryption {
+    pub mode: CipherMode,
+    pub secret: Vec<u8>,
+}
+
+
+// The parameters of one direction's cipher, as exchanged in the hellos.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CipherParams {
+    pub mode: CipherMode,
+    pub iv: Vec<u8>,
+    pub key_salt: Vec<u8>,
+    pub key_hash: String,
+    pub iterations: u32,
+    pub block_size: usize,
+}
+
+impl CipherParams {
+
+    // Fresh parameters for the server to encrypt with. The IV and the salt are random hex: ASCII,
+    // so they survive either packet encoder as text, and the server takes them as the bytes of that
+    // text.
+    pub fn new(mode: CipherMode) -> CipherParams {
+        CipherParams {
+            mode,
+            iv: secure_hex(8).into_bytes(),
+            key_salt: secure_hex(32).into_bytes(),
+            key_hash: KEY_HASH.to_string(),
+            iterations: KEY_STRETCH_ITERATIONS,
+            block_size: mode.block_size(),
+        }
+    }
+
+    // The hello capabilities describing these parameters: the `encryption` dict current servers
+    // read, and the flat `cipher.*` keys of older ones (xpra 4.x), which newer servers ignore.
+    pub fn caps(&self) -> Value {
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        let mut encryption = json!({
+            "cipher": "AES",
+            "mode": self.mode.mode_name(),
+            "iv": text(&self.iv),
+            "key_salt": text(&self.key_salt),
+            "key_size": KEY_SIZE,
+            "key_hash": self.key_hash,
+            "key_stretch": "PBKDF2",
+            "key_stretch_iterations": self.iterations,
+            "padding": { "options": [PADDING] },
+            "always-pad": false,
+            "stream": true,
+        });
+        if self.block_size > 0 {
+            encryption["block_size"] = json!(self.block_size);
+        }
+        let mut caps = json!({
+            "encryption": encryption,
+            "cipher": "AES",
+            "cipher.padding.options": [PADDING],
+        });
+        for key in ["mode", "iv", "key_salt", "key_size", "key_hash", "key_stretch_iterations", "block_size"] {
+            if let Some(value) = caps["encryption"].get(key).cloned() {
+                caps[format!("cipher.{key}")] = value;
+            }
+        }
+        caps
+    }
+
+    // The parameters the server wants us to encrypt with, from its hello: `None` when there are
+    // none at all, meaning the server is not encrypting this connection.
+    pub fn from_hello(hello: &Yaml) -> Result<Option<CipherParams>, String> {
+        let nested = &hello["encryption"];
+        let get = |key: &str| -> &Yaml {
+            if let Yaml::Hash(_) = nested {
+                &nested[key]
+            } else {
+                &hello[format!("cipher.{key}").as_str()]
+            }
+        };
+        let cipher = match nested {
+            Yaml::Hash(_) => yaml_str(&nested["cipher"]),
+            _ => yaml_str(&hello["cipher"]),
+        };
+        if cipher.is_empty() {
+            return Ok(None);
+        }
+        // the mode may also come as part of the cipher's name, as in "AES-CBC"
+        let mode = match get("mode") {
+            Yaml::String(mode) if !cipher.contains('-') => format!("{}-{}", cipher, mode),
+            _ => cipher.clone(),
+        };
+        let mode = CipherMode::parse(&mode)?;
+        let key_size = yaml_i64(get("key_size"));
+        if key_size != 0 && key_size != KEY_SIZE as i64 {
+            return Err(format!("unsupported key size {}", key_size));
+        }
+        let key_hash = match yaml_str(get("key_hash")) {
+            hash if hash.is_empty() => "SHA1".to_string(),
+            hash => hash.to_ascii_uppercase(),
+        };
+        if key_hash != "SHA1" && key_hash != "SHA256" {
+            return Err(format!("unsupported key hash {:?}", key_hash));
+        }
+        let iterations = yaml_i64(get("key_stretch_iterations"));
+        if iterations <= 0 || iterations > MAX_KEY_STRETCH_ITERATIONS as i64 {
+            return Err(format!("invalid key stretch iterations {}", iterations));
+        }
+        let block_size = match yaml_i64(get("block_size")) {
+            0 => mode.block_size(),
+            size if size > 0 && size < 256 && size % 16 == 0 => size as usize,
+            size => return Err(format!("invalid block size {}", size)),
+        };
+        if mode == CipherMode::Cbc && block_size == 0 {
+            return Err("CBC needs a block size".to_string());
+        }
+        let padding = match nested {
+            Yaml::Hash(_) => &nested["padding"]["options"],
+            _ => &hello["cipher.padding.options"],
+        };
+        if let Yaml::Array(options) = padding
+            && !options.contains(&Yaml::String(PADDING.to_string())) {
+            return Err(format!("unsupported padding {:?}", options));
+        }
+        Ok(Some(CipherParams {
+            mode,
+            // the IV is always text, a hex string
+            iv: yaml_str(get("iv")).into_bytes(),
+            key_salt: salt_bytes(get("key_salt")),
+            key_hash,
+            iterations: iterations as u32,
+            block_size,
+        }))
+    }
+}
+
+// The salt of a python server is raw random bytes, ours is text - a hex string. Which one came is
+// up to the wire: a byte string that isn't valid UTF-8 is binary, see net::rencode and net::serde,
+// anything else is the text it reads as, whatever it may look like.
+fn salt_bytes(value: &Yaml) -> Vec<u8> {
+    yaml_binary(value).unwrap_or_else(|| yaml_str(value).into_bytes())
+}
+
+
+enum CipherState {
+    Gcm(ctr::Ctr32BE<Aes256>),
+    Ctr(ctr::Ctr128BE<Aes256>),
+    CbcEncrypt(cbc::Encryptor<Aes256>),
+    CbcDecrypt(cbc::Decryptor<Aes256>),
+}
+
+// One direction of an encrypted connection.
+pub struct Cipher {
+    state: CipherState,
+    block_size: usize,
+}
+
+impl Cipher {
+
+    pub fn encryptor(params: &CipherParams, secret: &[u8]) -> Result<Cipher, String> {
+        Cipher::new(params, secret, true)
+    }
+
+    pub fn decryptor(params: &CipherParams, secret: &[u8]) -> Result<Cipher, String> {
+        Cipher::new(params, secret, false)
+    }
+
+    fn new(params: &CipherParams, secret: &[u8], encrypt: bool) -> Result<Cipher, String> {
+        let mut key = [0u8; KEY_SIZE];
+        let prf = match params.key_hash.as_str() {
+            "SHA1" => |key: &[u8], msg: &[u8]| hmac_sha1(key, msg).to_vec(),
+            _ => |key: &[u8], msg: &[u8]| hmac_sha256(key, msg).to_vec(),
+        };
+        pbkdf2(prf, secret, &params.key_salt, params.iterations, &mut key);
+        let iv: Option<[u8; 16]> = params.iv.as_slice().try_into().ok();
+        let state = match (params.mode, iv) {
+            (CipherMode::Gcm, _) if !params.iv.is_empty() => {
+                CipherState::Gcm(ctr::Ctr32BE::new(&key.into(), &gcm_counter(&key, &params.iv).into()))
+            }
+            (CipherMode::Ctr, Some(iv)) => CipherState::Ctr(ctr::Ctr128BE::new(&key.into(), &iv.into())),
+            (CipherMode::Cbc, Some(iv)) if encrypt => CipherState::CbcEncrypt(cbc::Encryptor::new(&key.into(), &iv.into())),
+            (CipherMode::Cbc, Some(iv)) => CipherState::CbcDecrypt(cbc::Decryptor::new(&key.into(), &iv.into())),
+            _ => return Err(format!("invalid IV length {}", params.iv.len())),
+        };
+        Ok(Cipher{ state, block_size: params.block_size })
+    }
+
+    // how many bytes a payload of `size` bytes takes on the wire, padding included
+    pub fn padded_size(&self, size: usize) -> usize {
+        if self.block_size == 0 {
+            return size;
+        }
+        size + self.block_size - size % self.block_size
+    }
+
+    pub fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut buf = data.to_vec();
+        let padding = self.padded_size(data.len()) - data.len();
+        buf.resize(data.len() + padding, padding as u8);
+        self.apply(&mut buf);
+        buf
+    }
+
+    // decrypt a payload read off the wire, `padded_size(size)` bytes of it, back to `size` bytes
+    pub fn decrypt(&mut self, mut data: Vec<u8>, size: usize) -> Vec<u8> {
+        self.apply(&mut data);
+        data.truncate(size);
+        data
+    }
+
+    fn apply(&mut self, buf: &mut [u8]) {
+        match &mut self.state {
+            CipherState::Gcm(stream) => stream.apply_keystream(buf),
+            CipherState::Ctr(stream) => stream.apply_keystream(buf),
+            // whole blocks only, which the padding guarantees:
+            CipherState::CbcEncrypt(cbc) => {
+                for block in buf.chunks_exact_mut(16) {
+                    cbc.encrypt_block_mut(block.into());
+                }
+            }
+            CipherState::CbcDecrypt(cbc) => {
+                for block in buf.chunks_exact_mut(16) {
+                    cbc.decrypt_block_mut(block.into());
+                }
+            }
+        }
+    }
+}
+
+// The first counter block GCM encrypts data with: inc32(J0), where J0 is the IV with a 32-bit
+// counter of 1 appended for a 96-bit IV, and the GHASH of the IV for any other length - such as
+// the 16 bytes xpra uses (NIST SP 800-38D, 7.1).
+fn gcm_counter(key: &[u8; KEY_SIZE], iv: &[u8]) -> [u8; 16] {
+    let mut j0 = [0u8; 16];
+    if iv.len() == 12 {
+        j0[..12].copy_from_slice(iv);
+        j0[15] = 1;
+    } else {
+        let mut h = [0u8; 16];
+        Aes256::new(key.into()).encrypt_block((&mut h).into());
+        let mut ghash = GHash::new(&h.into());
+        ghash.update_padded(iv);
+        let mut lengths = [0u8; 16];
+        lengths[8..].copy_from_slice(&(iv.len() as u64 * 8).to_be_bytes());
+        ghash.update(&[lengths.into()]);
+        j0 = ghash.finalize().into();
+    }
+    let counter = u32::from_be_bytes([j0[12], j0[13], j0[14], j0[15]]).wrapping_add(1);
+    j0[12..].copy_from_slice(&counter.to_be_bytes());
+    j0
+}
+
+// PBKDF2 (RFC 8018, 5.2) over the HMAC `prf`, filling `out`.
+fn pbkdf2(prf: fn(&[u8], &[u8]) -> Vec<u8>, password: &[u8], salt: &[u8], iterations: u32, out: &mut [u8]) {
+    let mut filled = 0;
+    let mut block_index = 1u32;
+    while filled < out.len() {
+        let mut msg = salt.to_vec();
+        msg.extend_from_slice(&block_index.to_be_bytes());
+        let mut u = prf(password, &msg);
+        let mut t = u.clone();
+        for _ in 1..iterations {
+            u = prf(password, &u);
+            t.iter_mut().zip(&u).for_each(|(t, u)| *t ^= u);
+        }
+        let len = t.len().min(out.len() - filled);
+        out[filled..filled + len].copy_from_slice(&t[..len]);
+        filled += len;
+        block_index += 1;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust2::YamlLoader;
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // RFC 6070 and its SHA256 counterpart
+    #[test]
+    fn pbkdf2_test_vectors() {
+        let sha1 = |key: &[u8], msg: &[u8]| hmac_sha1(key, msg).to_vec();
+        let sha256 = |key: &[u8], msg: &[u8]| hmac_sha256(key, msg).to_vec();
+        let mut out = [0u8; 20];
+        pbkdf2(sha1, b"password", b"salt", 2, &mut out);
+        assert_eq!(out.to_vec(), unhex("ea6c014dc72d6f8ccd1ed92ace1d41f0d8de8957"));
+        // longer than one SHA1 block, as our AES-256 keys are
+        let mut out = [0u8; 25];
+        pbkdf2(sha1, b"passwordPASSWORDpassword", b"saltSALTsaltSALTsaltSALTsaltSALTsalt", 4096, &mut out);
+        assert_eq!(out.to_vec(), unhex("3d2eec4fe41c849b80c8d83662c0e44a8b291a964cf2f07038"));
+        let mut out = [0u8; 32];
+        pbkdf2(sha256, b"password", b"salt", 1, &mut out);
+        assert_eq!(out.to_vec(), unhex("120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"));
+    }
+
+    // The GCM keystream has to match a real GCM encryption: test case 14 of the GCM specification,
+    // AES-256 with an all-zero key, IV and plaintext block.
+    #[test]
+    fn gcm_keystream_matches_gcm() {
+        let key = [0u8; 32];
+        assert_eq!(gcm_counter(&key, &[0u8; 12]).to_vec(), unhex("00000000000000000000000000000002"));
+        let mut data = [0u8; 16];
+        let mut stream = ctr::Ctr32BE::<Aes256>::new(&key.into(), &gcm_counter(&key, &[0u8; 12]).into());
+        stream.apply_keystream(&mut data);
+        assert_eq!(data.to_vec(), unhex("cea7403d4d606b6e074ec5d3baf39d18"));
+    }
+
+    // what python's `cryptography` produces for two packets in a row, with a key stretched from
+    // "secret" by PBKDF2-HMAC-SHA1 - what an xpra peer would send us
+    #[test]
+    fn matches_python_cryptography() {
+        let expected = [
+            (CipherMode::Gcm, "87402fa969ac99efbfce0a9101d967d14140e3e7", "b1ea046e6f3baa"),
+            (CipherMode::Ctr, "abb8d08fa9e77bcb2b600a391c410e4b5af3579b", "563bc4d2d1b6a2"),
+            (CipherMode::Cbc, "5039aff23a2a34bb95012684e68407366e1a64fc2b9f2cd6a81f3fe957ca4698",
+             "b03d8799aa49af6d659bd6009eb9fea3c6415048fedc3257e822624471403c5a"),
+        ];
+        for (mode, first, second) in expected {
+            let params = CipherParams {
+                mode,
+                iv: b"0123456789abcdef".to_vec(),
+                key_salt: b"salt".to_vec(),
+                key_hash: "SHA1".to_string(),
+                iterations: 1000,
+                block_size: mode.block_size(),
+            };
+            let mut encryptor = Cipher::encryptor(&params, b"secret").unwrap();
+            assert_eq!(encryptor.encrypt(&b"xpra".repeat(5)), unhex(first), "{mode:?}");
+            assert_eq!(encryptor.encrypt(b"!!!!!!!"), unhex(second), "{mode:?}");
+            let mut decryptor = Cipher::decryptor(&params, b"secret").unwrap();
+            assert_eq!(decryptor.decrypt(unhex(first), 20), b"xpra".repeat(5));
+            assert_eq!(decryptor.decrypt(unhex(second), 7), b"!!!!!!!");
+        }
+    }
+
+    #[test]
+    fn every_mode_round_trips_across_packets() {
+        for mode in [CipherMode::Gcm, CipherMode::Cbc, CipherMode::Ctr] {
+            let params = CipherParams::new(mode);
+            let mut encryptor = Cipher::encryptor(&params, b"secret").unwrap();
+            let mut decryptor = Cipher::decryptor(&params, b"secret").unwrap();
+            for packet in [&b"hello"[..], &[7u8; 64], b"", &[1u8; 100]] {
+                let wire = encryptor.encrypt(packet);
+                assert_eq!(wire.len(), encryptor.padded_size(packet.len()));
+                assert_ne!(wire.get(..packet.len()), Some(packet).filter(|p| !p.is_empty()));
+                assert_eq!(decryptor.decrypt(wire, packet.len()), packet);
+            }
+        }
+    }
+
+    #[test]
+    fn cbc_always_pads() {
+        let cipher = Cipher::encryptor(&CipherParams::new(CipherMode::Cbc), b"secret").unwrap();
+        assert_eq!(cipher.padded_size(0), 32);
+        assert_eq!(cipher.padded_size(31), 32);
+        assert_eq!(cipher.padded_size(32), 64);
+        let cipher = Cipher::encryptor(&CipherParams::new(CipherMode::Ctr), b"secret").unwrap();
+        assert_eq!(cipher.padded_size(31), 31);
+    }
+
+    #[test]
+    fn our_caps_parse_back() {
+        let params = CipherParams::new(CipherMode::Cbc);
+        let mut legacy = params.caps();
+        legacy.as_object_mut().unwrap().remove("encryption");
+        // with the nested dict, and without it, as an older server sends them
+        for caps in [params.caps(), legacy] {
+            let yaml = YamlLoader::load_from_str(&caps.to_string()).unwrap().remove(0);
+            let parsed = CipherParams::from_hello(&yaml).unwrap().unwrap();
+            assert_eq!(parsed.mode, CipherMode::Cbc);
+            assert_eq!(parsed.iv, params.iv);
+            assert_eq!(parsed.key_salt, params.key_salt);
+            assert_eq!(parsed.key_hash, "SHA256");
+            assert_eq!(parsed.iterations, KEY_STRETCH_ITERATIONS);
+            assert_eq!(parsed.block_size, CBC_BLOCK_SIZE);
+        }
+    }
+
+    #[test]
+    fn server_caps_are_validated() {
+        let parse = |caps: &str| {
+            CipherParams::from_hello(&YamlLoader::load_from_str(caps).unwrap().remove(0))
+        };
+        assert_eq!(parse("{version: 6.4}"), Ok(None));
+        let valid = "{cipher: AES, mode: GCM, iv: '0123456789abcdef', key_salt: [115, 97, 108, 116], key_stretch_iterations: 1000}";
+        let params = parse(&format!("{{encryption: {valid}}}")).unwrap().unwrap();
+        assert_eq!(params.mode, CipherMode::Gcm);
+        assert_eq!(params.key_hash, "SHA1");
+        assert_eq!(params.key_salt, b"salt");
+        // text is never decoded, even when it would make valid base64
+        let text = parse("{encryption: {cipher: AES, mode: GCM, key_salt: c2FsdA==, key_stretch_iterations: 1000}}");
+        assert_eq!(text.unwrap().unwrap().key_salt, b"c2FsdA==");
+        assert!(parse("{encryption: {cipher: AES, mode: CBC, key_stretch_iterations: 1000, block_size: 256}}").is_err());
+        assert!(parse("{encryption: {cipher: AES, mode: OFB, key_stretch_iterations: 1000}}").is_err());
+        assert!(parse("{encryption: {cipher: AES, mode: GCM, key_stretch_iterations: 0}}").is_err());
+        assert!(parse("{encryption: {cipher: AES, mode: GCM, key_stretch_iterations: 1000, key_size: 16}}").is_err());
+        assert!(parse("{encryption: {cipher: AES, mode: GCM, key_stretch_iterations: 1000, key_hash: MD5}}").is_err());
+    }
+}
@@ -1,17 +1,39 @@
 use std::collections::HashMap;
 use std::io::{Read, Error, ErrorKind};
 use std::result::{Result};
-use log::{trace, warn};
+use log::{trace};
 
 use super::connection::Connection;
+use super::crypto::Cipher;
+use super::serde::PacketEncoder;
 
 // The compression algorithm is carried in the high bits of the header's "level" byte (xpra
 // net/protocol/header.py): 0x10 = lz4, 0x40 = brotli, 0x80 = zstd (the low nibble is the level).
-// We advertise only lz4 (see the client's send_hello), so that is the only one we accept here.
+// We decompress all three, and compress our own packets with lz4 only.
 const LZ4_FLAG: u8 = 0x10;
+const BROTLI_FLAG: u8 = 0x40;
+const ZSTD_FLAG: u8 = 0x80;
+// the names of the above, as listed in the hello's `compressors`
+pub const COMPRESSORS: [&str; 3] = ["lz4", "brotli", "zstd"];
+// The level we compress our own packets at. lz4 has no real levels in xpra's framing, any
+// non-zero value just says "compressed".
+const LZ4_LEVEL: u8 = 1;
+// Below this many bytes compressing isn't worth it - nearly all our packets are small input
+// events. This is xpra's own threshold (`MIN_COMPRESS_SIZE`, net/protocol/socket_handler.py), so
+// what we compress is what the python client would: clipboard uploads and large log records.
+const MIN_COMPRESS_SIZE: usize = 378;
+// The most a compressed payload may expand to. Every algorithm here can turn a few kilobytes into
+// gigabytes, so without a cap any peer could have us allocate until we are killed. Far above any
+// real packet: a full screen update of a 4k display is ~32MB before the server compresses it.
+const MAX_DECOMPRESSED_SIZE: usize = 256 * 1024 * 1024;
 // the header's flags byte: the packet encoder used for the payload. FLAGS_FLUSH (0x8) and
-// FLAGS_CIPHER (0x2) can be set alongside it, so this is a mask, not an equality test.
-const FLAGS_YAML: u8 = 0x4;
+// FLAGS_CIPHER can be set alongside it, so these are masks, not values to compare against.
+// See net::serde's PacketEncoder for which one we use.
+pub const FLAGS_RENCODE: u8 = 0x1;
+// the payload is encrypted, see net::crypto
+const FLAGS_CIPHER: u8 = 0x2;
+pub const FLAGS_YAML: u8 = 0x4;
+pub const FLAGS_RENCODEPLUS: u8 = 0x10;
 
 // Out-of-band chunks: rather than inlining a large binary item (pixel data, a window icon, a
 // clipboard payload, ...) into the packet's YAML payload as base64, the server can send it as its
@@ -27,11 +49,14 @@ const MAX_CHUNKS: usize = 4;
 const MAX_CHUNK_INDEX: u8 = 16;
 
 
-// One logical packet as read off the wire: the main (YAML) payload, plus whatever out-of-band
-// chunks preceded it, keyed by the index of the packet field each one belongs to.
+// One logical packet as read off the wire: the main payload and the encoder its header named,
+// plus whatever out-of-band chunks preceded it, keyed by the index of the packet field each one
+// belongs to. `encrypted` says whether all of it came encrypted.
 pub struct RawPacket {
     pub payload: Vec<u8>,
     pub chunks: HashMap<u8, Vec<u8>>,
+    pub encoder: PacketEncoder,
+    pub encrypted: bool,
 }
 
 impl RawPacket {
@@ -42,9 +67,12 @@ impl RawPacket {
 }
 
 
-pub fn read_packet(stream: &mut Connection) -> Result<RawPacket, Error> {
+// Read the next packet, decrypting whatever comes flagged as encrypted with `cipher` - whether an
+// unencrypted packet is acceptable is for the caller to decide.
+pub fn read_packet(stream: &mut Connection, mut cipher: Option<&mut Cipher>) -> Result<RawPacket, Error> {
     let mut chunks: HashMap<u8, Vec<u8>> = HashMap::new();
     let mut received: usize = 0;
+    let mut all_encrypted = true;
     loop {
         let mut header = [0; 8];
         stream.read_exact(&mut header)?;
@@ -64,20 +92,30 @@ pub fn read_packet(stream: &mut Connection) -> Result<RawPacket, Error> {
         if index >= MAX_CHUNK_INDEX {
             return Err(Error::new(ErrorKind::InvalidData, format!("invalid chunk index: {:?}", index)));
         }
-        // read payload:
-        let mut payload = vec![0u8; payload_size];
+        // read payload - with its padding, when encrypted (see net::crypto):
+        let encrypted = header[1] & FLAGS_CIPHER != 0;
+        let wire_size = match (&cipher, encrypted) {
+            (Some(cipher), true) => cipher.padded_size(payload_size),
+            (None, true) => return Err(Error::new(ErrorKind::InvalidData, "encrypted packet received, but encryption is not enabled")),
+            (_, false) => payload_size,
+        };
+        let mut payload = vec![0u8; wire_size];
         let payload_buf: &mut [u8] = payload.as_mut_slice();
         stream.read_exact(payload_buf)?;
+        if let (Some(cipher), true) = (cipher.as_deref_mut(), encrypted) {
+            payload = cipher.decrypt(payload, payload_size);
+        }
+        all_encrypted &= encrypted;
         if index == 0 {
             // the main packet, which ends this one: only its header names a packet encoder
-            // (chunks are raw binary and are sent with no flags at all).
-            if header[1] & FLAGS_YAML == 0 {
+            // (chunks are raw binary, and flagged as encrypted at most).
+            let Some(encoder) = PacketEncoder::from_flags(header[1]) else {
                 return Err(Error::new(ErrorKind::InvalidData, format!("unsupported packet encoding: {:?}", header[1])));
-            }
+            };
             if compression != 0 {
                 payload = decompress(compression, &payload)?;
             }
-            return Ok(RawPacket{ payload, chunks });
+            return Ok(RawPacket{ payload, chunks, encoder, encrypted: all_encrypted });
         }
         // an out-of-band chunk: hold on to it until the main packet arrives. Count it whether or
         // not we end up keeping it, so a peer can't keep us reading chunks indefinitely.
@@ -90,56 +128,123 @@ pub fn read_packet(stream: &mut Connection) -> Result<RawPacket, Error> {
         }
         // Only the network layer's own compression is signalled in the header (xpra's
         // `LevelCompressed`); data that is already compressed by the application - pixels above
-        // all - rides here uncompressed as far as we are concerned.
+        // all - rides here uncompressed as far as we are concerned. The server mostly uses the
+        // algorithm we negotiated, bar clipboard payloads over ~380 bytes, which it brotli
+        // compresses unconditionally (xpra server/source/clipboard.py).
         if compression != 0 {
-            match decompress(compression, &payload) {
-                Ok(data) => payload = data,
-                Err(e) => {
-                    // The server compresses with the algorithm we advertised (lz4), bar one case
-                    // it never negotiates: clipboard payloads over ~380 bytes, which it brotli
-                    // compresses unconditionally (xpra server/source/clipboard.py). Losing the
-                    // item beats losing the session, so drop the chunk and carry on - the field
-                    // then reads back as empty.
-                    warn!("dropping chunk {:?}: {}", index, e);
-                    continue;
-                }
-            }
+            payload = decompress(compression, &payload)?;
         }
         chunks.insert(index, payload);
     }
 }
 
 
-// Undo the packet compression signalled by the header's "level" byte. Only lz4 is supported (the
-// only compressor we advertise); anything else is a protocol violation on our part and errors.
+// Undo the packet compression signalled by the header's "level" byte. Anything but the three
+// algorithms above is a protocol violation and errors, as does a payload that fails to decompress
+// or would expand past MAX_DECOMPRESSED_SIZE.
 fn decompress(compression: u8, payload: &[u8]) -> Result<Vec<u8>, Error> {
-    if compression & LZ4_FLAG == 0 {
+    let (name, result) = if compression & LZ4_FLAG != 0 {
+        ("lz4", decompress_lz4(payload))
+    } else if compression & BROTLI_FLAG != 0 {
+        ("brotli", read_bounded(brotli_decompressor::Decompressor::new(payload, 4096)))
+    } else if compression & ZSTD_FLAG != 0 {
+        let result = ruzstd::decoding::StreamingDecoder::new(payload)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+            .and_then(read_bounded);
+        ("zstd", result)
+    } else {
         return Err(Error::new(ErrorKind::InvalidData, format!("unsupported compression flag: {:#x}", compression)));
-    }
+    };
+    result.map_err(|e| Error::new(ErrorKind::InvalidData, format!("{} decompression failed: {}", name, e)))
+}
+
+fn decompress_lz4(payload: &[u8]) -> Result<Vec<u8>, Error> {
     // xpra frames lz4 as a 4-byte little-endian uncompressed-size prefix followed by a raw lz4
-    // block - exactly lz4_flex's size-prepended block format.
+    // block - exactly lz4_flex's size-prepended block format. lz4_flex allocates whatever the
+    // prefix says up front, so check it first.
+    let Some(prefix) = payload.first_chunk::<4>() else {
+        return Err(Error::new(ErrorKind::InvalidData, "missing size prefix"));
+    };
+    let size = u32::from_le_bytes(*prefix) as usize;
+    if size > MAX_DECOMPRESSED_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} bytes is too large", size)));
+    }
     lz4_flex::block::decompress_size_prepended(payload)
-        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("lz4 decompression failed: {}", e)))
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
 }
 
-
-pub fn make_header(data: &[u8]) -> Vec<u8>{
-    let mut buf = Vec::<u8>::new();
-    buf.push(0x50);     // "P"
-    buf.push(0x4);      // FLAGS_YAML
-    buf.push(0);        // no compression
-    buf.push(0);        // chunk index 0: we never split our own packets into chunks
-    let len = data.len();
-    for i in 0..4 {
-        let l8 = len >> (8*(3-i));
-        buf.push((l8 & 0xff) as u8);
+// brotli and zstd streams don't declare their decompressed size, so stop reading at the limit.
+fn read_bounded(reader: impl Read) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    reader.take(MAX_DECOMPRESSED_SIZE as u64 + 1).read_to_end(&mut data)?;
+    if data.len() > MAX_DECOMPRESSED_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData, "too large"));
     }
+    Ok(data)
+}
+
+
+// `compression` is the header's level byte: 0, or one of the algorithm flags above plus a level.
+pub fn make_header(encoder: PacketEncoder, compression: u8, data: &[u8]) -> Vec<u8>{
+    let mut buf = vec![
+        0x50,               // "P"
+        encoder.flag(),
+        compression,
+        0,                  // chunk index 0: we never split our own packets into chunks
+    ];
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
     buf
 }
 
 
-pub fn write_packet(stream: &mut Connection, data: &[u8]) -> Result<(), Error> {
-    let mut packet = make_header(data);
-    packet.extend_from_slice(data);
+// Send one packet. With `compress` set (the server accepts lz4, see the client's process_hello),
+// payloads over MIN_COMPRESS_SIZE are lz4 compressed - unless that doesn't make them any smaller.
+// With a `cipher`, the (compressed) payload is then encrypted.
+pub fn write_packet(stream: &mut Connection, encoder: PacketEncoder, compress: bool, cipher: Option<&mut Cipher>,
+                    data: &[u8]) -> Result<(), Error> {
+    let compressed = (compress && data.len() >= MIN_COMPRESS_SIZE)
+        .then(|| lz4_flex::block::compress_prepend_size(data))
+        .filter(|compressed| compressed.len() < data.len());
+    let (compression, data) = match &compressed {
+        Some(compressed) => (LZ4_FLAG | LZ4_LEVEL, compressed.as_slice()),
+        None => (0, data),
+    };
+    // the header carries the unpadded size, the peer works the padding out by itself
+    let mut packet = make_header(encoder, compression, data);
+    match cipher {
+        Some(cipher) => {
+            packet[1] |= FLAGS_CIPHER;
+            packet.extend_from_slice(&cipher.encrypt(data));
+        }
+        None => packet.extend_from_slice(data),
+    }
     stream.write_all(&packet)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decompresses_all_advertised_algorithms() {
+        let text = b"xpra xpra xpra xpra xpra xpra xpra xpra".to_vec();
+        let lz4 = lz4_flex::block::compress_prepend_size(&text);
+        assert_eq!(decompress(LZ4_FLAG | 1, &lz4).unwrap(), text);
+        // `printf hello | brotli`
+        assert_eq!(decompress(BROTLI_FLAG | 1, b"\x0b\x02\x80hello\x03").unwrap(), b"hello");
+        // `printf "xpra xpra ..." | zstd --no-check`
+        let zstd = b"\x28\xb5\x2f\xfd\x00\x58\x5d\x00\x00\x28\x78\x70\x72\x61\x20\x01\x00\x70\x8a\x16";
+        assert_eq!(decompress(ZSTD_FLAG | 3, zstd).unwrap(), text);
+    }
+
+    #[test]
+    fn rejects_bad_compressed_payloads() {
+        assert!(decompress(0x20, b"data").is_err());
+        assert!(decompress(LZ4_FLAG, b"\x01").is_err());
+        assert!(decompress(BROTLI_FLAG, b"not brotli").is_err());
+        assert!(decompress(ZSTD_FLAG, b"not zstd").is_err());
+        // a size prefix of 4GB: refused before anything is allocated
+        assert!(decompress(LZ4_FLAG, b"\xff\xff\xff\xff\x00").is_err());
+    }
+}
@@ -0,0 +1,114 @@
+// The certificates the user chose to trust, SSH `known_hosts` style: for an `ssl://`/`wss://`
+// server whose certificate nothing else vouches for (a self-signed one, most of the time), the
+// SHA-256 fingerprint it had the first time, which it must still have on every later connection
+// (see tls::TlsOptions::fingerprint, and main.rs for when it is consulted).
+//
+// One `HOST:PORT FINGERPRINT` line per server, keyed on the address as the target gave it, with
+// the fingerprint in lowercase hex. Blank lines and `#` comments are kept as they are, so the file
+// can be edited by hand - which is also how a pin is removed.
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const FILENAME: &str = "ssl-known-hosts";
+
+pub struct KnownHosts {
+    path: PathBuf,
+}
+
+impl KnownHosts {
+    // The store in the user's xpra config directory, wherever that is: `$XDG_CONFIG_HOME/xpra`
+    // (`~/.config/xpra`), or `%APPDATA%\Xpra` on Windows - the directories xpra itself uses. `None`
+    // when the environment names no such directory.
+    pub fn open() -> Option<Self> {
+        config_dir().map(|dir| KnownHosts::at(dir.join(FILENAME)))
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        KnownHosts { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The fingerprint pinned for `address`, if any. A store that does not exist yet is empty.
+    pub fn get(&self, address: &str) -> Result<Option<String>, String> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("failed to read {:?}: {}", self.path, e)),
+        };
+        Ok(contents.lines().find_map(|line| {
+            let (host, fingerprint) = parse_line(line)?;
+            (host == address).then(|| fingerprint.to_string())
+        }))
+    }
+
+    // Pins `fingerprint` for `address`, in place of whatever was pinned for it before.
+    pub fn set(&self, address: &str, fingerprint: &str) -> Result<(), String> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("failed to read {:?}: {}", self.path, e)),
+        };
+        let mut lines: Vec<String> = contents
+            .lines()
+            .filter(|line| parse_line(line).is_none_or(|(host, _)| host != address))
+            .map(str::to_string)
+            .collect();
+        lines.push(format!("{} {}", address, fingerprint));
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("failed to create {:?}: {}", dir, e))?;
+        }
+        fs::write(&self.path, lines.join("\n") + "\n")
+            .map_err(|e| format!("failed to write {:?}: {}", self.path, e))
+    }
+}
+
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(host), Some(fingerprint)) => Some((host, fingerprint)),
+        _ => None,
+    }
+}
+
+fn config_dir() -> Option<PathBuf> {
+    let non_empty = |name: &str| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    if cfg!(windows) {
+        non_empty("APPDATA").map(|dir| dir.join("Xpra"))
+    } else {
+        non_empty("XDG_CONFIG_HOME")
+            .or_else(|| non_empty("HOME").map(|home| home.join(".config")))
+            .map(|dir| dir.join("xpra"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins_are_replaced_and_the_rest_of_the_file_is_kept() {
+        let dir = env::temp_dir().join(format!("rust-xpra-test-{}-known-hosts", std::process::id()));
+        let store = KnownHosts::at(dir.join(FILENAME));
+        assert_eq!(store.get("a:443").unwrap(), None);
+        store.set("a:443", "01").unwrap();
+        store.set("b:443", "02").unwrap();
+        fs::write(store.path(), format!("# pinned by hand\n{}", fs::read_to_string(store.path()).unwrap())).unwrap();
+        store.set("a:443", "03").unwrap();
+        let a = store.get("a:443").unwrap();
+        let b = store.get("b:443").unwrap();
+        let contents = fs::read_to_string(store.path()).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(a.as_deref(), Some("03"));
+        assert_eq!(b.as_deref(), Some("02"));
+        assert_eq!(contents, "# pinned by hand\nb:443 02\na:443 03\n");
+    }
+}
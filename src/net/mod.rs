@@ -1,8 +1,13 @@
 pub mod connection;
+pub mod crypto;
 pub mod io;
+pub mod known_hosts;
 pub mod packet;
+pub mod proxy;
 pub mod rand;
+pub mod rencode;
 pub mod serde;
+pub mod sessions;
 pub mod sha1;
 pub mod sha256;
 pub mod ssh;
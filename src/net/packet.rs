@@ -6,8 +6,7 @@ use log::warn;
 use yaml_rust2::Yaml;
 
 
-// @[derive(Debug)]
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Packet {
     pub main: Vec<Yaml>,
     pub raw: HashMap<u8, Vec<u8>>,
@@ -19,7 +18,7 @@ pub struct Packet {
 impl fmt::Debug for Packet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Packet")
-            .field("type", &self.main[0])
+            .field("type", &self.main.first())
             .finish()
     }
 }
@@ -35,6 +34,19 @@ impl Packet {
         self.main.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.main.is_empty()
+    }
+
+    // field 0, or "" for an empty packet or a type that isn't a string
+    pub fn packet_type(&self) -> String {
+        self.main.first().map(yaml_str).unwrap_or_default()
+    }
+
+    pub fn has(&self, index: u8) -> bool {
+        (index as usize) < self.main.len()
+    }
+
     pub fn get_u32(&self, index: u8) -> u32 { yaml_u32(&self.main[index as usize]) }
 
     pub fn get_i32(&self, index: u8) -> i32 {
@@ -76,12 +88,91 @@ impl Packet {
     }
 
     pub fn get_bytes(&mut self, index: u8) -> Vec<u8> {
-        let raw = self.raw.remove(&index);
-        if raw.is_some() {
-            return raw.unwrap();
+        if let Some(raw) = self.raw.remove(&index) {
+            return raw;
         }
         yaml_bytes(&self.main[index as usize])
     }
+
+    // The checked accessors the client's typed packets are parsed with (see client/packets.rs).
+    // Unlike the get_* ones above, which index `main` directly and quietly turn a value of the
+    // wrong type into 0 / "", a missing field or an unexpected type is an error naming the packet
+    // type and the field - what a truncated or garbled packet from the server should be, rather
+    // than a panic on the UI thread.
+    pub fn field(&self, index: u8, name: &str) -> Result<&Yaml, String> {
+        match self.main.get(index as usize) {
+            Some(value) => Ok(value),
+            None => Err(self.field_error(index, name, "is missing")),
+        }
+    }
+
+    pub fn field_int<T: TryFrom<i64>>(&self, index: u8, name: &str) -> Result<T, String> {
+        match self.field(index, name)? {
+            Yaml::Integer(value) => T::try_from(*value)
+                .map_err(|_| self.field_error(index, name, &format!("is out of range: {}", value))),
+            other => Err(self.type_error(index, name, "an integer", other)),
+        }
+    }
+
+    pub fn field_str(&self, index: u8, name: &str) -> Result<String, String> {
+        match self.field(index, name)? {
+            Yaml::String(value) => Ok(value.clone()),
+            other => Err(self.type_error(index, name, "a string", other)),
+        }
+    }
+
+    pub fn field_bool(&self, index: u8, name: &str) -> Result<bool, String> {
+        match self.field(index, name)? {
+            Yaml::Boolean(value) => Ok(*value),
+            // as in yaml_bool: some senders use 0/1
+            Yaml::Integer(value) => Ok(*value != 0),
+            other => Err(self.type_error(index, name, "a boolean", other)),
+        }
+    }
+
+    pub fn field_hash(&self, index: u8, name: &str) -> Result<&Yaml, String> {
+        match self.field(index, name)? {
+            value @ Yaml::Hash(_) => Ok(value),
+            other => Err(self.type_error(index, name, "a dictionary", other)),
+        }
+    }
+
+    // Binary data: an out-of-band chunk or a (rencode) raw byte string when there is one, which is
+    // taken out of the packet like get_bytes does, otherwise a base64 string in the yaml payload.
+    pub fn field_bytes(&mut self, index: u8, name: &str) -> Result<Vec<u8>, String> {
+        if let Some(raw) = self.raw.remove(&index) {
+            return Ok(raw);
+        }
+        match self.field(index, name)? {
+            Yaml::String(value) => general_purpose::STANDARD.decode(value.replace("\n", ""))
+                .map_err(|e| self.field_error(index, name, &format!("is not valid base64: {}", e))),
+            other => Err(self.type_error(index, name, "binary data", other)),
+        }
+    }
+
+    fn type_error(&self, index: u8, name: &str, expected: &str, value: &Yaml) -> String {
+        self.field_error(index, name, &format!("should be {}, not {}", expected, yaml_kind(value)))
+    }
+
+    fn field_error(&self, index: u8, name: &str, problem: &str) -> String {
+        format!("invalid {:?} packet: field {} ({}) {}", self.packet_type(), index, name, problem)
+    }
+}
+
+
+// How a value's type reads in an error message.
+fn yaml_kind(value: &Yaml) -> &'static str {
+    match value {
+        Yaml::Real(_) => "a float",
+        Yaml::Integer(_) => "an integer",
+        Yaml::String(_) => "a string",
+        Yaml::Boolean(_) => "a boolean",
+        Yaml::Array(_) => "a list",
+        Yaml::Hash(_) => "a dictionary",
+        Yaml::Alias(_) => "an alias",
+        Yaml::Null => "null",
+        Yaml::BadValue => "a bad value",
+    }
 }
 
 
@@ -111,7 +202,7 @@ pub fn yaml_u64(value: &Yaml) -> u64 {
 
 pub fn yaml_i64(value: &Yaml) -> i64 {
     if let Yaml::Integer(ivalue) = value {
-        return *ivalue as i64;
+        return *ivalue;
     }
     0
 }
@@ -150,6 +241,20 @@ pub fn yaml_bytes(value: &Yaml) -> Vec<u8> {
     Vec::new()
 }
 
+// A nested byte string that isn't text, which both packet decoders hand over as the list of its
+// bytes (see net::rencode and net::serde): `None` for anything else, text included. Nothing sets
+// that list apart from a list of small integers, `[]` among them, so this is only for the values
+// that are binary when they aren't text: a cipher salt, audio codec headers.
+pub fn yaml_binary(value: &Yaml) -> Option<Vec<u8>> {
+    let Yaml::Array(items) = value else {
+        return None;
+    };
+    items.iter().map(|item| match item {
+        Yaml::Integer(byte) => u8::try_from(*byte).ok(),
+        _ => None,
+    }).collect()
+}
+
 
 // Look up a key in a hash, without assuming anything about the value's type: for the nested caps
 // dicts (`mmap.write.token`) and packet options (a draw packet's `chunks` list) that the typed
@@ -164,8 +269,7 @@ pub fn yaml_hash<'a>(value: &'a Yaml, key: &str) -> Option<&'a Yaml> {
 pub fn yaml_hash_str(value: &Yaml, key: String) -> String {
     if let Yaml::Hash(hash) = value {
         let yaml_key: Yaml = Yaml::String(key);
-        let yaml_value = &hash[&yaml_key];
-        if let Yaml::String(value) = yaml_value {
+        if let Some(Yaml::String(value)) = hash.get(&yaml_key) {
             return value.to_string();
         }
     }
@@ -188,8 +292,7 @@ pub fn yaml_hash_bool(value: &Yaml, key: String) -> Option<bool> {
 pub fn yaml_hash_i32(value: &Yaml, key: String) -> i32 {
     if let Yaml::Hash(hash) = value {
         let yaml_key: Yaml = Yaml::String(key);
-        let yaml_value = &hash[&yaml_key];
-        if let Yaml::Integer(ivalue) = yaml_value {
+        if let Some(Yaml::Integer(ivalue)) = hash.get(&yaml_key) {
             return *ivalue as i32;
         }
     }
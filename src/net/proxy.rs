@@ -0,0 +1,402 @@
+// Outbound proxies, for networks where the only way out is through one: the tcp connection under
+// a `tcp://`, `ssl://`, `ws://` or `wss://` target is made through an HTTP proxy (a `CONNECT`
+// tunnel) or a SOCKS5 one, and everything else - the TLS handshake, the websocket upgrade, the
+// xpra protocol - runs end to end inside the tunnel as it would on a direct connection, so the
+// proxy sees nothing but the host and port. `ssh://` has ssh's own `ProxyCommand`/`ProxyJump`.
+//
+// The proxy is either given explicitly (`--proxy`, see main.rs), or taken from the environment
+// variables curl and most other tools read (see `from_env`).
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+
+use base64::Engine;
+use base64::engine::general_purpose;
+use log::debug;
+
+use super::uri::{host_only, percent_decode, Scheme};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProxyKind {
+    Http,
+    // `socks5://` resolves the target's host name locally, `socks5h://` leaves it to the proxy -
+    // as curl spells the difference.
+    Socks5 { remote_dns: bool },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    // `host:port` of the proxy itself
+    pub address: String,
+    // Basic auth for an HTTP proxy, username/password auth (RFC 1929) for a SOCKS5 one
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+// the ports a proxy URL without one gets: squid's for HTTP, the registered one for SOCKS
+const HTTP_PORT: u16 = 3128;
+const SOCKS_PORT: u16 = 1080;
+
+// SOCKS5 (RFC 1928 and 1929)
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_USER_PASS: u8 = 2;
+const SOCKS_NO_ACCEPTABLE_METHOD: u8 = 0xFF;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_IPV4: u8 = 1;
+const SOCKS_DOMAIN: u8 = 3;
+const SOCKS_IPV6: u8 = 4;
+
+impl Proxy {
+    // `http://[USER[:PASSWORD]@]HOST[:PORT]`, `socks5://...` or `socks5h://...`, with the user
+    // name and password percent-encoded. An HTTPS proxy - TLS to the proxy itself - is not
+    // supported: the tunnel through it carries TLS of its own for `ssl://` and `wss://` anyway.
+    pub fn parse(url: &str) -> Result<Proxy, String> {
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| format!("proxy {:?} has no protocol", url))?;
+        let (kind, default_port) = match scheme.to_ascii_lowercase().as_str() {
+            "http" => (ProxyKind::Http, HTTP_PORT),
+            "socks5" => (ProxyKind::Socks5 { remote_dns: false }, SOCKS_PORT),
+            "socks5h" => (ProxyKind::Socks5 { remote_dns: true }, SOCKS_PORT),
+            _ => return Err(format!("unsupported proxy protocol {:?}: only 'http', 'socks5' and 'socks5h' are \
+                                     supported", scheme)),
+        };
+        // a trailing slash is common in environment variables (`http://proxy:3128/`)
+        let rest = rest.trim_end_matches('/');
+        let (userinfo, host_port) = match rest.rsplit_once('@') {
+            Some((userinfo, host_port)) => (Some(userinfo), host_port),
+            None => (None, rest),
+        };
+        if host_port.is_empty() || host_port.contains('/') {
+            return Err(format!("invalid proxy address in {:?}", url));
+        }
+        // the port is whatever follows the last colon, unless that is inside an IPv6 address
+        let address = if host_port.rsplit_once(':').is_some_and(|(_, port)| !port.contains(']')) {
+            host_port.to_string()
+        } else {
+            format!("{host_port}:{default_port}")
+        };
+        let (username, password) = match userinfo {
+            Some(userinfo) => {
+                let (username, password) = match userinfo.split_once(':') {
+                    Some((username, password)) => (username, Some(percent_decode(password)?)),
+                    None => (userinfo, None),
+                };
+                (Some(percent_decode(username)?), password)
+            }
+            None => (None, None),
+        };
+        Ok(Proxy { kind, address, username, password })
+    }
+
+    // A tcp connection to `address` (`host:port`), through the proxy.
+    pub fn connect(&self, address: &str) -> Result<TcpStream, String> {
+        debug!("connecting to {} through proxy {}", address, self.address);
+        let mut stream = TcpStream::connect(&self.address)
+            .map_err(|e| format!("failed to connect to proxy {:?}: {}", self.address, e))?;
+        match self.kind {
+            ProxyKind::Http => self.http_connect(&mut stream, address),
+            ProxyKind::Socks5 { remote_dns } => self.socks5_connect(&mut stream, address, remote_dns),
+        }
+        .map_err(|e| format!("proxy {:?} did not connect us to {:?}: {}", self.address, address, e))?;
+        Ok(stream)
+    }
+
+    fn http_connect(&self, stream: &mut TcpStream, address: &str) -> Result<(), String> {
+        let mut request = format!("CONNECT {address} HTTP/1.1\r\nHost: {address}\r\n");
+        if let Some(username) = &self.username {
+            let credentials = format!("{}:{}", username, self.password.as_deref().unwrap_or(""));
+            let credentials = general_purpose::STANDARD.encode(credentials);
+            request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+        // one byte at a time, as for the websocket upgrade: whatever follows the headers is the
+        // start of the tunnelled stream, and must be left in the socket for whoever reads it next
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).map_err(|e| e.to_string())?;
+            response.push(byte[0]);
+            if response.len() > 8192 {
+                return Err("response too large".to_string());
+            }
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.split("\r\n").next().unwrap_or("");
+        let status = status_line.split_whitespace().nth(1).unwrap_or("");
+        match status {
+            _ if status.starts_with('2') => Ok(()),
+            "407" if self.username.is_none() => Err("it requires authentication: give the proxy URL a \
+                                                    USER:PASSWORD@".to_string()),
+            "407" => Err("it rejected our credentials".to_string()),
+            _ => Err(format!("unexpected response {:?}", status_line)),
+        }
+    }
+
+    fn socks5_connect(&self, stream: &mut TcpStream, address: &str, remote_dns: bool) -> Result<(), String> {
+        let error = |e: io::Error| e.to_string();
+        let methods: &[u8] = if self.username.is_some() { &[SOCKS_NO_AUTH, SOCKS_USER_PASS] } else { &[SOCKS_NO_AUTH] };
+        let mut greeting = vec![SOCKS_VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).map_err(error)?;
+        let mut choice = [0u8; 2];
+        stream.read_exact(&mut choice).map_err(error)?;
+        if choice[0] != SOCKS_VERSION {
+            return Err("it is not a SOCKS5 proxy".to_string());
+        }
+        match choice[1] {
+            SOCKS_NO_AUTH => {}
+            SOCKS_USER_PASS if self.username.is_some() => {
+                let username = self.username.as_deref().unwrap_or("").as_bytes();
+                let password = self.password.as_deref().unwrap_or("").as_bytes();
+                if username.len() > 255 || password.len() > 255 {
+                    return Err("the user name and password are limited to 255 bytes each".to_string());
+                }
+                // the sub-negotiation has its own version number, 1
+                let mut auth = vec![1, username.len() as u8];
+                auth.extend_from_slice(username);
+                auth.push(password.len() as u8);
+                auth.extend_from_slice(password);
+                stream.write_all(&auth).map_err(error)?;
+                let mut status = [0u8; 2];
+                stream.read_exact(&mut status).map_err(error)?;
+                if status[1] != 0 {
+                    return Err("it rejected our credentials".to_string());
+                }
+            }
+            SOCKS_NO_ACCEPTABLE_METHOD if self.username.is_none() => {
+                return Err("it requires authentication: give the proxy URL a USER:PASSWORD@".to_string());
+            }
+            method => return Err(format!("it chose an authentication method we did not offer: {}", method)),
+        }
+
+        let (host, port) = address.rsplit_once(':').ok_or_else(|| format!("missing port in {:?}", address))?;
+        let port: u16 = port.parse().map_err(|_| format!("invalid port in {:?}", address))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) if remote_dns => None,
+            Err(_) => {
+                let resolved = (host, port).to_socket_addrs().map_err(|e| format!("cannot resolve {:?}: {}", host, e))?;
+                Some(resolved.map(|addr| addr.ip()).next().ok_or_else(|| format!("cannot resolve {:?}", host))?)
+            }
+        };
+        let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+        match ip {
+            Some(IpAddr::V4(ip)) => {
+                request.push(SOCKS_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Some(IpAddr::V6(ip)) => {
+                request.push(SOCKS_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            None => {
+                if host.len() > 255 {
+                    return Err(format!("host name {:?} is too long", host));
+                }
+                request.extend_from_slice(&[SOCKS_DOMAIN, host.len() as u8]);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).map_err(error)?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).map_err(error)?;
+        if reply[1] != 0 {
+            return Err(socks5_error(reply[1]).to_string());
+        }
+        // the address the proxy connected from, which we have no use for - but it has to be read
+        // past before the tunnelled stream starts
+        let bound_len = match reply[3] {
+            SOCKS_IPV4 => 4,
+            SOCKS_IPV6 => 16,
+            SOCKS_DOMAIN => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).map_err(error)?;
+                len[0] as usize
+            }
+            atyp => return Err(format!("invalid address type {} in its reply", atyp)),
+        };
+        let mut bound = vec![0u8; bound_len + 2];
+        stream.read_exact(&mut bound).map_err(error)
+    }
+}
+
+fn socks5_error(reply: u8) -> &'static str {
+    match reply {
+        1 => "general SOCKS server failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown SOCKS error",
+    }
+}
+
+// The proxy the environment sets for a `scheme` connection to `address`, if any: `https_proxy` for
+// the TLS transports, `http_proxy` for plain websockets (which are HTTP, at least to begin with),
+// and `all_proxy` for any of them, plain tcp included - unless `no_proxy` exempts the host. The
+// lowercase spelling of each wins over the uppercase one, as in curl.
+pub fn from_env(scheme: Scheme, address: &str) -> Result<Option<Proxy>, String> {
+    let var = |name: &str| {
+        [name.to_string(), name.to_ascii_uppercase()].into_iter()
+            .find_map(|name| env::var(&name).ok().filter(|value| !value.is_empty()).map(|value| (name, value)))
+    };
+    let specific = match scheme {
+        Scheme::Tls | Scheme::WebSocketTls => var("https_proxy"),
+        Scheme::WebSocket => var("http_proxy"),
+        Scheme::Tcp => None,
+        Scheme::Ssh | Scheme::Socket => return Ok(None),
+    };
+    let Some((name, url)) = specific.or_else(|| var("all_proxy")) else {
+        return Ok(None);
+    };
+    if let Some((_, no_proxy)) = var("no_proxy") && bypasses(&no_proxy, address) {
+        return Ok(None);
+    }
+    Proxy::parse(&url).map(Some).map_err(|e| format!("{} is invalid: {}", name, e))
+}
+
+// Whether `no_proxy`, a comma-separated list of host names, domains and IP addresses - each with
+// an optional port - or `*`, exempts `address`. A name matches itself and every host under it,
+// with or without a leading dot: `example.com` and `.example.com` both cover `xpra.example.com`.
+// CIDR ranges are not supported.
+pub fn bypasses(no_proxy: &str, address: &str) -> bool {
+    let host = host_only(address).trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    let port = address.rsplit_once(':').map(|(_, port)| port).unwrap_or("");
+    no_proxy.split(',').map(str::trim).filter(|entry| !entry.is_empty()).any(|entry| {
+        if entry == "*" {
+            return true;
+        }
+        let entry = entry.to_ascii_lowercase();
+        // `[::1]:8080`, `10.0.0.1:8080` or `example.com:8080`: a bare IPv6 address has colons too
+        let (name, entry_port) = match entry.strip_prefix('[').and_then(|bracketed| bracketed.split_once(']')) {
+            Some((name, rest)) => (name, rest.strip_prefix(':')),
+            None => match entry.rsplit_once(':') {
+                Some((name, entry_port)) if !name.contains(':') => (name, Some(entry_port)),
+                _ => (entry.as_str(), None),
+            },
+        };
+        let name = name.trim_start_matches('.');
+        let host_matches = host == name || host.strip_suffix(name).is_some_and(|sub| sub.ends_with('.'));
+        host_matches && entry_port.is_none_or(|entry_port| entry_port == port)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn proxy_urls_are_parsed() {
+        assert_eq!(Proxy::parse("http://proxy.corp:8080/").unwrap(), Proxy {
+            kind: ProxyKind::Http, address: "proxy.corp:8080".to_string(), username: None, password: None,
+        });
+        assert_eq!(Proxy::parse("socks5h://me:p%40ss@[::1]").unwrap(), Proxy {
+            kind: ProxyKind::Socks5 { remote_dns: true },
+            address: "[::1]:1080".to_string(),
+            username: Some("me".to_string()),
+            password: Some("p@ss".to_string()),
+        });
+        assert_eq!(Proxy::parse("HTTP://proxy").unwrap().address, "proxy:3128");
+        assert_eq!(Proxy::parse("socks5://proxy").unwrap().kind, ProxyKind::Socks5 { remote_dns: false });
+        assert!(Proxy::parse("https://proxy:443").unwrap_err().contains("unsupported proxy protocol"));
+        assert!(Proxy::parse("proxy:3128").unwrap_err().contains("has no protocol"));
+        assert!(Proxy::parse("http://").unwrap_err().contains("invalid proxy address"));
+    }
+
+    #[test]
+    fn no_proxy_matches_hosts_domains_and_ports() {
+        let no_proxy = "localhost, .internal,example.com:10000,10.0.0.1,[::1]";
+        for address in ["localhost:10000", "xpra.internal:443", "example.com:10000", "a.example.com:10000",
+                        "10.0.0.1:443", "[::1]:10000", "LOCALHOST:1"] {
+            assert!(bypasses(no_proxy, address), "{address}");
+        }
+        for address in ["xpra.external:443", "example.com:443", "badexample.com:10000", "10.0.0.10:443",
+                        "internal.com:443"] {
+            assert!(!bypasses(no_proxy, address), "{address}");
+        }
+        assert!(bypasses("*", "anything:1"));
+        assert!(!bypasses("", "anything:1"));
+    }
+
+    // a proxy that reads what the client sends in `steps` of so many bytes, answering each with its
+    // reply, and then sends "xpra" down the tunnel to show it is up
+    fn fake_proxy(steps: Vec<(usize, Vec<u8>)>) -> (String, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            for (len, reply) in steps {
+                let mut request = vec![0u8; len];
+                stream.read_exact(&mut request).unwrap();
+                received.extend_from_slice(&request);
+                stream.write_all(&reply).unwrap();
+            }
+            let _ = stream.write_all(b"xpra");
+            received
+        });
+        (address, server)
+    }
+
+    fn tunnelled(stream: Result<TcpStream, String>) -> [u8; 4] {
+        let mut data = [0u8; 4];
+        stream.unwrap().read_exact(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn http_connect_tunnels_with_basic_auth() {
+        let expected = "CONNECT xpra.example.com:443 HTTP/1.1\r\nHost: xpra.example.com:443\r\n\
+                        Proxy-Authorization: Basic bWU6c2VjcmV0\r\n\r\n";
+        let established = b"HTTP/1.1 200 Connection established\r\n\r\n".to_vec();
+        let (address, server) = fake_proxy(vec![(expected.len(), established)]);
+        let proxy = Proxy::parse(&format!("http://me:secret@{address}")).unwrap();
+        assert_eq!(&tunnelled(proxy.connect("xpra.example.com:443")), b"xpra");
+        assert_eq!(String::from_utf8(server.join().unwrap()).unwrap(), expected);
+
+        let request_len = "CONNECT a:1 HTTP/1.1\r\nHost: a:1\r\n\r\n".len();
+        let refused = b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n".to_vec();
+        let (address, server) = fake_proxy(vec![(request_len, refused)]);
+        let error = Proxy::parse(&format!("http://{address}")).unwrap().connect("a:1").unwrap_err();
+        server.join().unwrap();
+        assert!(error.contains("requires authentication"), "{error}");
+    }
+
+    #[test]
+    fn socks5_authenticates_and_sends_the_host_name_or_address() {
+        // greeting (no auth, or user/pass), the credentials, then CONNECT to "xpra.example.com":443
+        let mut credentials = vec![1, 2, b'm', b'e', 6];
+        credentials.extend_from_slice(b"secret");
+        let mut connect = vec![5, 1, 0, 3, 16];
+        connect.extend_from_slice(b"xpra.example.com");
+        connect.extend_from_slice(&443u16.to_be_bytes());
+        let expected = [&[5, 2, 0, 2][..], &credentials, &connect].concat();
+        let (address, server) = fake_proxy(vec![
+            (4, vec![5, 2]),
+            (credentials.len(), vec![1, 0]),
+            (connect.len(), vec![5, 0, 0, 1, 127, 0, 0, 1, 0x1F, 0x90]),
+        ]);
+        let proxy = Proxy::parse(&format!("socks5h://me:secret@{address}")).unwrap();
+        assert_eq!(&tunnelled(proxy.connect("xpra.example.com:443")), b"xpra");
+        assert_eq!(server.join().unwrap(), expected);
+
+        // an IP address is sent as one, and the proxy's refusal is reported by name
+        let (address, server) = fake_proxy(vec![
+            (3, vec![5, 0]),
+            (10, vec![5, 5, 0, 1, 0, 0, 0, 0, 0, 0]),
+        ]);
+        let error = Proxy::parse(&format!("socks5://{address}")).unwrap().connect("10.0.0.1:10000").unwrap_err();
+        assert_eq!(server.join().unwrap(), [5, 1, 0, 5, 1, 0, 1, 10, 0, 0, 1, 0x27, 0x10]);
+        assert!(error.contains("connection refused"), "{error}");
+    }
+}
@@ -0,0 +1,464 @@
+// rencode, in the "rencodeplus" flavour xpra uses as its default packet encoder (xpra
+// net/rencodeplus/rencodeplus.pyx). A compact binary serialization: every item starts with a
+// typecode byte, small integers, short strings, short lists and small dicts are folded into that
+// byte, and byte strings travel as-is - no base64, no escaping, no text parsing. Hand-rolled like
+// the rest of net/ since the whole format fits on a page.
+//
+// Decoding produces the same `Yaml` values the YAML encoder does, so nothing above net/ has to
+// care which encoder a packet came in. The one thing rencode has that YAML lacks is a binary type:
+// a top-level byte string (pixel data, an icon, a clipboard payload, a challenge salt) lands in
+// `Packet::raw` under its field index, exactly where an out-of-band chunk would, so that
+// `Packet::get_bytes` returns it without any decoding. Like rencodeplus itself we can't tell text
+// from binary on the wire, so a byte string that is valid UTF-8 also goes into `main` as a
+// `Yaml::String`, for `get_str`; one that isn't leaves a `Yaml::Null` placeholder there. A nested
+// byte string has no index to be filed under and becomes the list of its bytes instead when it
+// isn't text, as a nested `!!binary` value does in a YAML payload (see net::serde), which
+// `yaml_binary` reads back.
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::str;
+
+use serde_json::Value;
+use yaml_rust2::Yaml;
+use yaml_rust2::yaml::Hash;
+
+use super::packet::Packet;
+
+const CHR_LIST: u8 = 59;
+const CHR_DICT: u8 = 60;
+const CHR_INT: u8 = 61;
+const CHR_INT1: u8 = 62;
+const CHR_INT2: u8 = 63;
+const CHR_INT4: u8 = 64;
+const CHR_INT8: u8 = 65;
+const CHR_FLOAT32: u8 = 66;
+const CHR_FLOAT64: u8 = 44;
+const CHR_TRUE: u8 = 67;
+const CHR_FALSE: u8 = 68;
+const CHR_NONE: u8 = 69;
+const CHR_TERM: u8 = 127;
+
+// the ranges of typecodes that carry their own value or length:
+const INT_POS_FIXED_START: u8 = 0;
+const INT_POS_FIXED_COUNT: u8 = 44;
+const INT_NEG_FIXED_START: u8 = 70;
+const INT_NEG_FIXED_COUNT: u8 = 32;
+const DICT_FIXED_START: u8 = 102;
+const DICT_FIXED_COUNT: u8 = 25;
+const STR_FIXED_START: u8 = 128;
+const STR_FIXED_COUNT: u8 = 64;
+const LIST_FIXED_START: u8 = STR_FIXED_START + STR_FIXED_COUNT;
+// fixed lists take up the rest, 192 to 255:
+const LIST_FIXED_COUNT: u8 = 64;
+
+// the longest arbitrary-precision integer xpra will decode (`MAX_INT_LENGTH`), in digits.
+const MAX_INT_LENGTH: usize = 64;
+// how deeply lists and dicts may nest: far beyond anything xpra sends, and well short of what it
+// would take for a hostile peer to exhaust our stack with a few kilobytes of opening brackets.
+const MAX_DEPTH: usize = 64;
+
+
+// Decode one rencoded packet into its positional fields and the byte strings among them, keyed by
+// field index (see the top of this file). `chunks` are the out-of-band chunks that came with it:
+// the sender leaves an empty placeholder in the packet for those, which must not replace them.
+pub fn decode_packet(data: &[u8], mut chunks: HashMap<u8, Vec<u8>>) -> Result<Packet, Error> {
+    let mut decoder = Decoder { data, pos: 0 };
+    let typecode = decoder.byte()?;
+    let count = if typecode >= LIST_FIXED_START {
+        Some((typecode - LIST_FIXED_START) as usize)
+    } else if typecode == CHR_LIST {
+        None
+    } else {
+        return Err(invalid(format!("packet is not a list: typecode {typecode}")));
+    };
+    let mut main = Vec::new();
+    loop {
+        match count {
+            Some(count) if main.len() == count => break,
+            None if decoder.peek()? == CHR_TERM => {
+                decoder.pos += 1;
+                break;
+            }
+            _ => {}
+        }
+        if !is_string(decoder.peek()?) {
+            main.push(decoder.value(1)?);
+            continue;
+        }
+        let bytes = decoder.string()?;
+        // the packet type is always text, and nothing ever asks for it as bytes:
+        let index = main.len();
+        main.push(match str::from_utf8(bytes) {
+            Ok(text) => Yaml::String(text.to_string()),
+            Err(_) => Yaml::Null,
+        });
+        if index == 0 || index > u8::MAX as usize {
+            continue;
+        }
+        chunks.entry(index as u8).or_insert_with(|| bytes.to_vec());
+    }
+    if decoder.pos != data.len() {
+        return Err(invalid(format!("{} bytes of trailing data", data.len() - decoder.pos)));
+    }
+    Ok(Packet{ main, raw: chunks, decode_time_us: None })
+}
+
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+
+    fn peek(&self) -> Result<u8, Error> {
+        self.data.get(self.pos).copied().ok_or_else(truncated)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.data.len()).ok_or_else(truncated)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    // the ascii digits up to (and consuming) `terminator`, at most `max` of them.
+    fn digits(&mut self, terminator: u8, max: usize) -> Result<&'a str, Error> {
+        let start = self.pos;
+        loop {
+            let b = self.byte()?;
+            if b == terminator {
+                break;
+            }
+            if self.pos - start > max || !(b.is_ascii_digit() || (b == b'-' && self.pos - 1 == start)) {
+                return Err(invalid(format!("invalid number at offset {start}")));
+            }
+        }
+        // only ascii made it past the loop above:
+        Ok(str::from_utf8(&self.data[start..self.pos - 1]).unwrap_or_default())
+    }
+
+    fn string(&mut self) -> Result<&'a [u8], Error> {
+        let typecode = self.byte()?;
+        if (STR_FIXED_START..STR_FIXED_START + STR_FIXED_COUNT).contains(&typecode) {
+            return self.take((typecode - STR_FIXED_START) as usize);
+        }
+        // a longer string: its length in ascii, a colon, then the bytes - and the typecode we
+        // just read was the first digit of that length.
+        self.pos -= 1;
+        let length = self.digits(b':', 20)?;
+        let length = length.parse::<usize>().map_err(|e| invalid(format!("invalid string length: {e}")))?;
+        self.take(length)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Yaml, Error> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("too many nested containers".to_string()));
+        }
+        let typecode = self.peek()?;
+        if is_string(typecode) {
+            let bytes = self.string()?;
+            return Ok(match str::from_utf8(bytes) {
+                Ok(text) => Yaml::String(text.to_string()),
+                Err(_) => Yaml::Array(bytes.iter().map(|&byte| Yaml::Integer(byte as i64)).collect()),
+            });
+        }
+        self.pos += 1;
+        if (INT_POS_FIXED_START..INT_POS_FIXED_START + INT_POS_FIXED_COUNT).contains(&typecode) {
+            return Ok(Yaml::Integer((typecode - INT_POS_FIXED_START) as i64));
+        }
+        if (INT_NEG_FIXED_START..INT_NEG_FIXED_START + INT_NEG_FIXED_COUNT).contains(&typecode) {
+            return Ok(Yaml::Integer(-1 - (typecode - INT_NEG_FIXED_START) as i64));
+        }
+        if typecode >= LIST_FIXED_START {
+            let count = (typecode - LIST_FIXED_START) as usize;
+            let items = (0..count).map(|_| self.value(depth + 1)).collect::<Result<_, _>>()?;
+            return Ok(Yaml::Array(items));
+        }
+        if (DICT_FIXED_START..DICT_FIXED_START + DICT_FIXED_COUNT).contains(&typecode) {
+            let mut hash = Hash::new();
+            for _ in 0..typecode - DICT_FIXED_START {
+                let key = self.value(depth + 1)?;
+                hash.insert(key, self.value(depth + 1)?);
+            }
+            return Ok(Yaml::Hash(hash));
+        }
+        match typecode {
+            CHR_LIST => {
+                let mut items = Vec::new();
+                while self.peek()? != CHR_TERM {
+                    items.push(self.value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Yaml::Array(items))
+            }
+            CHR_DICT => {
+                let mut hash = Hash::new();
+                while self.peek()? != CHR_TERM {
+                    let key = self.value(depth + 1)?;
+                    hash.insert(key, self.value(depth + 1)?);
+                }
+                self.pos += 1;
+                Ok(Yaml::Hash(hash))
+            }
+            CHR_INT1 => Ok(Yaml::Integer(i8::from_be_bytes(self.array()?) as i64)),
+            CHR_INT2 => Ok(Yaml::Integer(i16::from_be_bytes(self.array()?) as i64)),
+            CHR_INT4 => Ok(Yaml::Integer(i32::from_be_bytes(self.array()?) as i64)),
+            CHR_INT8 => Ok(Yaml::Integer(i64::from_be_bytes(self.array()?))),
+            CHR_INT => {
+                let digits = self.digits(CHR_TERM, MAX_INT_LENGTH)?;
+                // too big for an i64 (a uuid, say): keep the digits, the way yaml-rust2 hands
+                // back an oversized integer literal.
+                Ok(match digits.parse::<i64>() {
+                    Ok(i) => Yaml::Integer(i),
+                    Err(_) if digits.trim_start_matches('-').is_empty() =>
+                        return Err(invalid("empty integer".to_string())),
+                    Err(_) => Yaml::Real(digits.to_string()),
+                })
+            }
+            CHR_FLOAT32 => Ok(Yaml::Real(f32::from_be_bytes(self.array()?).to_string())),
+            CHR_FLOAT64 => Ok(Yaml::Real(f64::from_be_bytes(self.array()?).to_string())),
+            CHR_TRUE => Ok(Yaml::Boolean(true)),
+            CHR_FALSE => Ok(Yaml::Boolean(false)),
+            CHR_NONE => Ok(Yaml::Null),
+            other => Err(invalid(format!("invalid typecode {other} at offset {}", self.pos - 1))),
+        }
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+}
+
+fn is_string(typecode: u8) -> bool {
+    (STR_FIXED_START..STR_FIXED_START + STR_FIXED_COUNT).contains(&typecode) || typecode.is_ascii_digit()
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("rencode: {message}"))
+}
+
+fn truncated() -> Error {
+    invalid("truncated data".to_string())
+}
+
+
+// Encode one of our own packets. They are built as JSON values (see the client's write_json), and
+// every JSON type has a direct rencode equivalent; strings go out as their UTF-8 bytes.
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_value(&mut out, value);
+    out
+}
+
+fn encode_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(CHR_NONE),
+        Value::Bool(true) => out.push(CHR_TRUE),
+        Value::Bool(false) => out.push(CHR_FALSE),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                encode_int(out, i);
+            } else if let Some(u) = n.as_u64() {
+                // above i64::MAX: only the arbitrary-precision form can carry it.
+                out.push(CHR_INT);
+                out.extend_from_slice(u.to_string().as_bytes());
+                out.push(CHR_TERM);
+            } else {
+                out.push(CHR_FLOAT64);
+                out.extend_from_slice(&n.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::String(s) => encode_bytes(out, s.as_bytes()),
+        Value::Array(items) => {
+            let fixed = items.len() < LIST_FIXED_COUNT as usize;
+            out.push(if fixed { LIST_FIXED_START + items.len() as u8 } else { CHR_LIST });
+            for item in items {
+                encode_value(out, item);
+            }
+            if !fixed {
+                out.push(CHR_TERM);
+            }
+        }
+        Value::Object(map) => {
+            let fixed = map.len() < DICT_FIXED_COUNT as usize;
+            out.push(if fixed { DICT_FIXED_START + map.len() as u8 } else { CHR_DICT });
+            for (key, item) in map {
+                encode_bytes(out, key.as_bytes());
+                encode_value(out, item);
+            }
+            if !fixed {
+                out.push(CHR_TERM);
+            }
+        }
+    }
+}
+
+fn encode_int(out: &mut Vec<u8>, i: i64) {
+    if (0..INT_POS_FIXED_COUNT as i64).contains(&i) {
+        out.push(INT_POS_FIXED_START + i as u8);
+    } else if (-(INT_NEG_FIXED_COUNT as i64)..0).contains(&i) {
+        out.push(INT_NEG_FIXED_START + (-1 - i) as u8);
+    } else if let Ok(i) = i8::try_from(i) {
+        out.push(CHR_INT1);
+        out.extend_from_slice(&i.to_be_bytes());
+    } else if let Ok(i) = i16::try_from(i) {
+        out.push(CHR_INT2);
+        out.extend_from_slice(&i.to_be_bytes());
+    } else if let Ok(i) = i32::try_from(i) {
+        out.push(CHR_INT4);
+        out.extend_from_slice(&i.to_be_bytes());
+    } else {
+        out.push(CHR_INT8);
+        out.extend_from_slice(&i.to_be_bytes());
+    }
+}
+
+fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.len() < STR_FIXED_COUNT as usize {
+        out.push(STR_FIXED_START + bytes.len() as u8);
+    } else {
+        out.extend_from_slice(bytes.len().to_string().as_bytes());
+        out.push(b':');
+    }
+    out.extend_from_slice(bytes);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::net::packet::yaml_binary;
+
+    // a packet's main list, and its raw chunks by index
+    type Decoded = (Vec<Yaml>, HashMap<u8, Vec<u8>>);
+
+    fn decode(data: &[u8]) -> Result<Decoded, Error> {
+        decode_packet(data, HashMap::new()).map(|packet| (packet.main, packet.raw))
+    }
+
+    fn text(s: &str) -> Yaml {
+        Yaml::String(s.to_string())
+    }
+
+    // byte sequences as produced by xpra's rencodeplus.dumps():
+    #[test]
+    fn decodes_reference_encodings() {
+        // ["ping", 1234, -5, True, None]
+        let data = b"\xc5\x84ping\x3f\x04\xd2\x4a\x43\x45";
+        let (main, raw) = decode(data).unwrap();
+        assert_eq!(main, vec![text("ping"), Yaml::Integer(1234), Yaml::Integer(-5),
+                              Yaml::Boolean(true), Yaml::Null]);
+        assert!(raw.is_empty());
+
+        // ["x", {"a": [1, 2]}, 2**40, -2**33]
+        let data = b"\xc4\x81x\x67\x81a\xc2\x01\x02\x41\x00\x00\x01\x00\x00\x00\x00\x00\x41\xff\xff\xff\xfe\x00\x00\x00\x00";
+        let (main, _) = decode(data).unwrap();
+        let mut hash = Hash::new();
+        hash.insert(text("a"), Yaml::Array(vec![Yaml::Integer(1), Yaml::Integer(2)]));
+        assert_eq!(main, vec![text("x"), Yaml::Hash(hash), Yaml::Integer(1 << 40),
+                              Yaml::Integer(-(1 << 33))]);
+    }
+
+    #[test]
+    fn round_trips_our_own_packets() {
+        let long_text = "x".repeat(100);
+        let packet = json!(["hello", {
+            "version": "6.4", "zero": 0, "small": 43, "neg": -32, "byte": -100, "short": 1000,
+            "int": 100000, "long": 10_000_000_000i64, "huge": u64::MAX, "float": 0.5,
+            "flag": true, "none": null, "text": long_text,
+            "list": (0..70).collect::<Vec<i32>>(),
+        }]);
+        let (main, raw) = decode(&encode(&packet)).unwrap();
+        assert_eq!(main[0], text("hello"));
+        assert!(raw.is_empty());
+        let get = |key: &str| main[1][key].clone();
+        assert_eq!(get("version"), text("6.4"));
+        assert_eq!(get("zero"), Yaml::Integer(0));
+        assert_eq!(get("small"), Yaml::Integer(43));
+        assert_eq!(get("neg"), Yaml::Integer(-32));
+        assert_eq!(get("byte"), Yaml::Integer(-100));
+        assert_eq!(get("short"), Yaml::Integer(1000));
+        assert_eq!(get("int"), Yaml::Integer(100000));
+        assert_eq!(get("long"), Yaml::Integer(10_000_000_000));
+        assert_eq!(get("huge"), Yaml::Real(u64::MAX.to_string()));
+        assert_eq!(get("float").as_f64(), Some(0.5));
+        assert_eq!(get("flag"), Yaml::Boolean(true));
+        assert_eq!(get("none"), Yaml::Null);
+        assert_eq!(get("text"), text(&"x".repeat(100)));
+        assert_eq!(get("list").as_vec().map(Vec::len), Some(70));
+        // 14 keys: more than a fixed dict can hold would have used CHR_DICT, this one fits
+        assert_eq!(main[1].as_hash().map(|h| h.len()), Some(14));
+    }
+
+    #[test]
+    fn top_level_byte_strings_land_in_raw() {
+        // ["draw", 1, b"\xff\x00", "text"]
+        let data = b"\xc4\x84draw\x01\x82\xff\x00\x84text";
+        let (main, raw) = decode(data).unwrap();
+        assert_eq!(main, vec![text("draw"), Yaml::Integer(1), Yaml::Null, text("text")]);
+        assert_eq!(raw.get(&2), Some(&vec![0xff, 0x00]));
+        assert_eq!(raw.get(&3), Some(&b"text".to_vec()));
+        assert_eq!(raw.get(&0), None);
+    }
+
+    #[test]
+    fn chunks_win_over_placeholders() {
+        let chunks = HashMap::from([(2u8, b"pixels".to_vec())]);
+        let packet = decode_packet(b"\xc3\x84draw\x01\x80", chunks).unwrap();
+        assert_eq!(packet.main[2], text(""));
+        assert_eq!(packet.raw.get(&2), Some(&b"pixels".to_vec()));
+    }
+
+    #[test]
+    fn nested_binary_is_a_list_of_bytes() {
+        // ["x", [b"\xff\x00"]]
+        let (main, _) = decode(b"\xc2\x81x\xc1\x82\xff\x00").unwrap();
+        assert_eq!(main[1], Yaml::Array(vec![Yaml::Array(vec![Yaml::Integer(255), Yaml::Integer(0)])]));
+        assert_eq!(yaml_binary(&main[1][0]), Some(b"\xff\x00".to_vec()));
+    }
+
+    #[test]
+    fn long_strings_use_a_length_prefix() {
+        let mut data = b"\xc2\x81x100:".to_vec();
+        data.extend(std::iter::repeat_n(0xfe, 100));
+        let (_, raw) = decode(&data).unwrap();
+        assert_eq!(raw.get(&1).map(Vec::len), Some(100));
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        for data in [
+            &b""[..],
+            b"\x01",                // not a list
+            b"\xc2\x84ping",        // one item short
+            b"\xc1\x88ping",        // string overruns the buffer
+            b"\xc1\x3f\x04",        // truncated int2
+            b"\xc1\x3d12a\x7f",     // garbage in an arbitrary-precision int
+            b"\xc1\x3d\x7f",        // empty arbitrary-precision int
+            b"\xc1\x3b\x01",        // unterminated list
+            b"\xc1\x99999999999999999999999:", // absurd string length
+            b"\xc1\x01\x02",        // trailing data
+            b"\xc1\x7e",            // not a typecode
+        ] {
+            assert!(decode(data).is_err(), "{data:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let mut data = vec![0xc1];
+        data.extend(std::iter::repeat_n(0xc1, 1000));
+        data.push(0xc0);
+        assert!(decode(&data).is_err());
+    }
+}
@@ -1,44 +1,162 @@
 use std::io::{Error, ErrorKind};
 use std::result::{Result};
 use std::{str};
+use base64::Engine;
+use base64::engine::general_purpose;
 use log::{error};
+use serde_json::Value;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, ScanError, TScalarStyle};
 use yaml_rust2::{YamlLoader, Yaml};
-use crate::net::io::RawPacket;
+use crate::net::io::{RawPacket, FLAGS_RENCODE, FLAGS_RENCODEPLUS, FLAGS_YAML};
 use crate::net::packet::Packet;
+use crate::net::rencode;
 
 
 pub const VERSION_KEY_STR: &str = "version";
 
 
-// Turn one packet read off the wire into a `Packet`: its YAML payload gives the positional
-// fields, and any out-of-band chunks that came with it are kept aside in `raw`, keyed by the
-// field index they belong to. `Packet::get_bytes` reads those in preference to the (empty)
-// placeholder the sender left in the YAML - see net::io.
+// The packet encoders we speak, in order of preference: this is the order we advertise them in
+// the hello, and the first one the server also supports is the one we send with.
+// rencodeplus is xpra's default and much cheaper for both ends than YAML, which remains the
+// fallback every server supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketEncoder {
+    RencodePlus,
+    Yaml,
+}
+
+pub const PACKET_ENCODERS: [PacketEncoder; 2] = [PacketEncoder::RencodePlus, PacketEncoder::Yaml];
+
+impl PacketEncoder {
+
+    // the name used in the hello's `encoders` list
+    pub fn name(self) -> &'static str {
+        match self {
+            PacketEncoder::RencodePlus => "rencodeplus",
+            PacketEncoder::Yaml => "yaml",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PacketEncoder> {
+        PACKET_ENCODERS.into_iter().find(|encoder| encoder.name() == name)
+    }
+
+    // the bit identifying this encoder in a packet header's flags byte
+    pub fn flag(self) -> u8 {
+        match self {
+            PacketEncoder::RencodePlus => FLAGS_RENCODEPLUS,
+            PacketEncoder::Yaml => FLAGS_YAML,
+        }
+    }
+
+    // Which encoder a packet header's flags byte names. Plain rencode (0x1) is decoded as
+    // rencodeplus: the two differ only in how the encoder handles Python types we never see.
+    pub fn from_flags(flags: u8) -> Option<PacketEncoder> {
+        if flags & (FLAGS_RENCODEPLUS | FLAGS_RENCODE) != 0 {
+            Some(PacketEncoder::RencodePlus)
+        } else if flags & FLAGS_YAML != 0 {
+            Some(PacketEncoder::Yaml)
+        } else {
+            None
+        }
+    }
+}
+
+
+// Turn one packet read off the wire into a `Packet`: its payload gives the positional fields,
+// and any out-of-band chunks that came with it are kept aside in `raw`, keyed by the field index
+// they belong to. `Packet::get_bytes` reads those in preference to the (empty) placeholder the
+// sender left in the payload - see net::io. rencodeplus adds its own top-level byte strings to
+// `raw` the same way, see net::rencode.
 pub fn parse_packet(raw: RawPacket) -> Result<Packet, Error> {
-    let RawPacket{ mut payload, chunks } = raw;
-    let payload_buf: &mut [u8] = payload.as_mut_slice();
-    let payload_str = match str::from_utf8(payload_buf) {
-        Ok(payload_str) => payload_str,
-        Err(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
-    };
-    let ret = YamlLoader::load_from_str(payload_str);
-    if ! ret.is_ok() {
-        return Err(Error::new(ErrorKind::InvalidData, ret.unwrap_err()));
-    }
-    let yaml_packet = ret.unwrap();
+    let RawPacket{ payload, chunks, encoder, .. } = raw;
+    if encoder == PacketEncoder::RencodePlus {
+        return rencode::decode_packet(&payload, chunks);
+    }
+    let payload_str = str::from_utf8(&payload).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let mut yaml_packet = load_yaml(payload_str)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
     if yaml_packet.len() != 1 {
         error!("expected 1 item, got {:?}", yaml_packet.len());
         return Err(Error::new(ErrorKind::InvalidData, "too many items"));
     }
-    let packet = &yaml_packet[0];
     // error!("packet = {:?}", packet);
-    match packet {
+    match yaml_packet.remove(0) {
         Yaml::Array(array) => {
-            Ok(Packet{ main: array.to_vec(), raw: chunks, decode_time_us: None })
+            Ok(Packet{ main: array, raw: chunks, decode_time_us: None })
         },
-        _ => {
+        packet => {
             error!("packet is not an array: {:?}", packet);
             Err(Error::new(ErrorKind::InvalidData, "received invalid packet data type"))
         },
     }
 }
+
+
+// PyYAML ships a Python bytes value as a `!!binary` base64 scalar, a tag yaml-rust2 drops on the
+// floor. A nested one is handed on as the list of its bytes instead, the way net::rencode decodes
+// a nested byte string, so that it can be told apart from text (see `yaml_binary`). A packet's own
+// fields keep their base64 string, for `Packet::get_bytes`: pixel data has no business in a list.
+struct BinaryLoader {
+    loader: YamlLoader,
+    depth: usize,
+}
+
+impl MarkedEventReceiver for BinaryLoader {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match &event {
+            Event::SequenceStart(..) | Event::MappingStart(..) => self.depth += 1,
+            Event::SequenceEnd | Event::MappingEnd => self.depth -= 1,
+            Event::Scalar(value, _, anchor, Some(tag))
+                if self.depth > 1 && tag.handle == "tag:yaml.org,2002:" && tag.suffix == "binary" => {
+                let base64 = value.split_whitespace().collect::<String>();
+                if let Ok(bytes) = general_purpose::STANDARD.decode(base64) {
+                    self.loader.on_event(Event::SequenceStart(*anchor, None), mark);
+                    for byte in bytes {
+                        self.loader.on_event(Event::Scalar(byte.to_string(), TScalarStyle::Plain, 0, None), mark);
+                    }
+                    self.loader.on_event(Event::SequenceEnd, mark);
+                    return;
+                }
+            }
+            _ => {}
+        }
+        self.loader.on_event(event, mark);
+    }
+}
+
+fn load_yaml(text: &str) -> Result<Vec<Yaml>, ScanError> {
+    let mut receiver = BinaryLoader { loader: YamlLoader::default(), depth: 0 };
+    Parser::new_from_str(text).load(&mut receiver, true)?;
+    Ok(receiver.loader.documents().to_vec())
+}
+
+
+// Serialize one of our own packets with `encoder`. The YAML encoder gets JSON, which is valid
+// YAML and all the server's YAML loader needs.
+pub fn encode_packet(encoder: PacketEncoder, data: &Value) -> Vec<u8> {
+    match encoder {
+        PacketEncoder::RencodePlus => rencode::encode(data),
+        PacketEncoder::Yaml => data.to_string().into_bytes(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::net::packet::{yaml_binary, yaml_bytes};
+
+    #[test]
+    fn nested_binary_is_a_list_of_bytes() {
+        let payload = b"- hello\n- !!binary AAE=\n- salt: !!binary |\n    c2Fs\n    dA==\n  text: c2FsdA==\n";
+        let raw = RawPacket { payload: payload.to_vec(), chunks: HashMap::new(), encoder: PacketEncoder::Yaml, encrypted: false };
+        let packet = parse_packet(raw).unwrap();
+        assert_eq!(packet.main[1], Yaml::String("AAE=".to_string()));
+        assert_eq!(yaml_bytes(&packet.main[1]), [0, 1]);
+        assert_eq!(yaml_binary(&packet.main[2]["salt"]), Some(b"salt".to_vec()));
+        assert_eq!(yaml_binary(&packet.main[2]["text"]), None);
+    }
+}
@@ -0,0 +1,249 @@
+// The xpra sessions running on this machine, found the way `xpra list` finds them: by the Unix-domain
+// sockets their servers listen on, in the standard socket directories. This is what `:N` targets
+// resolve through when connecting (see uri::Target), and what `--list-sessions` prints.
+//
+// Depending on the xpra version and its `socket-dirs`, the socket for display `:10` is any one of:
+//  * `DIR/10/socket`, in the session directory of xpra 5 and later
+//  * `DIR/10`, the socket itself
+//  * `DIR/HOSTNAME-10`, the name xpra 4 and older used (and still use in `~/.xpra`)
+// and there may be stale ones left behind by servers that did not exit cleanly, which is why a
+// socket only counts once something answers on it.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::VERSION;
+use super::connection::Connection;
+use super::io::{read_packet, write_packet};
+use super::packet::yaml_hash_str;
+use super::serde::{encode_packet, parse_packet, PacketEncoder, PACKET_ENCODERS};
+
+// how long a server has to answer `--list-sessions`' version request: it is on this machine, so
+// one that takes longer than this is busy or stuck, and we have others to ask.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SessionState {
+    // a server accepted the connection
+    Live,
+    // nothing listens on the socket any more: its server is gone
+    Dead,
+    // the socket cannot be connected to for some other reason, usually permissions
+    Unknown,
+}
+
+impl SessionState {
+    // as `xpra list` prints them
+    pub fn name(self) -> &'static str {
+        match self {
+            SessionState::Live => "LIVE",
+            SessionState::Dead => "DEAD",
+            SessionState::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    // the display number, without the `:`
+    pub display: String,
+    pub path: PathBuf,
+}
+
+// `:10` or `local:10` - the display number, or `None` if `target` is not a local display.
+pub fn parse_display(target: &str) -> Option<&str> {
+    let display = target.strip_prefix("local").unwrap_or(target).strip_prefix(':')?;
+    (!display.is_empty() && display.bytes().all(|b| b.is_ascii_digit())).then_some(display)
+}
+
+// Where xpra servers put their sockets, most preferred first and without duplicates:
+// `$XDG_RUNTIME_DIR/xpra`, `/run/user/$UID/xpra` (the same directory, usually) and `~/.xpra`.
+pub fn socket_dirs() -> Vec<PathBuf> {
+    let non_empty = |name: &str| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let mut dirs = Vec::new();
+    dirs.extend(non_empty("XDG_RUNTIME_DIR").map(|dir| dir.join("xpra")));
+    dirs.extend(user_id().map(|uid| PathBuf::from(format!("/run/user/{uid}/xpra"))));
+    dirs.extend(non_empty("HOME").map(|home| home.join(".xpra")));
+    let mut unique: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        let canonical = fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone());
+        if !unique.iter().any(|seen| fs::canonicalize(seen).unwrap_or_else(|_| seen.clone()) == canonical) {
+            unique.push(dir);
+        }
+    }
+    unique
+}
+
+// The socket of the live session on `display`, from the standard socket directories.
+pub fn find_socket(display: &str) -> Result<PathBuf, String> {
+    if !cfg!(unix) {
+        return Err("local sessions are not supported on this platform".to_string());
+    }
+    let dirs = socket_dirs();
+    find_socket_in(&dirs, display).ok_or_else(|| {
+        let searched: Vec<String> = dirs.iter().map(|dir| format!("{:?}", dir)).collect();
+        format!("no live session found for display :{} in {}", display, searched.join(", "))
+    })
+}
+
+pub fn find_socket_in(dirs: &[PathBuf], display: &str) -> Option<PathBuf> {
+    sessions_in(dirs)
+        .into_iter()
+        .filter(|session| session.display == display)
+        .find(|session| state(&session.path) == SessionState::Live)
+        .map(|session| session.path)
+}
+
+// Every session socket in `dirs`, live or not, in directory order and then by display number.
+pub fn sessions_in(dirs: &[PathBuf]) -> Vec<Session> {
+    let mut sessions = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else { continue };
+        let mut found: Vec<Session> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let display = name.rsplit_once('-').map_or(name.as_str(), |(_, display)| display);
+                if display.is_empty() || !display.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                let path = entry.path();
+                let path = if path.is_dir() { path.join("socket") } else { path };
+                is_socket(&path).then(|| Session { display: display.to_string(), path })
+            })
+            .collect();
+        found.sort_by_key(|session| (session.display.parse::<u64>().unwrap_or(u64::MAX), session.path.clone()));
+        sessions.extend(found);
+    }
+    sessions
+}
+
+// Whether anything listens on the socket at `path`.
+pub fn state(path: &Path) -> SessionState {
+    match connect(path) {
+        Ok(_) => SessionState::Live,
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => SessionState::Dead,
+        Err(_) => SessionState::Unknown,
+    }
+}
+
+// The state of the session at `path` and, when it is live, the version of its server - which it
+// sends back in reply to a hello that asks for nothing else (a `version` request, as `xpra version`
+// makes), without authenticating us first.
+pub fn probe(path: &Path) -> (SessionState, Option<String>) {
+    let mut stream = match connect(path) {
+        Ok(stream) => stream,
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => return (SessionState::Dead, None),
+        Err(_) => return (SessionState::Unknown, None),
+    };
+    (SessionState::Live, server_version(&mut stream))
+}
+
+// `None` for a server that will not say: one that insists on authentication first answers with a
+// challenge, and one that does not know the request disconnects us.
+fn server_version(stream: &mut Connection) -> Option<String> {
+    let hello = json!(["hello", {
+        "version": VERSION,
+        "request": "version",
+        "encoders": PACKET_ENCODERS.map(PacketEncoder::name),
+    }]);
+    // YAML, as for the client's own hello: every server reads it
+    let encoder = PacketEncoder::Yaml;
+    write_packet(stream, encoder, false, None, &encode_packet(encoder, &hello)).ok()?;
+    let packet = parse_packet(read_packet(stream, None).ok()?).ok()?;
+    if packet.packet_type() != "hello" || !packet.has(1) {
+        return None;
+    }
+    let version = yaml_hash_str(&packet.main[1], "version".to_string());
+    (!version.is_empty()).then_some(version)
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> std::io::Result<Connection> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    stream.set_write_timeout(Some(PROBE_TIMEOUT))?;
+    Ok(Connection::Socket(stream))
+}
+
+#[cfg(not(unix))]
+fn connect(_path: &Path) -> std::io::Result<Connection> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+fn is_socket(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+}
+
+#[cfg(not(unix))]
+fn is_socket(_path: &Path) -> bool {
+    false
+}
+
+// our own uid, for `/run/user/$UID`: the owner of our /proc entry, which saves binding getuid(2)
+// for this one use. Not every Unix has a /proc, but those without one have no /run/user either.
+#[cfg(unix)]
+fn user_id() -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata("/proc/self").ok().map(|metadata| metadata.uid())
+}
+
+#[cfg(not(unix))]
+fn user_id() -> Option<u32> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    #[test]
+    fn local_displays_are_parsed() {
+        assert_eq!(parse_display(":10"), Some("10"));
+        assert_eq!(parse_display("local:10"), Some("10"));
+        for target in [":", "local:", ":10.0", "host:10", "local::10", "10", "localhost:10"] {
+            assert_eq!(parse_display(target), None, "{target}");
+        }
+    }
+
+    #[test]
+    fn sockets_are_found_in_every_layout_and_probed() {
+        let dir = env::temp_dir().join(format!("rust-xpra-test-{}-sessions", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("10")).unwrap();
+        // a live session in an xpra 5 session directory, a dead one under its xpra 4 name, and
+        // things that are not sessions at all
+        let live = UnixListener::bind(dir.join("10").join("socket")).unwrap();
+        drop(UnixListener::bind(dir.join("host-11")).unwrap());
+        fs::write(dir.join("12"), "").unwrap();
+        drop(UnixListener::bind(dir.join("notes")).unwrap());
+        let listener = live.try_clone().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = Connection::Socket(stream);
+            let hello = parse_packet(read_packet(&mut stream, None).unwrap()).unwrap();
+            assert_eq!(yaml_hash_str(&hello.main[1], "request".to_string()), "version");
+            let reply = encode_packet(PacketEncoder::Yaml, &json!(["hello", {"version": "6.4.1"}]));
+            write_packet(&mut stream, PacketEncoder::Yaml, false, None, &reply).unwrap();
+        });
+
+        let dirs = [dir.clone()];
+        let sessions = sessions_in(&dirs);
+        let found: Vec<(&str, &Path)> = sessions.iter().map(|s| (s.display.as_str(), s.path.as_path())).collect();
+        assert_eq!(found, [("10", dir.join("10").join("socket").as_path()), ("11", dir.join("host-11").as_path())]);
+        assert_eq!(probe(&sessions[1].path), (SessionState::Dead, None));
+        assert_eq!(find_socket_in(&dirs, "11"), None);
+        assert_eq!(probe(&sessions[0].path), (SessionState::Live, Some("6.4.1".to_string())));
+        server.join().unwrap();
+        // a listener that is not accepting yet still makes a session live
+        assert_eq!(find_socket_in(&dirs, "10"), Some(dir.join("10").join("socket")));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,6 +1,10 @@
-// Minimal SHA1 implementation (RFC 3174), used only to compute the
-// Sec-WebSocket-Accept handshake value. Not intended for anything
-// security-sensitive.
+// Minimal SHA1 implementation (RFC 3174), used to compute the
+// Sec-WebSocket-Accept handshake value, and as the HMAC behind the PBKDF2
+// key stretching of a server that asks for SHA1 (see net::crypto) - HMAC-SHA1
+// does not rely on the collision resistance SHA1 has lost. Not intended for
+// anything else security-sensitive.
+const BLOCK: usize = 64;
+
 pub fn sha1(data: &[u8]) -> [u8; 20] {
     let mut h0: u32 = 0x67452301;
     let mut h1: u32 = 0xEFCDAB89;
@@ -60,9 +64,24 @@ pub fn sha1(data: &[u8]) -> [u8; 20] {
     out
 }
 
+// HMAC-SHA1 (RFC 2104), the same construction as sha256's hmac_sha256.
+pub fn hmac_sha1(key: &[u8], msg: &[u8]) -> [u8; 20] {
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..20].copy_from_slice(&sha1(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = block.map(|b| b ^ 0x36).to_vec();
+    inner.extend_from_slice(msg);
+    let mut outer = block.map(|b| b ^ 0x5c).to_vec();
+    outer.extend_from_slice(&sha1(&inner));
+    sha1(&outer)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::sha1;
+    use super::{hmac_sha1, sha1};
 
     #[test]
     fn test_vectors() {
@@ -74,6 +93,16 @@ mod tests {
         );
     }
 
+    // RFC 2202 test cases 1 and 6
+    #[test]
+    fn hmac_test_vectors() {
+        assert_eq!(hmac_sha1(&[0x0b; 20], b"Hi There"), hex("b617318655057264e28bc0b6fb378c8ef146be00"));
+        assert_eq!(
+            hmac_sha1(&[0xaa; 80], b"Test Using Larger Than Block-Size Key - Hash Key First"),
+            hex("aa4ae5e15272d00e95705637ce8a3b55ed402112")
+        );
+    }
+
     fn hex(s: &str) -> [u8; 20] {
         let mut out = [0u8; 20];
         for i in 0..20 {